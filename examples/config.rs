@@ -0,0 +1,45 @@
+//! Shows how a host program can hand configuration data to a script.
+//!
+//! Run with `cargo run --example config`.
+
+use std::collections::HashMap;
+
+use callisp::ast::Ast;
+use callisp::env::Environment;
+use callisp::eval::eval_expr;
+use callisp::parser::parse_expr;
+
+const SCRIPT: &str = r#"
+(def db (get config "database"))
+(putstr (get db "host"))
+(println (get db "port"))
+(println (get (get settings "features") "beta" false))
+(println (get settings "retries"))
+"#;
+
+fn main() {
+    let mut env = Environment::outer_new();
+
+    // Values of different types can be mixed by converting them to Ast first.
+    let mut database: HashMap<String, Ast> = HashMap::new();
+    database.insert("host".to_string(), "localhost".into());
+    database.insert("port".to_string(), 5432.into());
+    let mut config = HashMap::new();
+    config.insert("database".to_string(), database);
+    env.bind_value("config", config);
+
+    env.bind_json(
+        "settings",
+        r#"{"features": {"beta": true}, "retries": [1, 2, 4]}"#,
+    )
+    .expect("invalid JSON");
+
+    let mut to_parse = SCRIPT;
+    while let Ok((rest, expr)) = parse_expr(to_parse) {
+        to_parse = rest;
+        if let Err(e) = eval_expr(expr, &mut env) {
+            eprintln!("{}", e);
+            return;
+        }
+    }
+}
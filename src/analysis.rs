@@ -0,0 +1,463 @@
+//! Contains static analysis over unevaluated expressions. The cross-reference (`--xref`) uses it
+//! to find the names each top-level form refers to, and to report definitions that are never
+//! used. Lambdas look names up where they are called rather than capturing them, so there is no
+//! closure capture for it to narrow down.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::ast::{Ast, LispAtom};
+use crate::special_forms::SPECIAL_FORMS;
+
+/// Find the symbols referenced in expr that aren't bound within expr, in sorted order.
+///
+/// This is purely syntactic: builtins such as `+` are free variables too, since nothing in the
+/// expression binds them. The binding structure of `lambda`, `def`, every variant of `let`,
+/// `match`, and the looping forms is understood, special form names are not references, and
+/// quoted data is ignored (except for unquoted parts of a quasiquote).
+///
+/// Like the other walks over expressions, this uses an explicit stack of tasks rather than
+/// recursion, so deeply nested expressions can't overflow the stack.
+pub fn free_vars(expr: &Ast) -> BTreeSet<String> {
+    let mut free = BTreeSet::new();
+    let mut bound = Bound::default();
+    let mut tasks = vec![Task::Expr(expr)];
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Expr(expr) => {
+                let mut next = vec![];
+                collect(expr, &bound, &mut free, &mut next);
+                // The tasks for expr are run in order, before anything that came after it
+                tasks.extend(next.into_iter().rev());
+            }
+            Task::Template(template) => {
+                if let Ast::List(list) = template {
+                    match list.split_first() {
+                        Some((Ast::Atom(LispAtom::Symbol(head)), args))
+                            if head == "unquote" || head == "unquote-splicing" =>
+                        {
+                            tasks.extend(args.iter().rev().map(Task::Expr))
+                        }
+                        _ => tasks.extend(list.iter().rev().map(Task::Template)),
+                    }
+                }
+            }
+            Task::Bind(names) => names.into_iter().for_each(|name| bound.bind(name)),
+            Task::Unbind(len) => bound.unbind_to(len),
+        }
+    }
+    free
+}
+
+/// The names bound at a point in the walk, in the order they were bound, with a count of each so
+/// looking a name up doesn't depend on how many are bound.
+#[derive(Default)]
+struct Bound {
+    names: Vec<String>,
+    counts: HashMap<String, usize>,
+}
+
+impl Bound {
+    fn bind(&mut self, name: String) {
+        *self.counts.entry(name.clone()).or_default() += 1;
+        self.names.push(name);
+    }
+
+    /// Unbind the most recently bound names until there are len left.
+    fn unbind_to(&mut self, len: usize) {
+        for name in self.names.drain(len..) {
+            if let Some(count) = self.counts.get_mut(&name) {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(&name);
+                }
+            }
+        }
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.counts.contains_key(name)
+    }
+
+    fn len(&self) -> usize {
+        self.names.len()
+    }
+}
+
+/// A step of the walk done by [free_vars].
+enum Task<'a> {
+    /// Find the free variables of an expression.
+    Expr(&'a Ast),
+    /// Find the free variables in the unquoted parts of a quasiquoted template.
+    Template(&'a Ast),
+    /// Bind names for the tasks after this one.
+    Bind(Vec<String>),
+    /// Unbind the names bound since there were this many.
+    Unbind(usize),
+}
+
+/// Add the tasks for finding the free variables of expr to next, in the order they are to be run.
+fn collect<'a>(
+    expr: &'a Ast,
+    bound: &Bound,
+    free: &mut BTreeSet<String>,
+    next: &mut Vec<Task<'a>>,
+) {
+    match expr {
+        Ast::Atom(LispAtom::Symbol(symbol))
+            if !bound.contains(symbol.as_str()) && !SPECIAL_FORMS.contains_key(symbol.as_str()) =>
+        {
+            free.insert(symbol.clone());
+        }
+        Ast::List(list) => match list.split_first() {
+            Some((Ast::Atom(LispAtom::Symbol(head)), args)) if !bound.contains(head) => {
+                collect_form(head, list, args, bound.len(), next)
+            }
+            _ => next.extend(list.iter().map(Task::Expr)),
+        },
+        Ast::Vector(items) => next.extend(items.iter().map(Task::Expr)),
+        _ => (),
+    }
+}
+
+/// Add the tasks for body with names bound, and then unbound again.
+fn collect_with<'a>(names: Vec<String>, body: &'a [Ast], len: usize, next: &mut Vec<Task<'a>>) {
+    next.push(Task::Bind(names));
+    next.extend(body.iter().map(Task::Expr));
+    next.push(Task::Unbind(len));
+}
+
+/// Add the tasks for a lambda body with its parameters bound. Parameters with defaults are
+/// written `(name default)`, and markers such as `&rest` are skipped.
+fn collect_lambda<'a>(params: &'a [Ast], body: &'a [Ast], len: usize, next: &mut Vec<Task<'a>>) {
+    for param in params {
+        match param {
+            Ast::List(pair) => {
+                // Defaults can refer to the parameters before them
+                next.extend(pair.get(1).map(Task::Expr));
+                next.push(Task::Bind(
+                    pair.first().and_then(symbol_name).into_iter().collect(),
+                ));
+            }
+            _ => next.push(Task::Bind(symbol_name(param).into_iter().collect())),
+        }
+    }
+    next.extend(body.iter().map(Task::Expr));
+    next.push(Task::Unbind(len));
+}
+
+fn symbol_name(ast: &Ast) -> Option<String> {
+    match ast {
+        Ast::Atom(LispAtom::Symbol(name)) if !name.starts_with('&') => Some(name.clone()),
+        _ => None,
+    }
+}
+
+/// Split a let-style binding list `((name value) ...)` into names and value expressions.
+fn let_bindings(bindings: Option<&Ast>) -> Vec<(Option<String>, Option<&Ast>)> {
+    match bindings {
+        Some(Ast::List(bindings)) => bindings
+            .iter()
+            .map(|binding| match binding {
+                Ast::List(pair) => (pair.first().and_then(symbol_name), pair.get(1)),
+                _ => (None, None),
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Add the tasks for a form starting with the symbol head. len is the number of names bound
+/// before the form, which it goes back to afterwards.
+fn collect_form<'a>(
+    head: &str,
+    list: &'a [Ast],
+    args: &'a [Ast],
+    len: usize,
+    next: &mut Vec<Task<'a>>,
+) {
+    let rest = &args[1.min(args.len())..];
+    match head {
+        "quote" => (),
+        "quasiquote" => next.extend(args.iter().map(Task::Template)),
+        "lambda" | "λ" => match args.split_first() {
+            Some((Ast::List(params), body)) => collect_lambda(params, body, len, next),
+            _ => next.extend(rest.iter().map(Task::Expr)),
+        },
+        "def" => match args.first() {
+            Some(Ast::List(signature)) => {
+                // Function definition sugar: (def (name params...) body...)
+                collect_lambda(signature, rest, len, next);
+            }
+            Some(name) => {
+                // The name is bound inside the value so recursive functions don't count it.
+                collect_with(symbol_name(name).into_iter().collect(), rest, len, next);
+            }
+            None => (),
+        },
+        "let" | "let*" | "letrec" | "loop" => {
+            // Named let: (let name ((binding value) ...) body...)
+            let (loop_name, args) = match args.split_first() {
+                Some((Ast::Atom(LispAtom::Symbol(name)), rest)) if head == "let" => {
+                    (Some(name.clone()), rest)
+                }
+                _ => (None, args),
+            };
+            let bindings = let_bindings(args.first());
+            let names: Vec<String> = bindings
+                .iter()
+                .filter_map(|(name, _)| name.clone())
+                .collect();
+
+            if head == "letrec" {
+                next.push(Task::Bind(names.clone()));
+            }
+            for (name, value) in bindings {
+                next.extend(value.map(Task::Expr));
+                if head == "let*" {
+                    next.push(Task::Bind(name.into_iter().collect()));
+                }
+            }
+            next.push(Task::Unbind(len));
+
+            collect_with(
+                loop_name.into_iter().chain(names).collect(),
+                &args[1.min(args.len())..],
+                len,
+                next,
+            );
+        }
+        "letfn" => {
+            // (letfn ((name (params...) body...) ...) body...), where every function can see all
+            // of the names
+            let definitions = match args.first() {
+                Some(Ast::List(definitions)) => definitions.as_slice(),
+                _ => &[],
+            };
+            let names: Vec<String> = definitions
+                .iter()
+                .filter_map(|definition| match definition {
+                    Ast::List(definition) => definition.first().and_then(symbol_name),
+                    _ => None,
+                })
+                .collect();
+            // Each function's parameters are unbound again after its body, leaving the names
+            let functions_len = len + names.len();
+            next.push(Task::Bind(names));
+            for definition in definitions {
+                if let Ast::List(definition) = definition {
+                    if let Some(Ast::List(params)) = definition.get(1) {
+                        collect_lambda(params, &definition[2..], functions_len, next);
+                    }
+                }
+            }
+            next.extend(rest.iter().map(Task::Expr));
+            next.push(Task::Unbind(len));
+        }
+        "define-values" => next.extend(rest.iter().map(Task::Expr)),
+        "let-values" => {
+            // (let-values (((name ...) value) ...) body...)
+            let mut names = vec![];
+            if let Some(Ast::List(bindings)) = args.first() {
+                for binding in bindings {
+                    if let Ast::List(pair) = binding {
+                        if let Some(Ast::List(bound_names)) = pair.first() {
+                            names.extend(bound_names.iter().filter_map(symbol_name));
+                        }
+                        next.extend(pair[1.min(pair.len())..].iter().map(Task::Expr));
+                    }
+                }
+            }
+            collect_with(names, rest, len, next);
+        }
+        "for" => {
+            // (for ((name list) ...) :when test ... body...), where each list can use the names
+            // before it
+            for (name, value) in let_bindings(args.first()) {
+                next.extend(value.map(Task::Expr));
+                next.push(Task::Bind(name.into_iter().collect()));
+            }
+            next.extend(
+                rest.iter()
+                    .filter(|arg| !matches!(arg, Ast::Atom(LispAtom::Keyword(k)) if k == "when"))
+                    .map(Task::Expr),
+            );
+            next.push(Task::Unbind(len));
+        }
+        "dotimes" | "for-each" => {
+            // (dotimes (name count) body...) and (for-each (name list) body...)
+            let (name, value) = match args.first() {
+                Some(Ast::List(spec)) => (spec.first().and_then(symbol_name), spec.get(1)),
+                _ => (None, None),
+            };
+            next.extend(value.map(Task::Expr));
+            collect_with(name.into_iter().collect(), rest, len, next);
+        }
+        "match" => {
+            // (match value (pattern body...) ...), where each body can use its pattern's names
+            if let Some((value, clauses)) = args.split_first() {
+                next.push(Task::Expr(value));
+                for clause in clauses {
+                    if let Ast::List(clause) = clause {
+                        let mut names = vec![];
+                        if let Some(pattern) = clause.first() {
+                            pattern_names(pattern, &mut names);
+                        }
+                        collect_with(names, &clause[1.min(clause.len())..], len, next);
+                    }
+                }
+            }
+        }
+        _ if SPECIAL_FORMS.contains_key(head) => next.extend(args.iter().map(Task::Expr)),
+        _ => next.extend(list.iter().map(Task::Expr)),
+    }
+}
+
+/// The names bound by a `match` pattern. `_`, literals, quoted data, and the `list` at the head of
+/// a list pattern don't bind anything.
+fn pattern_names(pattern: &Ast, names: &mut Vec<String>) {
+    let mut patterns = vec![pattern];
+    while let Some(pattern) = patterns.pop() {
+        match pattern {
+            Ast::Atom(LispAtom::Symbol(name)) if name != "_" => names.push(name.clone()),
+            Ast::List(items) => match items.split_first() {
+                Some((Ast::Atom(LispAtom::Symbol(head)), items)) if head == "list" => {
+                    patterns.extend(items.iter().rev())
+                }
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_expr;
+
+    fn free(input: &str) -> Vec<String> {
+        let (_, expr) = parse_expr(input).unwrap();
+        free_vars(&expr).into_iter().collect()
+    }
+
+    #[test]
+    fn lambda_free_vars() {
+        assert_eq!(free("(lambda (x) (+ x y z))"), ["+", "y", "z"]);
+    }
+
+    #[test]
+    fn shadowing() {
+        assert_eq!(
+            free("(lambda (x) (lambda (y) (x y)))"),
+            Vec::<String>::new()
+        );
+        assert_eq!(free("(let ((x y)) (let ((y x)) (f y)))"), ["f", "y"]);
+        assert_eq!(free("(let* ((x 1) (y x)) y)"), Vec::<String>::new());
+        assert_eq!(free("(let ((x 1) (y x)) y)"), ["x"]);
+        assert_eq!(
+            free("(letrec ((f (lambda () (f)))) f)"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn nested_lambdas() {
+        assert_eq!(
+            free("(lambda (a) (lambda (b) (lambda (c) (list a b c d))))"),
+            ["d", "list"]
+        );
+    }
+
+    #[test]
+    fn named_let_and_loops() {
+        assert_eq!(free("(let go ((i n)) (go i))"), ["n"]);
+        assert_eq!(free("(dotimes (i n) (f i))"), ["f", "n"]);
+        assert_eq!(free("(for-each (x xs) (f x))"), ["f", "xs"]);
+    }
+
+    #[test]
+    fn def_binds_name() {
+        assert_eq!(free("(def f (lambda (n) (f (g n))))"), ["g"]);
+        assert_eq!(free("(def (f n) (f (g n)))"), ["g"]);
+    }
+
+    #[test]
+    fn letfn_functions() {
+        assert_eq!(
+            free("(letfn ((f (x) (g x y)) (g (a &optional (b z)) (f a))) (f w))"),
+            ["w", "y", "z"]
+        );
+    }
+
+    #[test]
+    fn match_patterns() {
+        assert_eq!(free("(match v ((list a b) (+ a b)) (_ 0))"), ["+", "v"]);
+        assert_eq!(
+            free("(match (f v) (1 one) ((quote x) x) ((list a (list _ c)) (g a c d)) (b (h a b)))"),
+            ["a", "d", "f", "g", "h", "one", "v", "x"]
+        );
+    }
+
+    #[test]
+    fn multiple_values() {
+        assert_eq!(free("(define-values (q r) (f x))"), ["f", "x"]);
+        assert_eq!(
+            free("(let-values (((a b) (f a)) ((c) (g))) (list a b c d))"),
+            ["a", "d", "f", "g", "list"]
+        );
+    }
+
+    #[test]
+    fn for_comprehension() {
+        assert_eq!(
+            free("(for ((x xs) (y (f x))) :when (g x y) (list x y z))"),
+            ["f", "g", "list", "xs", "z"]
+        );
+    }
+
+    #[test]
+    fn parameter_defaults() {
+        assert_eq!(
+            free("(lambda (a &optional (b (+ a c)) &key (d b)) (list a b d e))"),
+            ["+", "c", "e", "list"]
+        );
+        assert_eq!(free("(def (f &rest xs) (g xs))"), ["g"]);
+    }
+
+    #[test]
+    fn quote_and_quasiquote() {
+        assert_eq!(free("(quote (a b c))"), Vec::<String>::new());
+        assert_eq!(
+            free("(quasiquote (a (unquote b) (c (unquote-splicing d))))"),
+            ["b", "d"]
+        );
+        assert_eq!(free("(if x (quote y) z)"), ["x", "z"]);
+    }
+
+    #[test]
+    fn deep_nesting() {
+        // Deep enough to overflow the stack if the walk were recursive
+        let depth = 100_000;
+        let mut expr = Ast::Atom(LispAtom::Symbol("x".to_string()));
+        for _ in 0..depth {
+            expr = Ast::List(vec![Ast::Atom(LispAtom::Symbol("f".to_string())), expr]);
+        }
+        assert_eq!(free_vars(&expr).into_iter().collect::<Vec<_>>(), ["f", "x"]);
+
+        let mut expr = Ast::Atom(LispAtom::Symbol("y".to_string()));
+        for _ in 0..depth {
+            expr = Ast::List(vec![
+                Ast::Atom(LispAtom::Symbol("lambda".to_string())),
+                Ast::List(vec![Ast::Atom(LispAtom::Symbol("x".to_string()))]),
+                expr,
+            ]);
+        }
+        assert_eq!(free_vars(&expr).into_iter().collect::<Vec<_>>(), ["y"]);
+    }
+
+    #[test]
+    fn no_free_vars() {
+        assert_eq!(free("(lambda (x y) (x y 1 \"s\"))"), Vec::<String>::new());
+        assert_eq!(free("42"), Vec::<String>::new());
+    }
+}
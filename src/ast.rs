@@ -1,14 +1,34 @@
 //! The AST module contains structs and enums for the abstract syntax tree.
 
-use crate::env::Environment;
+use crate::bigint::BigInt;
+use crate::env::{self, Environment};
 use crate::error::LispError;
 use crate::eval;
+use crate::map::LispMap;
+use crate::port::Port;
+use crate::promise::Promise;
 use dyn_clone::DynClone;
 
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::Infallible;
 use std::fmt::{Debug, Display};
+use std::iter::Chain;
+use std::rc::Rc;
+
+thread_local! {
+    /// The cells whose values are being displayed, so a cell that holds itself isn't displayed
+    /// forever.
+    static DISPLAYING_CELLS: RefCell<Vec<*const RefCell<Ast>>> = const { RefCell::new(vec![]) };
+}
 
 /// Stores an expression.
-#[derive(Debug, Clone)]
+///
+/// Lists, vectors, and pairs can be nested deeply enough to overflow the stack if they are walked
+/// recursively, so cloning, comparing, displaying, and dropping an Ast all use an explicit stack
+/// instead.
+#[derive(Debug)]
 pub enum Ast {
     /// An atom, such as a number, string, or symbol.
     Atom(LispAtom),
@@ -16,61 +36,318 @@ pub enum Ast {
     /// A list created using ().
     List(Vec<Ast>),
 
+    /// A vector created using []. Evaluating a vector evaluates its items, rather than calling a
+    /// function like a list.
+    Vector(Vec<Ast>),
+
+    /// A map from atoms to values, created with `hash-map` or `{key value ...}`.
+    Map(LispMap),
+
+    /// A pair whose tail isn't a list, written `(head . tail)`. An improper list like
+    /// `(1 2 . 3)` is a pair whose tail is another pair. Pairs are made with [Ast::cons], which
+    /// makes a list instead when the tail is a list, so the tail of a pair is never a list.
+    Pair(Box<Ast>, Box<Ast>),
+
     /// A callable function.
     Function(Box<dyn LispCallable>),
 
     /// A type (like int or float).
     Type(LispType),
 
+    /// A handle to an environment. Handles are equal when they share exactly the same scopes, so
+    /// two `(current-env)` handles from the same place are equal. A handle bound in one of the
+    /// scopes it shares keeps that scope alive, so the bindings in it are never freed, though the
+    /// finalizers of the environment it came from still run.
+    Env(Rc<RefCell<Environment>>),
+
+    /// A port that strings can be read from or written to. Ports are compared by identity.
+    Port(Port),
+
+    /// A delayed expression created by `delay`. Promises are compared by identity.
+    Promise(Promise),
+
+    /// A mutable cell created by `atom` (called a cell here to tell it apart from [Ast::Atom]).
+    /// Every copy shares the same value, and cells are compared by identity. A cell that holds
+    /// itself, directly or inside its value, is never freed.
+    Cell(Rc<RefCell<Ast>>),
+
     /// Basically a none type.
     Unspecified,
 }
 
 impl PartialEq for Ast {
     fn eq(&self, other: &Self) -> bool {
-        match self {
-            Ast::Atom(val) => match other {
-                Ast::Atom(other) => val == other,
-                _ => false,
-            },
-            Ast::List(items) => match other {
-                Ast::List(other) => items == other,
-                _ => false,
-            },
-            Ast::Type(typ) => match other {
-                Ast::Type(other) => typ == other,
+        let mut pairs = vec![(self, other)];
+        while let Some(pair) = pairs.pop() {
+            let equal = match pair {
+                (Ast::Atom(val), Ast::Atom(other)) => val == other,
+                (Ast::List(items), Ast::List(other)) | (Ast::Vector(items), Ast::Vector(other)) => {
+                    pairs.extend(items.iter().zip(other));
+                    items.len() == other.len()
+                }
+                (Ast::Type(typ), Ast::Type(other)) => typ == other,
+                (Ast::Map(map), Ast::Map(other)) => map == other,
+                (Ast::Pair(head, tail), Ast::Pair(other_head, other_tail)) => {
+                    pairs.push((head, other_head));
+                    pairs.push((tail, other_tail));
+                    true
+                }
+                (Ast::Env(env), Ast::Env(other)) => env::same_env(env, other),
+                (Ast::Port(port), Ast::Port(other)) => port.ptr_eq(other),
+                (Ast::Promise(promise), Ast::Promise(other)) => promise.ptr_eq(other),
+                (Ast::Cell(cell), Ast::Cell(other)) => Rc::ptr_eq(cell, other),
+                (Ast::Function(func), Ast::Function(other)) => func.eq_callable(&**other),
+                // Unspecified is never equal to anything
                 _ => false,
-            },
-            // TODO: Maybe two functions are equal if they have the same body?
-            Ast::Function(_) => false,
-            Ast::Unspecified => false,
+            };
+            if !equal {
+                return false;
+            }
         }
+        true
     }
 }
 
-impl Display for Ast {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Clone for Ast {
+    fn clone(&self) -> Self {
+        let Ok(ast) = map_leaves(self, |leaf| Ok::<_, Infallible>(leaf.clone_leaf()));
+        ast
+    }
+}
+
+impl Drop for Ast {
+    fn drop(&mut self) {
+        let Some(mut children) = self.children() else {
+            return;
+        };
+        if !children.any(|child| child.children().is_some()) {
+            return;
+        }
+
+        // Move every nested item onto one flat stack, so each is dropped with no children left
+        let mut stack = vec![];
+        self.take_children(&mut stack);
+        while let Some(mut item) = stack.pop() {
+            item.take_children(&mut stack);
+        }
+    }
+}
+
+/// The items of a list or vector, or the head and then the tail of a pair.
+pub(crate) type Children<'a> = Chain<std::slice::Iter<'a, Ast>, std::slice::Iter<'a, Ast>>;
+
+impl Ast {
+    /// Returns false only for the Bool false and nil. Every other value, including empty lists
+    /// and 0, is true when used as a condition.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(
+            self,
+            Ast::Atom(LispAtom::Bool(false)) | Ast::Atom(LispAtom::Nil)
+        )
+    }
+
+    /// Put head in front of tail. If tail is a list this is a list starting with head, and
+    /// otherwise it is the pair `(head . tail)`.
+    pub fn cons(head: Ast, mut tail: Ast) -> Ast {
+        match &mut tail {
+            Ast::List(items) => {
+                let mut list = Vec::with_capacity(items.len() + 1);
+                list.push(head);
+                list.append(items);
+                Ast::List(list)
+            }
+            _ => Ast::Pair(Box::new(head), Box::new(tail)),
+        }
+    }
+
+    /// The values inside a list, vector, or pair, which are walked with an explicit stack.
+    pub(crate) fn children(&self) -> Option<Children<'_>> {
         match self {
-            Self::Atom(atom) => write!(f, "{}", atom),
-            Self::List(list) => {
-                write!(f, "(")?;
+            Ast::List(items) | Ast::Vector(items) => Some(items.iter().chain([].iter())),
+            Ast::Pair(head, tail) => Some(
+                std::slice::from_ref(&**head)
+                    .iter()
+                    .chain(std::slice::from_ref(&**tail)),
+            ),
+            _ => None,
+        }
+    }
 
-                // Display a space-separated list of inner items
-                let mut list = list.iter();
-                if let Some(ast) = list.next() {
-                    write!(f, "{}", ast)?;
+    /// Move the values inside a list, vector, or pair onto stack, leaving it without children.
+    fn take_children(&mut self, stack: &mut Vec<Ast>) {
+        match self {
+            Ast::List(items) | Ast::Vector(items) => stack.append(items),
+            Ast::Pair(head, tail) => {
+                stack.push(std::mem::replace(&mut **head, Ast::Unspecified));
+                stack.push(std::mem::replace(&mut **tail, Ast::Unspecified));
+            }
+            _ => (),
+        }
+    }
 
-                    for ast in list {
-                        write!(f, " {}", ast)?;
+    /// Clone an Ast that isn't a list, vector, or pair.
+    fn clone_leaf(&self) -> Ast {
+        match self {
+            Ast::Atom(atom) => Ast::Atom(atom.clone()),
+            Ast::List(_) | Ast::Vector(_) | Ast::Pair(..) => {
+                unreachable!("lists and pairs are cloned by map_leaves")
+            }
+            Ast::Function(func) => Ast::Function(func.clone()),
+            Ast::Type(typ) => Ast::Type(*typ),
+            Ast::Map(map) => Ast::Map(map.clone()),
+            Ast::Env(env) => Ast::Env(env.clone()),
+            Ast::Port(port) => Ast::Port(port.clone()),
+            Ast::Promise(promise) => Ast::Promise(promise.clone()),
+            Ast::Cell(cell) => Ast::Cell(cell.clone()),
+            Ast::Unspecified => Ast::Unspecified,
+        }
+    }
+}
+
+/// Build a copy of ast with every item that isn't a list, vector, or pair replaced by f(item),
+/// keeping the structure of the lists, vectors, and pairs. The items are visited in order (the
+/// head of a pair before its tail), and the first error is returned.
+pub(crate) fn map_leaves<E>(
+    ast: &Ast,
+    mut f: impl FnMut(&Ast) -> Result<Ast, E>,
+) -> Result<Ast, E> {
+    let Some(children) = ast.children() else {
+        return f(ast);
+    };
+    // Rebuild a list, vector, or pair like the one the items came from
+    let rebuild = |like: &Ast, mut items: Vec<Ast>| match like {
+        Ast::Vector(_) => Ast::Vector(items),
+        Ast::Pair(..) => {
+            let tail = items.pop().expect("a pair has a tail");
+            let head = items.pop().expect("a pair has a head");
+            // The tail might have been mapped to a list
+            Ast::cons(head, tail)
+        }
+        _ => Ast::List(items),
+    };
+
+    // Each level being built holds the list, vector, or pair, the items left to visit, and the
+    // items mapped so far
+    let capacity = children.size_hint().0;
+    let mut levels = vec![(ast, children, Vec::with_capacity(capacity))];
+    loop {
+        let (_, items, mapped) = levels.last_mut().expect("there is always a level");
+        match items.next() {
+            Some(item) => match item.children() {
+                Some(children) => {
+                    let capacity = children.size_hint().0;
+                    levels.push((item, children, Vec::with_capacity(capacity)))
+                }
+                None => mapped.push(f(item)?),
+            },
+            None => {
+                let (like, _, done) = levels.pop().expect("there is always a level");
+                let done = rebuild(like, done);
+                match levels.last_mut() {
+                    Some((_, _, mapped)) => mapped.push(done),
+                    None => return Ok(done),
+                }
+            }
+        }
+    }
+}
+
+/// The brackets a list, vector, or pair is written between.
+fn brackets(ast: &Ast) -> (char, char) {
+    match ast {
+        Ast::Vector(_) => ('[', ']'),
+        _ => ('(', ')'),
+    }
+}
+
+/// Write ast to out, with lists and pairs in parentheses, vectors in square brackets, and their
+/// items separated by spaces. A chain of pairs is written as one improper list, like
+/// `(1 2 . 3)`. Items that aren't lists, vectors, or pairs are written by write_leaf.
+pub(crate) fn write_tree<W: std::fmt::Write>(
+    ast: &Ast,
+    out: &mut W,
+    mut write_leaf: impl FnMut(&Ast, &mut W) -> std::fmt::Result,
+) -> std::fmt::Result {
+    let Some(children) = ast.children() else {
+        return write_leaf(ast, out);
+    };
+
+    let (open, close) = brackets(ast);
+    out.write_char(open)?;
+    // Each level holds the items left to write, the closing bracket, and whether it is a pair
+    let mut levels = vec![(children.peekable(), close, matches!(ast, Ast::Pair(..)))];
+    while let Some((items, close, is_pair)) = levels.last_mut() {
+        match items.next() {
+            Some(item) => {
+                let last = items.peek().is_none();
+                if *is_pair && last {
+                    if let Some(children) =
+                        item.children().filter(|_| matches!(item, Ast::Pair(..)))
+                    {
+                        // Carry on with the rest of the chain inside the same parentheses
+                        *items = children.peekable();
+                        continue;
+                    }
+                    out.write_str(". ")?;
+                }
+                match item.children() {
+                    Some(children) => {
+                        let (open, close) = brackets(item);
+                        out.write_char(open)?;
+                        levels.push((children.peekable(), close, matches!(item, Ast::Pair(..))));
+                        // The separator after this list is written once it is closed
+                        continue;
                     }
+                    None => write_leaf(item, out)?,
                 }
+                if !last {
+                    out.write_char(' ')?;
+                }
+            }
+            None => {
+                out.write_char(*close)?;
+                levels.pop();
+                if levels
+                    .last_mut()
+                    .is_some_and(|(items, _, _)| items.peek().is_some())
+                {
+                    out.write_char(' ')?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
 
-                write!(f, ")")
+impl Display for Ast {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_tree(self, f, |leaf, f| match leaf {
+            Self::Atom(atom) => write!(f, "{}", atom),
+            Self::List(_) | Self::Vector(_) | Self::Pair(..) => {
+                unreachable!("lists and pairs are written by write_tree")
             }
             Self::Type(typ) => write!(f, "{}", typ),
+            Self::Map(map) => write!(f, "{}", map),
             Self::Function(_) => write!(f, "<function>"),
+            Self::Env(_) => write!(f, "#env"),
+            Self::Port(port) => write!(f, "#port<{}>", port.name()),
+            Self::Promise(_) => write!(f, "#promise"),
+            Self::Cell(cell) => {
+                let ptr = Rc::as_ptr(cell);
+                if DISPLAYING_CELLS.with(|cells| cells.borrow().contains(&ptr)) {
+                    return write!(f, "#<atom ...>");
+                }
+                let Ok(value) = cell.try_borrow() else {
+                    // Only while its value is being replaced
+                    return write!(f, "#<atom>");
+                };
+                DISPLAYING_CELLS.with(|cells| cells.borrow_mut().push(ptr));
+                let result = write!(f, "#<atom {}>", value);
+                DISPLAYING_CELLS.with(|cells| cells.borrow_mut().pop());
+                result
+            }
             Self::Unspecified => Ok(()), // unspecified doesn't display anything
-        }
+        })
     }
 }
 
@@ -89,22 +366,160 @@ pub enum LispAtom {
     /// An integer.
     Int(i64),
 
+    /// An integer too large to fit in an Int. Integers that fit are always stored as Ints.
+    BigInt(BigInt),
+
     /// A floating point number.
     Float(f64),
+
+    /// An exact fraction stored as a numerator and a denominator. Rationals are always in lowest
+    /// terms with a denominator greater than 1, so that integers are always stored as Ints.
+    Rational(i64, i64),
+
+    /// A keyword such as `:name`. Keywords evaluate to themselves. The stored name doesn't
+    /// include the leading colon.
+    Keyword(String),
+
+    /// Raw binary data, written in hex like `#x"00ff"`.
+    Bytes(Vec<u8>),
+
+    /// A single unicode character, written like `#\a`, `#\space`, or `#\x41`.
+    Char(char),
+
+    /// The value `nil`, meaning that there is no value, such as when a key is missing from a
+    /// map. nil is false when used as a condition, and is only equal to itself: it isn't false,
+    /// the empty list, or [Ast::Unspecified] (which is what forms run only for their side effects
+    /// return).
+    Nil,
 }
 
 impl Display for LispAtom {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Symbol(symbol) => write!(f, "{}", symbol),
-            Self::String(s) => write!(f, "\"{}\"", s),
+            Self::String(s) => {
+                // Escaped so the parser reads the string back the same
+                write!(f, "\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' | '\\' => write!(f, "\\{}", c)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
             Self::Int(n) => write!(f, "{}", n),
-            Self::Float(n) => write!(f, "{}", n), // TODO: Is there a better way of formatting floats?
+            Self::BigInt(n) => write!(f, "{}", n),
+            Self::Float(n) => write!(f, "{}", format_float(*n)),
+            Self::Rational(num, den) => write!(f, "{}/{}", num, den),
             Self::Bool(b) => write!(f, "{}", b),
+            Self::Keyword(name) => write!(f, ":{}", name),
+            Self::Nil => write!(f, "nil"),
+            Self::Char(c) => match CHAR_NAMES.iter().find(|(_, named)| named == c) {
+                Some((name, _)) => write!(f, "#\\{}", name),
+                None if c.is_control() => write!(f, "#\\x{:x}", *c as u32),
+                None => write!(f, "#\\{}", c),
+            },
+            Self::Bytes(bytes) => {
+                write!(f, "#x\"")?;
+                for byte in bytes {
+                    write!(f, "{:02x}", byte)?;
+                }
+                write!(f, "\"")
+            }
+        }
+    }
+}
+
+/// The characters that have names in character literals, like `#\space`.
+pub(crate) const CHAR_NAMES: [(&str, char); 4] = [
+    ("space", ' '),
+    ("newline", '\n'),
+    ("tab", '\t'),
+    ("return", '\r'),
+];
+
+/// Format a float so that the parser reads it back as the same float. Floats always have a decimal
+/// point or an exponent, so `1.0` can't be mistaken for the Int `1`, and use the shortest digits
+/// that round-trip. NaN and the infinities are written as `nan`, `inf`, and `-inf`.
+pub(crate) fn format_float(num: f64) -> String {
+    if num.is_nan() {
+        "nan".to_string()
+    } else if num.is_infinite() {
+        if num > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        // Debug formatting adds the ".0" and switches to an exponent for very large or small
+        // numbers, which Display doesn't
+        format!("{:?}", num)
+    }
+}
+
+impl From<i64> for Ast {
+    fn from(value: i64) -> Self {
+        Ast::Atom(LispAtom::Int(value))
+    }
+}
+
+/// Big integers that fit in an i64 become Ints.
+impl From<BigInt> for Ast {
+    fn from(value: BigInt) -> Self {
+        match value.to_i64() {
+            Some(num) => Ast::Atom(LispAtom::Int(num)),
+            None => Ast::Atom(LispAtom::BigInt(value)),
         }
     }
 }
 
+impl From<i32> for Ast {
+    fn from(value: i32) -> Self {
+        Ast::Atom(LispAtom::Int(value.into()))
+    }
+}
+
+impl From<f64> for Ast {
+    fn from(value: f64) -> Self {
+        Ast::Atom(LispAtom::Float(value))
+    }
+}
+
+impl From<bool> for Ast {
+    fn from(value: bool) -> Self {
+        Ast::Atom(LispAtom::Bool(value))
+    }
+}
+
+impl From<String> for Ast {
+    fn from(value: String) -> Self {
+        Ast::Atom(LispAtom::String(value))
+    }
+}
+
+impl From<&str> for Ast {
+    fn from(value: &str) -> Self {
+        Ast::Atom(LispAtom::String(value.to_string()))
+    }
+}
+
+impl<T: Into<Ast>> From<Vec<T>> for Ast {
+    fn from(value: Vec<T>) -> Self {
+        Ast::List(value.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Maps are converted to association lists of `(key value)` pairs, sorted by key.
+impl<T: Into<Ast>> From<HashMap<String, T>> for Ast {
+    fn from(value: HashMap<String, T>) -> Self {
+        let mut pairs: Vec<_> = value.into_iter().collect();
+        pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ast::List(
+            pairs
+                .into_iter()
+                .map(|(key, value)| Ast::List(vec![key.into(), value.into()]))
+                .collect(),
+        )
+    }
+}
+
 impl Clone for Box<dyn LispCallable> {
     fn clone(&self) -> Self {
         dyn_clone::clone_box(&**self)
@@ -118,38 +533,229 @@ pub trait LispCallable: Debug + DynClone {
 
     /// Call the function and return the result.
     fn call(&self, args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError>;
+
+    /// A short human-readable description of the function, used when inspecting values.
+    fn describe(&self) -> String {
+        "function".to_string()
+    }
+
+    /// A short usage string for the function, shown by `doc` and `help`, if there is one.
+    fn doc(&self) -> Option<String> {
+        None
+    }
+
+    /// Returns true if other is the same function. By default functions are never equal.
+    fn eq_callable(&self, other: &dyn LispCallable) -> bool {
+        let _ = other;
+        false
+    }
+
+    /// The function as Any, so that eq_callable can check whether other has the same type.
+    fn as_any(&self) -> Option<&dyn Any> {
+        None
+    }
 }
 
-/// Function created using `lambda`.
-#[derive(Debug, Clone)]
+/// The parameter list of a lambda, such as `(a &optional (b 1) &key c)`.
+///
+/// Optional and keyword parameters have an unevaluated default, which is evaluated when the
+/// function is called without them and can refer to the parameters before it. Parameters without a
+/// default are unspecified when they are missing.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Params {
+    /// Parameters that must always be given.
+    pub required: Vec<String>,
+
+    /// Parameters after `&optional`, filled from the positional arguments after the required
+    /// ones.
+    pub optional: Vec<(String, Option<Ast>)>,
+
+    /// The parameter after `&rest`, which is bound to a list of the remaining arguments.
+    pub rest: Option<String>,
+
+    /// Parameters after `&key`, given by name after the positional arguments as in
+    /// `(f 1 :width 10)`.
+    pub keys: Vec<(String, Option<Ast>)>,
+}
+
+impl Params {
+    /// A parameter list with only required parameters.
+    pub fn positional(names: Vec<String>) -> Self {
+        Self {
+            required: names,
+            ..Self::default()
+        }
+    }
+
+    /// Returns true if a call with num_args arguments could be valid. Keyword arguments are
+    /// checked when they are bound.
+    fn accepts(&self, num_args: usize) -> bool {
+        num_args >= self.required.len()
+            && (self.rest.is_some()
+                || !self.keys.is_empty()
+                || num_args <= self.required.len() + self.optional.len())
+    }
+
+    /// Bind args to the parameters in the innermost scope of env, evaluating the defaults of any
+    /// that are missing.
+    fn bind(&self, args: Vec<Ast>, env: &mut Environment) -> Result<(), LispError> {
+        let mut args = args.into_iter().peekable();
+
+        for name in &self.required {
+            env.bind(name.clone(), args.next().ok_or(LispError::BadArity)?);
+        }
+        for (name, default) in &self.optional {
+            // With keyword parameters, the first keyword ends the positional arguments
+            let value = match args.next_if(|arg| {
+                self.keys.is_empty() || !matches!(arg, Ast::Atom(LispAtom::Keyword(_)))
+            }) {
+                Some(value) => value,
+                None => eval_default(default, env)?,
+            };
+            env.bind(name.clone(), value);
+        }
+        if let Some(rest) = &self.rest {
+            env.bind(rest.clone(), Ast::List(args.collect()));
+            return Ok(());
+        }
+
+        let mut given = HashMap::new();
+        while let Some(arg) = args.next() {
+            if self.keys.is_empty() {
+                return Err(LispError::BadArity);
+            }
+            let Ast::Atom(LispAtom::Keyword(key)) = &arg else {
+                return Err(LispError::ValueError(format!(
+                    "expected a keyword argument but got {}",
+                    arg
+                )));
+            };
+            if !self.keys.iter().any(|(name, _)| name == key) {
+                return Err(LispError::ValueError(format!(
+                    "unknown keyword argument :{}",
+                    key
+                )));
+            }
+            let value = args.next().ok_or_else(|| {
+                LispError::ValueError(format!("keyword argument :{} has no value", key))
+            })?;
+            if given.insert(key.clone(), value).is_some() {
+                return Err(LispError::ValueError(format!(
+                    "keyword argument :{} given more than once",
+                    key
+                )));
+            }
+        }
+        for (name, default) in &self.keys {
+            let value = match given.remove(name) {
+                Some(value) => value,
+                None => eval_default(default, env)?,
+            };
+            env.bind(name.clone(), value);
+        }
+        Ok(())
+    }
+}
+
+fn eval_default(default: &Option<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    match default {
+        Some(default) => eval::eval_expr(default.clone(), env),
+        None => Ok(Ast::Unspecified),
+    }
+}
+
+impl Display for Params {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let with_default = |(name, default): &(String, Option<Ast>)| match default {
+            Some(default) => format!("({} {})", name, default),
+            None => name.clone(),
+        };
+
+        let mut parts = self.required.clone();
+        if !self.optional.is_empty() {
+            parts.push("&optional".to_string());
+            parts.extend(self.optional.iter().map(with_default));
+        }
+        if let Some(rest) = &self.rest {
+            parts.push("&rest".to_string());
+            parts.push(rest.clone());
+        }
+        if !self.keys.is_empty() {
+            parts.push("&key".to_string());
+            parts.extend(self.keys.iter().map(with_default));
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// Function created using `lambda`. Lambdas are equal if they have the same parameters and body.
+#[derive(Debug, Clone, PartialEq)]
 pub struct LispLambda {
-    arity: usize,
-    bindings: Vec<String>,
-    body: Ast,
+    params: Params,
+    body: Vec<Ast>,
+    docstring: Option<String>,
 }
 
 impl LispLambda {
-    /// Create a new lambda function with specified arity, bindings, and body.
-    pub fn new(arity: usize, bindings: Vec<String>, body: Ast) -> Self {
+    /// Create a new lambda function with specified parameters and body. The body expressions are
+    /// evaluated in order and the value of the last one is returned.
+    pub fn new(params: Params, body: Vec<Ast>) -> Self {
         Self {
-            arity,
-            bindings,
+            params,
             body,
+            docstring: None,
         }
     }
+
+    /// Set the documentation shown by `doc` and `help`.
+    pub fn with_docstring(mut self, docstring: String) -> Self {
+        self.docstring = Some(docstring);
+        self
+    }
 }
 
 impl LispCallable for LispLambda {
     fn arity(&self, num_args: usize) -> bool {
-        num_args == self.arity
+        self.params.accepts(num_args)
+    }
+
+    fn describe(&self) -> String {
+        format!("lambda ({})", self.params)
+    }
+
+    fn doc(&self) -> Option<String> {
+        let usage = format!("(lambda ({}) ...)", self.params);
+        Some(match &self.docstring {
+            Some(docstring) => format!("{}: {}", usage, docstring),
+            None => usage,
+        })
+    }
+
+    fn eq_callable(&self, other: &dyn LispCallable) -> bool {
+        other
+            .as_any()
+            .and_then(|other| other.downcast_ref::<LispLambda>())
+            .is_some_and(|other| self == other)
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
     }
 
     fn call(&self, args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
         // Create bindings
-        env.new_scope(self.bindings.iter().cloned().zip(args).collect());
+        env.new_scope(HashMap::new());
 
         // Evaluate in new environment
-        let res = eval::eval_expr(self.body.clone(), env);
+        let res = self.params.bind(args, env).and_then(|()| {
+            env.without_loops(|env| {
+                let mut result = Ast::Unspecified;
+                for expr in &self.body {
+                    result = eval::eval_expr(expr.clone(), env)?;
+                }
+                Ok(result)
+            })
+        });
 
         env.pop_scope();
 
@@ -159,7 +765,7 @@ impl LispCallable for LispLambda {
 
 /// A Lisp type.
 /// TODO: Add structs (user-defined types).
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum LispType {
     /// An integer.
     Int,
@@ -167,6 +773,9 @@ pub enum LispType {
     /// A floating point number.
     Float,
 
+    /// An exact fraction.
+    Rational,
+
     /// A string.
     String,
 
@@ -176,17 +785,125 @@ pub enum LispType {
     /// A list.
     List,
 
+    /// A vector.
+    Vector,
+
+    /// A hash-map.
+    Map,
+
+    /// A dotted pair.
+    Pair,
+
     /// A function.
     Function,
 
     /// A type. This can be a bit confusing.
     Type,
 
+    /// An environment handle.
+    Env,
+
+    /// A port.
+    Port,
+
+    /// A promise.
+    Promise,
+
+    /// A mutable cell created by `atom`.
+    Cell,
+
     /// A symbol.
     Symbol,
 
+    /// A keyword.
+    Keyword,
+
+    /// Raw binary data.
+    Bytes,
+
+    /// A character.
+    Char,
+
+    /// The type of nil.
+    Nil,
+
     /// An unspecified type.
     Unspecified,
+
+    /// Any number. No value has this type directly, but ints and floats are subtypes of it.
+    Number,
+}
+
+impl LispType {
+    /// Every type, in order.
+    pub const ALL: [LispType; 22] = [
+        Self::Int,
+        Self::Float,
+        Self::Rational,
+        Self::String,
+        Self::Bool,
+        Self::List,
+        Self::Vector,
+        Self::Map,
+        Self::Pair,
+        Self::Function,
+        Self::Type,
+        Self::Env,
+        Self::Port,
+        Self::Promise,
+        Self::Cell,
+        Self::Symbol,
+        Self::Keyword,
+        Self::Bytes,
+        Self::Char,
+        Self::Nil,
+        Self::Unspecified,
+        Self::Number,
+    ];
+
+    /// The short name of the type, such as `int`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Int => "int",
+            Self::Float => "float",
+            Self::Rational => "rational",
+            Self::String => "string",
+            Self::Bool => "bool",
+            Self::List => "list",
+            Self::Vector => "vector",
+            Self::Map => "hash-map",
+            Self::Pair => "pair",
+            Self::Function => "function",
+            Self::Type => "type",
+            Self::Env => "environment",
+            Self::Port => "port",
+            Self::Promise => "promise",
+            Self::Cell => "atom",
+            Self::Symbol => "symbol",
+            Self::Keyword => "keyword",
+            Self::Bytes => "bytes",
+            Self::Char => "char",
+            Self::Nil => "nil",
+            Self::Unspecified => "unspecified",
+            Self::Number => "number",
+        }
+    }
+
+    /// Get the type with the given short name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|typ| typ.name() == name)
+    }
+
+    /// Returns true if every value of this type is also a value of other. Every type is a subtype
+    /// of itself, ints are rationals, and all three numeric types are numbers.
+    pub fn is_subtype(&self, other: &Self) -> bool {
+        self == other
+            || matches!(
+                (self, other),
+                (Self::Int, Self::Rational)
+                    | (Self::Int | Self::Float | Self::Rational, Self::Number)
+            )
+    }
 }
 
 impl Display for LispType {
@@ -194,13 +911,26 @@ impl Display for LispType {
         match self {
             Self::Int => write!(f, "builtin type int"),
             Self::Float => write!(f, "builtin type float"),
+            Self::Rational => write!(f, "builtin type rational"),
             Self::String => write!(f, "builtin type string"),
             Self::Bool => write!(f, "builtin type bool"),
             Self::List => write!(f, "list"),
+            Self::Vector => write!(f, "vector"),
+            Self::Map => write!(f, "hash-map"),
+            Self::Pair => write!(f, "pair"),
             Self::Function => write!(f, "function"),
             Self::Type => write!(f, "type"),
+            Self::Env => write!(f, "environment"),
+            Self::Port => write!(f, "port"),
+            Self::Promise => write!(f, "promise"),
+            Self::Cell => write!(f, "atom"),
             Self::Symbol => write!(f, "symbol"),
+            Self::Keyword => write!(f, "keyword"),
+            Self::Bytes => write!(f, "bytes"),
+            Self::Char => write!(f, "char"),
+            Self::Nil => write!(f, "nil"),
             Self::Unspecified => write!(f, "unspecified"),
+            Self::Number => write!(f, "number"),
         }
     }
 }
@@ -210,15 +940,155 @@ impl From<&Ast> for LispType {
         match value {
             Ast::Atom(atom) => match atom {
                 LispAtom::Symbol(_) => Self::Symbol,
-                LispAtom::Int(_) => Self::Int,
+                LispAtom::Int(_) | LispAtom::BigInt(_) => Self::Int,
                 LispAtom::Float(_) => Self::Float,
+                LispAtom::Rational(..) => Self::Rational,
                 LispAtom::String(_) => Self::String,
                 LispAtom::Bool(_) => Self::Bool,
+                LispAtom::Keyword(_) => Self::Keyword,
+                LispAtom::Bytes(_) => Self::Bytes,
+                LispAtom::Char(_) => Self::Char,
+                LispAtom::Nil => Self::Nil,
             },
             Ast::List(_) => Self::List,
+            Ast::Vector(_) => Self::Vector,
+            Ast::Map(_) => Self::Map,
+            Ast::Pair(..) => Self::Pair,
             Ast::Function(_) => Self::Function,
             Ast::Type(_) => Self::Type,
+            Ast::Env(_) => Self::Env,
+            Ast::Port(_) => Self::Port,
+            Ast::Promise(_) => Self::Promise,
+            Ast::Cell(_) => Self::Cell,
             Ast::Unspecified => Self::Unspecified,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEPTH: usize = 100_000;
+
+    /// A list nested depth levels deep around a single 1, such as `((1))` for depth 2.
+    fn nested(depth: usize) -> Ast {
+        let mut ast = Ast::from(1);
+        for _ in 0..depth {
+            ast = Ast::List(vec![ast]);
+        }
+        ast
+    }
+
+    #[test]
+    fn deep_list_operations() {
+        let deep = nested(DEPTH);
+        let copy = deep.clone();
+        assert_eq!(deep, copy);
+        assert_ne!(deep, nested(DEPTH - 1));
+
+        let written = deep.to_string();
+        assert_eq!(
+            written,
+            format!("{}1{}", "(".repeat(DEPTH), ")".repeat(DEPTH))
+        );
+
+        let Ok(mapped) = map_leaves(&deep, |_| Ok::<_, Infallible>(Ast::from(2)));
+        assert_ne!(mapped, deep);
+        drop(deep);
+        drop(copy);
+        drop(mapped);
+    }
+
+    #[test]
+    fn deep_improper_lists() {
+        // (99999 99998 ... 1 . 0) is a chain of DEPTH - 1 pairs, each nested in the last's tail
+        let mut deep = Ast::from(0);
+        for i in 1..DEPTH {
+            deep = Ast::cons(Ast::from(i as i64), deep);
+        }
+        let copy = deep.clone();
+        assert_eq!(deep, copy);
+        assert_ne!(deep, Ast::cons(Ast::from(0), Ast::from(0)));
+
+        let heads: Vec<String> = (1..DEPTH).rev().map(|i| i.to_string()).collect();
+        assert_eq!(deep.to_string(), format!("({} . 0)", heads.join(" ")));
+
+        let Ok(mapped) = map_leaves(&deep, |_| Ok::<_, Infallible>(Ast::from(1)));
+        assert_ne!(mapped, deep);
+
+        // Pairs nested in the head are written as separate lists
+        let mut left = Ast::from(0);
+        for _ in 0..DEPTH {
+            left = Ast::cons(left, Ast::from(1));
+        }
+        assert_eq!(left, left.clone());
+        assert_eq!(
+            left.to_string(),
+            format!("{}0{}", "(".repeat(DEPTH), " . 1)".repeat(DEPTH))
+        );
+        drop(deep);
+        drop(copy);
+        drop(mapped);
+        drop(left);
+    }
+
+    #[test]
+    fn write_tree_separators() {
+        let ast = Ast::List(vec![
+            Ast::from(1),
+            Ast::List(vec![]),
+            Ast::List(vec![Ast::from(2), Ast::List(vec![Ast::from(3)])]),
+            Ast::from("x"),
+        ]);
+        assert_eq!(ast.to_string(), "(1 () (2 (3)) \"x\")");
+        assert_eq!(Ast::List(vec![]).to_string(), "()");
+        assert_eq!(Ast::from(4).to_string(), "4");
+    }
+
+    #[test]
+    fn equality_of_leaves() {
+        assert_eq!(Ast::from(vec![1, 2]), Ast::from(vec![1, 2]));
+        assert_ne!(Ast::from(vec![1, 2]), Ast::from(vec![1, 2, 3]));
+        assert_ne!(Ast::Unspecified, Ast::Unspecified);
+        assert_ne!(Ast::from(1), Ast::List(vec![Ast::from(1)]));
+    }
+
+    #[test]
+    fn deep_vectors_mixed_with_lists() {
+        // Alternate vectors and lists, so both kinds have to be handled by each walk
+        let mut deep = Ast::from(1);
+        for i in 0..DEPTH {
+            deep = match i % 2 {
+                0 => Ast::Vector(vec![deep]),
+                _ => Ast::List(vec![deep]),
+            };
+        }
+        let copy = deep.clone();
+        assert_eq!(deep, copy);
+        // The outermost is a list, since DEPTH is even
+        assert_eq!(
+            deep.to_string(),
+            format!("{}1{}", "([".repeat(DEPTH / 2), "])".repeat(DEPTH / 2))
+        );
+
+        let Ok(mapped) = map_leaves(&deep, |_| Ok::<_, Infallible>(Ast::from(1)));
+        assert_eq!(mapped, deep);
+        drop(deep);
+        drop(copy);
+        drop(mapped);
+    }
+
+    #[test]
+    fn vectors_are_not_lists() {
+        let vector = Ast::Vector(vec![Ast::from(1), Ast::List(vec![Ast::from(2)])]);
+        assert_eq!(vector.to_string(), "[1 (2)]");
+        assert_eq!(Ast::Vector(vec![]).to_string(), "[]");
+        assert_eq!(vector, vector.clone());
+        assert_ne!(
+            Ast::Vector(vec![Ast::from(1)]),
+            Ast::List(vec![Ast::from(1)])
+        );
+        assert_eq!(LispType::from(&vector), LispType::Vector);
+    }
+}
@@ -0,0 +1,440 @@
+//! Arbitrary-precision integers, used when the result of integer arithmetic doesn't fit in an
+//! i64.
+//!
+//! Numbers are stored as a sign and a magnitude made of 32-bit limbs, least significant first.
+//! This is deliberately simple (schoolbook multiplication and binary long division) rather than
+//! fast.
+
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::ops::{Add, Mul, Neg, Sub};
+use std::str::FromStr;
+
+/// An arbitrary-precision integer.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BigInt {
+    negative: bool,
+    // Never has trailing zero limbs, so zero is empty (and never negative)
+    magnitude: Vec<u32>,
+}
+
+impl BigInt {
+    fn from_parts(negative: bool, mut magnitude: Vec<u32>) -> Self {
+        while magnitude.last() == Some(&0) {
+            magnitude.pop();
+        }
+        let negative = negative && !magnitude.is_empty();
+        Self {
+            negative,
+            magnitude,
+        }
+    }
+
+    /// Returns true if the number is zero.
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+
+    /// Returns true if the number is less than zero.
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    /// The absolute value of the number.
+    pub fn abs(&self) -> Self {
+        Self::from_parts(false, self.magnitude.clone())
+    }
+
+    /// The greatest common divisor of the absolute values of two numbers, which is zero only if
+    /// both are zero.
+    pub fn gcd(&self, other: &Self) -> Self {
+        let (mut a, mut b) = (self.abs(), other.abs());
+        while !b.is_zero() {
+            let (_, remainder) = a.div_rem(&b).expect("b isn't zero");
+            (a, b) = (b, remainder);
+        }
+        a
+    }
+
+    /// Get the number as an i64, if it fits.
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.magnitude.len() > 2 {
+            return None;
+        }
+        let value = self
+            .magnitude
+            .iter()
+            .rev()
+            .fold(0u64, |acc, &limb| (acc << 32) | limb as u64) as i128;
+        i64::try_from(if self.negative { -value } else { value }).ok()
+    }
+
+    /// Get the nearest f64 to the number (or an infinity if it is too large).
+    pub fn to_f64(&self) -> f64 {
+        let value = self
+            .magnitude
+            .iter()
+            .rev()
+            .fold(0.0, |acc, &limb| acc * 4294967296.0 + limb as f64);
+        if self.negative {
+            -value
+        } else {
+            value
+        }
+    }
+
+    /// The number of bits needed to store the magnitude.
+    pub fn bits(&self) -> u64 {
+        match self.magnitude.last() {
+            Some(top) => (self.magnitude.len() as u64 - 1) * 32 + (32 - top.leading_zeros()) as u64,
+            None => 0,
+        }
+    }
+
+    /// Divide by other, truncating toward zero, and return the quotient and remainder. The
+    /// remainder has the same sign as self. Returns None if other is zero.
+    pub fn div_rem(&self, other: &Self) -> Option<(Self, Self)> {
+        if other.is_zero() {
+            return None;
+        }
+        let (quotient, remainder) = div_rem_magnitude(&self.magnitude, &other.magnitude);
+        Some((
+            Self::from_parts(self.negative != other.negative, quotient),
+            Self::from_parts(self.negative, remainder),
+        ))
+    }
+
+    /// Raise the number to the power of exp.
+    pub fn pow(&self, mut exp: u32) -> Self {
+        let mut base = self.clone();
+        let mut result = Self::from(1i64);
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = &result * &base;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = &base * &base;
+            }
+        }
+        result
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(value: i64) -> Self {
+        let abs = BigInt::from(value.unsigned_abs());
+        if value < 0 {
+            -&abs
+        } else {
+            abs
+        }
+    }
+}
+
+impl From<u64> for BigInt {
+    fn from(value: u64) -> Self {
+        Self::from_parts(false, vec![value as u32, (value >> 32) as u32])
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_magnitude(&self.magnitude, &other.magnitude),
+            (true, true) => cmp_magnitude(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::from_parts(!self.negative, self.magnitude.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::from_parts(
+                self.negative,
+                add_magnitude(&self.magnitude, &other.magnitude),
+            );
+        }
+
+        // Opposite signs, so subtract the smaller magnitude from the larger
+        match cmp_magnitude(&self.magnitude, &other.magnitude) {
+            Ordering::Less => BigInt::from_parts(
+                other.negative,
+                sub_magnitude(&other.magnitude, &self.magnitude),
+            ),
+            _ => BigInt::from_parts(
+                self.negative,
+                sub_magnitude(&self.magnitude, &other.magnitude),
+            ),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        let mut product = vec![0u32; self.magnitude.len() + other.magnitude.len()];
+        for (i, &a) in self.magnitude.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in other.magnitude.iter().enumerate() {
+                let sum = product[i + j] as u64 + a as u64 * b as u64 + carry;
+                product[i + j] = sum as u32;
+                carry = sum >> 32;
+            }
+            product[i + other.magnitude.len()] = carry as u32;
+        }
+        BigInt::from_parts(self.negative != other.negative, product)
+    }
+}
+
+impl Display for BigInt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        // Split into base 10^9 chunks, least significant first
+        let mut chunks = vec![];
+        let mut rest = self.magnitude.clone();
+        while !rest.is_empty() {
+            let (quotient, remainder) = div_rem_magnitude(&rest, &[1_000_000_000]);
+            chunks.push(remainder.first().copied().unwrap_or(0));
+            rest = quotient;
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        if let Some(first) = chunks.next() {
+            write!(f, "{}", first)?;
+        }
+        for chunk in chunks {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+/// Error returned when parsing a string that isn't a decimal integer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseBigIntError;
+
+impl FromStr for BigInt {
+    type Err = ParseBigIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        if digits.is_empty() {
+            return Err(ParseBigIntError);
+        }
+
+        let mut magnitude: Vec<u32> = vec![];
+        for c in digits.chars() {
+            let digit = c.to_digit(10).ok_or(ParseBigIntError)?;
+            // magnitude = magnitude * 10 + digit
+            let mut carry = digit as u64;
+            for limb in magnitude.iter_mut() {
+                let value = *limb as u64 * 10 + carry;
+                *limb = value as u32;
+                carry = value >> 32;
+            }
+            if carry > 0 {
+                magnitude.push(carry as u32);
+            }
+        }
+        Ok(Self::from_parts(negative, magnitude))
+    }
+}
+
+fn cmp_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut sum = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &limb) in long.iter().enumerate() {
+        let value = limb as u64 + short.get(i).copied().unwrap_or(0) as u64 + carry;
+        sum.push(value as u32);
+        carry = value >> 32;
+    }
+    sum.push(carry as u32);
+    sum
+}
+
+/// Subtract b from a, where a >= b.
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut difference = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &limb) in a.iter().enumerate() {
+        let mut value = limb as i64 - b.get(i).copied().unwrap_or(0) as i64 - borrow;
+        borrow = (value < 0) as i64;
+        if value < 0 {
+            value += 1 << 32;
+        }
+        difference.push(value as u32);
+    }
+    difference
+}
+
+/// Binary long division. b must not be zero.
+fn div_rem_magnitude(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if let [divisor] = b {
+        // Dividing by a single limb is common (printing does it), so do it a limb at a time
+        let divisor = *divisor as u64;
+        let mut quotient = vec![0u32; a.len()];
+        let mut remainder = 0u64;
+        for (i, &limb) in a.iter().enumerate().rev() {
+            let value = (remainder << 32) | limb as u64;
+            quotient[i] = (value / divisor) as u32;
+            remainder = value % divisor;
+        }
+        return (trim(quotient), trim(vec![remainder as u32]));
+    }
+
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder: Vec<u32> = vec![];
+    for i in (0..a.len() * 32).rev() {
+        // remainder = remainder * 2 + bit i of a
+        let bit = (a[i / 32] >> (i % 32)) & 1;
+        let mut carry = bit;
+        for limb in remainder.iter_mut() {
+            let next = *limb >> 31;
+            *limb = (*limb << 1) | carry;
+            carry = next;
+        }
+        if carry > 0 {
+            remainder.push(carry);
+        }
+
+        if cmp_magnitude(&remainder, b) != Ordering::Less {
+            remainder = trim(sub_magnitude(&remainder, b));
+            quotient[i / 32] |= 1 << (i % 32);
+        }
+    }
+    (trim(quotient), remainder)
+}
+
+fn trim(mut magnitude: Vec<u32>) -> Vec<u32> {
+    while magnitude.last() == Some(&0) {
+        magnitude.pop();
+    }
+    magnitude
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(s: &str) -> BigInt {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn round_trip_strings() {
+        for s in [
+            "0",
+            "1",
+            "-1",
+            "4294967296",
+            "-9223372036854775809",
+            "123456789012345678901234567890",
+        ] {
+            assert_eq!(big(s).to_string(), s);
+        }
+        assert_eq!(big("-0").to_string(), "0");
+        assert_eq!(big("+007").to_string(), "7");
+        assert!("12a".parse::<BigInt>().is_err());
+        assert!("-".parse::<BigInt>().is_err());
+    }
+
+    #[test]
+    fn i64_conversions() {
+        for n in [0, 1, -1, i64::MAX, i64::MIN, 1 << 32, -(1 << 32)] {
+            assert_eq!(BigInt::from(n).to_i64(), Some(n));
+            assert_eq!(BigInt::from(n).to_string(), n.to_string());
+        }
+        assert_eq!(big("9223372036854775808").to_i64(), None);
+        assert_eq!(big("-9223372036854775809").to_i64(), None);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = big("123456789012345678901234567890");
+        let b = big("-987654321098765432109876543210");
+        assert_eq!((&a + &b).to_string(), "-864197532086419753208641975320");
+        assert_eq!((&a - &b).to_string(), "1111111110111111111011111111100");
+        assert_eq!(
+            (&a * &b).to_string(),
+            "-121932631137021795226185032733622923332237463801111263526900"
+        );
+        let (q, r) = b.div_rem(&a).unwrap();
+        assert_eq!(q.to_string(), "-8");
+        assert_eq!(r.to_string(), "-9000000000900000000090");
+        assert_eq!(&(&q * &a) + &r, b);
+        assert!(a.div_rem(&BigInt::default()).is_none());
+        assert_eq!(
+            BigInt::from(2i64).pow(100).to_string(),
+            "1267650600228229401496703205376"
+        );
+        assert!(a > b);
+        assert!(-&a > b);
+        assert_eq!(a.gcd(&b).to_string(), "9000000000900000000090");
+        assert_eq!(b.abs().gcd(&BigInt::default()), b.abs());
+        assert!(b.is_negative() && !b.abs().is_negative());
+    }
+
+    #[test]
+    fn factorial() {
+        let mut product = BigInt::from(1i64);
+        for i in 1..=100i64 {
+            product = &product * &BigInt::from(i);
+        }
+        assert_eq!(
+            product.to_string(),
+            "93326215443944152681699238856266700490715968264381621468592963895217599993229915608941463976156518286253697920827223758251185210916864000000000000000000000000"
+        );
+
+        // Dividing back down gets to 1 exactly
+        for i in 1..=100i64 {
+            let (quotient, remainder) = product.div_rem(&BigInt::from(i)).unwrap();
+            assert!(remainder.is_zero());
+            product = quotient;
+        }
+        assert_eq!(product, BigInt::from(1i64));
+    }
+}
@@ -1,70 +1,456 @@
 //! Contains all the built-in functions for callisp.
 
-use crate::ast::{Ast, LispAtom, LispCallable, LispType};
-use crate::env::Environment;
+use crate::analysis;
+use crate::ast::{map_leaves, Ast, LispAtom, LispCallable, LispType};
+use crate::bigint::BigInt;
+use crate::env::{self, Environment};
 use crate::error::LispError;
 use crate::eval;
+use crate::json;
+use crate::map::LispMap;
+use crate::parser;
+use crate::port::Port;
+use crate::printer::display_string;
+use crate::random::Rng;
+use crate::rational::{self, Exact};
+use crate::special_forms::{self, SPECIAL_FORMS};
+use crate::template;
+use crate::time;
 
-use std::collections::HashMap;
+use std::cell::{RefCell, RefMut};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::rc::Rc;
 
-macro_rules! fn_map {
+use lazy_static::lazy_static;
+
+/// Register builtins by name. The registered name replaces the builtin's own, so a builtin
+/// registered under several names (like `%` and `rem`) reports the one it was called by.
+macro_rules! fn_list {
     ($($name:literal => $func:ident),+ ,) => {
-        {
-            let mut map: HashMap<String, Ast> = HashMap::new();
-            $(
-                map.insert($name.to_string(), Ast::Function(Box::new($func)));
-            )+
-            map
-        }
+        vec![$(($name, Ast::Function(Box::new(LispBuiltin { name: $name, ..$func })))),+]
     };
 }
 
+/// Builtins that exist in some builds but were left out of this one, mapped to the feature or
+/// platform they require. Whenever a builtin is registered behind a cfg, add the opposite cfg here
+/// so that using it gives a helpful error instead of a bare undefined identifier.
+pub(crate) fn unavailable_builtins() -> HashMap<String, String> {
+    #[allow(unused_mut)]
+    let mut map = HashMap::new();
+    #[cfg(target_arch = "wasm32")]
+    for name in [
+        "read-file",
+        "write-file",
+        "append-file",
+        "file-exists?",
+        "delete-file",
+        "open",
+        "read-bytes",
+        "write-bytes",
+    ] {
+        map.insert(name.to_string(), "a filesystem".to_string());
+    }
+    #[cfg(target_arch = "wasm32")]
+    for name in ["getenv", "setenv", "env-vars"] {
+        map.insert(name.to_string(), "environment variables".to_string());
+    }
+    #[cfg(target_arch = "wasm32")]
+    map.insert("shell".to_string(), "running programs".to_string());
+    #[cfg(target_arch = "wasm32")]
+    map.insert("monotonic-ms".to_string(), "a monotonic clock".to_string());
+    // Blocking the browser's main thread would freeze the page
+    #[cfg(target_arch = "wasm32")]
+    map.insert(
+        "sleep".to_string(),
+        "a thread that is allowed to block".to_string(),
+    );
+    #[cfg(all(not(feature = "shell"), not(target_arch = "wasm32")))]
+    map.insert("shell".to_string(), "the shell feature".to_string());
+    #[cfg(not(feature = "regex"))]
+    for name in ["re-match?", "re-find", "re-replace"] {
+        map.insert(name.to_string(), "the regex feature".to_string());
+    }
+    map
+}
+
 pub(crate) fn builtins_hashmap() -> HashMap<String, Ast> {
-    fn_map! {
+    registrations()
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect()
+}
+
+lazy_static! {
+    /// The names of the builtin functions, which definitions in a program don't change.
+    static ref BUILTIN_FUNCTIONS: HashSet<&'static str> = registrations()
+        .into_iter()
+        .filter(|(_, value)| matches!(value, Ast::Function(_)))
+        .map(|(name, _)| name)
+        .collect();
+}
+
+/// Returns true if name is registered as a builtin function, whether or not it is available in
+/// this build.
+pub(crate) fn is_builtin_function(name: &str) -> bool {
+    BUILTIN_FUNCTIONS.contains(name)
+}
+
+/// Every builtin in registration order. Names are checked by [check_registry], so a name
+/// registered twice is caught instead of the later one silently winning.
+fn registrations() -> Vec<(&'static str, Ast)> {
+    let mut builtins = fn_list! {
         "+" => LISP_ADD,
         "-" => LISP_SUB,
         "*" => LISP_MUL,
         "/" => LISP_DIV,
-        "%" => LISP_MOD,
+        "%" => LISP_REM,
+        "rem" => LISP_REM,
+        "quot" => LISP_QUOT,
+        "int" => LISP_INT,
+        "float" => LISP_FLOAT,
+        "floor" => LISP_FLOOR,
+        "ceil" => LISP_CEIL,
+        "round" => LISP_ROUND,
+        "truncate" => LISP_TRUNCATE,
+        "gcd" => LISP_GCD,
+        "lcm" => LISP_LCM,
+        "bit-and" => LISP_BIT_AND,
+        "bit-or" => LISP_BIT_OR,
+        "bit-xor" => LISP_BIT_XOR,
+        "bit-not" => LISP_BIT_NOT,
+        "shift-left" => LISP_SHIFT_LEFT,
+        "shift-right" => LISP_SHIFT_RIGHT,
+        "sqrt" => LISP_SQRT,
+        "pow" => LISP_POW,
+        "exp" => LISP_EXP,
+        "log" => LISP_LOG,
+        "random" => LISP_RANDOM,
+        "random-seed" => LISP_RANDOM_SEED,
+        "sin" => LISP_SIN,
+        "cos" => LISP_COS,
+        "tan" => LISP_TAN,
+        "asin" => LISP_ASIN,
+        "acos" => LISP_ACOS,
+        "atan" => LISP_ATAN,
+        "atan2" => LISP_ATAN2,
         "eval" => LISP_EVAL,
         "exit" => LISP_EXIT,
+        "at-exit" => LISP_AT_EXIT,
+        "error" => LISP_ERROR,
+        "assert" => LISP_ASSERT,
+        "assert=" => LISP_ASSERT_EQUAL,
+        "current-env" => LISP_CURRENT_ENV,
+        "make-env" => LISP_MAKE_ENV,
+        "env-bind!" => LISP_ENV_BIND,
         "use" => LISP_USE,
+        "print" => LISP_PRINT,
         "println" => LISP_PRINTLN,
+        "newline" => LISP_NEWLINE,
         "putstr" => LISP_PUT_STR,
         "readline" => LISP_READ_LINE,
+        "read" => LISP_READ,
+        "read-string" => LISP_READ_STRING,
+        "json-parse" => LISP_JSON_PARSE,
+        "json-stringify" => LISP_JSON_STRINGIFY,
+        "read-line" => LISP_READ_PORT_LINE,
+        "write-string" => LISP_WRITE_STRING,
+        "close" => LISP_CLOSE,
         "equal?" => LISP_EQUAL,
+        "eq?" => LISP_EQ,
+        "not" => LISP_NOT,
+        "=" => LISP_NUM_EQ,
         ">" => LISP_GT,
         ">=" => LISP_GE,
         "<" => LISP_LT,
         "<=" => LISP_LE,
         "list" => LISP_LIST,
         "list?" => LISP_IS_LIST,
+        "cons" => LISP_CONS,
+        "first" => LISP_FIRST,
+        "rest" => LISP_REST,
+        "pair?" => LISP_IS_PAIR,
+        "vector" => LISP_VECTOR,
+        "vector?" => LISP_IS_VECTOR,
+        "vector-ref" => LISP_VECTOR_REF,
+        "vector->list" => LISP_VECTOR_TO_LIST,
+        "list->vector" => LISP_LIST_TO_VECTOR,
+        "hash-map" => LISP_HASH_MAP,
+        "hash-map?" => LISP_IS_HASH_MAP,
+        "assoc" => LISP_ASSOC,
+        "dissoc" => LISP_DISSOC,
+        "keys" => LISP_KEYS,
+        "vals" => LISP_VALS,
+        "contains-key?" => LISP_CONTAINS_KEY,
         "empty?" => LISP_IS_EMPTY,
         "count" => LISP_COUNT,
+        "nth" => LISP_NTH,
+        "tree-map" => LISP_TREE_MAP,
+        "get" => LISP_GET,
         "type" => LISP_GET_TYPE,
+        "type-name" => LISP_TYPE_NAME,
+        "type-of-name" => LISP_TYPE_OF_NAME,
+        "subtype?" => LISP_IS_SUBTYPE,
+        "int?" => LISP_IS_INT,
+        "float?" => LISP_IS_FLOAT,
+        "number?" => LISP_IS_NUMBER,
+        "string?" => LISP_IS_STRING,
+        "bool?" => LISP_IS_BOOL,
+        "symbol?" => LISP_IS_SYMBOL,
+        "function?" => LISP_IS_FUNCTION,
+        "rational?" => LISP_IS_RATIONAL,
+        "nan?" => LISP_IS_NAN,
+        "zero?" => LISP_IS_ZERO,
+        "positive?" => LISP_IS_POSITIVE,
+        "negative?" => LISP_IS_NEGATIVE,
+        "even?" => LISP_IS_EVEN,
+        "odd?" => LISP_IS_ODD,
+        "infinite?" => LISP_IS_INFINITE,
+        "finite?" => LISP_IS_FINITE,
+        "numerator" => LISP_NUMERATOR,
+        "denominator" => LISP_DENOMINATOR,
+        "has-builtin?" => LISP_HAS_BUILTIN,
+        "free-vars" => LISP_FREE_VARS,
+        "macroexpand" => LISP_MACROEXPAND,
+        "force" => LISP_FORCE,
+        "atom" => LISP_ATOM,
+        "deref" => LISP_DEREF,
+        "reset!" => LISP_RESET,
+        "swap!" => LISP_SWAP,
+        "macroexpand-all" => LISP_MACROEXPAND_ALL,
+        "inspect" => LISP_INSPECT,
+        "trace" => LISP_TRACE,
+        "untrace" => LISP_UNTRACE,
+        "doc" => LISP_DOC,
+        "help" => LISP_HELP,
+        "hexdump" => LISP_HEXDUMP,
+        "bytes->string" => LISP_BYTES_TO_STRING,
+        "string->bytes" => LISP_STRING_TO_BYTES,
+        "char?" => LISP_IS_CHAR,
+        "char-code" => LISP_CHAR_CODE,
+        "code-char" => LISP_CODE_CHAR,
+        "string->list" => LISP_STRING_TO_LIST,
+        "list->string" => LISP_LIST_TO_STRING,
+        "name" => LISP_NAME,
+        "symbol" => LISP_SYMBOL,
+        "keyword" => LISP_KEYWORD,
+        "keyword?" => LISP_IS_KEYWORD,
+        "nil?" => LISP_IS_NIL,
+        "keyword->string" => LISP_KEYWORD_TO_STRING,
+        "string->keyword" => LISP_STRING_TO_KEYWORD,
+        "gensym" => LISP_GENSYM,
+        "render-template" => LISP_RENDER_TEMPLATE,
+        "set-fold-case!" => LISP_SET_FOLD_CASE,
         "do" => LISP_DO,
+        "now" => LISP_NOW,
+        "format-time" => LISP_FORMAT_TIME,
+    };
+
+    #[cfg(not(target_arch = "wasm32"))]
+    builtins.extend(fn_list! {
+        "read-file" => LISP_READ_FILE,
+        "write-file" => LISP_WRITE_FILE,
+        "append-file" => LISP_APPEND_FILE,
+        "file-exists?" => LISP_FILE_EXISTS,
+        "delete-file" => LISP_DELETE_FILE,
+        "open" => LISP_OPEN,
+        "read-bytes" => LISP_READ_BYTES,
+        "write-bytes" => LISP_WRITE_BYTES,
+        "getenv" => LISP_GETENV,
+        "setenv" => LISP_SETENV,
+        "env-vars" => LISP_ENV_VARS,
+        "sleep" => LISP_SLEEP,
+        "monotonic-ms" => LISP_MONOTONIC_MS,
+    });
+
+    #[cfg(all(feature = "shell", not(target_arch = "wasm32")))]
+    builtins.extend(fn_list! {
+        "shell" => LISP_SHELL,
+    });
+
+    #[cfg(feature = "regex")]
+    builtins.extend(fn_list! {
+        "re-match?" => LISP_RE_MATCH,
+        "re-find" => LISP_RE_FIND,
+        "re-replace" => LISP_RE_REPLACE,
+    });
+
+    builtins.push(("pi", Ast::Atom(LispAtom::Float(std::f64::consts::PI))));
+    builtins.push(("e", Ast::Atom(LispAtom::Float(std::f64::consts::E))));
+    builtins.push(("stdin", Ast::Port(Port::stdin())));
+    builtins.push(("stdout", Ast::Port(Port::stdout())));
+
+    // The types themselves, so that `(equal? (type x) Int)` works. Each also has a lowercase
+    // alias like `<int>`, since case folding reads `Int` as `int`, the conversion builtin.
+    for (name, alias, typ) in TYPES {
+        builtins.push((name, Ast::Type(typ)));
+        builtins.push((alias, Ast::Type(typ)));
+    }
+
+    builtins
+}
+
+/// The names the types are bound to, and their lowercase aliases.
+const TYPES: [(&str, &str, LispType); 22] = [
+    ("Int", "<int>", LispType::Int),
+    ("Float", "<float>", LispType::Float),
+    ("Rational", "<rational>", LispType::Rational),
+    ("String", "<string>", LispType::String),
+    ("Bool", "<bool>", LispType::Bool),
+    ("List", "<list>", LispType::List),
+    ("Vector", "<vector>", LispType::Vector),
+    ("HashMap", "<hashmap>", LispType::Map),
+    ("Pair", "<pair>", LispType::Pair),
+    ("Function", "<function>", LispType::Function),
+    ("Type", "<type>", LispType::Type),
+    ("Env", "<env>", LispType::Env),
+    ("Port", "<port>", LispType::Port),
+    ("Promise", "<promise>", LispType::Promise),
+    ("Atom", "<atom>", LispType::Cell),
+    ("Symbol", "<symbol>", LispType::Symbol),
+    ("Keyword", "<keyword>", LispType::Keyword),
+    ("Bytes", "<bytes>", LispType::Bytes),
+    ("Char", "<char>", LispType::Char),
+    ("Nil", "<nil>", LispType::Nil),
+    ("Unspecified", "<unspecified>", LispType::Unspecified),
+    ("Number", "<number>", LispType::Number),
+];
+
+/// Check that every builtin can actually be used: no name is registered twice, none is hidden by
+/// a special form (which are looked up first), and every name reads back as a symbol. Panics
+/// naming the first offender.
+pub(crate) fn check_registry() {
+    check_names(registrations().iter().map(|(name, _)| *name));
+}
+
+fn check_names<'a>(names: impl IntoIterator<Item = &'a str>) {
+    for name in SPECIAL_FORMS.keys() {
+        assert!(
+            parser::is_valid_symbol(name),
+            "special form {:?} is not a valid symbol",
+            name
+        );
+    }
+
+    let mut seen = HashSet::new();
+    for name in names {
+        assert!(
+            seen.insert(name),
+            "builtin {:?} is registered more than once",
+            name
+        );
+        assert!(
+            !SPECIAL_FORMS.contains_key(name),
+            "builtin {:?} is hidden by the special form with the same name",
+            name
+        );
+        assert!(
+            parser::is_valid_symbol(name),
+            "builtin {:?} is not a valid symbol",
+            name
+        );
     }
 }
 
 fn ast_to_int(ast: &Ast) -> Result<i64, LispError> {
     match ast {
         Ast::Atom(LispAtom::Int(num)) => Ok(*num),
+        Ast::Atom(LispAtom::BigInt(num)) => {
+            Err(LispError::Overflow(format!("{} (more than 64 bits)", num)))
+        }
         _ => Err(LispError::TypeError),
     }
 }
 
+/// Get an Int or BigInt as a BigInt.
+fn ast_to_bigint(ast: &Ast) -> Option<BigInt> {
+    match ast {
+        Ast::Atom(LispAtom::Int(num)) => Some(BigInt::from(*num)),
+        Ast::Atom(LispAtom::BigInt(num)) => Some(num.clone()),
+        _ => None,
+    }
+}
+
+/// Get any exact number (Int, BigInt, or Rational) as a numerator and a positive denominator.
+fn ast_to_big_ratio(ast: &Ast) -> Option<(BigInt, BigInt)> {
+    match ast {
+        Ast::Atom(LispAtom::Rational(num, den)) => Some((BigInt::from(*num), BigInt::from(*den))),
+        _ => Some((ast_to_bigint(ast)?, BigInt::from(1i64))),
+    }
+}
+
 fn ast_to_float(ast: &Ast) -> Result<f64, LispError> {
     match ast {
         Ast::Atom(LispAtom::Float(num)) => Ok(*num),
         Ast::Atom(LispAtom::Int(num)) => Ok(*num as f64),
+        Ast::Atom(LispAtom::BigInt(num)) => Ok(num.to_f64()),
+        Ast::Atom(LispAtom::Rational(num, den)) => Ok(rational::to_float((*num, *den))),
         _ => Err(LispError::TypeError),
     }
 }
 
-fn ast_to_string(ast: Ast) -> Result<String, LispError> {
-    match ast {
-        Ast::Atom(LispAtom::String(string)) => Ok(string),
+/// Convert an integral float to an i64, erroring if it is NaN or outside the range of an i64.
+fn float_to_int(num: f64) -> Result<i64, LispError> {
+    // i64::MAX isn't representable as an f64, so compare against 2^63 exclusively.
+    if (-9_223_372_036_854_775_808.0..9_223_372_036_854_775_808.0).contains(&num) {
+        Ok(num as i64)
+    } else {
+        Err(LispError::ValueError(format!(
+            "{} is out of integer range",
+            num
+        )))
+    }
+}
+
+/// Apply a rounding function to a number, returning an Int. Exact numbers are rounded with
+/// exact_round, so Ints are returned unchanged.
+fn round_with(
+    args: Vec<Ast>,
+    round: fn(f64) -> f64,
+    exact_round: fn(Exact) -> i64,
+) -> Result<Ast, LispError> {
+    let arg = take_first(args)?;
+    let num = match (rational::exact(&arg), arg) {
+        (Some(num), _) => exact_round(num),
+        (None, Ast::Atom(LispAtom::Float(num))) => float_to_int(round(num))?,
+        (None, big @ Ast::Atom(LispAtom::BigInt(_))) => return Ok(big),
+        _ => return Err(LispError::TypeError),
+    };
+    Ok(Ast::Atom(LispAtom::Int(num)))
+}
+
+/// Apply a float function to a single number argument, returning a Float.
+fn float_fn(args: Vec<Ast>, func: fn(f64) -> f64) -> Result<Ast, LispError> {
+    let num = get_first(&args).and_then(ast_to_float)?;
+    Ok(Ast::Atom(LispAtom::Float(func(num))))
+}
+
+/// Like [float_fn], but errors if the argument is outside of [-1, 1].
+fn unit_domain_float_fn(args: Vec<Ast>, func: fn(f64) -> f64) -> Result<Ast, LispError> {
+    let num = get_first(&args).and_then(ast_to_float)?;
+    if !(-1.0..=1.0).contains(&num) {
+        return Err(LispError::ValueError(format!(
+            "{} is outside of the range [-1, 1]",
+            num
+        )));
+    }
+    Ok(Ast::Atom(LispAtom::Float(func(num))))
+}
+
+fn ast_to_string(mut ast: Ast) -> Result<String, LispError> {
+    match &mut ast {
+        Ast::Atom(LispAtom::String(string)) => Ok(std::mem::take(string)),
+        _ => Err(LispError::TypeError),
+    }
+}
+
+/// Get the name of a string, symbol, or keyword. Keywords don't include the leading colon.
+fn ast_to_name(mut ast: Ast) -> Result<String, LispError> {
+    match &mut ast {
+        Ast::Atom(LispAtom::String(name))
+        | Ast::Atom(LispAtom::Symbol(name))
+        | Ast::Atom(LispAtom::Keyword(name)) => Ok(std::mem::take(name)),
         _ => Err(LispError::TypeError),
     }
 }
@@ -97,6 +483,14 @@ fn exactly_one(num_args: usize) -> bool {
     num_args == 1
 }
 
+fn one_or_two(num_args: usize) -> bool {
+    num_args == 1 || num_args == 2
+}
+
+fn two_or_three(num_args: usize) -> bool {
+    num_args == 2 || num_args == 3
+}
+
 fn exactly_two(num_args: usize) -> bool {
     num_args == 2
 }
@@ -113,14 +507,15 @@ fn at_least_two(num_args: usize) -> bool {
 /// TODO: Also used for Rust modules so should probably change name.
 #[derive(Clone)]
 pub struct LispBuiltin {
+    name: &'static str,
+    doc: &'static str,
     arity: fn(usize) -> bool,
     func: fn(Vec<Ast>, &mut Environment) -> Result<Ast, LispError>,
 }
 
 impl Debug for LispBuiltin {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        // TODO: Implementation could probably be better
-        write!(f, "builtin function")
+        write!(f, "builtin {}", self.name)
     }
 }
 
@@ -129,12 +524,125 @@ impl LispCallable for LispBuiltin {
         (self.arity)(num_args)
     }
 
+    fn describe(&self) -> String {
+        self.name.to_string()
+    }
+
+    fn doc(&self) -> Option<String> {
+        Some(self.doc.to_string())
+    }
+
+    /// Builtins are equal if they are registered under the same name. Names are unique (see
+    /// [check_registry]), whereas function pointers aren't a reliable identity.
+    fn eq_callable(&self, other: &dyn LispCallable) -> bool {
+        other
+            .as_any()
+            .and_then(|other| other.downcast_ref::<LispBuiltin>())
+            .is_some_and(|other| self.name == other.name)
+    }
+
+    fn as_any(&self) -> Option<&dyn std::any::Any> {
+        Some(self)
+    }
+
     fn call(&self, args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
         (self.func)(args, env)
     }
 }
 
+/// A function wrapped by `trace`, which prints every call and the value it returns.
+#[derive(Debug, Clone)]
+struct LispTraced {
+    label: String,
+    inner: Box<dyn LispCallable>,
+}
+
+impl LispCallable for LispTraced {
+    fn arity(&self, num_args: usize) -> bool {
+        self.inner.arity(num_args)
+    }
+
+    fn describe(&self) -> String {
+        format!("traced {}", self.inner.describe())
+    }
+
+    fn doc(&self) -> Option<String> {
+        self.inner.doc()
+    }
+
+    fn as_any(&self) -> Option<&dyn std::any::Any> {
+        Some(self)
+    }
+
+    fn call(&self, args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+        let indent = "  ".repeat(env.adjust_trace_depth(1));
+        let mut call = format!("{}({}", indent, self.label);
+        for arg in &args {
+            call.push(' ');
+            call.push_str(&display_string(arg, env.printer()));
+        }
+        call.push_str(")\n");
+
+        let result = env
+            .write_output(&call)
+            .and_then(|_| self.inner.call(args, env));
+        env.adjust_trace_depth(-1);
+
+        let value = result?;
+        env.write_output(&format!(
+            "{}=> {}\n",
+            indent,
+            display_string(&value, env.printer())
+        ))?;
+        Ok(value)
+    }
+}
+
+/// Wrap a function so that its calls are printed, labelled with the optional second argument.
+const LISP_TRACE: LispBuiltin = LispBuiltin {
+    name: "trace",
+    doc: "(trace f [label]): a version of f that prints each call and its result",
+    arity: one_or_two,
+    func: |args, _env| {
+        let mut args = args.into_iter();
+        let func = args.next().ok_or(LispError::BadArity)?;
+        let Ast::Function(inner) = &func else {
+            return Err(LispError::TypeError);
+        };
+        let label = match args.next() {
+            Some(label) => ast_to_name(label)?,
+            None => inner.describe(),
+        };
+        Ok(Ast::Function(Box::new(LispTraced {
+            label,
+            inner: inner.clone(),
+        })))
+    },
+};
+
+/// Get the original function back from one wrapped by `trace`. Other values are returned as is.
+const LISP_UNTRACE: LispBuiltin = LispBuiltin {
+    name: "untrace",
+    doc: "(untrace f): the function that f traces, or f if it isn't traced",
+    arity: exactly_one,
+    func: |args, _env| {
+        let func = take_first(args)?;
+        let traced = match &func {
+            Ast::Function(func) => func
+                .as_any()
+                .and_then(|func| func.downcast_ref::<LispTraced>()),
+            _ => None,
+        };
+        match traced {
+            Some(traced) => Ok(Ast::Function(traced.inner.clone())),
+            None => Ok(func),
+        }
+    },
+};
+
 const LISP_EXIT: LispBuiltin = LispBuiltin {
+    name: "exit",
+    doc: "(exit [code]): exit with code, or 0",
     arity: one_or_zero,
     func: |args, _env| {
         let code = match args.into_iter().next() {
@@ -142,256 +650,4868 @@ const LISP_EXIT: LispBuiltin = LispBuiltin {
             None => 0,
         };
 
-        std::process::exit(code);
+        Err(LispError::Exit(code))
     },
 };
 
-const LISP_EVAL: LispBuiltin = LispBuiltin {
+/// Register a thunk to be called when the interpreter exits normally, either by calling `exit` or
+/// by reaching the end of the script.
+const LISP_AT_EXIT: LispBuiltin = LispBuiltin {
+    name: "at-exit",
+    doc: "(at-exit thunk): call a function of no arguments when the program exits normally",
     arity: exactly_one,
-    func: |args, env| eval::eval_expr(take_first(args)?, env),
-};
-
-const LISP_ADD: LispBuiltin = LispBuiltin {
-    arity: at_least_one,
-    func: |args, _env| {
-        if args.len() > 1 {
-            // let all_ints = args.iter().fold(true, |acc, ast| {
-            //     acc && matches!(ast, Ast::Atom(LispAtom::Int(_)))
-            // });
-            let all_ints = args
-                .iter()
-                .all(|ast| matches!(ast, Ast::Atom(LispAtom::Int(_))));
-            if all_ints {
-                let sum = to_list_of_ints(args)?.into_iter().sum();
-                Ok(Ast::Atom(LispAtom::Int(sum)))
-            } else {
-                let sum = to_list_of_floats(args)?.into_iter().sum();
-                Ok(Ast::Atom(LispAtom::Float(sum)))
-            }
-        } else {
-            let arg = take_first(args)?;
-            if matches!(
-                arg,
-                Ast::Atom(LispAtom::Float(_)) | Ast::Atom(LispAtom::Int(_))
-            ) {
-                Ok(arg)
-            } else {
-                Err(LispError::TypeError)
+    func: |args, env| {
+        let thunk = take_first(args)?;
+        match &thunk {
+            Ast::Function(func) if func.arity(0) => {
+                env.at_exit(thunk);
+                Ok(Ast::Unspecified)
             }
+            Ast::Function(_) => Err(LispError::BadArity),
+            _ => Err(LispError::TypeError),
         }
     },
 };
 
-const LISP_SUB: LispBuiltin = LispBuiltin {
-    arity: at_least_one,
+/// Raise an error with a message string and an optional payload of any value.
+const LISP_ERROR: LispBuiltin = LispBuiltin {
+    name: "error",
+    doc: "(error message [payload]): raise an error with a message and optional payload",
+    arity: one_or_two,
     func: |args, _env| {
-        let difference = if args.len() > 1 {
-            to_list_of_floats(args)?
-                .into_iter()
-                .reduce(|acc, num| acc - num)
-                .ok_or(LispError::BadArity)?
-        } else {
-            -1.0 * get_first(&args).and_then(ast_to_float)?
-        };
-
-        Ok(Ast::Atom(LispAtom::Float(difference)))
+        let mut args = args.into_iter();
+        let message = args
+            .next()
+            .ok_or(LispError::BadArity)
+            .and_then(ast_to_string)?;
+        Err(LispError::UserError(message, args.next()))
     },
 };
 
-const LISP_MUL: LispBuiltin = LispBuiltin {
-    arity: at_least_one,
-    func: |args, _env| {
-        let product = to_list_of_floats(args)?
-            .into_iter()
-            .reduce(|acc, num| acc * num)
-            .ok_or(LispError::BadArity)?;
-
-        Ok(Ast::Atom(LispAtom::Float(product)))
-    },
-};
+/// The message for a failed assertion, with the optional message argument appended. The values
+/// being checked aren't part of it, since they are the error's payload and get shown after it.
+fn assertion_message(detail: Option<&str>, message: Option<Ast>) -> Result<String, LispError> {
+    let mut result = "assertion failed".to_string();
+    if let Some(message) = message {
+        result = format!("{}: {}", result, ast_to_string(message)?);
+    }
+    if let Some(detail) = detail {
+        result = format!("{}: {}", result, detail);
+    }
+    Ok(result)
+}
 
-const LISP_DIV: LispBuiltin = LispBuiltin {
-    arity: at_least_one,
+/// Raise an error if the value is false, with an optional message. The value is the payload.
+const LISP_ASSERT: LispBuiltin = LispBuiltin {
+    name: "assert",
+    doc: "(assert value [message]): raise an error if value is false",
+    arity: one_or_two,
     func: |args, _env| {
-        let quotient = if args.len() > 1 {
-            to_list_of_floats(args)?
-                .into_iter()
-                .reduce(|acc, num| acc / num)
-                .ok_or(LispError::BadArity)?
-        } else {
-            1.0 / get_first(&args).and_then(ast_to_float)?
-        };
-
-        Ok(Ast::Atom(LispAtom::Float(quotient)))
+        let mut args = args.into_iter();
+        let value = args.next().ok_or(LispError::BadArity)?;
+        let message = args.next();
+        if value.is_truthy() {
+            return Ok(Ast::Unspecified);
+        }
+        let message = assertion_message(None, message)?;
+        Err(LispError::UserError(message, Some(value)))
     },
 };
 
-const LISP_MOD: LispBuiltin = LispBuiltin {
-    arity: exactly_two,
+/// Raise an error unless two values are `equal?`, with an optional message. The payload is a map
+/// with both values under `:actual` and `:expected`, so the error shows which is which.
+const LISP_ASSERT_EQUAL: LispBuiltin = LispBuiltin {
+    name: "assert=",
+    doc: "(assert= actual expected [message]): raise an error unless the values are equal?",
+    arity: two_or_three,
     func: |args, _env| {
-        let n = args.first().ok_or(LispError::BadArity)?;
-        let n = ast_to_int(n)?;
-        let modulus = args.get(1).ok_or(LispError::BadArity)?;
-        let modulus = ast_to_int(modulus)?;
-        Ok(Ast::Atom(LispAtom::Int(n % modulus)))
+        let mut args = args.into_iter();
+        let actual = args.next().ok_or(LispError::BadArity)?;
+        let expected = args.next().ok_or(LispError::BadArity)?;
+        let message = args.next();
+        if actual == expected {
+            return Ok(Ast::Unspecified);
+        }
+        let message = assertion_message(Some("values are not equal"), message)?;
+        let mut payload = LispMap::new();
+        payload.insert(&Ast::Atom(LispAtom::Keyword("actual".to_string())), actual)?;
+        payload.insert(
+            &Ast::Atom(LispAtom::Keyword("expected".to_string())),
+            expected,
+        )?;
+        Err(LispError::UserError(message, Some(Ast::Map(payload))))
     },
 };
 
-const LISP_USE: LispBuiltin = LispBuiltin {
-    arity: exactly_one,
+/// Evaluates an expression. By default the expression is evaluated in the global scope, so a `def`
+/// inside it creates a global binding even when `eval` is called from inside a function. Passing
+/// `:local` as the second argument evaluates in the caller's scope instead, and passing an
+/// environment handle evaluates in that environment.
+const LISP_EVAL: LispBuiltin = LispBuiltin {
+    name: "eval",
+    doc: "(eval expr [:global | :local | env]): evaluate expr, in the global scope by default",
+    arity: one_or_two,
     func: |args, env| {
-        let file = take_first(args).and_then(ast_to_string)?;
-        crate::execute_file(file.into(), env)
+        let mut args = args.into_iter();
+        let expr = args.next().ok_or(LispError::BadArity)?;
+        let mode = args.next();
+        // The expression isn't part of any loop body, so it can't recur
+        env.without_loops(|env| match &mode {
+            None => env.in_global_scope(|env| eval::eval_expr(expr, env)),
+            Some(Ast::Atom(LispAtom::Keyword(mode))) if mode == "global" => {
+                env.in_global_scope(|env| eval::eval_expr(expr, env))
+            }
+            Some(Ast::Atom(LispAtom::Keyword(mode))) if mode == "local" => {
+                eval::eval_expr(expr, env)
+            }
+            Some(Ast::Env(target)) => eval::eval_expr(expr, &mut *borrow_env(target)?),
+            Some(mode) => Err(LispError::ValueError(format!(
+                "expected :global or :local but got {}",
+                mode
+            ))),
+        })
     },
 };
 
-const LISP_PRINTLN: LispBuiltin = LispBuiltin {
-    arity: exactly_one,
-    func: |args, _env| {
-        let arg = take_first(args)?;
-        println!("{}", arg);
-        Ok(Ast::Unspecified)
-    },
-};
+fn borrow_env(env: &Rc<RefCell<Environment>>) -> Result<RefMut<'_, Environment>, LispError> {
+    env.try_borrow_mut()
+        .map_err(|_| LispError::ValueError("environment is already being evaluated in".to_string()))
+}
 
-// TODO: be able to write to any file (not just stdout).
-const LISP_PUT_STR: LispBuiltin = LispBuiltin {
-    arity: exactly_one,
-    func: |args, _env| {
-        let string = take_first(args).and_then(ast_to_string)?;
-        println!("{}", string);
-        Ok(Ast::Unspecified)
-    },
+const LISP_CURRENT_ENV: LispBuiltin = LispBuiltin {
+    name: "current-env",
+    doc: "(current-env): a handle to the current environment",
+    arity: exactly_zero,
+    func: |_args, env| Ok(Ast::Env(Rc::new(RefCell::new(env.share())))),
 };
 
-const LISP_READ_LINE: LispBuiltin = LispBuiltin {
+const LISP_MAKE_ENV: LispBuiltin = LispBuiltin {
+    name: "make-env",
+    doc: "(make-env): a handle to a new environment containing only the builtins",
     arity: exactly_zero,
-    func: |_args, _env| {
-        let mut buf = String::new();
-        std::io::stdin()
-            .read_line(&mut buf)
-            .map_err(|_| LispError::IOError)?;
-        // TODO: This might break compatibility with windows
-        buf.pop(); // Remove trailing '\n'
-        Ok(Ast::Atom(LispAtom::String(buf)))
-    },
+    func: |_args, _env| Ok(Ast::Env(Rc::new(RefCell::new(Environment::outer_new())))),
 };
 
-const LISP_EQUAL: LispBuiltin = LispBuiltin {
-    arity: at_least_two,
+const LISP_ENV_BIND: LispBuiltin = LispBuiltin {
+    name: "env-bind!",
+    doc: "(env-bind! env symbol value): bind symbol to value in an environment handle",
+    arity: |num_args| num_args == 3,
     func: |args, _env| {
-        let mut iter = args.into_iter();
-        let first = iter.next().ok_or(LispError::BadArity)?;
-        for arg in iter {
-            if arg != first {
-                return Ok(Ast::Atom(LispAtom::Bool(false)));
-            }
-        }
-
-        Ok(Ast::Atom(LispAtom::Bool(true)))
+        let mut args = args.into_iter();
+        let Some(Ast::Env(target)) = &args.next() else {
+            return Err(LispError::TypeError);
+        };
+        let Some(Ast::Atom(LispAtom::Symbol(name))) = &args.next() else {
+            return Err(LispError::TypeError);
+        };
+        let value = args.next().ok_or(LispError::BadArity)?;
+        borrow_env(target)?.bind(name.clone(), value);
+        Ok(Ast::Unspecified)
     },
 };
 
-const LISP_GT: LispBuiltin = LispBuiltin {
-    arity: at_least_two,
-    func: |args, _env| {
-        let nums = to_list_of_floats(args)?;
-        let mut result = true;
-        for i in 0..nums.len() - 1 {
-            result &= nums[i] > nums[i + 1];
+/// An exact number as a big numerator and denominator, which may not be in lowest terms.
+type BigRatio = (BigInt, BigInt);
+
+fn big_ratio_add((a, b): BigRatio, (c, d): &BigRatio) -> BigRatio {
+    (&(&a * d) + &(c * &b), &b * d)
+}
+
+fn big_ratio_sub((a, b): BigRatio, (c, d): &BigRatio) -> BigRatio {
+    (&(&a * d) - &(c * &b), &b * d)
+}
+
+fn big_ratio_mul((a, b): BigRatio, (c, d): &BigRatio) -> BigRatio {
+    (&a * c, &b * d)
+}
+
+fn big_ratio_div((a, b): BigRatio, (c, d): &BigRatio) -> BigRatio {
+    (&a * d, &b * c)
+}
+
+/// Convert an exact number to an Int, BigInt, or Rational in lowest terms. There is no big
+/// Rational type, so a fraction whose numerator or denominator doesn't fit in an Int is converted
+/// to the nearest Float instead.
+fn big_ratio_to_ast((num, den): BigRatio) -> Ast {
+    let divisor = num.gcd(&den);
+    let (mut num, _) = num.div_rem(&divisor).expect("den isn't zero");
+    let (mut den, _) = den.div_rem(&divisor).expect("den isn't zero");
+    if den.is_negative() {
+        (num, den) = (-&num, -&den);
+    }
+    if den == BigInt::from(1i64) {
+        return Ast::from(num);
+    }
+    match (num.to_i64(), den.to_i64()) {
+        (Some(num), Some(den)) => Ast::Atom(LispAtom::Rational(num, den)),
+        _ => Ast::Atom(LispAtom::Float(big_ratio_to_float(&num, &den))),
+    }
+}
+
+/// The nearest Float to num/den, even when both are too large to be Floats themselves.
+fn big_ratio_to_float(num: &BigInt, den: &BigInt) -> f64 {
+    // Scale the numerator so the quotient has about 64 significant bits, then scale back
+    let shift = (den.bits() + 64).saturating_sub(num.bits());
+    let scaled = num * &BigInt::from(2i64).pow(shift as u32);
+    let (quotient, _) = scaled.div_rem(den).expect("den isn't zero");
+    let half = -((shift / 2) as i32);
+    quotient.to_f64() * 2f64.powi(half) * 2f64.powi(half - (shift % 2) as i32)
+}
+
+/// Fold numeric arguments. Exact numbers stay exact: Ints and Rationals are folded with exact_op,
+/// and if that overflows (or there are BigInts) the exact values are folded again with big_op.
+/// If there is a Float the arguments are all converted to Floats and folded with float_op.
+fn numeric_fold(
+    args: Vec<Ast>,
+    exact_op: fn(Exact, Exact) -> Option<Exact>,
+    big_op: fn(BigRatio, &BigRatio) -> BigRatio,
+    float_op: fn(f64, f64) -> f64,
+) -> Result<Ast, LispError> {
+    if let Some(nums) = args.iter().map(rational::exact).collect::<Option<Vec<_>>>() {
+        let (first, rest) = nums.split_first().ok_or(LispError::BadArity)?;
+        if let Some(num) = rest.iter().try_fold(*first, |acc, &num| exact_op(acc, num)) {
+            return Ok(rational::to_ast(num));
         }
+    }
 
-        Ok(Ast::Atom(LispAtom::Bool(result)))
-    },
+    if let Some(nums) = args
+        .iter()
+        .map(ast_to_big_ratio)
+        .collect::<Option<Vec<_>>>()
+    {
+        let mut nums = nums.into_iter();
+        let first = nums.next().ok_or(LispError::BadArity)?;
+        return Ok(big_ratio_to_ast(
+            nums.fold(first, |acc, num| big_op(acc, &num)),
+        ));
+    }
+
+    to_list_of_floats(args)?
+        .into_iter()
+        .reduce(float_op)
+        .map(|num| Ast::Atom(LispAtom::Float(num)))
+        .ok_or(LispError::BadArity)
+}
+
+const LISP_ADD: LispBuiltin = LispBuiltin {
+    name: "+",
+    doc: "(+ num ...): add numbers",
+    arity: at_least_one,
+    func: |args, _env| numeric_fold(args, rational::add, big_ratio_add, |a, b| a + b),
 };
 
-const LISP_GE: LispBuiltin = LispBuiltin {
-    arity: at_least_two,
+const LISP_SUB: LispBuiltin = LispBuiltin {
+    name: "-",
+    doc: "(- num ...): subtract the rest of the numbers from the first, or negate a single number",
+    arity: at_least_one,
     func: |args, _env| {
-        let nums = to_list_of_floats(args)?;
-        let mut result = true;
-        for i in 0..nums.len() - 1 {
-            result &= nums[i] >= nums[i + 1];
+        if args.len() > 1 {
+            return numeric_fold(args, rational::sub, big_ratio_sub, |a, b| a - b);
         }
 
-        Ok(Ast::Atom(LispAtom::Bool(result)))
+        match take_first(args)? {
+            Ast::Atom(LispAtom::Float(num)) => Ok(Ast::Atom(LispAtom::Float(-num))),
+            // (- x) is (- 0 x) for exact numbers
+            arg => numeric_fold(
+                vec![Ast::Atom(LispAtom::Int(0)), arg],
+                rational::sub,
+                big_ratio_sub,
+                |a, b| a - b,
+            ),
+        }
     },
 };
 
-const LISP_LT: LispBuiltin = LispBuiltin {
-    arity: at_least_two,
+const LISP_MUL: LispBuiltin = LispBuiltin {
+    name: "*",
+    doc: "(* num ...): multiply numbers",
+    arity: at_least_one,
+    func: |args, _env| numeric_fold(args, rational::mul, big_ratio_mul, |a, b| a * b),
+};
+
+/// Division of exact numbers is exact, so `(/ 1 3)` is the Rational 1/3 and `(/ 6 3)` is the Int
+/// 2. Dividing by zero is an error rather than producing an infinity or NaN.
+const LISP_DIV: LispBuiltin = LispBuiltin {
+    name: "/",
+    doc: "(/ num ...): divide the first number by the rest, or take the reciprocal of a single number",
+    arity: at_least_one,
     func: |args, _env| {
-        let nums = to_list_of_floats(args)?;
-        let mut result = true;
-        for i in 0..nums.len() - 1 {
-            result &= nums[i] < nums[i + 1];
+        // (/ x) is the reciprocal of x
+        let args = if args.len() > 1 {
+            args
+        } else {
+            vec![Ast::Atom(LispAtom::Int(1)), take_first(args)?]
+        };
+
+        if to_list_of_floats(args.clone())?[1..].contains(&0.0) {
+            return Err(LispError::DivideByZero);
         }
 
-        Ok(Ast::Atom(LispAtom::Bool(result)))
+        numeric_fold(args, rational::div, big_ratio_div, |a, b| a / b)
     },
 };
 
-const LISP_LE: LispBuiltin = LispBuiltin {
-    arity: at_least_two,
-    func: |args, _env| {
-        let nums = to_list_of_floats(args)?;
-        let mut result = true;
-        for i in 0..nums.len() - 1 {
-            result &= nums[i] <= nums[i + 1];
+/// Apply a checked integer division operation to exactly two integer arguments. BigInts (and
+/// results that don't fit in an Int) are handled by big_op instead.
+fn int_division(
+    args: Vec<Ast>,
+    op: fn(i64, i64) -> Option<i64>,
+    big_op: fn((BigInt, BigInt)) -> BigInt,
+) -> Result<Ast, LispError> {
+    if let [Ast::Atom(LispAtom::Int(n)), Ast::Atom(LispAtom::Int(d))] = args[..] {
+        if d == 0 {
+            return Err(LispError::DivideByZero);
+        }
+        if let Some(num) = op(n, d) {
+            return Ok(Ast::Atom(LispAtom::Int(num)));
         }
+    }
 
-        Ok(Ast::Atom(LispAtom::Bool(result)))
-    },
+    let n = ast_to_bigint(&args[0]).ok_or(LispError::TypeError)?;
+    let d = ast_to_bigint(&args[1]).ok_or(LispError::TypeError)?;
+    let result = n.div_rem(&d).ok_or(LispError::DivideByZero)?;
+    Ok(Ast::from(big_op(result)))
+}
+
+/// Remainder of truncating division. The result has the same sign as the dividend, so
+/// `(= a (+ (* (quot a b) b) (rem a b)))` always holds.
+const LISP_REM: LispBuiltin = LispBuiltin {
+    name: "rem",
+    doc: "(rem n d): the remainder of truncating integer division, with the sign of n",
+    arity: exactly_two,
+    func: |args, _env| int_division(args, i64::checked_rem, |(_, rem)| rem),
 };
 
-const LISP_LIST: LispBuiltin = LispBuiltin {
-    arity: |_num_args| true,
-    func: |args, _env| Ok(Ast::List(args)),
+/// Integer division, truncating toward zero.
+const LISP_QUOT: LispBuiltin = LispBuiltin {
+    name: "quot",
+    doc: "(quot n d): integer division truncating toward zero",
+    arity: exactly_two,
+    func: |args, _env| int_division(args, i64::checked_div, |(quot, _)| quot),
 };
 
-const LISP_IS_LIST: LispBuiltin = LispBuiltin {
+/// Convert a value to an Int. Floats and Rationals are truncated toward zero, strings are parsed,
+/// and booleans become 1 or 0.
+const LISP_INT: LispBuiltin = LispBuiltin {
+    name: "int",
+    doc: "(int x): convert a number, numeric string, or boolean to an integer",
     arity: exactly_one,
     func: |args, _env| {
-        let is_list = matches!(get_first(&args)?, Ast::List(_));
-        Ok(Ast::Atom(LispAtom::Bool(is_list)))
+        let num = match take_first(args)? {
+            Ast::Atom(LispAtom::Int(num)) => num,
+            big @ Ast::Atom(LispAtom::BigInt(_)) => return Ok(big),
+            Ast::Atom(LispAtom::Float(num)) => float_to_int(num.trunc())?,
+            Ast::Atom(LispAtom::Rational(num, den)) => rational::truncate((num, den)),
+            Ast::Atom(LispAtom::Bool(b)) => b as i64,
+            Ast::Atom(LispAtom::String(ref string)) => {
+                return string.trim().parse::<BigInt>().map(Ast::from).map_err(|_| {
+                    LispError::ValueError(format!("cannot convert \"{}\" to an int", string))
+                })
+            }
+            _ => return Err(LispError::TypeError),
+        };
+        Ok(Ast::Atom(LispAtom::Int(num)))
     },
 };
 
-const LISP_IS_EMPTY: LispBuiltin = LispBuiltin {
+/// Convert a value to a Float. Strings are parsed, unless they would give a NaN or infinity, and
+/// booleans become 1.0 or 0.0.
+const LISP_FLOAT: LispBuiltin = LispBuiltin {
+    name: "float",
+    doc: "(float x): convert a number, numeric string, or boolean to a float",
     arity: exactly_one,
     func: |args, _env| {
-        let arg = get_first(&args)?;
-        let is_empty = match arg {
-            Ast::List(items) => !items.is_empty(),
-            _ => false,
+        let num = match take_first(args)? {
+            Ast::Atom(LispAtom::Int(num)) => num as f64,
+            Ast::Atom(LispAtom::Float(num)) => num,
+            Ast::Atom(LispAtom::BigInt(ref num)) => num.to_f64(),
+            Ast::Atom(LispAtom::Rational(num, den)) => rational::to_float((num, den)),
+            Ast::Atom(LispAtom::Bool(b)) => b as i64 as f64,
+            // Rust also parses "nan", "inf", and overflowing numbers, which aren't numeric strings
+            Ast::Atom(LispAtom::String(ref string)) => string
+                .trim()
+                .parse()
+                .ok()
+                .filter(|num: &f64| num.is_finite())
+                .ok_or_else(|| {
+                    LispError::ValueError(format!("cannot convert \"{}\" to a float", string))
+                })?,
+            _ => return Err(LispError::TypeError),
         };
-
-        Ok(Ast::Atom(LispAtom::Bool(is_empty)))
+        Ok(Ast::Atom(LispAtom::Float(num)))
     },
 };
 
-const LISP_COUNT: LispBuiltin = LispBuiltin {
+const LISP_FLOOR: LispBuiltin = LispBuiltin {
+    name: "floor",
+    doc: "(floor x): round x toward negative infinity",
     arity: exactly_one,
-    func: |args, _env| {
-        let arg = get_first(&args)?;
-        let length = match arg {
-            Ast::List(items) => items.len(),
-            _ => return Err(LispError::TypeError),
+    func: |args, _env| round_with(args, f64::floor, rational::floor),
+};
+
+const LISP_CEIL: LispBuiltin = LispBuiltin {
+    name: "ceil",
+    doc: "(ceil x): round x toward positive infinity",
+    arity: exactly_one,
+    func: |args, _env| round_with(args, f64::ceil, rational::ceil),
+};
+
+/// Rounds to the nearest integer, with halfway cases rounded away from zero (so 0.5 rounds to 1
+/// and -0.5 rounds to -1).
+const LISP_ROUND: LispBuiltin = LispBuiltin {
+    name: "round",
+    doc: "(round x): round x to the nearest integer, with halves away from zero",
+    arity: exactly_one,
+    func: |args, _env| round_with(args, f64::round, rational::round),
+};
+
+const LISP_TRUNCATE: LispBuiltin = LispBuiltin {
+    name: "truncate",
+    doc: "(truncate x): round x toward zero",
+    arity: exactly_one,
+    func: |args, _env| round_with(args, f64::trunc, rational::truncate),
+};
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+fn u64_to_int(num: u64) -> Result<Ast, LispError> {
+    Ok(Ast::from(BigInt::from(num)))
+}
+
+/// Greatest common divisor of all arguments, ignoring signs. `(gcd 0 0)` is 0.
+const LISP_GCD: LispBuiltin = LispBuiltin {
+    name: "gcd",
+    doc: "(gcd n m ...): the greatest common divisor of integers",
+    arity: at_least_two,
+    func: |args, _env| {
+        let result = to_list_of_ints(args)?
+            .into_iter()
+            .map(i64::unsigned_abs)
+            .fold(0, gcd);
+        u64_to_int(result)
+    },
+};
+
+/// Least common multiple of all arguments, ignoring signs. Is 0 if any argument is 0.
+const LISP_LCM: LispBuiltin = LispBuiltin {
+    name: "lcm",
+    doc: "(lcm n m ...): the least common multiple of integers",
+    arity: at_least_two,
+    func: |args, _env| {
+        let mut result: u64 = 1;
+        for num in to_list_of_ints(args)?.into_iter().map(i64::unsigned_abs) {
+            if num == 0 || result == 0 {
+                result = 0;
+                continue;
+            }
+            result = (result / gcd(result, num))
+                .checked_mul(num)
+                .ok_or_else(|| LispError::Overflow(format!("(lcm {} {})", result, num)))?;
+        }
+        u64_to_int(result)
+    },
+};
+
+/// Fold a bitwise operation over two or more Int arguments.
+fn bitwise_fold(args: Vec<Ast>, op: fn(i64, i64) -> i64) -> Result<Ast, LispError> {
+    let result = to_list_of_ints(args)?
+        .into_iter()
+        .reduce(op)
+        .ok_or(LispError::BadArity)?;
+    Ok(Ast::Atom(LispAtom::Int(result)))
+}
+
+/// Fold a shift over two or more Int arguments. Shift amounts must be in [0, 64).
+fn shift_fold(args: Vec<Ast>, op: fn(i64, u32) -> i64) -> Result<Ast, LispError> {
+    let mut nums = to_list_of_ints(args)?.into_iter();
+    let mut result = nums.next().ok_or(LispError::BadArity)?;
+    for amount in nums {
+        if !(0..64).contains(&amount) {
+            return Err(LispError::ValueError(format!(
+                "shift amount {} is not between 0 and 63",
+                amount
+            )));
+        }
+        result = op(result, amount as u32);
+    }
+    Ok(Ast::Atom(LispAtom::Int(result)))
+}
+
+const LISP_BIT_AND: LispBuiltin = LispBuiltin {
+    name: "bit-and",
+    doc: "(bit-and n m ...): bitwise and of integers",
+    arity: at_least_two,
+    func: |args, _env| bitwise_fold(args, |a, b| a & b),
+};
+
+const LISP_BIT_OR: LispBuiltin = LispBuiltin {
+    name: "bit-or",
+    doc: "(bit-or n m ...): bitwise or of integers",
+    arity: at_least_two,
+    func: |args, _env| bitwise_fold(args, |a, b| a | b),
+};
+
+const LISP_BIT_XOR: LispBuiltin = LispBuiltin {
+    name: "bit-xor",
+    doc: "(bit-xor n m ...): bitwise exclusive or of integers",
+    arity: at_least_two,
+    func: |args, _env| bitwise_fold(args, |a, b| a ^ b),
+};
+
+const LISP_BIT_NOT: LispBuiltin = LispBuiltin {
+    name: "bit-not",
+    doc: "(bit-not n): bitwise complement of an integer",
+    arity: exactly_one,
+    func: |args, _env| {
+        let num = get_first(&args).and_then(ast_to_int)?;
+        Ok(Ast::Atom(LispAtom::Int(!num)))
+    },
+};
+
+/// Shifts left, discarding bits shifted past the most significant bit.
+const LISP_SHIFT_LEFT: LispBuiltin = LispBuiltin {
+    name: "shift-left",
+    doc: "(shift-left n bits): shift an integer left",
+    arity: at_least_two,
+    func: |args, _env| shift_fold(args, |num, amount| num << amount),
+};
+
+/// Arithmetic shift right, so the sign of negative numbers is kept.
+const LISP_SHIFT_RIGHT: LispBuiltin = LispBuiltin {
+    name: "shift-right",
+    doc: "(shift-right n bits): shift an integer right",
+    arity: at_least_two,
+    func: |args, _env| shift_fold(args, |num, amount| num >> amount),
+};
+
+const LISP_SQRT: LispBuiltin = LispBuiltin {
+    name: "sqrt",
+    doc: "(sqrt x): the square root of a non-negative number",
+    arity: exactly_one,
+    func: |args, _env| {
+        let num = get_first(&args).and_then(ast_to_float)?;
+        if num < 0.0 {
+            return Err(LispError::ValueError(format!(
+                "cannot take square root of negative number {}",
+                num
+            )));
+        }
+        Ok(Ast::Atom(LispAtom::Float(num.sqrt())))
+    },
+};
+
+/// The largest result `pow` will compute exactly, in bits.
+const MAX_POW_BITS: u64 = 1 << 20;
+
+/// Raises the first argument to the power of the second. The result is exact when the base is
+/// exact and the exponent is an Int (unless a zero base is raised to a negative power, or the
+/// result is a fraction too large for a Rational), otherwise it is a Float.
+const LISP_POW: LispBuiltin = LispBuiltin {
+    name: "pow",
+    doc: "(pow base exponent): raise base to a power",
+    arity: exactly_two,
+    func: |args, _env| {
+        if let (Some((num, den)), Ast::Atom(LispAtom::Int(exp))) =
+            (rational::exact(&args[0]), &args[1])
+        {
+            let result = u32::try_from(exp.unsigned_abs())
+                .ok()
+                .filter(|_| *exp >= 0 || num != 0)
+                .and_then(|abs| Some((num.checked_pow(abs)?, den.checked_pow(abs)?)))
+                .and_then(|(num, den)| match *exp >= 0 {
+                    true => rational::make(num.into(), den.into()),
+                    false => rational::make(den.into(), num.into()),
+                });
+            if let Some(result) = result {
+                return Ok(rational::to_ast(result));
+            }
+        }
+
+        if let (Some((num, den)), Ast::Atom(LispAtom::Int(exp))) =
+            (ast_to_big_ratio(&args[0]), &args[1])
+        {
+            // 0, 1, and -1 stay small however large the exponent is
+            match (num.to_i64(), den.to_i64()) {
+                (Some(1), Some(1)) => return Ok(Ast::from(1)),
+                (Some(-1), Some(1)) => return Ok(Ast::from(if exp % 2 == 0 { 1 } else { -1 })),
+                (Some(0), _) if *exp > 0 => return Ok(Ast::from(0)),
+                _ => (),
+            }
+
+            // Refuse to build absurdly large numbers
+            let bits = num.bits().max(den.bits());
+            let too_big = bits.saturating_mul(exp.unsigned_abs()) > MAX_POW_BITS;
+            match u32::try_from(exp.unsigned_abs()) {
+                Ok(abs) if !too_big && (*exp >= 0 || !num.is_zero()) => {
+                    let (num, den) = (num.pow(abs), den.pow(abs));
+                    return Ok(big_ratio_to_ast(if *exp >= 0 {
+                        (num, den)
+                    } else {
+                        (den, num)
+                    }));
+                }
+                _ if *exp >= 0 || !num.is_zero() => {
+                    return Err(LispError::Overflow(format!("(pow {} {})", args[0], exp)));
+                }
+                _ => (),
+            }
+        }
+
+        let nums = to_list_of_floats(args)?;
+        Ok(Ast::Atom(LispAtom::Float(nums[0].powf(nums[1]))))
+    },
+};
+
+const LISP_EXP: LispBuiltin = LispBuiltin {
+    name: "exp",
+    doc: "(exp x): e raised to the power x",
+    arity: exactly_one,
+    func: |args, _env| {
+        let num = get_first(&args).and_then(ast_to_float)?;
+        Ok(Ast::Atom(LispAtom::Float(num.exp())))
+    },
+};
+
+/// Natural logarithm with one argument, or logarithm with the base given by the second argument.
+const LISP_LOG: LispBuiltin = LispBuiltin {
+    name: "log",
+    doc: "(log x [base]): the natural logarithm of x, or its logarithm in base",
+    arity: one_or_two,
+    func: |args, _env| {
+        let nums = to_list_of_floats(args)?;
+        let num = nums[0];
+        if num <= 0.0 {
+            return Err(LispError::ValueError(format!(
+                "cannot take logarithm of non-positive number {}",
+                num
+            )));
+        }
+
+        match nums.get(1) {
+            Some(&base) if base <= 0.0 || base == 1.0 => Err(LispError::ValueError(format!(
+                "invalid logarithm base {}",
+                base
+            ))),
+            Some(&base) => Ok(Ast::Atom(LispAtom::Float(num.ln() / base.ln()))),
+            None => Ok(Ast::Atom(LispAtom::Float(num.ln()))),
+        }
+    },
+};
+
+/// With no arguments returns a random Float in [0, 1). With an Int n returns a random Int in
+/// [0, n).
+const LISP_RANDOM: LispBuiltin = LispBuiltin {
+    name: "random",
+    doc: "(random [n]): a random float in [0, 1), or a random integer in [0, n)",
+    arity: one_or_zero,
+    func: |args, env| match args.first() {
+        None => Ok(Ast::Atom(LispAtom::Float(env.rng().next_float()))),
+        Some(bound) => {
+            let bound = ast_to_int(bound)?;
+            if bound <= 0 {
+                return Err(LispError::ValueError(format!(
+                    "random bound must be positive but got {}",
+                    bound
+                )));
+            }
+            let num = env.rng().next_below(bound as u64);
+            Ok(Ast::Atom(LispAtom::Int(num as i64)))
+        }
+    },
+};
+
+/// Reseed the random number generator so that runs are reproducible.
+const LISP_RANDOM_SEED: LispBuiltin = LispBuiltin {
+    name: "random-seed",
+    doc: "(random-seed n): seed the random number generator",
+    arity: exactly_one,
+    func: |args, env| {
+        let seed = get_first(&args).and_then(ast_to_int)?;
+        *env.rng() = Rng::with_seed(seed as u64);
+        Ok(Ast::Unspecified)
+    },
+};
+
+const LISP_SIN: LispBuiltin = LispBuiltin {
+    name: "sin",
+    doc: "(sin x): the sine of x radians",
+    arity: exactly_one,
+    func: |args, _env| float_fn(args, f64::sin),
+};
+
+const LISP_COS: LispBuiltin = LispBuiltin {
+    name: "cos",
+    doc: "(cos x): the cosine of x radians",
+    arity: exactly_one,
+    func: |args, _env| float_fn(args, f64::cos),
+};
+
+const LISP_TAN: LispBuiltin = LispBuiltin {
+    name: "tan",
+    doc: "(tan x): the tangent of x radians",
+    arity: exactly_one,
+    func: |args, _env| float_fn(args, f64::tan),
+};
+
+const LISP_ASIN: LispBuiltin = LispBuiltin {
+    name: "asin",
+    doc: "(asin x): the arcsine of x, in radians",
+    arity: exactly_one,
+    func: |args, _env| unit_domain_float_fn(args, f64::asin),
+};
+
+const LISP_ACOS: LispBuiltin = LispBuiltin {
+    name: "acos",
+    doc: "(acos x): the arccosine of x, in radians",
+    arity: exactly_one,
+    func: |args, _env| unit_domain_float_fn(args, f64::acos),
+};
+
+const LISP_ATAN: LispBuiltin = LispBuiltin {
+    name: "atan",
+    doc: "(atan x): the arctangent of x, in radians",
+    arity: exactly_one,
+    func: |args, _env| float_fn(args, f64::atan),
+};
+
+/// The angle of the point (x, y) from the positive x axis. Called as `(atan2 y x)`.
+const LISP_ATAN2: LispBuiltin = LispBuiltin {
+    name: "atan2",
+    doc: "(atan2 y x): the angle of the point (x, y), in radians",
+    arity: exactly_two,
+    func: |args, _env| {
+        let nums = to_list_of_floats(args)?;
+        Ok(Ast::Atom(LispAtom::Float(nums[0].atan2(nums[1]))))
+    },
+};
+
+/// Evaluates every expression in a file. Like `eval`, this always happens in the global scope.
+const LISP_USE: LispBuiltin = LispBuiltin {
+    name: "use",
+    doc: "(use file): evaluate every expression in a file in the global scope",
+    arity: exactly_one,
+    func: |args, env| {
+        let file = take_first(args).and_then(ast_to_string)?;
+        env.in_global_scope(|env| crate::execute_file(file.into(), env))
+    },
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+const LISP_READ_FILE: LispBuiltin = LispBuiltin {
+    name: "read-file",
+    doc: "(read-file path): the contents of a file as a string",
+    arity: exactly_one,
+    func: |args, _env| {
+        let path = take_first(args).and_then(ast_to_string)?;
+        let contents = std::fs::read_to_string(&path).map_err(|e| LispError::io(&path, e))?;
+        Ok(Ast::Atom(LispAtom::String(contents)))
+    },
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+const LISP_READ_BYTES: LispBuiltin = LispBuiltin {
+    name: "read-bytes",
+    doc: "(read-bytes path): the contents of a file as bytes",
+    arity: exactly_one,
+    func: |args, _env| {
+        let path = take_first(args).and_then(ast_to_string)?;
+        let contents = std::fs::read(&path).map_err(|e| LispError::io(&path, e))?;
+        Ok(Ast::Atom(LispAtom::Bytes(contents)))
+    },
+};
+
+/// Write bytes to a file, creating it or replacing what was in it.
+#[cfg(not(target_arch = "wasm32"))]
+const LISP_WRITE_BYTES: LispBuiltin = LispBuiltin {
+    name: "write-bytes",
+    doc: "(write-bytes path bytes): write bytes to a file, replacing its contents",
+    arity: exactly_two,
+    func: |args, _env| {
+        let mut args = args.into_iter();
+        let path = args
+            .next()
+            .ok_or(LispError::BadArity)
+            .and_then(ast_to_string)?;
+        let Some(Ast::Atom(LispAtom::Bytes(contents))) = &args.next() else {
+            return Err(LispError::TypeError);
         };
+        std::fs::write(&path, contents).map_err(|e| LispError::io(&path, e))?;
+        Ok(Ast::Unspecified)
+    },
+};
 
-        Ok(Ast::Atom(LispAtom::Float(length as f64)))
+/// Write a string to a file, creating it or replacing what was in it.
+#[cfg(not(target_arch = "wasm32"))]
+const LISP_WRITE_FILE: LispBuiltin = LispBuiltin {
+    name: "write-file",
+    doc: "(write-file path contents): write a string to a file, replacing its contents",
+    arity: exactly_two,
+    func: |args, _env| {
+        let mut args = args.into_iter();
+        let path = args
+            .next()
+            .ok_or(LispError::BadArity)
+            .and_then(ast_to_string)?;
+        let contents = args
+            .next()
+            .ok_or(LispError::BadArity)
+            .and_then(ast_to_string)?;
+        std::fs::write(&path, contents).map_err(|e| LispError::io(&path, e))?;
+        Ok(Ast::Unspecified)
     },
 };
 
-const LISP_GET_TYPE: LispBuiltin = LispBuiltin {
+/// Add a string to the end of a file, creating it if it doesn't exist.
+#[cfg(not(target_arch = "wasm32"))]
+const LISP_APPEND_FILE: LispBuiltin = LispBuiltin {
+    name: "append-file",
+    doc: "(append-file path contents): add a string to the end of a file",
+    arity: exactly_two,
+    func: |args, _env| {
+        use std::io::Write;
+
+        let mut args = args.into_iter();
+        let path = args
+            .next()
+            .ok_or(LispError::BadArity)
+            .and_then(ast_to_string)?;
+        let contents = args
+            .next()
+            .ok_or(LispError::BadArity)
+            .and_then(ast_to_string)?;
+        std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .map_err(|e| LispError::io(&path, e))?;
+        Ok(Ast::Unspecified)
+    },
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+const LISP_FILE_EXISTS: LispBuiltin = LispBuiltin {
+    name: "file-exists?",
+    doc: "(file-exists? path): true if there is a file or directory at path",
     arity: exactly_one,
     func: |args, _env| {
-        let arg = get_first(&args)?;
-        Ok(Ast::Type(LispType::from(arg)))
+        let path = take_first(args).and_then(ast_to_string)?;
+        let exists = std::path::Path::new(&path)
+            .try_exists()
+            .map_err(|e| LispError::io(&path, e))?;
+        Ok(Ast::Atom(LispAtom::Bool(exists)))
     },
 };
 
-const LISP_DO: LispBuiltin = LispBuiltin {
+/// Delete a file. It is an error if the file doesn't exist.
+#[cfg(not(target_arch = "wasm32"))]
+const LISP_DELETE_FILE: LispBuiltin = LispBuiltin {
+    name: "delete-file",
+    doc: "(delete-file path): delete a file",
+    arity: exactly_one,
+    func: |args, _env| {
+        let path = take_first(args).and_then(ast_to_string)?;
+        std::fs::remove_file(&path).map_err(|e| LispError::io(&path, e))?;
+        Ok(Ast::Unspecified)
+    },
+};
+
+/// Open a file as a port. Files opened for writing are created or emptied first.
+#[cfg(not(target_arch = "wasm32"))]
+const LISP_OPEN: LispBuiltin = LispBuiltin {
+    name: "open",
+    doc: "(open path mode): open a file as a port, where mode is read or write",
+    arity: exactly_two,
+    func: |args, _env| {
+        let mut args = args.into_iter();
+        let path = args
+            .next()
+            .ok_or(LispError::BadArity)
+            .and_then(ast_to_string)?;
+        let mode = args
+            .next()
+            .ok_or(LispError::BadArity)
+            .and_then(ast_to_name)?;
+        let port = match mode.as_str() {
+            "read" => {
+                let file = std::fs::File::open(&path).map_err(|e| LispError::io(&path, e))?;
+                Port::reader(&path, Rc::new(RefCell::new(std::io::BufReader::new(file))))
+            }
+            "write" => {
+                let file = std::fs::File::create(&path).map_err(|e| LispError::io(&path, e))?;
+                Port::writer(&path, Rc::new(RefCell::new(file)))
+            }
+            _ => {
+                return Err(LispError::ValueError(format!(
+                    "unknown port mode {}, expected read or write",
+                    mode
+                )))
+            }
+        };
+        Ok(Ast::Port(port))
+    },
+};
+
+fn get_port(args: &[Ast]) -> Result<&Port, LispError> {
+    match get_first(args)? {
+        Ast::Port(port) => Ok(port),
+        _ => Err(LispError::TypeError),
+    }
+}
+
+const LISP_READ_PORT_LINE: LispBuiltin = LispBuiltin {
+    name: "read-line",
+    doc: "(read-line port): read a line from a port, or unspecified at the end of the input",
+    arity: exactly_one,
+    func: |args, env| {
+        let Some(mut line) = get_port(&args)?.read_line(env)? else {
+            return Ok(Ast::Unspecified);
+        };
+        if line.ends_with('\n') {
+            line.pop();
+        }
+        Ok(Ast::Atom(LispAtom::String(line)))
+    },
+};
+
+const LISP_WRITE_STRING: LispBuiltin = LispBuiltin {
+    name: "write-string",
+    doc: "(write-string port string): write a string to a port",
+    arity: exactly_two,
+    func: |args, env| {
+        let Some(Ast::Atom(LispAtom::String(string))) = args.get(1) else {
+            return Err(LispError::TypeError);
+        };
+        get_port(&args)?.write_str(string, env)?;
+        Ok(Ast::Unspecified)
+    },
+};
+
+const LISP_CLOSE: LispBuiltin = LispBuiltin {
+    name: "close",
+    doc: "(close port): close a port",
+    arity: exactly_one,
+    func: |args, _env| {
+        get_port(&args)?.close()?;
+        Ok(Ast::Unspecified)
+    },
+};
+
+/// Look up an environment variable. Values that aren't valid unicode have the invalid parts
+/// replaced with U+FFFD rather than being an error.
+#[cfg(not(target_arch = "wasm32"))]
+const LISP_GETENV: LispBuiltin = LispBuiltin {
+    name: "getenv",
+    doc: "(getenv name): the value of an environment variable, or false if it isn't set",
+    arity: exactly_one,
+    func: |args, _env| {
+        let name = take_first(args).and_then(ast_to_string)?;
+        Ok(match std::env::var_os(name) {
+            Some(value) => Ast::Atom(LispAtom::String(value.to_string_lossy().into_owned())),
+            None => Ast::Atom(LispAtom::Bool(false)),
+        })
+    },
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+const LISP_SETENV: LispBuiltin = LispBuiltin {
+    name: "setenv",
+    doc: "(setenv name value): set an environment variable for this process and its children",
+    arity: exactly_two,
+    func: |args, _env| {
+        let mut args = args.into_iter();
+        let name = args
+            .next()
+            .ok_or(LispError::BadArity)
+            .and_then(ast_to_string)?;
+        let value = args
+            .next()
+            .ok_or(LispError::BadArity)
+            .and_then(ast_to_string)?;
+        // set_var panics on these instead of returning an error
+        if name.is_empty() || name.contains(['=', '\0']) || value.contains('\0') {
+            return Err(LispError::ValueError(format!(
+                "invalid environment variable {:?}",
+                name
+            )));
+        }
+        std::env::set_var(name, value);
+        Ok(Ast::Unspecified)
+    },
+};
+
+/// Every environment variable as an association list of `(name value)` pairs, sorted by name.
+#[cfg(not(target_arch = "wasm32"))]
+const LISP_ENV_VARS: LispBuiltin = LispBuiltin {
+    name: "env-vars",
+    doc: "(env-vars): every environment variable as an association list",
+    arity: exactly_zero,
+    func: |_args, _env| {
+        let mut vars: Vec<_> = std::env::vars_os()
+            .map(|(name, value)| {
+                (
+                    name.to_string_lossy().into_owned(),
+                    value.to_string_lossy().into_owned(),
+                )
+            })
+            .collect();
+        vars.sort();
+        Ok(Ast::List(
+            vars.into_iter()
+                .map(|(name, value)| Ast::List(vec![Ast::from(name), Ast::from(value)]))
+                .collect(),
+        ))
+    },
+};
+
+const LISP_NOW: LispBuiltin = LispBuiltin {
+    name: "now",
+    doc: "(now): milliseconds since the Unix epoch",
+    arity: exactly_zero,
+    func: |_args, _env| Ok(Ast::Atom(LispAtom::Int(time::now_ms()))),
+};
+
+/// Unlike `now`, this never jumps when the system clock is changed, so it is the one to use for
+/// timing code.
+#[cfg(not(target_arch = "wasm32"))]
+const LISP_MONOTONIC_MS: LispBuiltin = LispBuiltin {
+    name: "monotonic-ms",
+    doc: "(monotonic-ms): milliseconds from a clock that only moves forward, for timing",
+    arity: exactly_zero,
+    func: |_args, _env| Ok(Ast::Atom(LispAtom::Float(time::monotonic_ms()))),
+};
+
+const LISP_FORMAT_TIME: LispBuiltin = LispBuiltin {
+    name: "format-time",
+    doc: "(format-time ms format): format milliseconds since the epoch as a UTC date",
+    arity: exactly_two,
+    func: |args, _env| {
+        let (Some(Ast::Atom(LispAtom::Int(ms))), Some(Ast::Atom(LispAtom::String(format)))) =
+            (args.first(), args.get(1))
+        else {
+            return Err(LispError::TypeError);
+        };
+        Ok(Ast::Atom(LispAtom::String(time::format_time(*ms, format)?)))
+    },
+};
+
+/// Block for a number of milliseconds. The sleep is split into short steps so that it can be
+/// interrupted with Ctrl-C.
+#[cfg(not(target_arch = "wasm32"))]
+const LISP_SLEEP: LispBuiltin = LispBuiltin {
+    name: "sleep",
+    doc: "(sleep ms): wait for a number of milliseconds",
+    arity: exactly_one,
+    func: |args, env| {
+        use std::time::{Duration, Instant};
+
+        let ms = get_first(&args).and_then(ast_to_float)?;
+        if !(ms >= 0.0 && ms.is_finite()) {
+            return Err(LispError::TypeError);
+        }
+        let end = Duration::try_from_secs_f64(ms / 1000.0)
+            .ok()
+            .and_then(|duration| Instant::now().checked_add(duration))
+            .ok_or_else(|| LispError::ValueError(format!("can't sleep for {} ms", ms)))?;
+        loop {
+            env.check_interrupt()?;
+            let now = Instant::now();
+            if now >= end {
+                return Ok(Ast::Unspecified);
+            }
+            std::thread::sleep((end - now).min(Duration::from_millis(50)));
+        }
+    },
+};
+
+/// Run a program with arguments and wait for it to finish. The arguments are passed straight to
+/// the program rather than through a shell, so they can't inject other commands.
+#[cfg(all(feature = "shell", not(target_arch = "wasm32")))]
+const LISP_SHELL: LispBuiltin = LispBuiltin {
+    name: "shell",
+    doc: "(shell program arg ...): run a program, returning its exit code, stdout, and stderr",
     arity: at_least_one,
-    func: |args, _env| args.into_iter().last().ok_or(LispError::BadArity),
+    func: |args, _env| {
+        let mut args = args.into_iter().map(ast_to_string);
+        let program = args.next().ok_or(LispError::BadArity)??;
+        let args = args.collect::<Result<Vec<_>, _>>()?;
+        let output = std::process::Command::new(&program)
+            .args(args)
+            .output()
+            .map_err(|e| LispError::io(&program, e))?;
+        // Processes killed by a signal have no exit code
+        let code = output.status.code().unwrap_or(-1);
+        Ok(Ast::List(vec![
+            Ast::Atom(LispAtom::Int(code.into())),
+            Ast::from(String::from_utf8_lossy(&output.stdout).into_owned()),
+            Ast::from(String::from_utf8_lossy(&output.stderr).into_owned()),
+        ]))
+    },
+};
+
+/// Write the display form of values to the output, separated by spaces and followed by end.
+/// Strings are written without quotes and characters without `#\`.
+fn print_values(values: &[Ast], end: &str, env: &Environment) -> Result<Ast, LispError> {
+    let mut text = values
+        .iter()
+        .map(|value| match value {
+            Ast::Atom(LispAtom::String(string)) => string.clone(),
+            Ast::Atom(LispAtom::Char(c)) => c.to_string(),
+            value => display_string(value, env.printer()),
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    text.push_str(end);
+    env.write_output(&text)?;
+    Ok(Ast::Unspecified)
+}
+
+const LISP_PRINT: LispBuiltin = LispBuiltin {
+    name: "print",
+    doc: "(print value ...): print values separated by spaces",
+    arity: |_| true,
+    func: |args, env| print_values(&args, "", env),
+};
+
+const LISP_PRINTLN: LispBuiltin = LispBuiltin {
+    name: "println",
+    doc: "(println value ...): print values separated by spaces, followed by a newline",
+    arity: |_| true,
+    func: |args, env| print_values(&args, "\n", env),
+};
+
+const LISP_NEWLINE: LispBuiltin = LispBuiltin {
+    name: "newline",
+    doc: "(newline): print a newline",
+    arity: exactly_zero,
+    func: |_args, env| print_values(&[], "\n", env),
 };
+
+const LISP_PUT_STR: LispBuiltin = LispBuiltin {
+    name: "putstr",
+    doc: "(putstr string): print a string followed by a newline",
+    arity: exactly_one,
+    func: |args, env| {
+        let string = take_first(args).and_then(ast_to_string)?;
+        print_values(&[Ast::from(string)], "\n", env)
+    },
+};
+
+const LISP_READ_LINE: LispBuiltin = LispBuiltin {
+    name: "readline",
+    doc: "(readline): read a line from stdin",
+    arity: exactly_zero,
+    func: |_args, env| {
+        let mut buf = env.read_input_line()?.unwrap_or_default();
+        // TODO: This might break compatibility with windows
+        buf.pop(); // Remove trailing '\n'
+        Ok(Ast::Atom(LispAtom::String(buf)))
+    },
+};
+
+const LISP_READ: LispBuiltin = LispBuiltin {
+    name: "read",
+    doc: "(read): read one expression from stdin without evaluating it",
+    arity: exactly_zero,
+    func: |_args, env| {
+        let mut buf = String::new();
+        // Keep reading lines until the expression is complete, like a multiline REPL
+        loop {
+            match env.read_input_line()? {
+                Some(line) => buf.push_str(&line),
+                None if parser::skip_ignored(&buf).is_empty() => return Ok(Ast::Unspecified),
+                None => return Err(LispError::ParseError(buf.trim().to_string())),
+            }
+            if parser::skip_ignored(&buf).is_empty() {
+                continue;
+            }
+            match parser::with_fold_case(env.fold_case(), || parser::parse_expr(&buf)) {
+                Ok((rest, ast)) => {
+                    // Whatever follows the expression is left for the next read
+                    if !parser::skip_ignored(rest).is_empty() {
+                        env.unread_input(rest);
+                    }
+                    return Ok(ast);
+                }
+                Err(_) if parser::is_incomplete(&buf) => {}
+                Err(_) => return Err(LispError::ParseError(buf.trim().to_string())),
+            }
+        }
+    },
+};
+
+const LISP_READ_STRING: LispBuiltin = LispBuiltin {
+    name: "read-string",
+    doc: "(read-string string): parse one expression from string without evaluating it",
+    arity: exactly_one,
+    func: |args, env| {
+        let Some(Ast::Atom(LispAtom::String(string))) = &args.first() else {
+            return Err(LispError::TypeError);
+        };
+        if parser::skip_ignored(string).is_empty() {
+            return Err(LispError::ValueError(
+                "cannot read an expression from an empty string".to_string(),
+            ));
+        }
+        let parsed = parser::with_fold_case(env.fold_case(), || {
+            parser::parse_complete_expr(string.trim_end())
+        });
+        match parsed {
+            Ok((_, ast)) => Ok(ast),
+            // Report whatever is left after the first expression, or the whole string if there
+            // isn't a complete expression at the start
+            Err(_) => match parser::parse_expr(string) {
+                Ok((rest, _)) => Err(LispError::ParseError(rest.trim().to_string())),
+                Err(_) => Err(LispError::ParseError(string.trim().to_string())),
+            },
+        }
+    },
+};
+
+const LISP_JSON_PARSE: LispBuiltin = LispBuiltin {
+    name: "json-parse",
+    doc: "(json-parse string): parse JSON, with objects becoming association lists",
+    arity: exactly_one,
+    func: |args, _env| {
+        let string = take_first(args).and_then(ast_to_string)?;
+        json::parse(&string)
+    },
+};
+
+const LISP_JSON_STRINGIFY: LispBuiltin = LispBuiltin {
+    name: "json-stringify",
+    doc: "(json-stringify value): convert a value to JSON, with maps and association lists becoming objects",
+    arity: exactly_one,
+    func: |args, _env| {
+        Ok(Ast::Atom(LispAtom::String(json::stringify(get_first(
+            &args,
+        )?)?)))
+    },
+};
+
+/// Compile the regular expression in a pattern string and get the string it is matched against.
+#[cfg(feature = "regex")]
+fn regex_args(args: &[Ast]) -> Result<(regex::Regex, &str), LispError> {
+    let (Some(Ast::Atom(LispAtom::String(pattern))), Some(Ast::Atom(LispAtom::String(string)))) =
+        (args.first(), args.get(1))
+    else {
+        return Err(LispError::TypeError);
+    };
+    let regex = regex::Regex::new(pattern).map_err(|e| LispError::ValueError(e.to_string()))?;
+    Ok((regex, string))
+}
+
+#[cfg(feature = "regex")]
+const LISP_RE_MATCH: LispBuiltin = LispBuiltin {
+    name: "re-match?",
+    doc: "(re-match? pattern string): true if the regular expression matches part of string",
+    arity: exactly_two,
+    func: |args, _env| {
+        let (regex, string) = regex_args(&args)?;
+        Ok(Ast::Atom(LispAtom::Bool(regex.is_match(string))))
+    },
+};
+
+/// Find the first match of a regular expression. Without capture groups this is the matched
+/// text, and with them it is a list of the matched text followed by each group (false for groups
+/// that didn't take part in the match). Returns false if there is no match.
+#[cfg(feature = "regex")]
+const LISP_RE_FIND: LispBuiltin = LispBuiltin {
+    name: "re-find",
+    doc: "(re-find pattern string): the first match, with any capture groups, or false",
+    arity: exactly_two,
+    func: |args, _env| {
+        let (regex, string) = regex_args(&args)?;
+        let Some(captures) = regex.captures(string) else {
+            return Ok(Ast::Atom(LispAtom::Bool(false)));
+        };
+        let mut groups = captures.iter().map(|group| match group {
+            Some(group) => Ast::from(group.as_str()),
+            None => Ast::Atom(LispAtom::Bool(false)),
+        });
+        if captures.len() == 1 {
+            Ok(groups.next().expect("there is always a whole match"))
+        } else {
+            Ok(Ast::List(groups.collect()))
+        }
+    },
+};
+
+#[cfg(feature = "regex")]
+const LISP_RE_REPLACE: LispBuiltin = LispBuiltin {
+    name: "re-replace",
+    doc: "(re-replace pattern string replacement): replace every match, where $1 is a group",
+    arity: |num_args| num_args == 3,
+    func: |args, _env| {
+        let (regex, string) = regex_args(&args)?;
+        let Some(Ast::Atom(LispAtom::String(replacement))) = args.get(2) else {
+            return Err(LispError::TypeError);
+        };
+        let replaced = regex.replace_all(string, replacement.as_str());
+        Ok(Ast::Atom(LispAtom::String(replaced.into_owned())))
+    },
+};
+
+const LISP_EQUAL: LispBuiltin = LispBuiltin {
+    name: "equal?",
+    doc: "(equal? value ...): true if the values are structurally equal",
+    arity: at_least_two,
+    func: |args, _env| {
+        let mut iter = args.into_iter();
+        let first = iter.next().ok_or(LispError::BadArity)?;
+        for arg in iter {
+            if arg != first {
+                return Ok(Ast::Atom(LispAtom::Bool(false)));
+            }
+        }
+
+        Ok(Ast::Atom(LispAtom::Bool(true)))
+    },
+};
+
+/// Returns true if a and b are the same value without looking inside them: atoms of the same kind
+/// with the same value, the same type, or handles to the same environment, port, promise, or atom.
+///
+/// Lists and functions aren't shared yet, so there is no identity to compare and they are never
+/// identical, even to themselves. Once they are, this should compare them by pointer.
+fn identical(a: &Ast, b: &Ast) -> bool {
+    match (a, b) {
+        (Ast::Atom(a), Ast::Atom(b)) => a == b,
+        (Ast::Type(a), Ast::Type(b)) => a == b,
+        (Ast::Env(a), Ast::Env(b)) => env::same_env(a, b),
+        (Ast::Port(a), Ast::Port(b)) => a.ptr_eq(b),
+        (Ast::Promise(a), Ast::Promise(b)) => a.ptr_eq(b),
+        (Ast::Cell(a), Ast::Cell(b)) => Rc::ptr_eq(a, b),
+        _ => false,
+    }
+}
+
+/// Identity comparison. Unlike `equal?`, this never looks inside lists.
+const LISP_EQ: LispBuiltin = LispBuiltin {
+    name: "eq?",
+    doc: "(eq? value ...): true if the values are identical, without looking inside lists",
+    arity: at_least_two,
+    func: |args, _env| {
+        let result = args.windows(2).all(|pair| identical(&pair[0], &pair[1]));
+        Ok(Ast::Atom(LispAtom::Bool(result)))
+    },
+};
+
+/// Compare two numbers. Exact numbers are compared exactly, and otherwise both are compared as
+/// Floats. NaN can't be ordered, so comparing it is an error rather than silently false.
+fn compare_numbers(a: &Ast, b: &Ast) -> Result<Ordering, LispError> {
+    if let (Some(a), Some(b)) = (rational::exact(a), rational::exact(b)) {
+        return Ok(rational::cmp(a, b));
+    }
+    if let (Some((a, b)), Some((c, d))) = (ast_to_big_ratio(a), ast_to_big_ratio(b)) {
+        return Ok((&a * &d).cmp(&(&c * &b)));
+    }
+    let (a, b) = (ast_to_float(a)?, ast_to_float(b)?);
+    a.partial_cmp(&b)
+        .ok_or_else(|| LispError::ValueError(format!("cannot compare {} with {}", a, b)))
+}
+
+fn numbers_equal(a: &Ast, b: &Ast) -> Result<bool, LispError> {
+    Ok(compare_numbers(a, b)? == Ordering::Equal)
+}
+
+/// Returns true if test holds for every adjacent pair of numbers in args.
+fn compare_chain(args: Vec<Ast>, test: fn(Ordering) -> bool) -> Result<Ast, LispError> {
+    let mut result = true;
+    for pair in args.windows(2) {
+        result &= test(compare_numbers(&pair[0], &pair[1])?);
+    }
+
+    Ok(Ast::Atom(LispAtom::Bool(result)))
+}
+
+/// Compare the only argument with zero. Like the other comparisons, this is an error for NaN.
+fn sign_test(args: &[Ast], test: fn(Ordering) -> bool) -> Result<Ast, LispError> {
+    let ordering = compare_numbers(get_first(args)?, &Ast::Atom(LispAtom::Int(0)))?;
+    Ok(Ast::Atom(LispAtom::Bool(test(ordering))))
+}
+
+/// Both zero and negative zero are zero.
+const LISP_IS_ZERO: LispBuiltin = LispBuiltin {
+    name: "zero?",
+    doc: "(zero? num): true if num is zero",
+    arity: exactly_one,
+    func: |args, _env| sign_test(&args, Ordering::is_eq),
+};
+
+const LISP_IS_POSITIVE: LispBuiltin = LispBuiltin {
+    name: "positive?",
+    doc: "(positive? num): true if num is greater than zero",
+    arity: exactly_one,
+    func: |args, _env| sign_test(&args, Ordering::is_gt),
+};
+
+const LISP_IS_NEGATIVE: LispBuiltin = LispBuiltin {
+    name: "negative?",
+    doc: "(negative? num): true if num is less than zero",
+    arity: exactly_one,
+    func: |args, _env| sign_test(&args, Ordering::is_lt),
+};
+
+/// Returns true if the only argument is an even integer. Floats are a type error, even if they
+/// have an integer value.
+fn is_even(args: &[Ast]) -> Result<bool, LispError> {
+    match get_first(args)? {
+        Ast::Atom(LispAtom::Int(num)) => Ok(num % 2 == 0),
+        Ast::Atom(LispAtom::BigInt(num)) => {
+            let (_, remainder) = num.div_rem(&BigInt::from(2i64)).expect("2 isn't zero");
+            Ok(remainder.is_zero())
+        }
+        _ => Err(LispError::TypeError),
+    }
+}
+
+const LISP_IS_EVEN: LispBuiltin = LispBuiltin {
+    name: "even?",
+    doc: "(even? n): true if the integer n is even",
+    arity: exactly_one,
+    func: |args, _env| Ok(Ast::Atom(LispAtom::Bool(is_even(&args)?))),
+};
+
+const LISP_IS_ODD: LispBuiltin = LispBuiltin {
+    name: "odd?",
+    doc: "(odd? n): true if the integer n is odd",
+    arity: exactly_one,
+    func: |args, _env| Ok(Ast::Atom(LispAtom::Bool(!is_even(&args)?))),
+};
+
+/// Returns true if the argument is false, using the same truthiness as `if`.
+const LISP_NOT: LispBuiltin = LispBuiltin {
+    name: "not",
+    doc: "(not value): true if value is false",
+    arity: exactly_one,
+    func: |args, _env| Ok(Ast::Atom(LispAtom::Bool(!get_first(&args)?.is_truthy()))),
+};
+
+/// Numeric equality. Unlike `equal?`, Ints and Floats with the same value are equal.
+const LISP_NUM_EQ: LispBuiltin = LispBuiltin {
+    name: "=",
+    doc: "(= num ...): true if the numbers are numerically equal",
+    arity: at_least_two,
+    func: |args, _env| {
+        let mut result = true;
+        for pair in args.windows(2) {
+            result &= numbers_equal(&pair[0], &pair[1])?;
+        }
+
+        Ok(Ast::Atom(LispAtom::Bool(result)))
+    },
+};
+
+const LISP_GT: LispBuiltin = LispBuiltin {
+    name: ">",
+    doc: "(> num ...): true if the numbers are decreasing",
+    arity: at_least_two,
+    func: |args, _env| compare_chain(args, Ordering::is_gt),
+};
+
+const LISP_GE: LispBuiltin = LispBuiltin {
+    name: ">=",
+    doc: "(>= num ...): true if the numbers are non-increasing",
+    arity: at_least_two,
+    func: |args, _env| compare_chain(args, Ordering::is_ge),
+};
+
+const LISP_LT: LispBuiltin = LispBuiltin {
+    name: "<",
+    doc: "(< num ...): true if the numbers are increasing",
+    arity: at_least_two,
+    func: |args, _env| compare_chain(args, Ordering::is_lt),
+};
+
+const LISP_LE: LispBuiltin = LispBuiltin {
+    name: "<=",
+    doc: "(<= num ...): true if the numbers are non-decreasing",
+    arity: at_least_two,
+    func: |args, _env| compare_chain(args, Ordering::is_le),
+};
+
+const LISP_LIST: LispBuiltin = LispBuiltin {
+    name: "list",
+    doc: "(list value ...): a list of the values",
+    arity: |_num_args| true,
+    func: |args, _env| Ok(Ast::List(args)),
+};
+
+const LISP_IS_LIST: LispBuiltin = LispBuiltin {
+    name: "list?",
+    doc: "(list? value): true if value is a list",
+    arity: exactly_one,
+    func: |args, _env| {
+        let is_list = matches!(get_first(&args)?, Ast::List(_));
+        Ok(Ast::Atom(LispAtom::Bool(is_list)))
+    },
+};
+
+const LISP_CONS: LispBuiltin = LispBuiltin {
+    name: "cons",
+    doc: "(cons head tail): a list starting with head if tail is a list, or else (head . tail)",
+    arity: exactly_two,
+    func: |args, _env| {
+        let mut args = args.into_iter();
+        let head = args.next().ok_or(LispError::BadArity)?;
+        let tail = args.next().ok_or(LispError::BadArity)?;
+        Ok(Ast::cons(head, tail))
+    },
+};
+
+const LISP_FIRST: LispBuiltin = LispBuiltin {
+    name: "first",
+    doc: "(first list-or-pair): the first item of a list, or the head of a pair",
+    arity: exactly_one,
+    func: |args, _env| match get_first(&args)? {
+        Ast::List(items) => items
+            .first()
+            .cloned()
+            .ok_or_else(|| LispError::ValueError("first of an empty list".to_string())),
+        Ast::Pair(head, _) => Ok((**head).clone()),
+        _ => Err(LispError::TypeError),
+    },
+};
+
+const LISP_REST: LispBuiltin = LispBuiltin {
+    name: "rest",
+    doc: "(rest list-or-pair): a list without its first item, or the tail of a pair",
+    arity: exactly_one,
+    func: |args, _env| match get_first(&args)? {
+        Ast::List(items) if items.is_empty() => {
+            Err(LispError::ValueError("rest of an empty list".to_string()))
+        }
+        Ast::List(items) => Ok(Ast::List(items[1..].to_vec())),
+        Ast::Pair(_, tail) => Ok((**tail).clone()),
+        _ => Err(LispError::TypeError),
+    },
+};
+
+const LISP_IS_PAIR: LispBuiltin = LispBuiltin {
+    name: "pair?",
+    doc: "(pair? value): true if value is a pair or a non-empty list",
+    arity: exactly_one,
+    func: |args, _env| {
+        let is_pair = match get_first(&args)? {
+            Ast::List(items) => !items.is_empty(),
+            Ast::Pair(..) => true,
+            _ => false,
+        };
+        Ok(Ast::Atom(LispAtom::Bool(is_pair)))
+    },
+};
+
+const LISP_VECTOR: LispBuiltin = LispBuiltin {
+    name: "vector",
+    doc: "(vector value ...): create a vector of the values",
+    arity: |_| true,
+    func: |args, _env| Ok(Ast::Vector(args)),
+};
+
+const LISP_IS_VECTOR: LispBuiltin = LispBuiltin {
+    name: "vector?",
+    doc: "(vector? value): true if value is a vector",
+    arity: exactly_one,
+    func: |args, _env| {
+        let is_vector = matches!(get_first(&args)?, Ast::Vector(_));
+        Ok(Ast::Atom(LispAtom::Bool(is_vector)))
+    },
+};
+
+const LISP_VECTOR_REF: LispBuiltin = LispBuiltin {
+    name: "vector-ref",
+    doc: "(vector-ref vector index): the item at index",
+    arity: exactly_two,
+    func: |args, _env| {
+        let (Ast::Vector(items), Ast::Atom(LispAtom::Int(index))) = (&args[0], &args[1]) else {
+            return Err(LispError::TypeError);
+        };
+        usize::try_from(*index)
+            .ok()
+            .and_then(|i| items.get(i))
+            .cloned()
+            .ok_or_else(|| {
+                LispError::ValueError(format!(
+                    "index {} is out of range for length {}",
+                    index,
+                    items.len()
+                ))
+            })
+    },
+};
+
+const LISP_VECTOR_TO_LIST: LispBuiltin = LispBuiltin {
+    name: "vector->list",
+    doc: "(vector->list vector): a list of the items in a vector",
+    arity: exactly_one,
+    func: |args, _env| match &mut take_first(args)? {
+        Ast::Vector(items) => Ok(Ast::List(std::mem::take(items))),
+        _ => Err(LispError::TypeError),
+    },
+};
+
+const LISP_LIST_TO_VECTOR: LispBuiltin = LispBuiltin {
+    name: "list->vector",
+    doc: "(list->vector list): a vector of the items in a list",
+    arity: exactly_one,
+    func: |args, _env| match &mut take_first(args)? {
+        Ast::List(items) => Ok(Ast::Vector(std::mem::take(items))),
+        _ => Err(LispError::TypeError),
+    },
+};
+
+/// Insert pairs of keys and values into map.
+fn insert_pairs(map: &mut LispMap, pairs: &[Ast]) -> Result<(), LispError> {
+    for pair in pairs.chunks(2) {
+        let [key, value] = pair else {
+            return Err(LispError::BadArity);
+        };
+        map.insert(key, value.clone())?;
+    }
+    Ok(())
+}
+
+fn get_map(args: &[Ast]) -> Result<&LispMap, LispError> {
+    match get_first(args)? {
+        Ast::Map(map) => Ok(map),
+        _ => Err(LispError::TypeError),
+    }
+}
+
+const LISP_HASH_MAP: LispBuiltin = LispBuiltin {
+    name: "hash-map",
+    doc: "(hash-map key value ...): create a map from atom keys to values",
+    arity: |num_args| num_args % 2 == 0,
+    func: |args, _env| {
+        let mut map = LispMap::new();
+        insert_pairs(&mut map, &args)?;
+        Ok(Ast::Map(map))
+    },
+};
+
+const LISP_IS_HASH_MAP: LispBuiltin = LispBuiltin {
+    name: "hash-map?",
+    doc: "(hash-map? value): true if value is a map",
+    arity: exactly_one,
+    func: |args, _env| {
+        let is_map = matches!(get_first(&args)?, Ast::Map(_));
+        Ok(Ast::Atom(LispAtom::Bool(is_map)))
+    },
+};
+
+const LISP_ASSOC: LispBuiltin = LispBuiltin {
+    name: "assoc",
+    doc: "(assoc map key value ...): a copy of map with keys set to values",
+    arity: |num_args| num_args % 2 == 1,
+    func: |args, _env| {
+        let mut map = get_map(&args)?.clone();
+        insert_pairs(&mut map, &args[1..])?;
+        Ok(Ast::Map(map))
+    },
+};
+
+const LISP_DISSOC: LispBuiltin = LispBuiltin {
+    name: "dissoc",
+    doc: "(dissoc map key ...): a copy of map without keys",
+    arity: at_least_one,
+    func: |args, _env| {
+        let mut map = get_map(&args)?.clone();
+        for key in &args[1..] {
+            map.remove(key);
+        }
+        Ok(Ast::Map(map))
+    },
+};
+
+const LISP_KEYS: LispBuiltin = LispBuiltin {
+    name: "keys",
+    doc: "(keys map): a list of the keys of a map, in order",
+    arity: exactly_one,
+    func: |args, _env| {
+        let keys = get_map(&args)?.iter().map(|(key, _)| key);
+        Ok(Ast::List(keys.collect()))
+    },
+};
+
+const LISP_VALS: LispBuiltin = LispBuiltin {
+    name: "vals",
+    doc: "(vals map): a list of the values of a map, in order of their keys",
+    arity: exactly_one,
+    func: |args, _env| {
+        let values = get_map(&args)?.iter().map(|(_, value)| value.clone());
+        Ok(Ast::List(values.collect()))
+    },
+};
+
+const LISP_CONTAINS_KEY: LispBuiltin = LispBuiltin {
+    name: "contains-key?",
+    doc: "(contains-key? map key): true if map has a value for key",
+    arity: exactly_two,
+    func: |args, _env| {
+        let contains = get_map(&args)?.contains_key(&args[1]);
+        Ok(Ast::Atom(LispAtom::Bool(contains)))
+    },
+};
+
+const LISP_IS_EMPTY: LispBuiltin = LispBuiltin {
+    name: "empty?",
+    doc: "(empty? collection): true if the list, vector, hash-map, or string is empty",
+    arity: exactly_one,
+    func: |args, _env| {
+        let arg = get_first(&args)?;
+        let is_empty = match arg {
+            Ast::List(items) | Ast::Vector(items) => items.is_empty(),
+            Ast::Map(map) => map.is_empty(),
+            Ast::Atom(LispAtom::String(string)) => string.is_empty(),
+            _ => return Err(LispError::TypeError),
+        };
+
+        Ok(Ast::Atom(LispAtom::Bool(is_empty)))
+    },
+};
+
+/// Apply a function to every item of a tree of nested lists and pairs that isn't itself a list or
+/// pair, keeping the shape of the tree. A tree that isn't a list is passed to the function directly.
+const LISP_TREE_MAP: LispBuiltin = LispBuiltin {
+    name: "tree-map",
+    doc: "(tree-map f tree): apply f to every non-list item of nested lists and pairs",
+    arity: exactly_two,
+    func: |args, env| {
+        let [func, tree] = <[Ast; 2]>::try_from(args).map_err(|_| LispError::BadArity)?;
+        let Ast::Function(func) = &func else {
+            return Err(LispError::TypeError);
+        };
+        if !func.arity(1) {
+            return Err(LispError::BadArity);
+        }
+        map_leaves(&tree, |leaf| func.call(vec![leaf.clone()], env))
+    },
+};
+
+/// The number of items in a list, characters in a string, or bytes in bytes, as an Int.
+const LISP_COUNT: LispBuiltin = LispBuiltin {
+    name: "count",
+    doc: "(count collection): the number of items, entries, characters, or bytes",
+    arity: exactly_one,
+    func: |args, _env| {
+        let arg = get_first(&args)?;
+        let length = match arg {
+            Ast::List(items) | Ast::Vector(items) => items.len(),
+            Ast::Map(map) => map.len(),
+            Ast::Atom(LispAtom::String(string)) => string.chars().count(),
+            Ast::Atom(LispAtom::Bytes(bytes)) => bytes.len(),
+            _ => return Err(LispError::TypeError),
+        };
+
+        Ok(Ast::Atom(LispAtom::Int(length as i64)))
+    },
+};
+
+/// The item of a list, character of a string (as a string), or byte of bytes (as an Int) at a
+/// zero-based index.
+const LISP_NTH: LispBuiltin = LispBuiltin {
+    name: "nth",
+    doc: "(nth list-string-or-bytes index): the item, character, or byte at index",
+    arity: exactly_two,
+    func: |args, _env| {
+        let Some(Ast::Atom(LispAtom::Int(index))) = args.get(1) else {
+            return Err(LispError::TypeError);
+        };
+        let out_of_range = |length: usize| {
+            LispError::ValueError(format!(
+                "index {} is out of range for length {}",
+                index, length
+            ))
+        };
+        let position = usize::try_from(*index).ok();
+        match get_first(&args)? {
+            Ast::List(items) => position
+                .and_then(|i| items.get(i))
+                .cloned()
+                .ok_or_else(|| out_of_range(items.len())),
+            Ast::Atom(LispAtom::String(string)) => position
+                .and_then(|i| string.chars().nth(i))
+                .map(|c| Ast::Atom(LispAtom::String(c.to_string())))
+                .ok_or_else(|| out_of_range(string.chars().count())),
+            Ast::Atom(LispAtom::Bytes(bytes)) => position
+                .and_then(|i| bytes.get(i))
+                .map(|&byte| Ast::Atom(LispAtom::Int(byte.into())))
+                .ok_or_else(|| out_of_range(bytes.len())),
+            _ => Err(LispError::TypeError),
+        }
+    },
+};
+
+/// Look up a key in an association list of `(key value)` pairs. Returns the optional third
+/// argument (or unspecified) when the key is missing.
+const LISP_GET: LispBuiltin = LispBuiltin {
+    name: "get",
+    doc: "(get map-or-alist key [default]): look up key in a map or an association list, or nil",
+    arity: two_or_three,
+    func: |args, _env| {
+        let mut args = args.into_iter();
+        let collection = args.next().ok_or(LispError::BadArity)?;
+        let key = args.next().ok_or(LispError::BadArity)?;
+        let default = args.next().unwrap_or(Ast::Atom(LispAtom::Nil));
+        let pairs = match &collection {
+            Ast::List(pairs) => pairs,
+            Ast::Map(map) => return Ok(map.get(&key).cloned().unwrap_or(default)),
+            _ => return Err(LispError::TypeError),
+        };
+
+        for pair in pairs {
+            match pair {
+                Ast::List(pair) if pair.len() == 2 => {
+                    if pair[0] == key {
+                        return Ok(pair[1].clone());
+                    }
+                }
+                _ => return Err(LispError::TypeError),
+            }
+        }
+
+        Ok(default)
+    },
+};
+
+/// Returns a sorted list of the symbols referenced but not bound within an expression.
+const LISP_FREE_VARS: LispBuiltin = LispBuiltin {
+    name: "free-vars",
+    doc: "(free-vars expr): the symbols referenced but not bound in a quoted expression",
+    arity: exactly_one,
+    func: |args, _env| {
+        let vars = analysis::free_vars(get_first(&args)?);
+        Ok(Ast::List(
+            vars.into_iter()
+                .map(|var| Ast::Atom(LispAtom::Symbol(var)))
+                .collect(),
+        ))
+    },
+};
+
+/// Forces a promise created by `delay`. Anything else is returned unchanged.
+const LISP_FORCE: LispBuiltin = LispBuiltin {
+    name: "force",
+    doc: "(force promise): the value of a delayed expression, evaluating it the first time",
+    arity: exactly_one,
+    func: |args, _env| {
+        let value = take_first(args)?;
+        match &value {
+            Ast::Promise(promise) => promise.force(),
+            _ => Ok(value),
+        }
+    },
+};
+
+const LISP_ATOM: LispBuiltin = LispBuiltin {
+    name: "atom",
+    doc: "(atom value): a mutable cell holding value",
+    arity: exactly_one,
+    func: |args, _env| Ok(Ast::Cell(Rc::new(RefCell::new(take_first(args)?)))),
+};
+
+fn get_cell(arg: &Ast) -> Result<&Rc<RefCell<Ast>>, LispError> {
+    match arg {
+        Ast::Cell(cell) => Ok(cell),
+        _ => Err(LispError::TypeError),
+    }
+}
+
+const LISP_DEREF: LispBuiltin = LispBuiltin {
+    name: "deref",
+    doc: "(deref atom): the value in an atom",
+    arity: exactly_one,
+    func: |args, _env| Ok(get_cell(get_first(&args)?)?.borrow().clone()),
+};
+
+const LISP_RESET: LispBuiltin = LispBuiltin {
+    name: "reset!",
+    doc: "(reset! atom value): replace the value in an atom, returning the new value",
+    arity: exactly_two,
+    func: |args, _env| {
+        let [cell, value] = <[Ast; 2]>::try_from(args).map_err(|_| LispError::BadArity)?;
+        *get_cell(&cell)?.borrow_mut() = value.clone();
+        Ok(value)
+    },
+};
+
+/// Replaces the value in an atom with the result of calling a function on it. If the function
+/// fails, the atom keeps its old value.
+const LISP_SWAP: LispBuiltin = LispBuiltin {
+    name: "swap!",
+    doc: "(swap! atom f arg ...): set an atom to (f value arg ...), returning the new value",
+    arity: at_least_two,
+    func: |args, env| {
+        let mut args = args.into_iter();
+        let cell = args.next().ok_or(LispError::BadArity)?;
+        let func = args.next().ok_or(LispError::BadArity)?;
+        let (cell, Ast::Function(func)) = (get_cell(&cell)?, &func) else {
+            return Err(LispError::TypeError);
+        };
+
+        // The value is copied out so the function can use the atom itself
+        let mut call_args = vec![cell.borrow().clone()];
+        call_args.extend(args);
+        if !func.arity(call_args.len()) {
+            return Err(LispError::BadArity);
+        }
+        let value = func.call(call_args, env)?;
+        *cell.borrow_mut() = value.clone();
+        Ok(value)
+    },
+};
+
+const LISP_MACROEXPAND: LispBuiltin = LispBuiltin {
+    name: "macroexpand",
+    doc: "(macroexpand expr): a quoted expression with its outer form rewritten until it no longer expands",
+    arity: exactly_one,
+    func: |args, _env| special_forms::expand(take_first(args)?),
+};
+
+const LISP_MACROEXPAND_ALL: LispBuiltin = LispBuiltin {
+    name: "macroexpand-all",
+    doc: "(macroexpand-all expr): a quoted expression with every form inside it expanded",
+    arity: exactly_one,
+    func: |args, _env| special_forms::expand_all(take_first(args)?),
+};
+
+/// Number of list elements whose types are shown by `inspect`.
+const INSPECT_ELEMENTS: usize = 5;
+
+/// Build the multi-line description of a value printed by `inspect`.
+fn inspect_string(value: &Ast) -> String {
+    let mut lines = vec![
+        format!("value: {}", value),
+        format!("type: {}", LispType::from(value)),
+    ];
+
+    match value {
+        Ast::List(items) => {
+            lines.push(format!("length: {}", items.len()));
+            if !items.is_empty() {
+                let mut types: Vec<_> = items
+                    .iter()
+                    .take(INSPECT_ELEMENTS)
+                    .map(|item| LispType::from(item).to_string())
+                    .collect();
+                if items.len() > INSPECT_ELEMENTS {
+                    types.push("...".to_string());
+                }
+                lines.push(format!("element types: {}", types.join(", ")));
+            }
+        }
+        Ast::Atom(LispAtom::String(string)) => {
+            lines.push(format!("chars: {}", string.chars().count()));
+            lines.push(format!("bytes: {}", string.len()));
+        }
+        Ast::Function(func) => {
+            lines.push(format!("function: {}", func.describe()));
+            lines.push(format!("arity: {}", arity_string(&**func)));
+        }
+        _ => (),
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// Describe the numbers of arguments a function accepts, like `2`, `1 or 2`, or `1 or more`.
+/// Arities are only known by asking, so counts past a few arguments are assumed to carry on the
+/// same way.
+fn arity_string(func: &dyn LispCallable) -> String {
+    const CHECKED: usize = 8;
+    let accepted: Vec<_> = (0..=CHECKED).filter(|&n| func.arity(n)).collect();
+    match accepted.as_slice() {
+        [] => "none".to_string(),
+        [.., last] if *last == CHECKED && accepted.len() == CHECKED + 1 - accepted[0] => {
+            format!("{} or more", accepted[0])
+        }
+        [.., last] if *last == CHECKED => {
+            let counts: Vec<_> = accepted.iter().map(usize::to_string).collect();
+            format!("{}, ...", counts.join(", "))
+        }
+        [only] => only.to_string(),
+        [rest @ .., last] => {
+            let counts: Vec<_> = rest.iter().map(usize::to_string).collect();
+            format!("{} or {}", counts.join(", "), last)
+        }
+    }
+}
+
+/// Format bytes as lines of offset, hex, and ASCII columns.
+fn hexdump_string(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<_> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        dump += &format!("{:08x}  {:<47}  |{}|\n", line * 16, hex.join(" "), ascii);
+    }
+    dump
+}
+
+/// Print a structural description of a value to the error output and return the value unchanged.
+const LISP_INSPECT: LispBuiltin = LispBuiltin {
+    name: "inspect",
+    doc: "(inspect value): print a description of value to stderr and return it",
+    arity: exactly_one,
+    func: |args, env| {
+        let value = take_first(args)?;
+        env.write_error_output(&inspect_string(&value))?;
+        Ok(value)
+    },
+};
+
+/// The usage string of a function, or unspecified for anything without one.
+fn doc_string(value: &Ast) -> Option<String> {
+    match value {
+        Ast::Function(func) => func.doc(),
+        _ => None,
+    }
+}
+
+const LISP_DOC: LispBuiltin = LispBuiltin {
+    name: "doc",
+    doc: "(doc f): the usage string of a function",
+    arity: exactly_one,
+    func: |args, _env| Ok(doc_string(get_first(&args)?).map_or(Ast::Unspecified, Ast::from)),
+};
+
+const LISP_HELP: LispBuiltin = LispBuiltin {
+    name: "help",
+    doc: "(help f): print the usage string of a function",
+    arity: exactly_one,
+    func: |args, env| {
+        let value = get_first(&args)?;
+        match doc_string(value) {
+            Some(doc) => env.write_output(&format!("{}\n", doc))?,
+            None => env.write_output(&format!("no documentation for {}\n", value))?,
+        }
+        Ok(Ast::Unspecified)
+    },
+};
+
+/// Print the bytes of a string or bytes as a hex dump.
+const LISP_HEXDUMP: LispBuiltin = LispBuiltin {
+    name: "hexdump",
+    doc: "(hexdump string-or-bytes): print the bytes of a string or bytes",
+    arity: exactly_one,
+    func: |args, env| {
+        let bytes = take_first(args).and_then(ast_to_bytes)?;
+        env.write_output(&hexdump_string(&bytes))?;
+        Ok(Ast::Unspecified)
+    },
+};
+
+/// Get the bytes of bytes, or the UTF-8 encoding of a string.
+fn ast_to_bytes(mut ast: Ast) -> Result<Vec<u8>, LispError> {
+    match &mut ast {
+        Ast::Atom(LispAtom::Bytes(bytes)) => Ok(std::mem::take(bytes)),
+        Ast::Atom(LispAtom::String(string)) => Ok(std::mem::take(string).into_bytes()),
+        _ => Err(LispError::TypeError),
+    }
+}
+
+const LISP_BYTES_TO_STRING: LispBuiltin = LispBuiltin {
+    name: "bytes->string",
+    doc: "(bytes->string bytes): decode UTF-8 bytes into a string",
+    arity: exactly_one,
+    func: |args, _env| {
+        let Some(Ast::Atom(LispAtom::Bytes(bytes))) = args.first() else {
+            return Err(LispError::TypeError);
+        };
+        match std::str::from_utf8(bytes) {
+            Ok(string) => Ok(Ast::Atom(LispAtom::String(string.to_string()))),
+            Err(e) => Err(LispError::ValueError(format!(
+                "invalid UTF-8 at byte {}",
+                e.valid_up_to()
+            ))),
+        }
+    },
+};
+
+const LISP_STRING_TO_BYTES: LispBuiltin = LispBuiltin {
+    name: "string->bytes",
+    doc: "(string->bytes string): encode a string as UTF-8 bytes",
+    arity: exactly_one,
+    func: |args, _env| {
+        let string = take_first(args).and_then(ast_to_string)?;
+        Ok(Ast::Atom(LispAtom::Bytes(string.into_bytes())))
+    },
+};
+
+const LISP_IS_CHAR: LispBuiltin = LispBuiltin {
+    name: "char?",
+    doc: "(char? x): true if x is a character",
+    arity: exactly_one,
+    func: |args, _env| {
+        let is_char = matches!(args.first(), Some(Ast::Atom(LispAtom::Char(_))));
+        Ok(Ast::Atom(LispAtom::Bool(is_char)))
+    },
+};
+
+const LISP_CHAR_CODE: LispBuiltin = LispBuiltin {
+    name: "char-code",
+    doc: "(char-code char): the unicode code point of a character",
+    arity: exactly_one,
+    func: |args, _env| {
+        let Some(Ast::Atom(LispAtom::Char(c))) = args.first() else {
+            return Err(LispError::TypeError);
+        };
+        Ok(Ast::Atom(LispAtom::Int(*c as i64)))
+    },
+};
+
+const LISP_CODE_CHAR: LispBuiltin = LispBuiltin {
+    name: "code-char",
+    doc: "(code-char code): the character with a unicode code point",
+    arity: exactly_one,
+    func: |args, _env| {
+        let Some(Ast::Atom(LispAtom::Int(code))) = args.first() else {
+            return Err(LispError::TypeError);
+        };
+        u32::try_from(*code)
+            .ok()
+            .and_then(char::from_u32)
+            .map(|c| Ast::Atom(LispAtom::Char(c)))
+            .ok_or_else(|| LispError::ValueError(format!("{} is not a unicode code point", code)))
+    },
+};
+
+const LISP_STRING_TO_LIST: LispBuiltin = LispBuiltin {
+    name: "string->list",
+    doc: "(string->list string): a list of the characters in a string",
+    arity: exactly_one,
+    func: |args, _env| {
+        let string = take_first(args).and_then(ast_to_string)?;
+        let chars = string.chars().map(|c| Ast::Atom(LispAtom::Char(c)));
+        Ok(Ast::List(chars.collect()))
+    },
+};
+
+const LISP_LIST_TO_STRING: LispBuiltin = LispBuiltin {
+    name: "list->string",
+    doc: "(list->string chars): a string made of a list of characters",
+    arity: exactly_one,
+    func: |args, _env| {
+        let Some(Ast::List(chars)) = args.first() else {
+            return Err(LispError::TypeError);
+        };
+        chars
+            .iter()
+            .map(|c| match c {
+                Ast::Atom(LispAtom::Char(c)) => Ok(*c),
+                _ => Err(LispError::TypeError),
+            })
+            .collect::<Result<String, _>>()
+            .map(|string| Ast::Atom(LispAtom::String(string)))
+    },
+};
+
+/// Returns true if a builtin with the given name is available in this environment.
+const LISP_HAS_BUILTIN: LispBuiltin = LispBuiltin {
+    name: "has-builtin?",
+    doc: "(has-builtin? name): true if a builtin is available in this build",
+    arity: exactly_one,
+    func: |args, env| {
+        let name = take_first(args).and_then(ast_to_name)?;
+        Ok(Ast::Atom(LispAtom::Bool(env.has_builtin(&name))))
+    },
+};
+
+const LISP_GET_TYPE: LispBuiltin = LispBuiltin {
+    name: "type",
+    doc: "(type value): the type of value",
+    arity: exactly_one,
+    func: |args, _env| {
+        let arg = get_first(&args)?;
+        Ok(Ast::Type(LispType::from(arg)))
+    },
+};
+
+const LISP_NAME: LispBuiltin = LispBuiltin {
+    name: "name",
+    doc: "(name value): the name of a string, symbol, or keyword",
+    arity: exactly_one,
+    func: |args, _env| {
+        let name = take_first(args).and_then(ast_to_name)?;
+        Ok(Ast::Atom(LispAtom::String(name)))
+    },
+};
+
+const LISP_SYMBOL: LispBuiltin = LispBuiltin {
+    name: "symbol",
+    doc: "(symbol name): the symbol with the given name",
+    arity: exactly_one,
+    func: |args, _env| {
+        let name = take_first(args).and_then(ast_to_name)?;
+        if !parser::is_valid_symbol(&name) {
+            return Err(LispError::ValueError(format!(
+                "\"{}\" is not a valid symbol name",
+                name
+            )));
+        }
+        Ok(Ast::Atom(LispAtom::Symbol(name)))
+    },
+};
+
+/// Make a fresh symbol, such as `G__1`, for generated code. An optional string or symbol replaces
+/// the `G__` prefix.
+const LISP_GENSYM: LispBuiltin = LispBuiltin {
+    name: "gensym",
+    doc: "(gensym [prefix]): a new symbol that is different every time",
+    arity: one_or_zero,
+    func: |args, env| {
+        let prefix = match args.into_iter().next() {
+            Some(prefix) => ast_to_name(prefix)?,
+            None => "G__".to_string(),
+        };
+        let mut name = format!("{}{}", prefix, env.next_gensym());
+        // Generated symbols have to read back as themselves, even when symbols are being folded
+        if env.fold_case() {
+            name = name.to_lowercase();
+        }
+        if !parser::is_valid_symbol(&name) {
+            return Err(LispError::ValueError(format!(
+                "\"{}\" is not a valid symbol prefix",
+                prefix
+            )));
+        }
+        Ok(Ast::Atom(LispAtom::Symbol(name)))
+    },
+};
+
+const LISP_KEYWORD: LispBuiltin = LispBuiltin {
+    name: "keyword",
+    doc: "(keyword name): the keyword with the given name",
+    arity: exactly_one,
+    func: |args, _env| make_keyword(take_first(args).and_then(ast_to_name)?),
+};
+
+/// The keyword with the given name, if the parser would read `:name` back as the same keyword.
+fn make_keyword(name: String) -> Result<Ast, LispError> {
+    if name.is_empty() || !name.chars().all(parser::is_symbol_character) {
+        return Err(LispError::ValueError(format!(
+            "\"{}\" is not a valid keyword name",
+            name
+        )));
+    }
+    Ok(Ast::Atom(LispAtom::Keyword(name)))
+}
+
+const LISP_IS_NIL: LispBuiltin = LispBuiltin {
+    name: "nil?",
+    doc: "(nil? value): true if value is nil",
+    arity: exactly_one,
+    func: |args, _env| {
+        let is_nil = matches!(get_first(&args)?, Ast::Atom(LispAtom::Nil));
+        Ok(Ast::Atom(LispAtom::Bool(is_nil)))
+    },
+};
+
+const LISP_IS_KEYWORD: LispBuiltin = LispBuiltin {
+    name: "keyword?",
+    doc: "(keyword? value): true if value is a keyword",
+    arity: exactly_one,
+    func: |args, _env| {
+        let is_keyword = matches!(get_first(&args)?, Ast::Atom(LispAtom::Keyword(_)));
+        Ok(Ast::Atom(LispAtom::Bool(is_keyword)))
+    },
+};
+
+const LISP_KEYWORD_TO_STRING: LispBuiltin = LispBuiltin {
+    name: "keyword->string",
+    doc: "(keyword->string keyword): the name of a keyword, without the colon",
+    arity: exactly_one,
+    func: |args, _env| match get_first(&args)? {
+        Ast::Atom(LispAtom::Keyword(name)) => Ok(Ast::Atom(LispAtom::String(name.clone()))),
+        _ => Err(LispError::TypeError),
+    },
+};
+
+const LISP_STRING_TO_KEYWORD: LispBuiltin = LispBuiltin {
+    name: "string->keyword",
+    doc: "(string->keyword string): the keyword with a string as its name",
+    arity: exactly_one,
+    func: |args, _env| make_keyword(take_first(args).and_then(ast_to_string)?),
+};
+
+fn ast_to_type(ast: &Ast) -> Result<LispType, LispError> {
+    match ast {
+        Ast::Type(typ) => Ok(*typ),
+        _ => Err(LispError::TypeError),
+    }
+}
+
+const LISP_TYPE_NAME: LispBuiltin = LispBuiltin {
+    name: "type-name",
+    doc: "(type-name type): the name of a type, such as \"int\"",
+    arity: exactly_one,
+    func: |args, _env| {
+        let typ = get_first(&args).and_then(ast_to_type)?;
+        Ok(Ast::Atom(LispAtom::String(typ.name().to_string())))
+    },
+};
+
+const LISP_TYPE_OF_NAME: LispBuiltin = LispBuiltin {
+    name: "type-of-name",
+    doc: "(type-of-name name): the type with the given name",
+    arity: exactly_one,
+    func: |args, _env| {
+        let name = take_first(args).and_then(ast_to_name)?;
+        LispType::from_name(&name)
+            .map(Ast::Type)
+            .ok_or_else(|| LispError::ValueError(format!("unknown type name \"{}\"", name)))
+    },
+};
+
+const LISP_IS_SUBTYPE: LispBuiltin = LispBuiltin {
+    name: "subtype?",
+    doc: "(subtype? type other): true if type is a subtype of other",
+    arity: exactly_two,
+    func: |args, _env| {
+        let sub = ast_to_type(&args[0])?;
+        let sup = ast_to_type(&args[1])?;
+        Ok(Ast::Atom(LispAtom::Bool(sub.is_subtype(&sup))))
+    },
+};
+
+/// Returns true if the type of the only argument is a subtype of typ.
+fn has_type(args: &[Ast], typ: LispType) -> Result<Ast, LispError> {
+    let arg_type = LispType::from(get_first(args)?);
+    Ok(Ast::Atom(LispAtom::Bool(arg_type.is_subtype(&typ))))
+}
+
+const LISP_IS_INT: LispBuiltin = LispBuiltin {
+    name: "int?",
+    doc: "(int? value): true if value is an int",
+    arity: exactly_one,
+    func: |args, _env| has_type(&args, LispType::Int),
+};
+
+const LISP_IS_FLOAT: LispBuiltin = LispBuiltin {
+    name: "float?",
+    doc: "(float? value): true if value is a float",
+    arity: exactly_one,
+    func: |args, _env| has_type(&args, LispType::Float),
+};
+
+const LISP_IS_NUMBER: LispBuiltin = LispBuiltin {
+    name: "number?",
+    doc: "(number? value): true if value is a number",
+    arity: exactly_one,
+    func: |args, _env| has_type(&args, LispType::Number),
+};
+
+const LISP_IS_STRING: LispBuiltin = LispBuiltin {
+    name: "string?",
+    doc: "(string? value): true if value is a string",
+    arity: exactly_one,
+    func: |args, _env| has_type(&args, LispType::String),
+};
+
+const LISP_IS_BOOL: LispBuiltin = LispBuiltin {
+    name: "bool?",
+    doc: "(bool? value): true if value is a boolean",
+    arity: exactly_one,
+    func: |args, _env| has_type(&args, LispType::Bool),
+};
+
+const LISP_IS_SYMBOL: LispBuiltin = LispBuiltin {
+    name: "symbol?",
+    doc: "(symbol? value): true if value is a symbol",
+    arity: exactly_one,
+    func: |args, _env| has_type(&args, LispType::Symbol),
+};
+
+const LISP_IS_FUNCTION: LispBuiltin = LispBuiltin {
+    name: "function?",
+    doc: "(function? value): true if value is a function",
+    arity: exactly_one,
+    func: |args, _env| has_type(&args, LispType::Function),
+};
+
+/// Apply a test to a float, or return the given answer for an exact number (which is always
+/// finite).
+fn float_test(args: &[Ast], test: fn(f64) -> bool, exact: bool) -> Result<Ast, LispError> {
+    let result = match get_first(args)? {
+        Ast::Atom(LispAtom::Float(num)) => test(*num),
+        Ast::Atom(LispAtom::Int(_) | LispAtom::BigInt(_) | LispAtom::Rational(..)) => exact,
+        _ => return Err(LispError::TypeError),
+    };
+    Ok(Ast::Atom(LispAtom::Bool(result)))
+}
+
+const LISP_IS_NAN: LispBuiltin = LispBuiltin {
+    name: "nan?",
+    doc: "(nan? num): true if num is NaN",
+    arity: exactly_one,
+    func: |args, _env| float_test(&args, f64::is_nan, false),
+};
+
+const LISP_IS_INFINITE: LispBuiltin = LispBuiltin {
+    name: "infinite?",
+    doc: "(infinite? num): true if num is infinite",
+    arity: exactly_one,
+    func: |args, _env| float_test(&args, f64::is_infinite, false),
+};
+
+const LISP_IS_FINITE: LispBuiltin = LispBuiltin {
+    name: "finite?",
+    doc: "(finite? num): true if num is neither infinite nor NaN",
+    arity: exactly_one,
+    func: |args, _env| float_test(&args, f64::is_finite, true),
+};
+
+/// Returns true for exact numbers, which are Rationals and Ints.
+const LISP_IS_RATIONAL: LispBuiltin = LispBuiltin {
+    name: "rational?",
+    doc: "(rational? value): true if value is exact (a rational or an int)",
+    arity: exactly_one,
+    func: |args, _env| {
+        let arg = get_first(&args)?;
+        Ok(Ast::Atom(LispAtom::Bool(ast_to_big_ratio(arg).is_some())))
+    },
+};
+
+const LISP_NUMERATOR: LispBuiltin = LispBuiltin {
+    name: "numerator",
+    doc: "(numerator num): the numerator of a rational in lowest terms",
+    arity: exactly_one,
+    func: |args, _env| {
+        let (num, _) = ast_to_big_ratio(get_first(&args)?).ok_or(LispError::TypeError)?;
+        Ok(Ast::from(num))
+    },
+};
+
+const LISP_DENOMINATOR: LispBuiltin = LispBuiltin {
+    name: "denominator",
+    doc: "(denominator num): the denominator of a rational in lowest terms",
+    arity: exactly_one,
+    func: |args, _env| {
+        let (_, den) = ast_to_big_ratio(get_first(&args)?).ok_or(LispError::TypeError)?;
+        Ok(Ast::from(den))
+    },
+};
+
+/// Render a template string, replacing `<%= expr %>` tags with the value of expr and evaluating
+/// `<% expr... %>` tags for their side effects.
+const LISP_RENDER_TEMPLATE: LispBuiltin = LispBuiltin {
+    name: "render-template",
+    doc: "(render-template template): render a template with embedded expressions",
+    arity: exactly_one,
+    func: |args, env| {
+        let template = take_first(args).and_then(ast_to_string)?;
+        template::render(&template, env).map(|output| Ast::Atom(LispAtom::String(output)))
+    },
+};
+
+/// Turn case folding of symbols on or off for the current environment. Only affects expressions
+/// read afterwards, so in a file it has no effect on the rest of the file.
+const LISP_SET_FOLD_CASE: LispBuiltin = LispBuiltin {
+    name: "set-fold-case!",
+    doc: "(set-fold-case! bool): convert symbols read afterwards to lowercase",
+    arity: exactly_one,
+    func: |args, env| match take_first(args)? {
+        Ast::Atom(LispAtom::Bool(fold_case)) => {
+            env.set_fold_case(fold_case);
+            Ok(Ast::Unspecified)
+        }
+        _ => Err(LispError::TypeError),
+    },
+};
+
+const LISP_DO: LispBuiltin = LispBuiltin {
+    name: "do",
+    doc: "(do value ...): the last value",
+    arity: at_least_one,
+    func: |args, _env| args.into_iter().last().ok_or(LispError::BadArity),
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{LispLambda, Params};
+    use crate::eval::{eval_captured, eval_str};
+
+    fn int(num: i64) -> Ast {
+        Ast::Atom(LispAtom::Int(num))
+    }
+
+    #[test]
+    fn rounding_floats() {
+        assert_eq!(eval_str("(floor 1.7)").unwrap(), int(1));
+        assert_eq!(eval_str("(floor -1.2)").unwrap(), int(-2));
+        assert_eq!(eval_str("(ceil 1.2)").unwrap(), int(2));
+        assert_eq!(eval_str("(ceil -1.7)").unwrap(), int(-1));
+        assert_eq!(eval_str("(truncate 1.7)").unwrap(), int(1));
+        assert_eq!(eval_str("(truncate -1.7)").unwrap(), int(-1));
+    }
+
+    #[test]
+    fn round_halfway_away_from_zero() {
+        assert_eq!(eval_str("(round 0.5)").unwrap(), int(1));
+        assert_eq!(eval_str("(round 1.5)").unwrap(), int(2));
+        assert_eq!(eval_str("(round -0.5)").unwrap(), int(-1));
+        assert_eq!(eval_str("(round 2.4)").unwrap(), int(2));
+    }
+
+    #[test]
+    fn rounding_ints_unchanged() {
+        for func in ["floor", "ceil", "round", "truncate"] {
+            assert_eq!(eval_str(&format!("({} -7)", func)).unwrap(), int(-7));
+        }
+    }
+
+    #[test]
+    fn rounding_out_of_range() {
+        assert!(matches!(
+            eval_str("(floor 1e300)"),
+            Err(LispError::ValueError(_))
+        ));
+        assert!(matches!(
+            eval_str("(round -1e19)"),
+            Err(LispError::ValueError(_))
+        ));
+        assert!(matches!(
+            eval_str("(ceil \"1\")"),
+            Err(LispError::TypeError)
+        ));
+    }
+
+    #[test]
+    fn name_conversions() {
+        let string = |s: &str| Ast::Atom(LispAtom::String(s.to_string()));
+        let symbol = |s: &str| Ast::Atom(LispAtom::Symbol(s.to_string()));
+        let keyword = |s: &str| Ast::Atom(LispAtom::Keyword(s.to_string()));
+
+        let sources = ["\"abc\"", "(quote abc)", "(keyword \"abc\")"];
+        for source in sources {
+            let name = eval_str(&format!("(name {})", source)).unwrap();
+            assert_eq!(name, string("abc"));
+            let sym = eval_str(&format!("(symbol {})", source)).unwrap();
+            assert_eq!(sym, symbol("abc"));
+            let kw = eval_str(&format!("(keyword {})", source)).unwrap();
+            assert_eq!(kw, keyword("abc"));
+        }
+
+        assert_eq!(keyword("abc").to_string(), ":abc");
+    }
+
+    #[test]
+    fn invalid_symbol_names() {
+        for name in ["\"\"", "\"a b\"", "\"12\"", "\"(x\"", "\"true\""] {
+            assert!(matches!(
+                eval_str(&format!("(symbol {})", name)),
+                Err(LispError::ValueError(_))
+            ));
+        }
+        assert!(matches!(
+            eval_str("(keyword \"\")"),
+            Err(LispError::ValueError(_))
+        ));
+        assert!(matches!(eval_str("(name 1)"), Err(LispError::TypeError)));
+    }
+
+    fn float(num: f64) -> Ast {
+        Ast::Atom(LispAtom::Float(num))
+    }
+
+    fn big(num: &str) -> Ast {
+        Ast::Atom(LispAtom::BigInt(num.parse().unwrap()))
+    }
+
+    fn ratio(num: i64, den: i64) -> Ast {
+        Ast::Atom(LispAtom::Rational(num, den))
+    }
+
+    #[test]
+    fn sqrt_exp_log() {
+        assert_eq!(eval_str("(sqrt 16)").unwrap(), float(4.0));
+        assert_eq!(eval_str("(exp 0)").unwrap(), float(1.0));
+        assert_eq!(eval_str("(log 1)").unwrap(), float(0.0));
+        assert_eq!(eval_str("(log 8 2)").unwrap(), float(3.0));
+        assert!(matches!(
+            eval_str("(sqrt -1)"),
+            Err(LispError::ValueError(_))
+        ));
+        assert!(matches!(
+            eval_str("(log -1)"),
+            Err(LispError::ValueError(_))
+        ));
+        assert!(matches!(eval_str("(log 0)"), Err(LispError::ValueError(_))));
+        assert!(matches!(
+            eval_str("(log 8 1)"),
+            Err(LispError::ValueError(_))
+        ));
+    }
+
+    #[test]
+    fn pow_int_fast_path() {
+        assert_eq!(eval_str("(pow 2 10)").unwrap(), int(1024));
+        assert_eq!(eval_str("(pow -3 3)").unwrap(), int(-27));
+        assert_eq!(eval_str("(pow 5 0)").unwrap(), int(1));
+        assert_eq!(eval_str("(pow 2 -1)").unwrap(), ratio(1, 2));
+        assert_eq!(eval_str("(pow 2.0 3)").unwrap(), float(8.0));
+    }
+
+    #[test]
+    fn pow_big() {
+        assert_eq!(eval_str("(pow 2 63)").unwrap(), big("9223372036854775808"));
+        assert_eq!(
+            eval_str("(pow -3 41)").unwrap(),
+            big("-36472996377170786403")
+        );
+        assert_eq!(eval_str("(pow -2 63)").unwrap(), int(i64::MIN));
+        assert_eq!(eval_str("(pow 1 4000000000)").unwrap(), int(1));
+        assert!(matches!(
+            eval_str("(pow 2 10000000000)"),
+            Err(LispError::Overflow(_))
+        ));
+        assert!(matches!(
+            eval_str("(pow 3 4000000000)"),
+            Err(LispError::Overflow(_))
+        ));
+        assert_eq!(
+            eval_str("(pow 3/2 100)").unwrap(),
+            float(4.065611775352152e17)
+        );
+        // 0, 1, and -1 can be raised to any power
+        assert_eq!(eval_str("(pow 1 10000000000)").unwrap(), int(1));
+        assert_eq!(eval_str("(pow -1 10000000001)").unwrap(), int(-1));
+        assert_eq!(eval_str("(pow -1 -10000000000)").unwrap(), int(1));
+        assert_eq!(eval_str("(pow 0 10000000000)").unwrap(), int(0));
+        assert_eq!(
+            eval_str("(pow 2 -100)").unwrap(),
+            float(7.888609052210118e-31)
+        );
+        assert_eq!(eval_str("(pow 2 -62)").unwrap(), ratio(1, 1 << 62));
+    }
+
+    #[test]
+    fn big_fractions() {
+        let fact = "(def fact (lambda (n) (if (< n 2) 1 (* n (fact (- n 1))))))";
+        let eval = |expr: &str| eval_str(&format!("{} {}", fact, expr)).unwrap();
+        assert_eq!(eval("(/ (* 2 (fact 25)) (* 4 (fact 25)))"), ratio(1, 2));
+        assert_eq!(eval("(/ (fact 25) (fact 23))"), int(600));
+        assert_eq!(eval("(/ (fact 25) (- (fact 23)))"), int(-600));
+        assert_eq!(eval("(/ 1 (fact 25))"), float(6.446950284384474e-26));
+        assert_eq!(eval("(/ 10 (fact 25))"), float(6.446950284384473e-25));
+        assert_eq!(eval("(pow (fact 25) -1)"), float(6.446950284384474e-26));
+        assert_eq!(eval("(+ 1/3 (fact 25))"), float(1.5511210043330986e25));
+        // Within one call the arithmetic stays exact
+        assert_eq!(eval("(+ 1/3 (fact 25) (- (fact 25)))"), ratio(1, 3));
+        assert_eq!(eval("(/ (* 2 (fact 25)) 3 (fact 25))"), ratio(2, 3));
+    }
+
+    #[test]
+    fn eval_defaults_to_global_scope() {
+        let program = "((lambda () (eval (quote (def x 1))))) x";
+        assert_eq!(eval_str(program).unwrap(), int(1));
+
+        let program = "((lambda () (eval (quote (def x 1)) (keyword \"global\")))) x";
+        assert_eq!(eval_str(program).unwrap(), int(1));
+    }
+
+    #[test]
+    fn eval_local_scope() {
+        let program = "((lambda () (eval (quote (def x 1)) (keyword \"local\")))) x";
+        assert!(matches!(eval_str(program), Err(LispError::Undefined(_))));
+
+        let program = "((lambda (y) (eval (quote y) (keyword \"local\"))) 5)";
+        assert_eq!(eval_str(program).unwrap(), int(5));
+    }
+
+    #[test]
+    fn use_is_global() {
+        let path = std::env::temp_dir().join("callisp_use_is_global.lisp");
+        std::fs::write(&path, "(def used 42)").unwrap();
+        let program = format!("((lambda () (use \"{}\"))) used", path.to_str().unwrap());
+        let result = eval_str(&program);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap(), int(42));
+    }
+
+    #[test]
+    fn trig_functions() {
+        assert_eq!(eval_str("(sin 0)").unwrap(), float(0.0));
+        assert_eq!(eval_str("(cos 0)").unwrap(), float(1.0));
+        assert_eq!(eval_str("(tan 0)").unwrap(), float(0.0));
+        assert_eq!(
+            eval_str("(asin 1)").unwrap(),
+            float(std::f64::consts::FRAC_PI_2)
+        );
+        assert_eq!(eval_str("(acos 1)").unwrap(), float(0.0));
+        assert_eq!(
+            eval_str("(atan 1)").unwrap(),
+            float(std::f64::consts::FRAC_PI_4)
+        );
+        assert_eq!(
+            eval_str("(atan2 1 -1)").unwrap(),
+            float(3.0 * std::f64::consts::FRAC_PI_4)
+        );
+        assert!(matches!(
+            eval_str("(asin 1.5)"),
+            Err(LispError::ValueError(_))
+        ));
+        assert!(matches!(
+            eval_str("(acos -2)"),
+            Err(LispError::ValueError(_))
+        ));
+    }
+
+    #[test]
+    fn math_constants() {
+        assert_eq!(eval_str("pi").unwrap(), float(std::f64::consts::PI));
+        assert_eq!(eval_str("e").unwrap(), float(std::f64::consts::E));
+        assert_eq!(eval_str("(def pi 3) pi").unwrap(), int(3));
+    }
+
+    #[test]
+    fn independent_environments() {
+        let program = "
+            (def a (make-env))
+            (def b (make-env))
+            (env-bind! a (quote x) 1)
+            (env-bind! b (quote x) 2)
+            (list (eval (quote x) a) (eval (quote x) b))";
+        assert_eq!(eval_str(program).unwrap(), Ast::List(vec![int(1), int(2)]));
+        assert!(matches!(
+            eval_str("(eval (quote x) (make-env))"),
+            Err(LispError::Undefined(_))
+        ));
+    }
+
+    #[test]
+    fn env_bind_visible_to_later_evals() {
+        let program = "
+            (def e (make-env))
+            (eval (quote (def y 10)) e)
+            (env-bind! e (quote y) (+ (eval (quote y) e) 1))
+            (eval (quote (+ y 1)) e)";
+        assert_eq!(eval_str(program).unwrap(), int(12));
+    }
+
+    #[test]
+    fn current_env_shares_bindings() {
+        let program = "
+            (def here (current-env))
+            (env-bind! here (quote z) 3)
+            z";
+        assert_eq!(eval_str(program).unwrap(), int(3));
+
+        let program = "((lambda (v) (eval (quote v) (current-env))) 7)";
+        assert_eq!(eval_str(program).unwrap(), int(7));
+    }
+
+    #[test]
+    fn env_equality_and_display() {
+        assert_eq!(
+            eval_str("(def e (make-env)) (equal? e e)").unwrap(),
+            Ast::Atom(LispAtom::Bool(true))
+        );
+        assert_eq!(
+            eval_str("(equal? (make-env) (make-env))").unwrap(),
+            Ast::Atom(LispAtom::Bool(false))
+        );
+        assert_eq!(
+            eval_str("(equal? (current-env) (current-env))").unwrap(),
+            Ast::Atom(LispAtom::Bool(true))
+        );
+        assert_eq!(
+            eval_str("(def here (current-env)) ((lambda () (equal? here (current-env))))").unwrap(),
+            Ast::Atom(LispAtom::Bool(false))
+        );
+        assert_eq!(eval_str("(make-env)").unwrap().to_string(), "#env");
+    }
+
+    #[test]
+    fn nested_eval_in_same_env_errors() {
+        let program = "
+            (def e (make-env))
+            (env-bind! e (quote self) e)
+            (eval (quote (eval 1 self)) e)";
+        assert!(matches!(eval_str(program), Err(LispError::ValueError(_))));
+    }
+
+    #[test]
+    fn gcd_and_lcm() {
+        assert_eq!(eval_str("(gcd 12 18)").unwrap(), int(6));
+        assert_eq!(eval_str("(gcd 12 18 8)").unwrap(), int(2));
+        assert_eq!(eval_str("(gcd -12 18)").unwrap(), int(6));
+        assert_eq!(eval_str("(gcd 0 0)").unwrap(), int(0));
+        assert_eq!(eval_str("(gcd 0 -5)").unwrap(), int(5));
+        assert_eq!(eval_str("(lcm 4 6)").unwrap(), int(12));
+        assert_eq!(eval_str("(lcm 4 6 10)").unwrap(), int(60));
+        assert_eq!(eval_str("(lcm -4 6)").unwrap(), int(12));
+        assert_eq!(eval_str("(lcm 4 0 6)").unwrap(), int(0));
+    }
+
+    #[test]
+    fn gcd_and_lcm_errors() {
+        assert_eq!(
+            eval_str("(lcm 9223372036854775807 2)").unwrap(),
+            big("18446744073709551614")
+        );
+        assert!(matches!(eval_str("(gcd 4 2.0)"), Err(LispError::TypeError)));
+        assert!(matches!(eval_str("(lcm 4.0 2)"), Err(LispError::TypeError)));
+    }
+
+    #[test]
+    fn random_is_deterministic_with_seed() {
+        let program = "(random-seed 42) (list (random 100) (random 100) (random 100) (random 100))";
+        let expected = Ast::List(vec![int(19), int(56), int(48), int(27)]);
+        assert_eq!(eval_str(program).unwrap(), expected);
+    }
+
+    #[test]
+    fn random_ranges() {
+        let program = "(random-seed 7) (list (random) (random 1))";
+        let Ast::List(items) = &eval_str(program).unwrap() else {
+            panic!("expected list");
+        };
+        let Ast::Atom(LispAtom::Float(num)) = items[0] else {
+            panic!("expected float");
+        };
+        assert!((0.0..1.0).contains(&num));
+        assert_eq!(items[1], int(0));
+    }
+
+    #[test]
+    fn random_bad_bounds() {
+        assert!(matches!(
+            eval_str("(random 0)"),
+            Err(LispError::ValueError(_))
+        ));
+        assert!(matches!(
+            eval_str("(random -5)"),
+            Err(LispError::ValueError(_))
+        ));
+        assert!(matches!(
+            eval_str("(random 1.5)"),
+            Err(LispError::TypeError)
+        ));
+    }
+
+    #[test]
+    fn quot_and_rem() {
+        assert_eq!(eval_str("(quot 7 2)").unwrap(), int(3));
+        assert_eq!(eval_str("(quot -7 2)").unwrap(), int(-3));
+        assert_eq!(eval_str("(rem -7 2)").unwrap(), int(-1));
+        assert_eq!(eval_str("(% 7 -2)").unwrap(), int(1));
+        assert!(matches!(
+            eval_str("(quot 1 0)"),
+            Err(LispError::DivideByZero)
+        ));
+        assert!(matches!(
+            eval_str("(rem 1 0)"),
+            Err(LispError::DivideByZero)
+        ));
+        assert_eq!(
+            eval_str("(quot -9223372036854775808 -1)").unwrap(),
+            big("9223372036854775808")
+        );
+        assert!(matches!(
+            eval_str("(quot 7.0 2)"),
+            Err(LispError::TypeError)
+        ));
+    }
+
+    #[test]
+    fn quot_rem_identity() {
+        for a in [-17, -9, -1, 0, 1, 8, 17] {
+            for b in [-5, -3, -1, 1, 2, 7] {
+                let Ast::Atom(LispAtom::Int(q)) = eval_str(&format!("(quot {} {})", a, b)).unwrap()
+                else {
+                    panic!("quot didn't return an int");
+                };
+                let Ast::Atom(LispAtom::Int(r)) = eval_str(&format!("(rem {} {})", a, b)).unwrap()
+                else {
+                    panic!("rem didn't return an int");
+                };
+                assert_eq!(a, q * b + r, "identity failed for {} and {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn free_vars_builtin() {
+        let symbol = |s: &str| Ast::Atom(LispAtom::Symbol(s.to_string()));
+        assert_eq!(
+            eval_str("(free-vars (quote (lambda (x) (+ x y z))))").unwrap(),
+            Ast::List(vec![symbol("+"), symbol("y"), symbol("z")])
+        );
+    }
+
+    #[test]
+    fn delay_and_force() {
+        let (result, stdout, _) = eval_captured(
+            "(def p (delay ((lambda () (putstr \"computing\") 42)))) \
+             (putstr \"before\") \
+             (list (force p) (force p))",
+        );
+        assert_eq!(result.unwrap(), Ast::List(vec![int(42), int(42)]));
+        assert_eq!(stdout, "before\ncomputing\n");
+
+        // Streams of alternating parity, built from delayed tails that call each other
+        let program = "(def (evens n) (list n (delay (odds (+ n 1))))) \
+                       (def (odds n) (list n (delay (evens (+ n 1))))) \
+                       (def (stream-nth s i) \
+                         (if (= i 0) (nth s 0) (stream-nth (force (nth s 1)) (- i 1)))) \
+                       (list (stream-nth (evens 0) 5) (stream-nth (odds 1) 100))";
+        assert_eq!(
+            eval_str(program).unwrap(),
+            Ast::List(vec![int(5), int(101)])
+        );
+
+        assert_eq!(eval_str("(force 5)").unwrap(), int(5));
+        assert_eq!(
+            eval_str("(def p (delay 1)) (list (eq? p p) (eq? p (delay 1)) (type p))").unwrap(),
+            Ast::List(vec![
+                Ast::from(true),
+                Ast::from(false),
+                Ast::Type(LispType::Promise)
+            ])
+        );
+        // A failed force leaves the promise unforced, so it can be retried
+        let mut env = Environment::outer_new();
+        let mut eval_in_env = |input: &str| {
+            let (_, expr) = parser::parse_expr(input).unwrap();
+            eval::eval_expr(expr, &mut env)
+        };
+        eval_in_env("(def p (delay (+ x 1)))").unwrap();
+        assert!(matches!(
+            eval_in_env("(force p)"),
+            Err(LispError::Undefined(_))
+        ));
+        eval_in_env("(def x 1)").unwrap();
+        assert_eq!(eval_in_env("(force p)").unwrap(), int(2));
+
+        // A promise that forces itself is an error rather than running until the stack overflows
+        let Err(LispError::ValueError(msg)) = eval_str("(def p (delay (force p))) (force p)")
+        else {
+            panic!("expected a value error");
+        };
+        assert_eq!(msg, "promise forced recursively");
+        // Like other failures, it leaves the promise unforced
+        eval_in_env("(def n 0)").unwrap();
+        eval_in_env("(def q (delay (do (def n (+ n 1)) (if (= n 1) (force q) n))))").unwrap();
+        assert!(matches!(
+            eval_in_env("(force q)"),
+            Err(LispError::ValueError(_))
+        ));
+        assert_eq!(eval_in_env("(force q)").unwrap(), int(2));
+    }
+
+    #[test]
+    fn atoms() {
+        // Two functions sharing one counter
+        let program = "(def counter (atom 0)) \
+                       (def (increment) (swap! counter + 1)) \
+                       (def (reset) (reset! counter 0)) \
+                       (increment) (increment) ";
+        assert_eq!(
+            eval_str(&format!("{}(deref counter)", program)).unwrap(),
+            int(2)
+        );
+        assert_eq!(
+            eval_str(&format!(
+                "{}(list (reset) (increment) (deref counter))",
+                program
+            ))
+            .unwrap(),
+            Ast::List(vec![int(0), int(1), int(1)])
+        );
+        assert_eq!(
+            eval_str("(def a (atom (list 1))) (swap! a (lambda (l x) (list x l)) 2) (deref a)")
+                .unwrap(),
+            Ast::List(vec![int(2), Ast::List(vec![int(1)])])
+        );
+
+        // A failing function leaves the value alone
+        let mut env = Environment::outer_new();
+        let mut eval_in_env = |input: &str| {
+            let (_, expr) = parser::parse_expr(input).unwrap();
+            eval::eval_expr(expr, &mut env)
+        };
+        eval_in_env("(def a (atom 5))").unwrap();
+        assert!(matches!(
+            eval_in_env("(swap! a (lambda (x) (/ x 0)))"),
+            Err(LispError::DivideByZero)
+        ));
+        assert!(matches!(
+            eval_in_env("(swap! a (lambda () 1))"),
+            Err(LispError::BadArity)
+        ));
+        assert_eq!(eval_in_env("(deref a)").unwrap(), int(5));
+        assert_eq!(
+            eval_in_env("(swap! a (lambda (x) (+ x (deref a))))").unwrap(),
+            int(10)
+        );
+
+        let (result, output, _) = eval_captured(
+            "(def a (atom 1)) (def b (atom 1)) (println a) (list (equal? a a) (equal? a b) (eq? a a))",
+        );
+        assert_eq!(
+            result.unwrap(),
+            Ast::List(vec![Ast::from(true), Ast::from(false), Ast::from(true)])
+        );
+        assert_eq!(output, "#<atom 1>\n");
+        assert!(matches!(eval_str("(deref 1)"), Err(LispError::TypeError)));
+
+        // Atoms that hold themselves don't display forever
+        let (result, output, _) = eval_captured(
+            "(def a (atom (list 1))) (reset! a a) (println a) (println (deref a)) \
+             (def b (atom 0)) (reset! b (list 1 b (atom 2))) (println b)",
+        );
+        result.unwrap();
+        assert_eq!(
+            output,
+            "#<atom #<atom ...>>\n#<atom #<atom ...>>\n#<atom (1 #<atom ...> #<atom 2>)>\n"
+        );
+        assert!(matches!(
+            eval_str("(swap! (atom 1) 2)"),
+            Err(LispError::TypeError)
+        ));
+    }
+
+    #[test]
+    fn macroexpand_builtins() {
+        let parse = |input: &str| crate::parser::parse_expr(input).unwrap().1;
+        assert_eq!(
+            eval_str("(macroexpand (quote (def (f x) (list (def (g) x)))))").unwrap(),
+            parse("(def f (lambda (x) (list (def (g) x))))")
+        );
+        assert_eq!(
+            eval_str("(macroexpand-all (quote (def (f x) (list (def (g) x)))))").unwrap(),
+            parse("(def f (lambda (x) (list (def g (lambda () x)))))")
+        );
+        assert_eq!(
+            eval_str("(macroexpand (quote (+ 1 2)))").unwrap(),
+            parse("(+ 1 2)")
+        );
+        assert_eq!(eval_str("(macroexpand 5)").unwrap(), int(5));
+    }
+
+    #[test]
+    fn bitwise_operations() {
+        assert_eq!(eval_str("(bit-and 12 10)").unwrap(), int(8));
+        assert_eq!(eval_str("(bit-and 15 7 3)").unwrap(), int(3));
+        assert_eq!(eval_str("(bit-or 12 10 1)").unwrap(), int(15));
+        assert_eq!(eval_str("(bit-xor 12 10)").unwrap(), int(6));
+        assert_eq!(eval_str("(bit-not 0)").unwrap(), int(-1));
+        assert_eq!(eval_str("(shift-left 1 4)").unwrap(), int(16));
+        assert_eq!(eval_str("(shift-left 1 2 3)").unwrap(), int(32));
+        assert_eq!(eval_str("(shift-right 256 4)").unwrap(), int(16));
+        assert_eq!(eval_str("(shift-right -16 2)").unwrap(), int(-4));
+        assert_eq!(eval_str("(shift-left 1 63)").unwrap(), int(i64::MIN));
+    }
+
+    #[test]
+    fn bitwise_errors() {
+        assert!(matches!(
+            eval_str("(shift-left 1 64)"),
+            Err(LispError::ValueError(_))
+        ));
+        assert!(matches!(
+            eval_str("(shift-right 1 -1)"),
+            Err(LispError::ValueError(_))
+        ));
+        assert!(matches!(
+            eval_str("(bit-and 1 2.0)"),
+            Err(LispError::TypeError)
+        ));
+        assert!(matches!(
+            eval_str("(bit-not 1.0)"),
+            Err(LispError::TypeError)
+        ));
+        assert!(matches!(eval_str("(bit-or 1)"), Err(LispError::BadArity)));
+    }
+
+    #[test]
+    fn inspect_returns_value() {
+        let (result, _, err) = eval_captured("(+ 1 (inspect 2))");
+        assert_eq!(result.unwrap(), int(3));
+        assert_eq!(err, "value: 2\ntype: builtin type int\n");
+    }
+
+    #[test]
+    fn inspect_golden() {
+        let (_, _, err) = eval_captured("(inspect (list 1 2.5 \"a\" (list) true sqrt))");
+        assert_eq!(
+            err,
+            "value: (1 2.5 \"a\" () true <function>)\n\
+             type: list\n\
+             length: 6\n\
+             element types: builtin type int, builtin type float, builtin type string, list, \
+             builtin type bool, ...\n"
+        );
+
+        let (_, _, err) = eval_captured("(inspect \"héllo\")");
+        assert_eq!(
+            err,
+            "value: \"héllo\"\ntype: builtin type string\nchars: 5\nbytes: 6\n"
+        );
+
+        let (_, _, err) = eval_captured("(inspect (lambda (x y) x))");
+        assert_eq!(
+            err,
+            "value: <function>\ntype: function\nfunction: lambda (x y)\narity: 2\n"
+        );
+
+        let (_, _, err) = eval_captured("(inspect +)");
+        assert_eq!(
+            err,
+            "value: <function>\ntype: function\nfunction: +\narity: 1 or more\n"
+        );
+
+        let (_, _, err) = eval_captured("(inspect get) (inspect hash-map) (inspect (trace first))");
+        assert_eq!(
+            err,
+            "value: <function>\ntype: function\nfunction: get\narity: 2 or 3\n\
+             value: <function>\ntype: function\nfunction: hash-map\narity: 0, 2, 4, 6, 8, ...\n\
+             value: <function>\ntype: function\nfunction: traced first\narity: 1\n"
+        );
+
+        let (_, _, err) = eval_captured("(inspect (quote sym))");
+        assert_eq!(err, "value: sym\ntype: symbol\n");
+    }
+
+    #[test]
+    fn hexdump_golden() {
+        let (result, out, _) = eval_captured("(hexdump \"Hello, world! 0123456789\")");
+        assert!(matches!(result, Ok(Ast::Unspecified)));
+        assert_eq!(
+            out,
+            "00000000  48 65 6c 6c 6f 2c 20 77 6f 72 6c 64 21 20 30 31  |Hello, world! 01|\n\
+             00000010  32 33 34 35 36 37 38 39                          |23456789|\n"
+        );
+    }
+
+    #[test]
+    fn int_conversion() {
+        assert_eq!(eval_str("(int 3.9)").unwrap(), int(3));
+        assert_eq!(eval_str("(int -3.9)").unwrap(), int(-3));
+        assert_eq!(eval_str("(int 7)").unwrap(), int(7));
+        assert_eq!(eval_str("(int \"-42\")").unwrap(), int(-42));
+        assert_eq!(eval_str("(int true)").unwrap(), int(1));
+        assert_eq!(eval_str("(int false)").unwrap(), int(0));
+        assert!(matches!(
+            eval_str("(int 1e300)"),
+            Err(LispError::ValueError(_))
+        ));
+        assert!(matches!(
+            eval_str("(int (sqrt 1e400))"),
+            Err(LispError::ValueError(_))
+        ));
+        assert!(matches!(
+            eval_str("(int \"4.5\")"),
+            Err(LispError::ValueError(_))
+        ));
+        assert!(matches!(
+            eval_str("(int \"abc\")"),
+            Err(LispError::ValueError(_))
+        ));
+        assert!(matches!(
+            eval_str("(int (list))"),
+            Err(LispError::TypeError)
+        ));
+    }
+
+    #[test]
+    fn float_conversion() {
+        assert_eq!(eval_str("(float 3)").unwrap(), float(3.0));
+        assert_eq!(eval_str("(float 2.5)").unwrap(), float(2.5));
+        assert_eq!(eval_str("(float \"1e3\")").unwrap(), float(1000.0));
+        assert_eq!(eval_str("(float true)").unwrap(), float(1.0));
+        for input in ["x", "nan", "inf", "-Infinity", "1e999"] {
+            assert!(
+                matches!(
+                    eval_str(&format!("(float \"{}\")", input)),
+                    Err(LispError::ValueError(_))
+                ),
+                "{}",
+                input
+            );
+        }
+        assert!(matches!(
+            eval_str("(float (quote x))"),
+            Err(LispError::TypeError)
+        ));
+    }
+
+    #[test]
+    fn numeric_equality() {
+        let t = Ast::Atom(LispAtom::Bool(true));
+        let f = Ast::Atom(LispAtom::Bool(false));
+        assert_eq!(eval_str("(= 1 1.0 1)").unwrap(), t);
+        assert_eq!(eval_str("(= 1 1 2)").unwrap(), f);
+        assert_eq!(
+            eval_str("(= 9007199254740993 9007199254740992)").unwrap(),
+            f
+        );
+        assert_eq!(eval_str("(equal? 1 1.0)").unwrap(), f);
+        assert!(matches!(eval_str("(= 1 \"1\")"), Err(LispError::TypeError)));
+        assert!(matches!(
+            eval_str("(= 1 2 \"1\")"),
+            Err(LispError::TypeError)
+        ));
+    }
+
+    #[test]
+    fn unavailable_builtin_message() {
+        let mut env = Environment::outer_new();
+        env.restrict("putstr", "native-io");
+        let (_, expr) = crate::parser::parse_expr("(putstr \"hi\")").unwrap();
+        let err = eval::eval_expr(expr, &mut env).unwrap_err();
+        assert!(matches!(
+            &err,
+            LispError::Unavailable(name, requirement) if name == "putstr" && requirement == "native-io"
+        ));
+        assert!(err
+            .to_string()
+            .contains("putstr is not available in this build (requires native-io)"));
+
+        let (_, expr) = crate::parser::parse_expr("(has-builtin? (quote putstr))").unwrap();
+        assert_eq!(
+            eval::eval_expr(expr, &mut env).unwrap(),
+            Ast::Atom(LispAtom::Bool(false))
+        );
+    }
+
+    #[test]
+    fn has_builtin() {
+        let t = Ast::Atom(LispAtom::Bool(true));
+        let f = Ast::Atom(LispAtom::Bool(false));
+        assert_eq!(eval_str("(has-builtin? (quote putstr))").unwrap(), t);
+        assert_eq!(eval_str("(has-builtin? \"+\")").unwrap(), t);
+        assert_eq!(eval_str("(has-builtin? (quote no-such-thing))").unwrap(), f);
+        assert_eq!(eval_str("(def x 1) (has-builtin? (quote x))").unwrap(), f);
+        // Definitions in the program don't change what the builtins are
+        assert_eq!(
+            eval_str("(def (myfn) 1) (has-builtin? (quote myfn))").unwrap(),
+            f
+        );
+        assert_eq!(
+            eval_str("(def putstr 5) (has-builtin? (quote putstr))").unwrap(),
+            t
+        );
+    }
+
+    #[test]
+    fn type_name_round_trip() {
+        for typ in LispType::ALL {
+            let program = format!(
+                "(type-of-name (type-name (type-of-name \"{}\")))",
+                typ.name()
+            );
+            assert_eq!(eval_str(&program).unwrap(), Ast::Type(typ));
+        }
+        assert_eq!(
+            eval_str("(type-name (type 1))").unwrap(),
+            Ast::Atom(LispAtom::String("int".to_string()))
+        );
+        assert!(matches!(
+            eval_str("(type-of-name \"integer\")"),
+            Err(LispError::ValueError(_))
+        ));
+    }
+
+    #[test]
+    fn number_subtypes() {
+        let t = Ast::Atom(LispAtom::Bool(true));
+        let f = Ast::Atom(LispAtom::Bool(false));
+        let number = "(type-of-name \"number\")";
+        assert_eq!(
+            eval_str(&format!("(subtype? (type 1) {})", number)).unwrap(),
+            t
+        );
+        assert_eq!(
+            eval_str(&format!("(subtype? (type 1.5) {})", number)).unwrap(),
+            t
+        );
+        assert_eq!(
+            eval_str(&format!("(subtype? {} (type 1))", number)).unwrap(),
+            f
+        );
+        assert_eq!(eval_str("(subtype? (type 1) (type 1.5))").unwrap(), f);
+        assert_eq!(eval_str("(subtype? (type \"a\") (type \"b\"))").unwrap(), t);
+        assert_eq!(eval_str("(number? 1)").unwrap(), t);
+        assert_eq!(eval_str("(number? 1.5)").unwrap(), t);
+        assert_eq!(eval_str("(number? \"1\")").unwrap(), f);
+    }
+
+    #[test]
+    fn arithmetic_preserves_ints() {
+        assert_eq!(eval_str("(- 5 2)").unwrap(), int(3));
+        assert_eq!(
+            eval_str("(equal? (- 5 2) 3)").unwrap(),
+            Ast::Atom(LispAtom::Bool(true))
+        );
+        assert_eq!(eval_str("(- 5 2.0)").unwrap(), float(3.0));
+        assert_eq!(eval_str("(* 2 3 4)").unwrap(), int(24));
+        assert_eq!(eval_str("(* 2 1.5)").unwrap(), float(3.0));
+        assert_eq!(eval_str("(/ 6 3)").unwrap(), int(2));
+        assert_eq!(eval_str("(/ 5 2)").unwrap(), ratio(5, 2));
+        assert_eq!(eval_str("(/ 6.0 3)").unwrap(), float(2.0));
+        assert_eq!(eval_str("(/ -1)").unwrap(), int(-1));
+        assert_eq!(eval_str("(/ 5)").unwrap(), ratio(1, 5));
+    }
+
+    #[test]
+    fn negation_keeps_type() {
+        assert_eq!(eval_str("(- 2)").unwrap(), int(-2));
+        assert_eq!(eval_str("(- 2.5)").unwrap(), float(-2.5));
+        assert_eq!(
+            eval_str("(- -9223372036854775807 1 1)").unwrap(),
+            big("-9223372036854775809")
+        );
+    }
+
+    #[test]
+    fn arithmetic_promotes_to_bigint() {
+        assert_eq!(
+            eval_str("(* 9223372036854775807 2)").unwrap(),
+            big("18446744073709551614")
+        );
+        assert_eq!(
+            eval_str("(+ 9223372036854775807 1)").unwrap(),
+            big("9223372036854775808")
+        );
+        assert_eq!(
+            eval_str("(/ -9223372036854775807 -1)").unwrap(),
+            int(9223372036854775807)
+        );
+        // Results that fit are Ints again
+        assert_eq!(
+            eval_str("(- (+ 9223372036854775807 1) 1)").unwrap(),
+            int(i64::MAX)
+        );
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert!(matches!(eval_str("(/ 5 0)"), Err(LispError::DivideByZero)));
+        assert!(matches!(
+            eval_str("(/ 5 0.0)"),
+            Err(LispError::DivideByZero)
+        ));
+        assert!(matches!(
+            eval_str("(/ 5 -0.0)"),
+            Err(LispError::DivideByZero)
+        ));
+        assert!(matches!(eval_str("(/ 0)"), Err(LispError::DivideByZero)));
+        assert!(matches!(
+            eval_str("(/ 60 2 0 3)"),
+            Err(LispError::DivideByZero)
+        ));
+        assert!(matches!(
+            eval_str("(/ 60.0 2 0.0 3)"),
+            Err(LispError::DivideByZero)
+        ));
+        assert_eq!(eval_str("(/ 0 5)").unwrap(), int(0));
+    }
+
+    #[test]
+    fn bigint_at_boundaries() {
+        let max = "9223372036854775807";
+        let min = "-9223372036854775808";
+        let eval = |program: String| eval_str(&program).unwrap();
+
+        assert_eq!(eval(format!("(+ {} 0)", max)), int(i64::MAX));
+        assert_eq!(eval(format!("(+ {} 1)", max)), big("9223372036854775808"));
+        assert_eq!(eval(format!("(+ {} -1)", min)), big("-9223372036854775809"));
+        assert_eq!(eval(format!("(- {} 0)", min)), int(i64::MIN));
+        assert_eq!(eval(format!("(- {} 1)", min)), big("-9223372036854775809"));
+        assert_eq!(eval(format!("(- {} -1)", max)), big("9223372036854775808"));
+        assert_eq!(eval(format!("(- {})", max)), int(-i64::MAX));
+        assert_eq!(eval(format!("(- {})", min)), big("9223372036854775808"));
+        assert_eq!(eval(format!("(* {} -1)", max)), int(-i64::MAX));
+        assert_eq!(eval(format!("(* {} -1)", min)), big("9223372036854775808"));
+        assert_eq!(eval(format!("(* {} 2)", min)), big("-18446744073709551616"));
+        assert_eq!(
+            eval(format!("(quot {} -1)", min)),
+            big("9223372036854775808")
+        );
+        assert_eq!(eval(format!("(rem {} -1)", min)), int(0));
+        assert_eq!(
+            eval(format!("(gcd {} {})", min, min)),
+            big("9223372036854775808")
+        );
+    }
+
+    #[test]
+    fn bigint_arithmetic() {
+        let t = Ast::Atom(LispAtom::Bool(true));
+        let fact = "(def fact (lambda (n) (if (< n 2) 1 (* n (fact (- n 1))))))";
+        assert_eq!(
+            eval_str(&format!("{} (fact 25)", fact)).unwrap(),
+            big("15511210043330985984000000")
+        );
+        assert_eq!(
+            eval_str(&format!("{} (= (/ (fact 100) (fact 98)) 9900)", fact)).unwrap(),
+            t
+        );
+        assert_eq!(
+            eval_str(&format!("{} (< (fact 30) (fact 31) 1e40)", fact)).unwrap(),
+            t
+        );
+        assert_eq!(
+            eval_str("(quot 100000000000000000000 7)").unwrap(),
+            big("14285714285714285714")
+        );
+        assert_eq!(eval_str("(rem 100000000000000000000 7)").unwrap(), int(2));
+        assert_eq!(eval_str("(rem -100000000000000000000 7)").unwrap(), int(-2));
+        assert_eq!(
+            eval_str("(- 100000000000000000000 100000000000000000000)").unwrap(),
+            int(0)
+        );
+        assert_eq!(
+            eval_str("(+ 100000000000000000000 0.5)").unwrap(),
+            float(1e20)
+        );
+        assert_eq!(
+            eval_str("(equal? 100000000000000000000 (* 10000000000 10000000000))").unwrap(),
+            t
+        );
+        assert_eq!(eval_str("(> 100000000000000000000 1/3)").unwrap(), t);
+        assert_eq!(
+            eval_str("(type-name (type 100000000000000000000))").unwrap(),
+            Ast::from("int")
+        );
+        assert_eq!(
+            eval_str("(int \"-100000000000000000000\")").unwrap(),
+            big("-100000000000000000000")
+        );
+        // There are no big rationals, so fractions that don't fit are Floats
+        assert_eq!(
+            eval_str("(/ 100000000000000000000 7)").unwrap(),
+            float(1.4285714285714287e19)
+        );
+        assert!(matches!(
+            eval_str("(bit-and 100000000000000000000 1)"),
+            Err(LispError::Overflow(_))
+        ));
+    }
+
+    #[test]
+    fn rational_arithmetic() {
+        assert_eq!(eval_str("(/ 1 3)").unwrap(), ratio(1, 3));
+        assert_eq!(eval_str("(+ 1/3 1/6)").unwrap(), ratio(1, 2));
+        assert_eq!(eval_str("(+ 1/3 2/3)").unwrap(), int(1));
+        assert_eq!(eval_str("(+ 1/2 1)").unwrap(), ratio(3, 2));
+        assert_eq!(eval_str("(+ 1/2 0.25)").unwrap(), float(0.75));
+        assert_eq!(eval_str("(- 1/2 1/3)").unwrap(), ratio(1, 6));
+        assert_eq!(eval_str("(- 1/2)").unwrap(), ratio(-1, 2));
+        assert_eq!(eval_str("(- 1/2 1.0)").unwrap(), float(-0.5));
+        assert_eq!(eval_str("(* 2/3 3/4)").unwrap(), ratio(1, 2));
+        assert_eq!(eval_str("(* 2/3 3)").unwrap(), int(2));
+        assert_eq!(eval_str("(* 1/2 3.0)").unwrap(), float(1.5));
+        assert_eq!(eval_str("(/ 1/2 1/4)").unwrap(), int(2));
+        assert_eq!(eval_str("(/ 2/3)").unwrap(), ratio(3, 2));
+        assert_eq!(eval_str("(/ -2/3)").unwrap(), ratio(-3, 2));
+        assert_eq!(eval_str("(/ 1/2 0.5)").unwrap(), float(1.0));
+        assert!(matches!(
+            eval_str("(/ 1/2 0)"),
+            Err(LispError::DivideByZero)
+        ));
+        assert_eq!(
+            eval_str("(* 9223372036854775807/2 3)").unwrap(),
+            float(1.3835058055282164e19)
+        );
+    }
+
+    #[test]
+    fn rational_pow_and_rounding() {
+        assert_eq!(eval_str("(pow 2/3 2)").unwrap(), ratio(4, 9));
+        assert_eq!(eval_str("(pow 2/3 -2)").unwrap(), ratio(9, 4));
+        assert_eq!(eval_str("(pow -2/3 -1)").unwrap(), ratio(-3, 2));
+        assert_eq!(eval_str("(pow 1/4 0.5)").unwrap(), float(0.5));
+        assert_eq!(eval_str("(floor -7/2)").unwrap(), int(-4));
+        assert_eq!(eval_str("(ceil -7/2)").unwrap(), int(-3));
+        assert_eq!(eval_str("(round -7/2)").unwrap(), int(-4));
+        assert_eq!(eval_str("(truncate -7/2)").unwrap(), int(-3));
+        assert_eq!(eval_str("(int 7/2)").unwrap(), int(3));
+        assert_eq!(eval_str("(float 1/4)").unwrap(), float(0.25));
+        assert_eq!(eval_str("(sqrt 1/4)").unwrap(), float(0.5));
+    }
+
+    #[test]
+    fn rational_comparisons() {
+        let t = Ast::Atom(LispAtom::Bool(true));
+        let f = Ast::Atom(LispAtom::Bool(false));
+        assert_eq!(eval_str("(= 1/2 2/4)").unwrap(), t);
+        assert_eq!(eval_str("(= 1/2 0.5)").unwrap(), t);
+        assert_eq!(eval_str("(= 4/2 2)").unwrap(), t);
+        assert_eq!(eval_str("(= 1/3 0.3333333333333333)").unwrap(), t);
+        assert_eq!(eval_str("(equal? 1/2 0.5)").unwrap(), f);
+        assert_eq!(eval_str("(< 1/3 1/2 1)").unwrap(), t);
+        assert_eq!(eval_str("(< 1/2 1/3)").unwrap(), f);
+        assert_eq!(eval_str("(<= 1/2 0.5 1)").unwrap(), t);
+        assert_eq!(eval_str("(> 1 2/3 0.5)").unwrap(), t);
+        assert_eq!(eval_str("(>= 1/2 1/2)").unwrap(), t);
+        // Exact comparison distinguishes values that are equal as floats
+        assert_eq!(
+            eval_str("(< 9007199254740992 9007199254740993/1)").unwrap(),
+            t
+        );
+    }
+
+    #[test]
+    fn rational_accessors() {
+        let t = Ast::Atom(LispAtom::Bool(true));
+        let f = Ast::Atom(LispAtom::Bool(false));
+        assert_eq!(eval_str("(numerator 6/4)").unwrap(), int(3));
+        assert_eq!(eval_str("(denominator 6/4)").unwrap(), int(2));
+        assert_eq!(eval_str("(numerator -5)").unwrap(), int(-5));
+        assert_eq!(eval_str("(denominator -5)").unwrap(), int(1));
+        assert!(matches!(
+            eval_str("(numerator 0.5)"),
+            Err(LispError::TypeError)
+        ));
+        assert_eq!(eval_str("(rational? 1/2)").unwrap(), t);
+        assert_eq!(eval_str("(rational? 2)").unwrap(), t);
+        assert_eq!(eval_str("(rational? 0.5)").unwrap(), f);
+        assert_eq!(eval_str("(number? 1/2)").unwrap(), t);
+        assert_eq!(
+            eval_str("(type-name (type 1/2))").unwrap(),
+            Ast::from("rational")
+        );
+        assert_eq!(eval_str("(subtype? (type 1) (type 1/2))").unwrap(), t);
+    }
+
+    #[test]
+    fn render_template_builtin() {
+        assert_eq!(
+            eval_str(r#"(def qty 2) (render-template "<% (def price 1/2) %>total: <%= (* qty price) %>")"#)
+                .unwrap(),
+            Ast::from("total: 1")
+        );
+        assert!(matches!(
+            eval_str(r#"(render-template "<%= undefined-name %>")"#),
+            Err(LispError::InTemplate(0, _))
+        ));
+    }
+
+    #[test]
+    fn fold_case_modes() {
+        let program = r#"(DEF Greeting "Hello, World") (list GREETING (keyword "Loud"))"#;
+        assert!(matches!(eval_str(program), Err(LispError::Undefined(_))));
+
+        let result = eval_str(&format!("(set-fold-case! true) {}", program));
+        let quoted = eval_str("(set-fold-case! true) (quote MixedCase)");
+        // Folding belongs to the environment, so it doesn't carry over to a fresh one
+        assert!(matches!(eval_str(program), Err(LispError::Undefined(_))));
+
+        assert_eq!(
+            result.unwrap(),
+            Ast::List(vec![
+                Ast::from("Hello, World"),
+                Ast::Atom(LispAtom::Keyword("Loud".to_string()))
+            ])
+        );
+        assert_eq!(quoted.unwrap().to_string(), "mixedcase");
+        assert_eq!(
+            eval_str("(set-fold-case! true) (list (EQUAL? (TYPE 1) <INT> <int>) (INT 2.5))")
+                .unwrap(),
+            Ast::List(vec![Ast::Atom(LispAtom::Bool(true)), int(2)])
+        );
+
+        // Environments made with make-env start without folding
+        let program = r#"(set-fold-case! true) (eval (quote (read-string "ABC")) (make-env))"#;
+        assert_eq!(eval_str(program).unwrap().to_string(), "ABC");
+    }
+
+    #[test]
+    fn tree_map_builtin() {
+        assert_eq!(
+            eval_str("(tree-map (lambda (x) (* x 10)) (list 1 (list 2 (list)) 3))").unwrap(),
+            Ast::List(vec![
+                int(10),
+                Ast::List(vec![int(20), Ast::List(vec![])]),
+                int(30)
+            ])
+        );
+        assert_eq!(
+            eval_str("(tree-map (lambda (x) (+ x 1)) 1)").unwrap(),
+            int(2)
+        );
+        assert!(matches!(
+            eval_str("(tree-map (lambda (x y) x) (list 1))"),
+            Err(LispError::BadArity)
+        ));
+        assert!(matches!(
+            eval_str("(tree-map (lambda (x) (+ x \"a\")) (list 1))"),
+            Err(LispError::TypeError)
+        ));
+    }
+
+    #[test]
+    fn deep_tree_map_and_print() {
+        let mut tree = int(1);
+        for _ in 0..100_000 {
+            tree = Ast::List(vec![tree]);
+        }
+        let mut env = Environment::outer_new();
+        let inc = env.get("+").unwrap();
+        let add_one = LispLambda::new(
+            Params::positional(vec!["x".to_string()]),
+            vec![Ast::List(vec![
+                inc,
+                Ast::Atom(LispAtom::Symbol("x".into())),
+                int(1),
+            ])],
+        );
+        let mapped = LISP_TREE_MAP
+            .call(
+                vec![Ast::Function(Box::new(add_one)), tree.clone()],
+                &mut env,
+            )
+            .unwrap();
+        let printed = display_string(&mapped, env.printer());
+        assert_eq!(
+            printed,
+            format!("{}2{}", "(".repeat(100_000), ")".repeat(100_000))
+        );
+        assert_ne!(mapped, tree);
+    }
+
+    #[test]
+    fn nan_and_infinity() {
+        let t = Ast::Atom(LispAtom::Bool(true));
+        let f = Ast::Atom(LispAtom::Bool(false));
+        // There is no syntax for NaN or infinity, so make them by overflowing
+        let inf = "(* 1e200 1e200)";
+        let nan = "(- (* 1e200 1e200) (* 1e200 1e200))";
+        let with_specials =
+            |program: &str| eval_str(&program.replace("INF", inf).replace("NAN", nan));
+
+        for program in ["(< NAN 1)", "(= NAN NAN)", "(>= 1 2 NAN)", "(< 1/2 NAN)"] {
+            assert!(
+                matches!(with_specials(program), Err(LispError::ValueError(_))),
+                "{}",
+                program
+            );
+        }
+        // The chain is checked in full, so a later NaN is still an error
+        assert!(matches!(
+            with_specials("(< 2 1 NAN)"),
+            Err(LispError::ValueError(_))
+        ));
+
+        assert_eq!(with_specials("(< 1 INF)").unwrap(), t);
+        assert_eq!(
+            with_specials("(> (- INF) 100000000000000000000)").unwrap(),
+            f
+        );
+        assert_eq!(with_specials("(= INF (* INF 2))").unwrap(), t);
+
+        assert_eq!(with_specials("(nan? NAN)").unwrap(), t);
+        assert_eq!(with_specials("(nan? (- INF INF))").unwrap(), t);
+        assert_eq!(eval_str("(nan? 1.5)").unwrap(), f);
+        assert_eq!(with_specials("(infinite? (- INF))").unwrap(), t);
+        assert_eq!(with_specials("(infinite? NAN)").unwrap(), f);
+        assert_eq!(eval_str("(finite? 1/2)").unwrap(), t);
+        assert_eq!(eval_str("(finite? 100000000000000000000)").unwrap(), t);
+        assert_eq!(with_specials("(finite? NAN)").unwrap(), f);
+        assert_eq!(with_specials("(finite? INF)").unwrap(), f);
+        assert!(matches!(
+            eval_str("(nan? \"nan\")"),
+            Err(LispError::TypeError)
+        ));
+    }
+
+    #[test]
+    fn arithmetic_promotion_table() {
+        // (program, expected result) for each operator, argument type, and arity
+        let cases = [
+            ("(+ 2)", int(2)),
+            ("(+ 2 3)", int(5)),
+            ("(+ 1/2 1/2)", int(1)),
+            ("(+ 1 1/2 1/3)", ratio(11, 6)),
+            ("(+ 1 0.5)", float(1.5)),
+            ("(+ 1/2 0.5)", float(1.0)),
+            ("(- 5)", int(-5)),
+            ("(- -9223372036854775808)", big("9223372036854775808")),
+            ("(- 1/2)", ratio(-1, 2)),
+            ("(- 2.5)", float(-2.5)),
+            ("(- 5 2)", int(3)),
+            ("(- 1 1/2)", ratio(1, 2)),
+            ("(- 10 1 2 3)", int(4)),
+            ("(- 1 0.5)", float(0.5)),
+            ("(* 4)", int(4)),
+            ("(* 2 3 4)", int(24)),
+            ("(* 2 1/4)", ratio(1, 2)),
+            ("(* 2/3 3/2)", int(1)),
+            ("(* 2 0.5)", float(1.0)),
+            ("(/ 1)", int(1)),
+            ("(/ -1)", int(-1)),
+            ("(/ 2)", ratio(1, 2)),
+            ("(/ 2/3)", ratio(3, 2)),
+            ("(/ 4.0)", float(0.25)),
+            ("(/ 6 3)", int(2)),
+            ("(/ 5 2)", ratio(5, 2)),
+            ("(/ 12 2 3)", int(2)),
+            ("(/ 1/2 1/4)", int(2)),
+            ("(/ 1 4.0)", float(0.25)),
+        ];
+        for (program, expected) in cases {
+            let result = eval_str(program).unwrap();
+            assert_eq!(result, expected, "{}", program);
+            assert_eq!(
+                LispType::from(&result),
+                LispType::from(&expected),
+                "{}",
+                program
+            );
+        }
+    }
+
+    #[test]
+    fn not_builtin() {
+        let t = Ast::Atom(LispAtom::Bool(true));
+        let f = Ast::Atom(LispAtom::Bool(false));
+        assert_eq!(eval_str("(not false)").unwrap(), t);
+        assert_eq!(eval_str("(not true)").unwrap(), f);
+        assert_eq!(eval_str("(not (list))").unwrap(), f);
+        assert_eq!(eval_str("(not 0)").unwrap(), f);
+        assert_eq!(eval_str("(not \"\")").unwrap(), f);
+        assert_eq!(eval_str("(if (not (not 0)) 1 2)").unwrap(), int(1));
+        assert!(matches!(eval_str("(not)"), Err(LispError::BadArity)));
+    }
+
+    #[test]
+    fn registry_is_valid() {
+        check_registry();
+    }
+
+    #[test]
+    #[should_panic(expected = "builtin \"list\" is registered more than once")]
+    fn registry_duplicate() {
+        check_names(["list", "count", "list"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "builtin \"if\" is hidden by the special form")]
+    fn registry_special_form_collision() {
+        check_names(["if"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "builtin \"two words\" is not a valid symbol")]
+    fn registry_invalid_name() {
+        check_names(["two words"]);
+    }
+
+    #[test]
+    fn type_predicates() {
+        let predicates = [
+            "int?",
+            "float?",
+            "number?",
+            "string?",
+            "bool?",
+            "symbol?",
+            "function?",
+        ];
+        // Whether each predicate above holds for a value of every kind
+        let cases = [
+            ("1", [true, false, true, false, false, false, false]),
+            (
+                "100000000000000000000",
+                [true, false, true, false, false, false, false],
+            ),
+            ("1/2", [false, false, true, false, false, false, false]),
+            ("1.5", [false, true, true, false, false, false, false]),
+            ("\"1\"", [false, false, false, true, false, false, false]),
+            ("false", [false, false, false, false, true, false, false]),
+            (
+                "(quote x)",
+                [false, false, false, false, false, true, false],
+            ),
+            ("(keyword \"x\")", [false; 7]),
+            ("(list 1)", [false; 7]),
+            (
+                "(lambda (x) x)",
+                [false, false, false, false, false, false, true],
+            ),
+            ("+", [false, false, false, false, false, false, true]),
+            ("Int", [false; 7]),
+            ("(current-env)", [false; 7]),
+            ("(def unspecified 1)", [false; 7]),
+        ];
+        for (value, expected) in cases {
+            for (predicate, expected) in predicates.iter().zip(expected) {
+                let program = format!("({} {})", predicate, value);
+                assert_eq!(
+                    eval_str(&program).unwrap(),
+                    Ast::Atom(LispAtom::Bool(expected)),
+                    "{}",
+                    program
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn type_values_are_bound() {
+        let t = Ast::Atom(LispAtom::Bool(true));
+        assert_eq!(eval_str("(equal? (type 1) Int)").unwrap(), t);
+        assert_eq!(eval_str("(equal? (type 1.5) Float)").unwrap(), t);
+        assert_eq!(eval_str("(equal? (type (list)) List)").unwrap(), t);
+        assert_eq!(eval_str("(subtype? Int Number)").unwrap(), t);
+        assert_eq!(
+            eval_str("(type-name Env)").unwrap(),
+            Ast::from("environment")
+        );
+    }
+
+    #[test]
+    fn numeric_predicates() {
+        let t = Ast::Atom(LispAtom::Bool(true));
+        let f = Ast::Atom(LispAtom::Bool(false));
+        let cases = [
+            ("(zero? 0)", &t),
+            ("(zero? 0.0)", &t),
+            ("(zero? -0.0)", &t),
+            ("(zero? 1/2)", &f),
+            ("(positive? -0.0)", &f),
+            ("(negative? -0.0)", &f),
+            ("(negative? -1/2)", &t),
+            ("(positive? 100000000000000000000)", &t),
+            ("(negative? (- 100000000000000000000))", &t),
+            ("(positive? -inf)", &f),
+            ("(even? 0)", &t),
+            ("(even? -4)", &t),
+            ("(odd? -3)", &t),
+            ("(odd? -4)", &f),
+            ("(even? 100000000000000000001)", &f),
+            ("(odd? 100000000000000000001)", &t),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(&eval_str(program).unwrap(), expected, "{}", program);
+        }
+
+        assert!(matches!(eval_str("(even? 2.0)"), Err(LispError::TypeError)));
+        assert!(matches!(eval_str("(odd? 1/2)"), Err(LispError::TypeError)));
+        assert!(matches!(
+            eval_str("(zero? \"0\")"),
+            Err(LispError::TypeError)
+        ));
+        assert!(matches!(
+            eval_str("(positive? nan)"),
+            Err(LispError::ValueError(_))
+        ));
+    }
+
+    #[test]
+    fn empty_builtin() {
+        let t = Ast::Atom(LispAtom::Bool(true));
+        let f = Ast::Atom(LispAtom::Bool(false));
+        assert_eq!(eval_str("(empty? (list))").unwrap(), t);
+        assert_eq!(eval_str("(empty? (list 1))").unwrap(), f);
+        assert_eq!(eval_str("(empty? \"\")").unwrap(), t);
+        assert_eq!(eval_str("(empty? \"a\")").unwrap(), f);
+        assert_eq!(eval_str("(empty? [])").unwrap(), t);
+        assert_eq!(eval_str("(empty? [1])").unwrap(), f);
+        assert_eq!(eval_str("(empty? {})").unwrap(), t);
+        assert_eq!(eval_str("(empty? {:a 1})").unwrap(), f);
+        assert!(matches!(eval_str("(empty? 0)"), Err(LispError::TypeError)));
+    }
+
+    #[test]
+    fn count_builtin() {
+        assert_eq!(eval_str("(count (list 1 (list 2 3) 4))").unwrap(), int(3));
+        assert_eq!(eval_str("(count (list))").unwrap(), int(0));
+        assert_eq!(eval_str("(count \"héllo\")").unwrap(), int(5));
+        assert_eq!(
+            eval_str("(equal? (count (list 1 2)) 2)").unwrap(),
+            Ast::Atom(LispAtom::Bool(true))
+        );
+        assert!(matches!(eval_str("(count 1)"), Err(LispError::TypeError)));
+    }
+
+    #[test]
+    fn eq_builtin() {
+        let t = Ast::Atom(LispAtom::Bool(true));
+        let f = Ast::Atom(LispAtom::Bool(false));
+        let cases = [
+            ("(eq? 1 1 1)", &t),
+            ("(eq? 1 1.0)", &f),
+            ("(eq? \"a\" \"a\")", &t),
+            ("(eq? (quote a) (quote a))", &t),
+            ("(eq? Int (type 1))", &t),
+            ("(def here (current-env)) (eq? here here)", &t),
+            ("(eq? (current-env) (current-env))", &t),
+            ("(eq? (current-env) (make-env))", &f),
+            // Lists and functions have no identity yet
+            ("(eq? (list) (list))", &f),
+            ("(def xs (list 1)) (eq? xs xs)", &f),
+            ("(eq? + +)", &f),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(&eval_str(program).unwrap(), expected, "{}", program);
+        }
+    }
+
+    #[test]
+    fn functions_compare_by_structure() {
+        let t = Ast::Atom(LispAtom::Bool(true));
+        let f = Ast::Atom(LispAtom::Bool(false));
+        let cases = [
+            ("(equal? (lambda (x) x) (lambda (x) x))", &t),
+            ("(equal? (lambda (x) (+ x 1)) (lambda (x) (+ x 1)))", &t),
+            ("(equal? (lambda (x) x) (lambda (y) y))", &f),
+            ("(equal? (lambda (x) (+ x 1)) (lambda (x) (+ x 2)))", &f),
+            ("(equal? (lambda (x y) x) (lambda (x) x))", &f),
+            ("(equal? + +)", &t),
+            ("(equal? + -)", &f),
+            // Aliases are registered separately, so they are different builtins
+            ("(equal? % rem)", &f),
+            ("(equal? + (lambda (x y) (+ x y)))", &f),
+        ];
+        for (program, expected) in cases {
+            assert_eq!(&eval_str(program).unwrap(), expected, "{}", program);
+        }
+    }
+
+    #[test]
+    fn gensym_builtin() {
+        let mut env = Environment::outer_new();
+        let mut gensym = |program: &str| {
+            let (_, expr) = parser::parse_expr(program).unwrap();
+            eval::eval_expr(expr, &mut env).unwrap()
+        };
+        let first = gensym("(gensym)");
+        let second = gensym("(gensym)");
+        assert_eq!(first, Ast::Atom(LispAtom::Symbol("G__1".to_string())));
+        assert_ne!(first, second);
+        assert_eq!(
+            gensym("(gensym \"tmp\")"),
+            Ast::Atom(LispAtom::Symbol("tmp3".to_string()))
+        );
+
+        // Generated symbols read back as the same symbol
+        let written = second.to_string();
+        assert_eq!(parser::parse_expr(&written).unwrap(), ("", second));
+
+        assert!(matches!(
+            eval_str("(gensym \"1\")"),
+            Err(LispError::ValueError(_))
+        ));
+        assert!(matches!(eval_str("(gensym 1)"), Err(LispError::TypeError)));
+
+        gensym("(set-fold-case! true)");
+        let folded = [gensym("(gensym)"), gensym("(gensym \"Tmp\")")];
+        let read_back = folded.clone().map(|symbol| {
+            let written = symbol.to_string();
+            parser::with_fold_case(true, || parser::parse_expr(&written).map(|(_, ast)| ast))
+                .unwrap()
+        });
+        assert_eq!(folded[0], Ast::Atom(LispAtom::Symbol("g__4".to_string())));
+        assert_eq!(folded[1], Ast::Atom(LispAtom::Symbol("tmp5".to_string())));
+        assert_eq!(read_back, folded);
+    }
+
+    #[test]
+    fn error_builtin() {
+        let Err(LispError::UserError(message, None)) = eval_str("(error \"bad input\")") else {
+            panic!("expected a user error");
+        };
+        assert_eq!(message, "bad input");
+
+        let result = eval_str("(def x 2) (+ 1 (error \"bad x\" (list (quote x) x)))");
+        let Err(error @ LispError::UserError(_, Some(_))) = result else {
+            panic!("expected a user error with a payload");
+        };
+        let LispError::UserError(_, Some(payload)) = &error else {
+            unreachable!()
+        };
+        assert_eq!(
+            payload,
+            &Ast::List(vec![Ast::Atom(LispAtom::Symbol("x".to_string())), int(2)])
+        );
+        assert!(error.to_string().ends_with("bad x: (x 2)"));
+
+        assert!(matches!(eval_str("(error 1)"), Err(LispError::TypeError)));
+    }
+
+    #[test]
+    fn assert_builtins() {
+        for program in [
+            "(assert (< 1 2))",
+            "(assert 0 \"zero is true\")",
+            "(assert= (+ 1 1) 2)",
+        ] {
+            assert!(
+                matches!(eval_str(program), Ok(Ast::Unspecified)),
+                "{}",
+                program
+            );
+        }
+
+        let Err(LispError::UserError(message, Some(payload))) = eval_str("(assert (< 2 1))") else {
+            panic!("expected a user error");
+        };
+        assert_eq!(message, "assertion failed");
+        assert_eq!(payload, Ast::Atom(LispAtom::Bool(false)));
+
+        let error = eval_str("(assert false \"must hold\")").unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("assertion failed: must hold: false"));
+
+        let result = eval_str("(assert= (list 1 2) (list 1 3) \"lists differ\")");
+        let Err(LispError::UserError(message, Some(payload))) = result else {
+            panic!("expected a user error");
+        };
+        assert_eq!(
+            message,
+            "assertion failed: lists differ: values are not equal"
+        );
+        assert_eq!(payload.to_string(), "{:actual (1 2) :expected (1 3)}");
+        // Like equal?, assert= doesn't treat 1 and 1.0 as the same
+        let error = eval_str("(assert= 1 1.0)").unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("assertion failed: values are not equal: {:actual 1 :expected 1.0}"));
+    }
+
+    #[test]
+    fn every_builtin_is_documented() {
+        for (name, value) in builtins_hashmap() {
+            if let Ast::Function(func) = &value {
+                assert!(
+                    func.doc().is_some_and(|doc| !doc.is_empty()),
+                    "builtin {} has no documentation",
+                    name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn doc_and_help() {
+        assert_eq!(
+            eval_str("(doc +)").unwrap(),
+            Ast::from("(+ num ...): add numbers")
+        );
+        assert_eq!(
+            eval_str("(doc (lambda (x y) (+ x y)))").unwrap(),
+            Ast::from("(lambda (x y) ...)")
+        );
+        assert!(matches!(eval_str("(doc 1)"), Ok(Ast::Unspecified)));
+
+        let (result, output, _) = eval_captured("(help sqrt) (help 1)");
+        assert!(matches!(result, Ok(Ast::Unspecified)));
+        assert_eq!(
+            output,
+            "(sqrt x): the square root of a non-negative number\nno documentation for 1\n"
+        );
+    }
+
+    #[test]
+    fn docstrings() {
+        let program =
+            "(def (greet name) \"Returns a greeting for name.\" (putstr \"greeting\") name) ";
+        assert_eq!(
+            eval_str(&format!("{}(doc greet)", program)).unwrap(),
+            Ast::from("(lambda (name) ...): Returns a greeting for name.")
+        );
+        // The docstring isn't evaluated as part of the body
+        let (result, output, _) = eval_captured(&format!("{}(greet \"bob\")", program));
+        assert_eq!(result.unwrap(), Ast::from("bob"));
+        assert_eq!(output, "greeting\n");
+
+        // A body that is only a string returns it rather than documenting the function
+        assert_eq!(
+            eval_str("(def (f) \"just a string\") (list (f) (doc f))").unwrap(),
+            Ast::List(vec![
+                Ast::from("just a string"),
+                Ast::from("(lambda () ...)")
+            ])
+        );
+        assert_eq!(
+            eval_str("((lambda (x) \"doc\" \"value\") 1)").unwrap(),
+            Ast::from("value")
+        );
+    }
+
+    #[test]
+    fn trace_factorial() {
+        let program = "
+            (def fact (lambda (n) (if (< n 1) 1 (* n (fact (- n 1))))))
+            (def fact (trace fact \"fact\"))
+            (fact 3)";
+        let (result, output, _) = eval_captured(program);
+        assert_eq!(result.unwrap(), int(6));
+        assert_eq!(
+            output,
+            "(fact 3)
+  (fact 2)
+    (fact 1)
+      (fact 0)
+      => 1
+    => 1
+  => 2
+=> 6
+"
+        );
+
+        // Arity errors still happen, and untrace removes the output
+        let (result, output, _) = eval_captured("((trace + \"add\"))");
+        assert!(matches!(result, Err(LispError::BadArity)));
+        assert_eq!(output, "");
+        let (result, output, _) = eval_captured("((untrace (trace +)) 1 2)");
+        assert_eq!(result.unwrap(), int(3));
+        assert_eq!(output, "");
+    }
+
+    #[test]
+    fn trace_depth_recovers_after_errors() {
+        let program = "
+            (def f (trace (lambda (x) (+ x \"a\")) \"f\"))
+            (def g (trace (lambda (x) x) \"g\"))";
+        let mut env = Environment::outer_new();
+        let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+        env.set_output(output.clone());
+        for expr in [program, "(f 1)", "(g 2)"] {
+            let mut rest = expr;
+            while let Ok((next, expr)) = parser::parse_expr(rest) {
+                rest = next;
+                let _ = eval::eval_expr(expr, &mut env);
+            }
+        }
+        let output = String::from_utf8(output.borrow().clone()).unwrap();
+        assert_eq!(output, "(f 1)\n(g 2)\n=> 2\n");
+
+        // Builtins are labelled with their names
+        let (result, output, _) = eval_captured("((trace -) 1)");
+        assert_eq!(result.unwrap(), int(-1));
+        assert_eq!(output, "(- 1)\n=> -1\n");
+    }
+
+    /// A path in the temporary directory that no other test uses.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("callisp-{}-{}", std::process::id(), name));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn read_and_write_files() {
+        let path = temp_path("round-trip.txt");
+        let program = format!(
+            "(write-file {path:?} \"first\") (write-file {path:?} \"héllo, wörld ✓\") (read-file {path:?})"
+        );
+        let result = eval_str(&program);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap(), Ast::from("héllo, wörld ✓"));
+
+        let missing = temp_path("missing.txt");
+        let Err(LispError::IOError(message)) = eval_str(&format!("(read-file {:?})", missing))
+        else {
+            panic!("expected an IO error");
+        };
+        assert!(message.starts_with(&missing), "{}", message);
+        assert!(message.contains("No such file"), "{}", message);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn file_lifecycle() {
+        let t = Ast::Atom(LispAtom::Bool(true));
+        let f = Ast::Atom(LispAtom::Bool(false));
+        let path = temp_path("lifecycle.log");
+        let mut env = Environment::outer_new();
+        env.bind_value("path", path.as_str());
+        let mut run = |program: &str| {
+            let (_, expr) = parser::parse_expr(program).unwrap();
+            eval::eval_expr(expr, &mut env)
+        };
+
+        assert_eq!(run("(file-exists? path)").unwrap(), f);
+        run("(append-file path \"one \")").unwrap();
+        assert_eq!(run("(file-exists? path)").unwrap(), t);
+        run("(append-file path \"two\")").unwrap();
+        assert_eq!(run("(read-file path)").unwrap(), Ast::from("one two"));
+        run("(delete-file path)").unwrap();
+        assert_eq!(run("(file-exists? path)").unwrap(), f);
+
+        let Err(LispError::IOError(message)) = run("(delete-file path)") else {
+            panic!("expected an IO error");
+        };
+        assert!(message.starts_with(&path), "{}", message);
+    }
+
+    #[test]
+    fn print_builtins() {
+        let (result, output, _) = eval_captured(
+            "(print \"a\" 1) (print) (println \" b\" 2.5 (list \"c\" 1/2)) (println) (putstr \"d\") (newline)",
+        );
+        assert!(matches!(result, Ok(Ast::Unspecified)));
+        assert_eq!(output, "a 1 b 2.5 (\"c\" 1/2)\n\nd\n\n");
+        assert!(matches!(eval_str("(putstr 1)"), Err(LispError::TypeError)));
+    }
+
+    fn eval_with_input(input: &str, program: &str) -> Vec<Result<Ast, LispError>> {
+        let mut env = Environment::outer_new();
+        env.set_input(Rc::new(RefCell::new(std::io::Cursor::new(
+            input.as_bytes().to_vec(),
+        ))));
+        let mut rest = program;
+        let mut results = vec![];
+        while let Ok((next, expr)) = parser::parse_expr(rest) {
+            rest = next;
+            results.push(eval::eval_expr(expr, &mut env));
+        }
+        results
+    }
+
+    #[test]
+    fn read_expressions_from_input() {
+        let results = eval_with_input(
+            "(def x\n  (+ 1 \"a)\"))\n  42\nrest of line\n",
+            "(read) (read) (readline) (read)",
+        );
+        assert_eq!(
+            results[0].as_ref().unwrap(),
+            &parser::parse_expr("(def x (+ 1 \"a)\"))").unwrap().1
+        );
+        assert_eq!(results[1].as_ref().unwrap(), &int(42));
+        assert_eq!(results[2].as_ref().unwrap(), &Ast::from("rest of line"));
+        assert!(matches!(results[3], Ok(Ast::Unspecified)));
+    }
+
+    #[test]
+    fn read_leaves_the_rest_of_the_line() {
+        let results = eval_with_input("1 2\n(a\n b) c\n", "(read) (read) (read) (readline)");
+        assert_eq!(results[0].as_ref().unwrap(), &int(1));
+        assert_eq!(results[1].as_ref().unwrap(), &int(2));
+        assert_eq!(
+            results[2].as_ref().unwrap(),
+            &parser::parse_expr("(a b)").unwrap().1
+        );
+        assert_eq!(results[3].as_ref().unwrap(), &Ast::from(" c"));
+
+        let results = eval_with_input("1 )\n", "(read) (read)");
+        assert_eq!(results[0].as_ref().unwrap(), &int(1));
+        assert!(matches!(&results[1], Err(LispError::ParseError(s)) if s == ")"));
+    }
+
+    #[test]
+    fn read_errors_at_end_of_input_mid_expression() {
+        let results = eval_with_input("(1 2\n  (3", "(read)");
+        assert!(matches!(&results[0], Err(LispError::ParseError(s)) if s == "(1 2\n  (3"));
+    }
+
+    #[test]
+    fn read_string_parses_without_evaluating() {
+        assert_eq!(
+            eval_str("(read-string \"(+ 1 2)\")").unwrap(),
+            parser::parse_expr("(+ 1 2)").unwrap().1
+        );
+        assert_eq!(
+            eval_str("(eval (read-string \" (+ 1 2) \"))").unwrap(),
+            int(3)
+        );
+        assert!(matches!(
+            eval_str("(read-string \"(+ 1 2) oops\")"),
+            Err(LispError::ParseError(rest)) if rest == "oops"
+        ));
+        assert!(matches!(
+            eval_str("(read-string \"  \")"),
+            Err(LispError::ValueError(_))
+        ));
+    }
+
+    #[test]
+    fn read_string_round_trips_display() {
+        let (_, value) =
+            parser::parse_expr("(def f (lambda (x) (list x 1/2 2.5 -3 \"s t\" true)))").unwrap();
+        let mut env = Environment::outer_new();
+        env.bind_value("text", value.to_string());
+        env.bind_value("value", value);
+        let (_, expr) = parser::parse_expr("(equal? (read-string text) value)").unwrap();
+        assert_eq!(eval::eval_expr(expr, &mut env).unwrap(), Ast::from(true));
+    }
+
+    #[test]
+    fn read_string_round_trips_escapes() {
+        let value = Ast::List(vec![Ast::from("say \"hi\""), Ast::from("C:\\dir\n")]);
+        let mut env = Environment::outer_new();
+        env.bind_value("text", value.to_string());
+        env.bind_value("value", value);
+        let (_, expr) = parser::parse_expr("(equal? (read-string text) value)").unwrap();
+        assert_eq!(eval::eval_expr(expr, &mut env).unwrap(), Ast::from(true));
+        assert!(matches!(
+            eval_str(r#"(read-string "\"bad \\q escape\"")"#),
+            Err(LispError::ParseError(text)) if text == "\"bad \\q escape\""
+        ));
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn file_ports() {
+        let path = temp_path("port.txt");
+        let program = format!(
+            "(def out (open {path:?} (quote write))) (write-string out \"one\") \
+             (write-string out \" line\ntwo\") (close out) \
+             (def in (open {path:?} (quote read))) \
+             (def lines (list (read-line in) (read-line in) (type (read-line in)))) (close in) lines"
+        );
+        let result = eval_str(&program);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            result.unwrap(),
+            Ast::List(vec![
+                Ast::from("one line"),
+                Ast::from("two"),
+                Ast::Type(LispType::Unspecified)
+            ])
+        );
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn port_errors() {
+        let path = temp_path("port-errors.txt");
+        let misuse = [
+            "(write-string out \"x\") (close out) (close out)",
+            "(read-line out)",
+            "(close out) (write-string out \"x\")",
+            "(write-string stdin \"x\")",
+            "(read-line stdout)",
+        ];
+        for program in misuse {
+            let program = format!("(def out (open {path:?} (quote write))) {program}");
+            assert!(
+                matches!(eval_str(&program), Err(LispError::ValueError(_))),
+                "{}",
+                program
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            eval_str(&format!("(open {path:?} (quote append))")),
+            Err(LispError::ValueError(_))
+        ));
+    }
+
+    #[test]
+    fn standard_ports_follow_the_environment() {
+        let (result, output, _) =
+            eval_captured("(write-string stdout \"hi\") (list (eq? stdout stdout) (type stdin))");
+        assert_eq!(output, "hi");
+        assert_eq!(
+            result.unwrap(),
+            Ast::List(vec![Ast::from(true), Ast::Type(LispType::Port)])
+        );
+
+        let results = eval_with_input("first\nsecond", "(read-line stdin) (read-line stdin)");
+        assert_eq!(results[0].as_ref().unwrap(), &Ast::from("first"));
+        assert_eq!(results[1].as_ref().unwrap(), &Ast::from("second"));
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn binary_files() {
+        let fixture = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/bytes/sample.bin"
+        );
+        let copy = temp_path("copy.bin");
+        let program = format!(
+            "(def data (read-bytes {fixture:?})) (write-bytes {copy:?} data) \
+             (list (count data) (nth data 2) (nth data 9) (equal? (read-bytes {copy:?}) data) data)"
+        );
+        let result = eval_str(&program);
+        std::fs::remove_file(&copy).unwrap();
+        assert_eq!(
+            result.unwrap(),
+            Ast::List(vec![
+                int(12),
+                int(b'c'.into()),
+                int(0xff),
+                Ast::from(true),
+                Ast::Atom(LispAtom::Bytes(b"\x00\x01callisp\xff\xfe\n".to_vec()))
+            ])
+        );
+
+        let Err(LispError::ValueError(message)) =
+            eval_str(&format!("(bytes->string (read-bytes {fixture:?}))"))
+        else {
+            panic!("expected invalid UTF-8 to be an error");
+        };
+        assert_eq!(message, "invalid UTF-8 at byte 9");
+    }
+
+    #[test]
+    fn bytes_and_strings() {
+        assert_eq!(
+            eval_str("(bytes->string (string->bytes \"héllo\"))").unwrap(),
+            Ast::from("héllo")
+        );
+        assert_eq!(
+            eval_str("(count (string->bytes \"héllo\"))").unwrap(),
+            int(6)
+        );
+        assert_eq!(eval_str("(nth #x\"0aff\" 1)").unwrap(), int(255));
+        assert_eq!(eval_str("(nth \"héllo\" 1)").unwrap(), Ast::from("é"));
+        assert_eq!(eval_str("(nth (list 1 2 3) 0)").unwrap(), int(1));
+        for program in ["(nth (list 1 2 3) 3)", "(nth \"abc\" -1)", "(nth #x\"\" 0)"] {
+            assert!(
+                matches!(eval_str(program), Err(LispError::ValueError(_))),
+                "{}",
+                program
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn environment_variables() {
+        let name = format!("CALLISP_TEST_{}", std::process::id());
+        let program = format!(
+            "(def before (getenv {name:?})) (setenv {name:?} \"sét\") \
+             (list before (getenv {name:?}) (get (env-vars) {name:?}))"
+        );
+        let result = eval_str(&program);
+        std::env::remove_var(&name);
+        assert_eq!(
+            result.unwrap(),
+            Ast::List(vec![Ast::from(false), Ast::from("sét"), Ast::from("sét")])
+        );
+
+        for program in ["(setenv \"\" \"x\")", "(setenv \"A=B\" \"x\")"] {
+            assert!(
+                matches!(eval_str(program), Err(LispError::ValueError(_))),
+                "{}",
+                program
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "shell", not(target_arch = "wasm32")))]
+    fn shell_runs_programs() {
+        assert_eq!(
+            eval_str("(shell \"echo\" \"hello\" \"$HOME;\")").unwrap(),
+            Ast::List(vec![int(0), Ast::from("hello $HOME;\n"), Ast::from("")])
+        );
+        assert_eq!(
+            eval_str("(nth (shell \"sh\" \"-c\" \"exit 3\") 0)").unwrap(),
+            int(3)
+        );
+
+        let Err(LispError::IOError(message)) = eval_str("(shell \"callisp-missing-program\")")
+        else {
+            panic!("expected an IO error");
+        };
+        assert!(
+            message.starts_with("callisp-missing-program: "),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn sleep_waits_at_least_the_duration() {
+        let start = std::time::Instant::now();
+        assert!(matches!(
+            eval_str("(sleep 20) (sleep 5.5)"),
+            Ok(Ast::Unspecified)
+        ));
+        assert!(start.elapsed() >= std::time::Duration::from_micros(25_500));
+
+        for program in ["(sleep -1)", "(sleep nan)", "(sleep \"1\")"] {
+            assert!(
+                matches!(eval_str(program), Err(LispError::TypeError)),
+                "{}",
+                program
+            );
+        }
+        // Too long to wait for, rather than a panic
+        for program in ["(sleep 1e300)", "(sleep 1.7e22)"] {
+            assert!(
+                matches!(eval_str(program), Err(LispError::ValueError(_))),
+                "{}",
+                program
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn clocks() {
+        let Ok(Ast::List(times)) = &eval_str(
+            "(list (monotonic-ms) (monotonic-ms) (now) (format-time 86400000 \"%Y-%m-%d\"))",
+        ) else {
+            panic!("expected a list");
+        };
+        let (Ast::Atom(LispAtom::Float(first)), Ast::Atom(LispAtom::Float(second))) =
+            (&times[0], &times[1])
+        else {
+            panic!("expected floats");
+        };
+        assert!(second - first >= 0.0);
+        // Some time after this was written
+        assert!(matches!(times[2], Ast::Atom(LispAtom::Int(ms)) if ms > 1_700_000_000_000));
+        assert_eq!(times[3], Ast::from("1970-01-02"));
+    }
+
+    #[test]
+    fn json_builtins() {
+        let mut env = Environment::outer_new();
+        env.bind_value(
+            "text",
+            r#"{"name": "callisp", "tags": ["lisp", "wasm"], "stars": 3}"#,
+        );
+        let (_, expr) = parser::parse_expr(
+            "(list (get (json-parse text) \"stars\") (json-stringify (json-parse text)))",
+        )
+        .unwrap();
+        assert_eq!(
+            eval::eval_expr(expr, &mut env).unwrap(),
+            Ast::List(vec![
+                int(3),
+                Ast::from(r#"{"name":"callisp","tags":["lisp","wasm"],"stars":3}"#)
+            ])
+        );
+
+        assert!(matches!(
+            eval_str("(json-parse \"[1,\")"),
+            Err(LispError::ParseError(_))
+        ));
+        assert!(matches!(
+            eval_str("(json-stringify (list + 1))"),
+            Err(LispError::ValueError(message)) if message == "cannot convert a function to JSON"
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn regular_expressions() {
+        assert_eq!(
+            eval_str("(re-match? \"^\\\\d+$\" \"123\")").unwrap(),
+            Ast::from(true)
+        );
+        assert_eq!(
+            eval_str("(re-find \"\\\\d+\" \"abc123def45\")").unwrap(),
+            Ast::from("123")
+        );
+        assert_eq!(
+            eval_str("(re-find \"(\\\\w+)@(\\\\w+)(!)?\" \"mail me@host now\")").unwrap(),
+            Ast::List(vec![
+                Ast::from("me@host"),
+                Ast::from("me"),
+                Ast::from("host"),
+                Ast::from(false)
+            ])
+        );
+        assert_eq!(
+            eval_str("(re-find \"x\" \"abc\")").unwrap(),
+            Ast::from(false)
+        );
+        assert_eq!(
+            eval_str("(re-replace \"(\\\\w+)=(\\\\w+)\" \"a=1 b=2\" \"$2=$1\")").unwrap(),
+            Ast::from("1=a 2=b")
+        );
+
+        let Err(LispError::ValueError(message)) = eval_str("(re-match? \"(\" \"\")") else {
+            panic!("expected an invalid pattern to be an error");
+        };
+        assert!(message.contains("unclosed group"), "{}", message);
+    }
+
+    #[test]
+    fn characters() {
+        let ch = |c| Ast::Atom(LispAtom::Char(c));
+        assert_eq!(eval_str("#\\a").unwrap(), ch('a'));
+        assert_eq!(eval_str("(char? #\\a)").unwrap(), Ast::from(true));
+        assert_eq!(eval_str("(char? \"a\")").unwrap(), Ast::from(false));
+        assert_eq!(eval_str("(type #\\a)").unwrap(), Ast::Type(LispType::Char));
+        assert_eq!(eval_str("(char-code #\\λ)").unwrap(), int(0x3bb));
+        assert_eq!(eval_str("(code-char 65)").unwrap(), ch('A'));
+        assert_eq!(
+            eval_str("(string->list \"aλ \")").unwrap(),
+            Ast::List(vec![ch('a'), ch('λ'), ch(' ')])
+        );
+        assert_eq!(
+            eval_str("(list->string (list #\\h #\\x69 #\\newline))").unwrap(),
+            Ast::from("hi\n")
+        );
+        assert_eq!(
+            eval_str("(list->string (string->list \"round trip\"))").unwrap(),
+            Ast::from("round trip")
+        );
+        assert_eq!(
+            eval_str("(equal? (code-char (char-code #\\z)) #\\z)").unwrap(),
+            Ast::from(true)
+        );
+        // Printed bare, like strings, unless they're inside another value
+        let (result, output, _) = eval_captured("(println #\\a #\\space (list #\\b))");
+        result.unwrap();
+        assert_eq!(output, "a   (#\\b)\n");
+
+        assert!(matches!(
+            eval_str("(code-char -1)"),
+            Err(LispError::ValueError(message)) if message == "-1 is not a unicode code point"
+        ));
+        assert!(matches!(
+            eval_str("(code-char 55296)"),
+            Err(LispError::ValueError(_))
+        ));
+        assert!(matches!(
+            eval_str("(char-code \"a\")"),
+            Err(LispError::TypeError)
+        ));
+        assert!(matches!(
+            eval_str("(list->string (list #\\a \"b\"))"),
+            Err(LispError::TypeError)
+        ));
+    }
+
+    #[test]
+    fn vectors() {
+        let vector = |items: Vec<Ast>| Ast::Vector(items);
+        assert_eq!(
+            eval_str("(vector 1 (+ 1 1) \"c\")").unwrap(),
+            vector(vec![int(1), int(2), Ast::from("c")])
+        );
+        assert_eq!(
+            eval_str("(equal? [1 2] (vector 1 2))").unwrap(),
+            Ast::from(true)
+        );
+        assert_eq!(
+            eval_str("(equal? [1 2] (list 1 2))").unwrap(),
+            Ast::from(false)
+        );
+        assert_eq!(eval_str("(vector? [1])").unwrap(), Ast::from(true));
+        assert_eq!(eval_str("(vector? (list 1))").unwrap(), Ast::from(false));
+        assert_eq!(eval_str("(list? [1])").unwrap(), Ast::from(false));
+        assert_eq!(eval_str("(type [])").unwrap(), Ast::Type(LispType::Vector));
+        assert!(matches!(
+            eval_str("(vector-ref [a b c] 1)"),
+            Err(LispError::Undefined(name)) if name == "a"
+        ));
+        assert_eq!(
+            eval_str("(vector-ref (quote [a b c]) 1)")
+                .unwrap()
+                .to_string(),
+            "b"
+        );
+        assert_eq!(
+            eval_str("(vector->list [1 2])").unwrap(),
+            Ast::List(vec![int(1), int(2)])
+        );
+        assert_eq!(
+            eval_str("(list->vector (list 1 2))").unwrap(),
+            vector(vec![int(1), int(2)])
+        );
+        assert_eq!(eval_str("(vector)").unwrap(), vector(vec![]));
+
+        assert!(matches!(
+            eval_str("(vector-ref [1 2] 2)"),
+            Err(LispError::ValueError(message)) if message == "index 2 is out of range for length 2"
+        ));
+        assert!(matches!(
+            eval_str("(vector-ref [1] -1)"),
+            Err(LispError::ValueError(_))
+        ));
+        assert!(matches!(
+            eval_str("(vector-ref (list 1) 0)"),
+            Err(LispError::TypeError)
+        ));
+        assert!(matches!(
+            eval_str("(vector->list (list 1))"),
+            Err(LispError::TypeError)
+        ));
+        assert!(matches!(
+            eval_str("(list->vector [1])"),
+            Err(LispError::TypeError)
+        ));
+    }
+
+    #[test]
+    fn hash_maps() {
+        let show = |input: &str| eval_str(input).unwrap().to_string();
+        assert_eq!(show("(hash-map \"b\" 2 \"a\" 1)"), r#"{"a" 1 "b" 2}"#);
+        assert_eq!(show("(hash-map)"), "{}");
+        assert_eq!(show("(type {})"), "hash-map");
+        assert_eq!(show("(hash-map? {})"), "true");
+        assert_eq!(show("(hash-map? (list))"), "false");
+
+        // Values can be used like any other
+        assert_eq!(eval_str("(+ (get {\"a\" 1} \"a\") 41)").unwrap(), int(42));
+        assert_eq!(eval_str("(get {\"a\" 1} \"b\" 0)").unwrap(), int(0));
+        assert_eq!(
+            eval_str("(get {\"a\" 1} \"b\")").unwrap(),
+            Ast::Atom(LispAtom::Nil)
+        );
+        assert_eq!(eval_str("(* 2 (get (get {1 {2 3}} 1) 2))").unwrap(), int(6));
+
+        // assoc and dissoc make copies
+        let program = "(def m {1 \"one\"}) \
+                       (def more (assoc m 2 \"two\" 1 \"uno\")) \
+                       (def less (dissoc more 1 3)) \
+                       (list m more less)";
+        assert_eq!(show(program), r#"({1 "one"} {1 "uno" 2 "two"} {2 "two"})"#);
+
+        assert_eq!(show("(keys {\"b\" 2 \"a\" 1})"), r#"("a" "b")"#);
+        assert_eq!(show("(vals {\"b\" 2 \"a\" 1})"), "(1 2)");
+        assert_eq!(show("(contains-key? {1 false} 1)"), "true");
+        assert_eq!(show("(contains-key? {1 false} 2)"), "false");
+        assert_eq!(show("(contains-key? {1 false} (list 1))"), "false");
+        assert_eq!(show("(count {1 2 3 4})"), "2");
+        assert_eq!(show("(count [1 2 3])"), "3");
+        assert_eq!(show("(equal? {1 2} {1 2.0})"), "false");
+        assert_eq!(show("(equal? {1 2} (hash-map 1 2))"), "true");
+
+        assert!(matches!(eval_str("(hash-map 1)"), Err(LispError::BadArity)));
+        assert!(matches!(eval_str("(assoc {} 1)"), Err(LispError::BadArity)));
+        assert!(matches!(
+            eval_str("(assoc (list) 1 2)"),
+            Err(LispError::TypeError)
+        ));
+        assert!(matches!(
+            eval_str("(hash-map [1] 2)"),
+            Err(LispError::TypeError)
+        ));
+        assert!(matches!(
+            eval_str("(keys (list))"),
+            Err(LispError::TypeError)
+        ));
+    }
+
+    #[test]
+    fn keyword_literals() {
+        let keyword = |s: &str| Ast::Atom(LispAtom::Keyword(s.to_string()));
+        // Keywords evaluate to themselves rather than being looked up
+        assert_eq!(
+            eval_str(":undefined-anywhere").unwrap(),
+            keyword("undefined-anywhere")
+        );
+        assert_eq!(eval_str("(quote :a)").unwrap(), keyword("a"));
+        assert_eq!(
+            eval_str("(quote (:a (:b)))").unwrap().to_string(),
+            "(:a (:b))"
+        );
+        assert_eq!(
+            eval_str("(equal? :a (keyword \"a\"))").unwrap(),
+            Ast::from(true)
+        );
+        assert_eq!(eval_str("(equal? :a :b)").unwrap(), Ast::from(false));
+        assert_eq!(eval_str("(equal? :a (quote a))").unwrap(), Ast::from(false));
+        assert_eq!(eval_str("(keyword? :a)").unwrap(), Ast::from(true));
+        assert_eq!(eval_str("(keyword? (quote a))").unwrap(), Ast::from(false));
+        assert_eq!(
+            eval_str("(keyword->string :a-b)").unwrap(),
+            Ast::from("a-b")
+        );
+        assert_eq!(eval_str("(string->keyword \"x\")").unwrap(), keyword("x"));
+        assert_eq!(eval_str("(get {:a 1 :b 2} :b)").unwrap(), int(2));
+
+        assert!(matches!(
+            eval_str("(string->keyword \"a b\")"),
+            Err(LispError::ValueError(message)) if message == "\"a b\" is not a valid keyword name"
+        ));
+        assert!(matches!(
+            eval_str("(string->keyword :a)"),
+            Err(LispError::TypeError)
+        ));
+        assert!(matches!(
+            eval_str("(keyword->string \"a\")"),
+            Err(LispError::TypeError)
+        ));
+        assert!(matches!(
+            eval_str("(def :x 1)"),
+            Err(LispError::SyntaxError(message))
+                if message == "can't define :x, since keywords always evaluate to themselves"
+        ));
+    }
+
+    #[test]
+    fn nil() {
+        let show = |input: &str| eval_str(input).unwrap().to_string();
+        assert_eq!(show("nil"), "nil");
+        assert_eq!(show("(list nil (quote nil))"), "(nil nil)");
+        assert_eq!(show("(type nil)"), "nil");
+        // nil is false in conditions, but is its own value
+        assert_eq!(show("(if nil 1 2)"), "2");
+        assert_eq!(show("(not nil)"), "true");
+        assert_eq!(show("(or nil 3)"), "3");
+        assert_eq!(show("(and 1 nil)"), "nil");
+        assert_eq!(show("(equal? nil nil)"), "true");
+        assert_eq!(show("(eq? nil nil)"), "true");
+        assert_eq!(show("(equal? nil false)"), "false");
+        assert_eq!(show("(equal? nil (list))"), "false");
+        assert_eq!(show("(equal? nil (print))"), "false");
+        assert_eq!(show("(nil? nil)"), "true");
+        for value in ["false", "(list)", "0", "(print)", "\"\""] {
+            assert_eq!(show(&format!("(nil? {})", value)), "false", "{}", value);
+        }
+        // Missing values are nil
+        assert_eq!(show("(get (list (list 1 2)) 3)"), "nil");
+        assert_eq!(show("(get {nil 1} nil)"), "1");
+        assert_eq!(show("(json-parse \"[null]\")"), "(nil)");
+        assert_eq!(show("(json-stringify (list nil))"), "\"[null]\"");
+        // A symbol that only starts with nil is still a symbol
+        assert!(matches!(eval_str("nilly"), Err(LispError::Undefined(_))));
+        assert!(matches!(eval_str("(def nil 1)"), Err(LispError::TypeError)));
+    }
+
+    #[test]
+    fn pairs() {
+        let show = |input: &str| eval_str(input).unwrap().to_string();
+        assert_eq!(show("(cons 1 2)"), "(1 . 2)");
+        assert_eq!(show("(cons 1 (cons 2 3))"), "(1 2 . 3)");
+        assert_eq!(show("(cons 1 (list 2 3))"), "(1 2 3)");
+        assert_eq!(show("(cons 1 (list))"), "(1)");
+        assert_eq!(show("(cons (list 1) [2])"), "((1) . [2])");
+        assert_eq!(show("(first (quote (1 2 . 3)))"), "1");
+        assert_eq!(show("(rest (quote (1 2 . 3)))"), "(2 . 3)");
+        assert_eq!(show("(rest (rest (quote (1 2 . 3))))"), "3");
+        assert_eq!(show("(first (list 1 2))"), "1");
+        assert_eq!(show("(rest (list 1 2))"), "(2)");
+        assert_eq!(show("(rest (list 1))"), "()");
+        assert_eq!(show("(equal? (cons 1 2) (quote (1 . 2)))"), "true");
+        assert_eq!(show("(equal? (cons 1 2) (cons 1 3))"), "false");
+        assert_eq!(show("(equal? (cons 1 2) (list 1 2))"), "false");
+        assert_eq!(show("(type (cons 1 2))"), "pair");
+        assert_eq!(show("(list? (cons 1 2))"), "false");
+        assert_eq!(show("(pair? (cons 1 2))"), "true");
+        assert_eq!(show("(pair? (list 1))"), "true");
+        assert_eq!(show("(pair? (list))"), "false");
+
+        assert!(matches!(
+            eval_str("(first (list))"),
+            Err(LispError::ValueError(message)) if message == "first of an empty list"
+        ));
+        assert!(matches!(
+            eval_str("(rest (list))"),
+            Err(LispError::ValueError(_))
+        ));
+        assert!(matches!(eval_str("(first 1)"), Err(LispError::TypeError)));
+        assert!(matches!(
+            eval_str("(1 . 2)"),
+            Err(LispError::SyntaxError(message))
+                if message == "can't evaluate (1 . 2), since it isn't a proper list"
+        ));
+    }
+}
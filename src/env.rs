@@ -2,35 +2,320 @@
 
 use crate::ast::Ast;
 use crate::builtins;
+use crate::error::LispError;
+use crate::json;
+use crate::printer::PrinterSettings;
+use crate::random::Rng;
 
+use std::cell::{Cell, RefCell, RefMut};
 use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use wasm_bindgen::prelude::*;
 
+/// A single scope of bindings. Scopes are shared so that environment handles created with
+/// [Environment::share] see (and can change) the same bindings as the original environment.
+type Scope = Rc<RefCell<HashMap<String, Ast>>>;
+
+/// Somewhere that output from the interpreter is written to.
+pub type Sink = Rc<RefCell<dyn Write>>;
+
+/// Somewhere that input to the interpreter is read from.
+pub type Source = Rc<RefCell<dyn BufRead>>;
+
 /// The environment that expressions are evaluated in.
 #[wasm_bindgen]
 pub struct Environment {
-    bindings: Vec<HashMap<String, Ast>>,
+    bindings: Vec<Scope>,
+    printer: PrinterSettings,
+    fold_case: bool,
+    rng: Rc<RefCell<Rng>>,
+    interrupt: Arc<AtomicBool>,
+    input: Source,
+    unread: Rc<RefCell<String>>,
+    output: Sink,
+    error_output: Sink,
+    unavailable: HashMap<String, String>,
+    at_exit: Rc<RefCell<Vec<Ast>>>,
+    finalizers: Vec<Box<dyn FnOnce()>>,
+    gensym_counter: Rc<Cell<u64>>,
+    trace_depth: usize,
+    loop_depth: usize,
 }
 
 impl Environment {
     /// Create an environment with the specified bindings at the outermost scope.
     pub fn with_binds(bindings: HashMap<String, Ast>) -> Self {
         Self {
-            bindings: vec![bindings],
+            bindings: vec![Rc::new(RefCell::new(bindings))],
+            printer: PrinterSettings::default(),
+            fold_case: false,
+            rng: Rc::new(RefCell::new(Rng::from_time())),
+            interrupt: Arc::new(AtomicBool::new(false)),
+            input: Rc::new(RefCell::new(io::BufReader::new(io::stdin()))),
+            unread: Rc::new(RefCell::new(String::new())),
+            output: Rc::new(RefCell::new(io::stdout())),
+            error_output: Rc::new(RefCell::new(io::stderr())),
+            unavailable: builtins::unavailable_builtins(),
+            at_exit: Rc::new(RefCell::new(vec![])),
+            finalizers: vec![],
+            gensym_counter: Rc::new(Cell::new(0)),
+            trace_depth: 0,
+            loop_depth: 0,
         }
     }
 
-    /// Create an environment with the bindings for all builtins.
+    /// Create an environment with the bindings for all builtins. Debug builds check the builtin
+    /// registry first, so a badly registered builtin is caught as soon as anything runs.
     pub fn outer_new() -> Self {
+        #[cfg(debug_assertions)]
+        builtins::check_registry();
+
+        Self::with_binds(builtins::builtins_hashmap())
+    }
+
+    /// Create a new environment that shares all of the scopes currently in this environment.
+    /// Bindings made through either environment in a shared scope are visible to both, but
+    /// scopes pushed afterwards are not shared. Thunks registered with `at-exit`, the `gensym`
+    /// counter, and the random number generator are shared too, but finalizers registered with
+    /// [Environment::on_drop] stay with the original.
+    pub fn share(&self) -> Self {
         Self {
-            bindings: vec![builtins::builtins_hashmap()],
+            bindings: self.bindings.clone(),
+            printer: self.printer.clone(),
+            fold_case: self.fold_case,
+            rng: self.rng.clone(),
+            interrupt: self.interrupt.clone(),
+            input: self.input.clone(),
+            unread: self.unread.clone(),
+            output: self.output.clone(),
+            error_output: self.error_output.clone(),
+            unavailable: self.unavailable.clone(),
+            at_exit: self.at_exit.clone(),
+            finalizers: vec![],
+            gensym_counter: self.gensym_counter.clone(),
+            trace_depth: self.trace_depth,
+            // recur can't reach a loop running in the original environment
+            loop_depth: 0,
+        }
+    }
+
+    /// Returns true if both environments have exactly the same scopes, such as two handles from
+    /// [Environment::share] with no scopes pushed since.
+    pub(crate) fn same_scopes(&self, other: &Environment) -> bool {
+        self.bindings.len() == other.bindings.len()
+            && self
+                .bindings
+                .iter()
+                .zip(&other.bindings)
+                .all(|(scope, other)| Rc::ptr_eq(scope, other))
+    }
+
+    /// Bind name to a Rust value converted into a Lisp value. Maps become association lists.
+    pub fn bind_value(&mut self, name: &str, value: impl Into<Ast>) {
+        self.bind(name.to_string(), value.into());
+    }
+
+    /// Bind `*args*` to the command-line arguments as a list of strings and `*script-name*` to
+    /// the path of the script being run, or false when there isn't one (such as in the REPL).
+    pub fn bind_args(&mut self, script_name: Option<&str>, args: Vec<String>) {
+        self.bind_value("*args*", args);
+        match script_name {
+            Some(name) => self.bind_value("*script-name*", name),
+            None => self.bind_value("*script-name*", false),
+        }
+    }
+
+    /// Parse a JSON document and bind name to the resulting Lisp value. Objects become
+    /// association lists of `(key value)` pairs.
+    pub fn bind_json(&mut self, name: &str, json: &str) -> Result<(), LispError> {
+        let value = json::parse(json)?;
+        self.bind(name.to_string(), value);
+        Ok(())
+    }
+
+    /// Remove a builtin from this environment, for sandboxing. Using it afterwards gives an error
+    /// saying it requires the given feature rather than just that it is undefined.
+    pub fn restrict(&mut self, name: &str, requirement: &str) {
+        self.bindings[0].borrow_mut().remove(name);
+        self.unavailable
+            .insert(name.to_string(), requirement.to_string());
+    }
+
+    /// Get the feature required by a builtin that isn't available in this environment, if any.
+    pub fn unavailable_requirement(&self, name: &str) -> Option<&str> {
+        self.unavailable.get(name).map(String::as_str)
+    }
+
+    /// Returns true if name is a builtin function that hasn't been restricted. Definitions in the
+    /// program don't affect this, even ones that replace a builtin.
+    pub fn has_builtin(&self, name: &str) -> bool {
+        !self.unavailable.contains_key(name) && builtins::is_builtin_function(name)
+    }
+
+    /// Register a function to run when this environment is dropped, such as one that releases a
+    /// resource held by a builtin. Finalizers run in reverse order of registration.
+    pub fn on_drop(&mut self, f: Box<dyn FnOnce()>) {
+        self.finalizers.push(f);
+    }
+
+    /// Get the next number for `gensym`. Numbers are never reused by an environment or any
+    /// environment shared with it.
+    pub(crate) fn next_gensym(&mut self) -> u64 {
+        let number = self.gensym_counter.get() + 1;
+        self.gensym_counter.set(number);
+        number
+    }
+
+    /// Get the number of traced calls currently running, and change it by delta.
+    pub(crate) fn adjust_trace_depth(&mut self, delta: isize) -> usize {
+        let depth = self.trace_depth;
+        self.trace_depth = depth.saturating_add_signed(delta);
+        depth
+    }
+
+    /// Get the number of `loop` forms currently running, and change it by delta.
+    pub(crate) fn adjust_loop_depth(&mut self, delta: isize) -> usize {
+        let depth = self.loop_depth;
+        self.loop_depth = depth.saturating_add_signed(delta);
+        depth
+    }
+
+    /// Run f as if no `loop` forms were running, such as for the body of a function, so that a
+    /// `recur` in it can't restart a loop in the caller.
+    pub(crate) fn without_loops<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let depth = std::mem::take(&mut self.loop_depth);
+        let res = f(self);
+        self.loop_depth = depth;
+        res
+    }
+
+    /// Register a thunk to be called by [Environment::run_at_exit].
+    pub(crate) fn at_exit(&mut self, thunk: Ast) {
+        self.at_exit.borrow_mut().push(thunk);
+    }
+
+    /// Call the thunks registered with `at-exit` in reverse order of registration. Each thunk is
+    /// only ever called once, and an error in one thunk is written to the error output without
+    /// stopping the rest.
+    ///
+    /// A thunk that calls `exit` isn't an error: the rest still run, and the code it passed is
+    /// returned so that it replaces the program's exit code. If several call `exit`, the last
+    /// one wins.
+    pub fn run_at_exit(&mut self) -> Option<i32> {
+        let mut exit_code = None;
+        loop {
+            // Don't hold the borrow while calling, since a thunk can register more thunks
+            let Some(thunk) = self.at_exit.borrow_mut().pop() else {
+                break;
+            };
+            let result = match &thunk {
+                Ast::Function(func) => func.call(vec![], self),
+                _ => Err(LispError::TypeError),
+            };
+            match result {
+                Ok(_) => (),
+                Err(LispError::Exit(code)) => exit_code = Some(code),
+                Err(e) => {
+                    let _ = self.write_error_output(&format!("{}\n", e));
+                }
+            }
+        }
+        exit_code
+    }
+
+    /// Get the settings used when printing values in display form.
+    pub fn printer(&self) -> &PrinterSettings {
+        &self.printer
+    }
+
+    /// Get a mutable reference to the printer settings so they can be changed.
+    pub fn printer_mut(&mut self) -> &mut PrinterSettings {
+        &mut self.printer
+    }
+
+    /// Returns true if symbols read for this environment are converted to lowercase.
+    pub fn fold_case(&self) -> bool {
+        self.fold_case
+    }
+
+    /// Turn case folding on or off for expressions read for this environment afterwards, such as
+    /// by the REPL, `use`, and `read`. See [crate::parser::with_fold_case].
+    pub fn set_fold_case(&mut self, fold_case: bool) {
+        self.fold_case = fold_case;
+    }
+
+    /// Get the random number generator used by the random builtins. It is shared with every
+    /// environment created by [Environment::share], so handles don't repeat each other's numbers.
+    pub fn rng(&self) -> RefMut<'_, Rng> {
+        self.rng.borrow_mut()
+    }
+
+    /// Read input (for the REPL and reading builtins) from source instead of stdin. Input given
+    /// back with [Environment::unread_input] is dropped, since it came from the old source.
+    pub fn set_input(&mut self, source: Source) {
+        self.input = source;
+        self.unread.borrow_mut().clear();
+    }
+
+    /// Read a line from the input source, including the trailing newline if there is one.
+    /// Returns `None` at the end of the input.
+    pub(crate) fn read_input_line(&self) -> Result<Option<String>, LispError> {
+        let unread = std::mem::take(&mut *self.unread.borrow_mut());
+        if !unread.is_empty() {
+            return Ok(Some(unread));
+        }
+        read_source_line(&self.input).map_err(|e| LispError::io("input", e))
+    }
+
+    /// Give back the rest of a line that was read but not used, such as the text after the
+    /// expression taken by `read`. The next read from the input starts with it.
+    pub(crate) fn unread_input(&self, text: &str) {
+        self.unread.borrow_mut().insert_str(0, text);
+    }
+
+    /// Send normal output (from printing builtins) to sink instead of stdout.
+    pub fn set_output(&mut self, sink: Sink) {
+        self.output = sink;
+    }
+
+    /// Send diagnostic output (from debugging builtins) to sink instead of stderr.
+    pub fn set_error_output(&mut self, sink: Sink) {
+        self.error_output = sink;
+    }
+
+    /// Write text to the output sink.
+    pub(crate) fn write_output(&self, text: &str) -> Result<(), LispError> {
+        write_sink(&self.output, text).map_err(|e| LispError::io("output", e))
+    }
+
+    /// Write text to the error output sink.
+    pub(crate) fn write_error_output(&self, text: &str) -> Result<(), LispError> {
+        write_sink(&self.error_output, text).map_err(|e| LispError::io("error output", e))
+    }
+
+    /// Get the flag used to interrupt evaluation. Setting it from another thread (such as a
+    /// Ctrl-C handler) makes the current evaluation stop with [LispError::Interrupted].
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        self.interrupt.clone()
+    }
+
+    /// Return an error if evaluation has been interrupted, clearing the interrupt.
+    pub(crate) fn check_interrupt(&self) -> Result<(), LispError> {
+        if self.interrupt.swap(false, Ordering::Relaxed) {
+            Err(LispError::Interrupted)
+        } else {
+            Ok(())
         }
     }
 
     /// Add an empty scope to the environment.
     pub fn new_scope(&mut self, bindings: HashMap<String, Ast>) {
-        self.bindings.push(bindings);
+        self.bindings.push(Rc::new(RefCell::new(bindings)));
     }
 
     /// Remove the top scope from the environment.
@@ -38,13 +323,23 @@ impl Environment {
         self.bindings.pop();
     }
 
+    /// Run f with only the outermost (global) scope visible. Local scopes are hidden for the
+    /// duration of the call and restored afterwards, so bindings created by f go into the global
+    /// scope.
+    pub fn in_global_scope<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let locals = self.bindings.split_off(1);
+        let res = f(self);
+        self.bindings.truncate(1);
+        self.bindings.extend(locals);
+        res
+    }
+
     /// Get the Ast matching a string stored in the bindings of the environment.
     pub fn get(&self, binding: &str) -> Option<Ast> {
         self.bindings
             .iter()
             .rev()
-            .find(|map| map.contains_key(binding))
-            .and_then(|map| map.get(binding).cloned())
+            .find_map(|map| map.borrow().get(binding).cloned())
     }
 
     /// Set a new binding in the environment. Will overwrite current binding if one exists.
@@ -52,8 +347,201 @@ impl Environment {
     /// TODO: Don't overwrite binding.
     pub fn bind(&mut self, binding: String, value: Ast) {
         self.bindings
-            .last_mut()
+            .last()
             .expect("empty environment")
+            .borrow_mut()
             .insert(binding, value);
     }
+
+    /// Replace the value of an existing binding, searching from the innermost scope outwards.
+    /// Returns [LispError::Undefined] if name isn't bound in any scope.
+    pub fn set_existing(&mut self, name: &str, value: Ast) -> Result<(), LispError> {
+        for scope in self.bindings.iter().rev() {
+            if let Some(slot) = scope.borrow_mut().get_mut(name) {
+                *slot = value;
+                return Ok(());
+            }
+        }
+        Err(LispError::Undefined(name.to_string()))
+    }
+}
+
+/// Write text to sink and flush it, so that prompts show up before waiting for input.
+pub(crate) fn write_sink(sink: &Sink, text: &str) -> io::Result<()> {
+    let mut sink = sink.borrow_mut();
+    sink.write_all(text.as_bytes())?;
+    sink.flush()
+}
+
+/// Read a line from source, including the trailing newline if there is one. Returns `None` at the
+/// end of the input.
+pub(crate) fn read_source_line(source: &Source) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    let read = source.borrow_mut().read_line(&mut line)?;
+    Ok((read > 0).then_some(line))
+}
+
+/// Returns true if two environment handles are the same handle or share the same scopes. A handle
+/// that is being evaluated in can't be looked at, so it is only equal to itself.
+pub(crate) fn same_env(a: &Rc<RefCell<Environment>>, b: &Rc<RefCell<Environment>>) -> bool {
+    Rc::ptr_eq(a, b)
+        || match (a.try_borrow(), b.try_borrow()) {
+            (Ok(a), Ok(b)) => a.same_scopes(&b),
+            _ => false,
+        }
+}
+
+impl Drop for Environment {
+    fn drop(&mut self) {
+        while let Some(finalizer) = self.finalizers.pop() {
+            finalizer();
+        }
+    }
+}
+
+impl Debug for Environment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Printing every binding would be huge (and could recurse forever if an environment is
+        // bound inside itself), so only show the shape.
+        write!(f, "Environment {{ scopes: {} }}", self.bindings.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::LispAtom;
+    use crate::eval::eval_expr;
+    use crate::parser::parse_expr;
+
+    fn eval_in(input: &str, env: &mut Environment) -> Ast {
+        let (_, expr) = parse_expr(input).unwrap();
+        eval_expr(expr, env).unwrap()
+    }
+
+    #[test]
+    fn finalizers_run_in_reverse_order() {
+        let order = Rc::new(RefCell::new(vec![]));
+        let mut env = Environment::outer_new();
+        for i in 1..=3 {
+            let order = order.clone();
+            env.on_drop(Box::new(move || order.borrow_mut().push(i)));
+        }
+
+        // Dropping a shared environment doesn't run the original's finalizers
+        drop(env.share());
+        assert!(order.borrow().is_empty());
+
+        drop(env);
+        assert_eq!(*order.borrow(), [3, 2, 1]);
+    }
+
+    #[test]
+    fn finalizers_run_with_handle_in_scope() {
+        let ran = Rc::new(Cell::new(false));
+        let mut env = Environment::outer_new();
+        let flag = ran.clone();
+        env.on_drop(Box::new(move || flag.set(true)));
+        // The handle keeps the global scope alive, but not the environment that owns the finalizers
+        eval_in("(def here (current-env))", &mut env);
+        drop(env);
+        assert!(ran.get());
+    }
+
+    #[test]
+    fn handle_bound_in_its_own_scope_leaks() {
+        let mut env = Environment::outer_new();
+        let global = Rc::downgrade(&env.bindings[0]);
+        eval_in("(def x 1)", &mut env);
+        drop(env);
+        assert!(global.upgrade().is_none());
+
+        // The handle holds the global scope that holds the handle, so the scope is never freed
+        let mut env = Environment::outer_new();
+        let global = Rc::downgrade(&env.bindings[0]);
+        eval_in("(def here (current-env))", &mut env);
+        drop(env);
+        assert!(global.upgrade().is_some());
+    }
+
+    #[test]
+    fn handles_share_the_rng() {
+        let mut env = Environment::outer_new();
+        eval_in("(random-seed 7)", &mut env);
+        let first = eval_in("(random)", &mut env);
+        eval_in("(random-seed 7)", &mut env);
+        let handle = eval_in("(current-env)", &mut env);
+        eval_in("(random)", &mut env);
+        let Ast::Env(handle) = &handle else {
+            panic!("expected an environment handle");
+        };
+        // The handle continues the same sequence instead of starting over from the seed
+        let second = eval_in("(random)", &mut handle.borrow_mut());
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn at_exit_runs_once() {
+        let mut env = Environment::outer_new();
+        eval_in("(def runs 0)", &mut env);
+        eval_in(
+            "(at-exit (lambda () (eval (quote (def runs (+ runs 1))))))",
+            &mut env,
+        );
+        assert_eq!(eval_in("runs", &mut env), Ast::from(0));
+
+        env.run_at_exit();
+        env.run_at_exit();
+        assert_eq!(eval_in("runs", &mut env), Ast::from(1));
+    }
+
+    #[test]
+    fn bind_value_deep_nesting() {
+        let mut inner = HashMap::new();
+        inner.insert("port".to_string(), vec![8080, 8081]);
+        let mut outer = HashMap::new();
+        outer.insert("server".to_string(), inner);
+
+        let mut env = Environment::outer_new();
+        env.bind_value("config", outer);
+        env.bind_value("name", "callisp");
+
+        let port = eval_in("(get (get config \"server\") \"port\")", &mut env);
+        assert_eq!(port, Ast::from(vec![8080, 8081]));
+        assert_eq!(eval_in("name", &mut env), Ast::from("callisp"));
+    }
+
+    #[test]
+    fn bind_json_mixed_array() {
+        let mut env = Environment::outer_new();
+        env.bind_json(
+            "data",
+            r#"{"items": [1, 2.5, "three", true, {"deep": [[]]}]}"#,
+        )
+        .unwrap();
+
+        let items = eval_in("(get data \"items\")", &mut env);
+        let Ast::List(items) = &items else {
+            panic!("expected list");
+        };
+        assert_eq!(items[0], Ast::from(1));
+        assert_eq!(items[1], Ast::from(2.5));
+        assert_eq!(items[2], Ast::from("three"));
+        assert_eq!(items[3], Ast::Atom(LispAtom::Bool(true)));
+        assert_eq!(
+            items[4],
+            Ast::List(vec![Ast::List(vec![
+                Ast::from("deep"),
+                Ast::List(vec![Ast::List(vec![])])
+            ])])
+        );
+    }
+
+    #[test]
+    fn bind_json_parse_failure() {
+        let mut env = Environment::outer_new();
+        let result = env.bind_json("data", "{\"a\": }");
+        assert!(matches!(result, Err(LispError::ParseError(_))));
+        assert!(env.get("data").is_none());
+    }
 }
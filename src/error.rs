@@ -4,11 +4,14 @@ use std::fmt::Display;
 
 use colored::Colorize;
 
+use crate::ast::Ast;
+
 /// All the possible errors that can occur inside the interpreter.
 #[derive(Debug)]
 pub enum LispError {
-    /// Error with input/output. Usually means a filesystem error occured.
-    IOError,
+    /// Error with input/output. Usually means a filesystem error occured. Stores what was being
+    /// read or written and the reason it failed.
+    IOError(String),
 
     /// Error parsing an expression.
     ParseError(String),
@@ -16,26 +19,104 @@ pub enum LispError {
     /// Constant or function not defined.
     Undefined(String),
 
+    /// A builtin that exists in other builds but not this one. Stores the name of the builtin and
+    /// the feature or platform it requires.
+    Unavailable(String, String),
+
     /// Type error.
     /// TODO: Add "expected" and "got"
     TypeError,
 
+    /// A type error that says what was expected and what was given instead.
+    TypeMismatch(String),
+
     /// Function called with incorrect number of arguments.
     BadArity,
+
+    /// A special form was used with invalid syntax.
+    SyntaxError(String),
+
+    /// Evaluation was interrupted by the user.
+    Interrupted,
+
+    /// A value of the correct type but outside the range an operation accepts.
+    ValueError(String),
+
+    /// Division (or remainder) by zero.
+    DivideByZero,
+
+    /// An exact result is too large to represent (integers grow as needed, but Rationals don't),
+    /// or an integer is too large for an operation that needs 64 bits. Stores the operation or
+    /// value that overflowed.
+    Overflow(String),
+
+    /// An error from evaluating a tag in a template, with the byte offset of the tag.
+    InTemplate(usize, Box<LispError>),
+
+    /// An error raised by a program with `error`, with its message and optional payload.
+    UserError(String, Option<Ast>),
+
+    /// Not really an error: `exit` was called with the given exit code. This unwinds evaluation
+    /// so that the interpreter can clean up before exiting.
+    Exit(i32),
+
+    /// Not really an error: `recur` was called with new values for the bindings of the innermost
+    /// `loop`. This unwinds evaluation back to the loop, so looping doesn't grow the stack.
+    Recur(Vec<Ast>),
+}
+
+impl LispError {
+    /// An IOError for an error that happened while reading or writing target.
+    pub(crate) fn io(target: impl Display, error: std::io::Error) -> Self {
+        LispError::IOError(format!("{}: {}", target, error))
+    }
 }
 
 impl Display for LispError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            LispError::IOError => write!(f, "{}", "ERROR: IO error.".red()),
+            LispError::IOError(msg) => write!(f, "{} {}", "ERROR: IO error:".red(), msg),
             LispError::ParseError(expr) => {
                 write!(f, "{} {}", "ERROR: Could not parse expression:".red(), expr)
             }
             LispError::Undefined(ident) => {
                 write!(f, "{} {}", "ERROR: Undefined identifier:".red(), ident)
             }
-            LispError::TypeError => todo!(),
-            LispError::BadArity => todo!(),
+            LispError::Unavailable(name, requirement) => write!(
+                f,
+                "{} {} is not available in this build (requires {})",
+                "ERROR:".red(),
+                name,
+                requirement
+            ),
+            LispError::TypeError => write!(f, "{}", "ERROR: Type error.".red()),
+            LispError::TypeMismatch(msg) => write!(f, "{} {}", "ERROR: Type error:".red(), msg),
+            LispError::BadArity => {
+                write!(f, "{}", "ERROR: Wrong number of arguments.".red())
+            }
+            LispError::SyntaxError(msg) => {
+                write!(f, "{} {}", "ERROR: Invalid syntax:".red(), msg)
+            }
+            LispError::Interrupted => write!(f, "{}", "interrupted".red()),
+            LispError::ValueError(msg) => {
+                write!(f, "{} {}", "ERROR: Invalid value:".red(), msg)
+            }
+            LispError::DivideByZero => write!(f, "{}", "ERROR: Division by zero.".red()),
+            LispError::Overflow(op) => {
+                write!(f, "{} {}", "ERROR: Integer overflow in".red(), op)
+            }
+            LispError::InTemplate(offset, error) => {
+                write!(f, "{} (in template at offset {})", error, offset)
+            }
+            LispError::UserError(message, payload) => {
+                write!(f, "{} {}", "ERROR:".red(), message)?;
+                match payload {
+                    Some(payload) => write!(f, ": {}", payload),
+                    None => Ok(()),
+                }
+            }
+            LispError::Exit(code) => write!(f, "exit with code {}", code),
+            LispError::Recur(_) => write!(f, "{} recur used outside of loop", "ERROR:".red()),
         }
     }
 }
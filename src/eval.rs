@@ -6,19 +6,30 @@ use crate::error::LispError;
 use crate::special_forms::{eval_special_form, SPECIAL_FORMS};
 
 /// Evaluate a lisp expression.
-pub fn eval_expr(input: Ast, env: &mut Environment) -> Result<Ast, LispError> {
-    match input {
+pub fn eval_expr(mut input: Ast, env: &mut Environment) -> Result<Ast, LispError> {
+    env.check_interrupt()?;
+
+    match &mut input {
         Ast::List(list) => match list.first() {
             Some(Ast::Atom(LispAtom::Symbol(symbol))) => {
                 if let Some(special_form) = SPECIAL_FORMS.get(symbol.as_str()) {
-                    eval_special_form(list, env, special_form)
+                    eval_special_form(std::mem::take(list), env, special_form)
                 } else {
-                    eval_list(list, env)
+                    eval_list(std::mem::take(list), env)
                 }
             }
-            _ => eval_list(list, env),
+            _ => eval_list(std::mem::take(list), env),
         },
-        Ast::Atom(LispAtom::Symbol(symbol)) => eval_symbol(&symbol, env), // Symbols get looked up in environment
+        Ast::Pair(..) => Err(LispError::SyntaxError(format!(
+            "can't evaluate {}, since it isn't a proper list",
+            input
+        ))),
+        Ast::Vector(items) => std::mem::take(items)
+            .into_iter()
+            .map(|item| eval_expr(item, env))
+            .collect::<Result<_, _>>()
+            .map(Ast::Vector),
+        Ast::Atom(LispAtom::Symbol(symbol)) => eval_symbol(symbol, env), // Symbols get looked up in environment
         _ => Ok(input), // Atoms and functions return themselves
     }
 }
@@ -38,7 +49,7 @@ fn eval_list(list: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
         .map(|ast| eval_expr(ast, env))
         .collect::<Result<_, LispError>>()?;
 
-    if let Ast::Function(func) = func {
+    if let Ast::Function(func) = &func {
         if func.arity(args.len()) {
             func.call(args, env)
         } else {
@@ -52,5 +63,137 @@ fn eval_list(list: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
 fn eval_symbol(symbol: &str, env: &mut Environment) -> Result<Ast, LispError> {
     // Look up symbol in environment
     env.get(symbol)
-        .ok_or(LispError::Undefined(symbol.to_string()))
+        .ok_or_else(|| match env.unavailable_requirement(symbol) {
+            Some(requirement) => {
+                LispError::Unavailable(symbol.to_string(), requirement.to_string())
+            }
+            None => LispError::Undefined(symbol.to_string()),
+        })
+}
+
+/// Parse the next expression in input the way env reads, so `set-fold-case!` affects the
+/// expressions after it.
+#[cfg(test)]
+fn read_next<'a>(input: &'a str, env: &Environment) -> nom::IResult<&'a str, Ast> {
+    crate::parser::with_fold_case(env.fold_case(), || crate::parser::parse_expr(input))
+}
+
+/// Parse and evaluate every expression in input in a fresh environment, returning the value of
+/// the last one. Used by tests.
+#[cfg(test)]
+pub(crate) fn eval_str(input: &str) -> Result<Ast, LispError> {
+    let mut env = Environment::outer_new();
+    let mut to_parse = input;
+    let mut result = Ast::Unspecified;
+    while let Ok((rest, expr)) = read_next(to_parse, &env) {
+        to_parse = rest;
+        result = eval_expr(expr, &mut env)?;
+    }
+    Ok(result)
+}
+
+/// Evaluate input with output and error output captured, returning the result and both
+/// outputs.
+#[cfg(test)]
+pub(crate) fn eval_captured(input: &str) -> (Result<Ast, LispError>, String, String) {
+    let output = std::rc::Rc::new(std::cell::RefCell::new(Vec::<u8>::new()));
+    let error_output = std::rc::Rc::new(std::cell::RefCell::new(Vec::<u8>::new()));
+    let mut env = Environment::outer_new();
+    env.set_output(output.clone());
+    env.set_error_output(error_output.clone());
+
+    let mut to_parse = input;
+    let mut result = Ok(Ast::Unspecified);
+    while let Ok((rest, expr)) = read_next(to_parse, &env) {
+        to_parse = rest;
+        result = eval_expr(expr, &mut env);
+        if result.is_err() {
+            break;
+        }
+    }
+
+    let output = String::from_utf8(output.borrow().clone()).unwrap();
+    let error_output = String::from_utf8(error_output.borrow().clone()).unwrap();
+    (result, output, error_output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_expr;
+
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use std::time::Duration;
+
+    fn eval_in(input: &str, env: &mut Environment) -> Result<Ast, LispError> {
+        let (_, expr) = parse_expr(input).unwrap();
+        eval_expr(expr, env)
+    }
+
+    #[test]
+    fn interrupt_long_evaluation() {
+        let mut env = Environment::outer_new();
+        eval_in(
+            "(def fib (lambda (n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))))",
+            &mut env,
+        )
+        .unwrap();
+
+        let flag = env.interrupt_flag();
+        let interrupter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            flag.store(true, Ordering::Relaxed);
+        });
+
+        let result = eval_in("(fib 100)", &mut env);
+        interrupter.join().unwrap();
+        assert!(matches!(result, Err(LispError::Interrupted)));
+
+        // The environment is still usable afterwards
+        assert_eq!(eval_in("(fib 10)", &mut env).unwrap(), Ast::from(55));
+    }
+
+    #[test]
+    fn vectors_evaluate_their_items() {
+        let mut env = Environment::outer_new();
+        eval_in("(def x 2)", &mut env).unwrap();
+        assert_eq!(
+            eval_in("[x (+ x 1) [x]]", &mut env).unwrap(),
+            Ast::Vector(vec![
+                Ast::from(2),
+                Ast::from(3),
+                Ast::Vector(vec![Ast::from(2)])
+            ])
+        );
+        // The first item isn't called
+        assert_eq!(
+            eval_in("[+ 1]", &mut env).unwrap().to_string(),
+            "[<function> 1]"
+        );
+        assert!(matches!(
+            eval_in("[1 undefined-name]", &mut env),
+            Err(LispError::Undefined(name)) if name == "undefined-name"
+        ));
+    }
+
+    #[test]
+    fn map_literals_evaluate_keys_and_values() {
+        let mut env = Environment::outer_new();
+        eval_in("(def k \"key\")", &mut env).unwrap();
+        let map = eval_in("{k (+ 1 2) 1 {2 [k]}}", &mut env).unwrap();
+        assert_eq!(map.to_string(), r#"{"key" 3 1 {2 ["key"]}}"#);
+        assert_eq!(
+            eval_in(
+                "(equal? {k 3 1 {2 [k]}} (hash-map 1 (hash-map 2 [\"key\"]) \"key\" 3))",
+                &mut env
+            )
+            .unwrap(),
+            Ast::from(true)
+        );
+        assert!(matches!(
+            eval_in("{(list 1) 2}", &mut env),
+            Err(LispError::TypeError)
+        ));
+    }
 }
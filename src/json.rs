@@ -0,0 +1,581 @@
+//! Contains a small JSON parser that converts JSON text into Lisp values, and the reverse.
+//!
+//! Objects become association lists of `(key value)` pairs with string keys, arrays become lists,
+//! and null becomes nil. Since objects and arrays are both lists, an array of two-item arrays with
+//! string first items, like `[["a", 1]]`, reads as the same value as `{"a": 1}`, and is written
+//! back as an object.
+
+use crate::ast::{format_float, Ast, LispAtom, LispType};
+use crate::bigint::BigInt;
+use crate::error::LispError;
+use crate::rational;
+
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+/// Arrays and objects nested deeper than this are an error, rather than overflowing the stack.
+const MAX_DEPTH: usize = 512;
+
+/// Parse a JSON document into a Lisp value.
+pub(crate) fn parse(input: &str) -> Result<Ast, LispError> {
+    let mut parser = JsonParser {
+        input,
+        chars: input.char_indices().peekable(),
+        depth: 0,
+    };
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    match parser.chars.peek() {
+        None => Ok(value),
+        Some(&(offset, _)) => Err(parser.error(offset, "trailing characters after JSON value")),
+    }
+}
+
+struct JsonParser<'a> {
+    input: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    depth: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn error(&self, offset: usize, msg: &str) -> LispError {
+        LispError::ParseError(format!("invalid JSON at offset {}: {}", offset, msg))
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars
+            .peek()
+            .map(|&(offset, _)| offset)
+            .unwrap_or(self.input.len())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.chars.peek(), Some((_, ' ' | '\t' | '\n' | '\r'))) {
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), LispError> {
+        let offset = self.offset();
+        match self.chars.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            _ => Err(self.error(offset, &format!("expected '{}'", expected))),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Ast, LispError> {
+        self.skip_whitespace();
+        let offset = self.offset();
+        match self.chars.peek() {
+            Some((_, '{' | '[')) if self.depth == MAX_DEPTH => {
+                Err(self.error(offset, "nested too deeply"))
+            }
+            Some((_, '{')) => {
+                self.depth += 1;
+                let object = self.parse_object();
+                self.depth -= 1;
+                object
+            }
+            Some((_, '[')) => {
+                self.depth += 1;
+                let array = self.parse_array();
+                self.depth -= 1;
+                array
+            }
+            Some((_, '"')) => Ok(Ast::Atom(LispAtom::String(self.parse_string()?))),
+            Some((_, '-' | '0'..='9')) => self.parse_number(),
+            Some((_, 't')) => self.parse_literal("true", Ast::Atom(LispAtom::Bool(true))),
+            Some((_, 'f')) => self.parse_literal("false", Ast::Atom(LispAtom::Bool(false))),
+            Some((_, 'n')) => self.parse_literal("null", Ast::Atom(LispAtom::Nil)),
+            Some(_) => Err(self.error(offset, "unexpected character")),
+            None => Err(self.error(offset, "unexpected end of input")),
+        }
+    }
+
+    fn parse_literal(&mut self, literal: &str, value: Ast) -> Result<Ast, LispError> {
+        let offset = self.offset();
+        if self.input[offset..].starts_with(literal) {
+            for _ in literal.chars() {
+                self.chars.next();
+            }
+            Ok(value)
+        } else {
+            Err(self.error(offset, &format!("expected {}", literal)))
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Ast, LispError> {
+        self.expect('{')?;
+        let mut pairs = vec![];
+        self.skip_whitespace();
+        if matches!(self.chars.peek(), Some((_, '}'))) {
+            self.chars.next();
+            return Ok(Ast::List(pairs));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let offset = self.offset();
+            if !matches!(self.chars.peek(), Some((_, '"'))) {
+                return Err(self.error(offset, "expected string key"));
+            }
+            let key = Ast::Atom(LispAtom::String(self.parse_string()?));
+            self.skip_whitespace();
+            self.expect(':')?;
+            let value = self.parse_value()?;
+            pairs.push(Ast::List(vec![key, value]));
+
+            self.skip_whitespace();
+            let offset = self.offset();
+            match self.chars.next() {
+                Some((_, ',')) => continue,
+                Some((_, '}')) => return Ok(Ast::List(pairs)),
+                _ => return Err(self.error(offset, "expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Ast, LispError> {
+        self.expect('[')?;
+        let mut items = vec![];
+        self.skip_whitespace();
+        if matches!(self.chars.peek(), Some((_, ']'))) {
+            self.chars.next();
+            return Ok(Ast::List(items));
+        }
+
+        loop {
+            items.push(self.parse_value()?);
+            self.skip_whitespace();
+            let offset = self.offset();
+            match self.chars.next() {
+                Some((_, ',')) => continue,
+                Some((_, ']')) => return Ok(Ast::List(items)),
+                _ => return Err(self.error(offset, "expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, LispError> {
+        let start = self.offset();
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            let Some((offset, c)) = self.chars.next() else {
+                return Err(self.error(start, "unterminated string"));
+            };
+            match c {
+                '"' => return Ok(string),
+                '\\' => string.push(self.parse_escape(offset)?),
+                c if c.is_control() => {
+                    return Err(self.error(offset, "control character in string"))
+                }
+                c => string.push(c),
+            }
+        }
+    }
+
+    fn parse_escape(&mut self, offset: usize) -> Result<char, LispError> {
+        match self.chars.next() {
+            Some((_, '"')) => Ok('"'),
+            Some((_, '\\')) => Ok('\\'),
+            Some((_, '/')) => Ok('/'),
+            Some((_, 'b')) => Ok('\u{8}'),
+            Some((_, 'f')) => Ok('\u{c}'),
+            Some((_, 'n')) => Ok('\n'),
+            Some((_, 'r')) => Ok('\r'),
+            Some((_, 't')) => Ok('\t'),
+            Some((_, 'u')) => {
+                let high = self.parse_hex4(offset)?;
+                if !(0xD800..0xDC00).contains(&high) {
+                    return char::from_u32(high)
+                        .ok_or_else(|| self.error(offset, "invalid unicode escape"));
+                }
+
+                // High surrogate, must be followed by an escaped low surrogate
+                if self.chars.next().map(|(_, c)| c) != Some('\\')
+                    || self.chars.next().map(|(_, c)| c) != Some('u')
+                {
+                    return Err(self.error(offset, "unpaired surrogate in unicode escape"));
+                }
+                let low = self.parse_hex4(offset)?;
+                if !(0xDC00..0xE000).contains(&low) {
+                    return Err(self.error(offset, "unpaired surrogate in unicode escape"));
+                }
+                let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+                char::from_u32(code).ok_or_else(|| self.error(offset, "invalid unicode escape"))
+            }
+            _ => Err(self.error(offset, "invalid escape sequence")),
+        }
+    }
+
+    fn parse_hex4(&mut self, offset: usize) -> Result<u32, LispError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .chars
+                .next()
+                .and_then(|(_, c)| c.to_digit(16))
+                .ok_or_else(|| self.error(offset, "invalid unicode escape"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn parse_number(&mut self) -> Result<Ast, LispError> {
+        let start = self.offset();
+        let mut is_float = false;
+        while let Some(&(_, c)) = self.chars.peek() {
+            match c {
+                '0'..='9' | '-' | '+' => (),
+                '.' | 'e' | 'E' => is_float = true,
+                _ => break,
+            }
+            self.chars.next();
+        }
+        let text = &self.input[start..self.offset()];
+        let invalid = || self.error(start, &format!("invalid number {}", text));
+        if !is_json_number(text) {
+            return Err(invalid());
+        }
+
+        if is_float {
+            match text.parse::<f64>() {
+                Ok(num) if num.is_finite() => Ok(Ast::Atom(LispAtom::Float(num))),
+                // Like 1e999, which would be an infinity
+                Ok(_) => Err(self.error(start, &format!("number {} is out of range", text))),
+                Err(_) => Err(invalid()),
+            }
+        } else {
+            // Integers too large for an i64 are big ints, so they keep every digit
+            text.parse::<BigInt>().map(Ast::from).map_err(|_| invalid())
+        }
+    }
+}
+
+/// Returns true if text follows the JSON grammar for numbers: an optional minus sign, an integer
+/// part with no leading zeros, then an optional fraction and exponent, each with at least one
+/// digit.
+fn is_json_number(text: &str) -> bool {
+    fn digits(text: &str) -> (&str, &str) {
+        let end = text
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len());
+        text.split_at(end)
+    }
+
+    let text = text.strip_prefix('-').unwrap_or(text);
+    let (int, mut rest) = digits(text);
+    if int.is_empty() || (int.len() > 1 && int.starts_with('0')) {
+        return false;
+    }
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let (fraction, after) = digits(fraction);
+        if fraction.is_empty() {
+            return false;
+        }
+        rest = after;
+    }
+    if let Some(exponent) = rest.strip_prefix(['e', 'E']) {
+        let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        let (exponent, after) = digits(exponent);
+        if exponent.is_empty() {
+            return false;
+        }
+        rest = after;
+    }
+    rest.is_empty()
+}
+
+/// Convert a Lisp value into JSON text. Non-empty lists where every item is a `(key value)` pair
+/// with a string key become objects, so association lists round-trip, and other lists become
+/// arrays. Hash-maps with string keys become objects too, and vectors become arrays. Keyword keys
+/// are written as strings, so `:a` becomes `"a"`. Rationals become floats. Values with no JSON
+/// form, like functions and symbols, are an error.
+pub(crate) fn stringify(value: &Ast) -> Result<String, LispError> {
+    let mut output = String::new();
+    write_value(value, &mut output, 0)?;
+    Ok(output)
+}
+
+fn write_value(value: &Ast, output: &mut String, depth: usize) -> Result<(), LispError> {
+    if depth == MAX_DEPTH {
+        return Err(LispError::ValueError(
+            "value is nested too deeply to convert to JSON".to_string(),
+        ));
+    }
+    let unsupported = || {
+        LispError::ValueError(format!(
+            "cannot convert a {} to JSON",
+            LispType::from(value).name()
+        ))
+    };
+
+    match value {
+        Ast::Atom(LispAtom::Int(num)) => output.push_str(&num.to_string()),
+        Ast::Atom(LispAtom::BigInt(num)) => output.push_str(&num.to_string()),
+        Ast::Atom(LispAtom::Float(num)) if num.is_finite() => output.push_str(&format_float(*num)),
+        Ast::Atom(LispAtom::Float(num)) => {
+            return Err(LispError::ValueError(format!(
+                "cannot convert {} to JSON",
+                format_float(*num)
+            )))
+        }
+        Ast::Atom(LispAtom::Rational(num, den)) => {
+            output.push_str(&format_float(rational::to_float((*num, *den))))
+        }
+        Ast::Atom(LispAtom::String(string)) => write_string(string, output),
+        Ast::Atom(LispAtom::Bool(b)) => output.push_str(&b.to_string()),
+        Ast::Atom(LispAtom::Nil) | Ast::Unspecified => output.push_str("null"),
+        Ast::List(items)
+            if !items.is_empty() && items.iter().all(|item| as_pair(item).is_some()) =>
+        {
+            output.push('{');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    output.push(',');
+                }
+                let (key, value) = as_pair(item).expect("checked above");
+                write_string(key, output);
+                output.push(':');
+                write_value(value, output, depth + 1)?;
+            }
+            output.push('}');
+        }
+        Ast::List(items) | Ast::Vector(items) => {
+            output.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    output.push(',');
+                }
+                write_value(item, output, depth + 1)?;
+            }
+            output.push(']');
+        }
+        Ast::Map(map) => {
+            output.push('{');
+            for (i, (key, value)) in map.iter().enumerate() {
+                let Some(key) = key_name(&key) else {
+                    return Err(LispError::ValueError(format!(
+                        "cannot convert a hash-map with the key {} to JSON",
+                        key
+                    )));
+                };
+                if i > 0 {
+                    output.push(',');
+                }
+                write_string(key, output);
+                output.push(':');
+                write_value(value, output, depth + 1)?;
+            }
+            output.push('}');
+        }
+        _ => return Err(unsupported()),
+    }
+    Ok(())
+}
+
+/// Get the key and value of a `(key value)` pair with a string or keyword key.
+fn as_pair(item: &Ast) -> Option<(&str, &Ast)> {
+    match item {
+        Ast::List(pair) => match pair.as_slice() {
+            [key, value] => Some((key_name(key)?, value)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The name an object key is written with: strings as they are, and keywords without the `:`.
+fn key_name(key: &Ast) -> Option<&str> {
+    match key {
+        Ast::Atom(LispAtom::String(key) | LispAtom::Keyword(key)) => Some(key),
+        _ => None,
+    }
+}
+
+fn write_string(string: &str, output: &mut String) {
+    output.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if c.is_control() => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::LispMap;
+
+    fn string(s: &str) -> Ast {
+        Ast::Atom(LispAtom::String(s.to_string()))
+    }
+
+    fn int(num: i64) -> Ast {
+        Ast::Atom(LispAtom::Int(num))
+    }
+
+    #[test]
+    fn parse_scalars() {
+        assert_eq!(parse("1").unwrap(), int(1));
+        assert_eq!(parse("-2.5e1").unwrap(), Ast::Atom(LispAtom::Float(-25.0)));
+        assert_eq!(parse(" true ").unwrap(), Ast::Atom(LispAtom::Bool(true)));
+        assert_eq!(parse("null").unwrap(), Ast::Atom(LispAtom::Nil));
+        assert_eq!(parse("\"hi\"").unwrap(), string("hi"));
+    }
+
+    #[test]
+    fn parse_numbers() {
+        assert_eq!(parse("0").unwrap(), int(0));
+        assert_eq!(parse("-0").unwrap(), int(0));
+        assert_eq!(parse("-9223372036854775808").unwrap(), int(i64::MIN));
+        let big = parse("[123456789012345678901234567890, -9223372036854775809]").unwrap();
+        assert_eq!(
+            big.to_string(),
+            "(123456789012345678901234567890 -9223372036854775809)"
+        );
+        assert_eq!(parse("0.5").unwrap(), Ast::Atom(LispAtom::Float(0.5)));
+        assert_eq!(parse("1E+2").unwrap(), Ast::Atom(LispAtom::Float(100.0)));
+        assert_eq!(parse("-0.0e-1").unwrap(), Ast::Atom(LispAtom::Float(-0.0)));
+
+        for input in [
+            "01", "-01", "00", "+1", "[+1]", "-", "1.", ".5", "1.e5", "1e", "1e+", "--1", "1-2",
+            "1e5.0", "0x10", "1e999", "-1e999",
+        ] {
+            assert!(
+                matches!(parse(input), Err(LispError::ParseError(_))),
+                "parsed {:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn parse_escapes() {
+        assert_eq!(
+            parse(r#""a\"b\\c\nd\u00e9\ud83d\ude00""#).unwrap(),
+            string("a\"b\\c\nd\u{e9}\u{1F600}")
+        );
+    }
+
+    #[test]
+    fn parse_nested() {
+        let ast = parse(r#"{"a": [1, "two", {"b": false}], "c": {}}"#).unwrap();
+        let expected = Ast::List(vec![
+            Ast::List(vec![
+                string("a"),
+                Ast::List(vec![
+                    int(1),
+                    string("two"),
+                    Ast::List(vec![Ast::List(vec![
+                        string("b"),
+                        Ast::Atom(LispAtom::Bool(false)),
+                    ])]),
+                ]),
+            ]),
+            Ast::List(vec![string("c"), Ast::List(vec![])]),
+        ]);
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn parse_malformed() {
+        for input in [
+            "",
+            "{",
+            "[1, 2",
+            "[1 2]",
+            "{\"a\" 1}",
+            "{1: 2}",
+            "\"abc",
+            "\"\\q\"",
+            "tru",
+            "1 2",
+            "\"\\ud83d\"",
+        ] {
+            assert!(
+                matches!(parse(input), Err(LispError::ParseError(_))),
+                "parsed {:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn parse_deeply_nested() {
+        let deep = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+        assert!(matches!(parse(&deep), Err(LispError::ParseError(_))));
+    }
+
+    #[test]
+    fn stringify_values() {
+        let input = r#"{"a":[1,-2.5,"t\"w\\o\n\u0001é",{"b":false}],"c":[]}"#;
+        let value = parse(input).unwrap();
+        assert_eq!(stringify(&value).unwrap(), input);
+        assert_eq!(parse(&stringify(&value).unwrap()).unwrap(), value);
+
+        assert_eq!(
+            stringify(&Ast::List(vec![
+                int(1),
+                Ast::Atom(LispAtom::Rational(1, 2)),
+                Ast::Atom(LispAtom::Nil),
+                Ast::Unspecified
+            ]))
+            .unwrap(),
+            "[1,0.5,null,null]"
+        );
+        // Pairs without string keys are just arrays
+        assert_eq!(
+            stringify(&Ast::List(vec![Ast::List(vec![int(1), int(2)])])).unwrap(),
+            "[[1,2]]"
+        );
+
+        let mut map = LispMap::new();
+        map.insert(
+            &Ast::from("b"),
+            Ast::Vector(vec![int(1), Ast::Vector(vec![])]),
+        )
+        .unwrap();
+        map.insert(&Ast::from("a"), Ast::Map(LispMap::new()))
+            .unwrap();
+        assert_eq!(stringify(&Ast::Map(map)).unwrap(), r#"{"a":{},"b":[1,[]]}"#);
+
+        // Keyword keys are written as strings
+        let keyword = |name: &str| Ast::Atom(LispAtom::Keyword(name.to_string()));
+        let mut map = LispMap::new();
+        map.insert(&keyword("a"), int(1)).unwrap();
+        assert_eq!(stringify(&Ast::Map(map)).unwrap(), r#"{"a":1}"#);
+        assert_eq!(
+            stringify(&Ast::List(vec![Ast::List(vec![keyword("b"), int(2)])])).unwrap(),
+            r#"{"b":2}"#
+        );
+    }
+
+    #[test]
+    fn stringify_unsupported() {
+        for value in [
+            Ast::Atom(LispAtom::Symbol("x".to_string())),
+            Ast::Atom(LispAtom::Float(f64::NAN)),
+            Ast::List(vec![Ast::Type(LispType::Int)]),
+            Ast::Map({
+                let mut map = LispMap::new();
+                map.insert(&int(1), int(2)).unwrap();
+                map
+            }),
+        ] {
+            assert!(
+                matches!(stringify(&value), Err(LispError::ValueError(_))),
+                "stringified {:?}",
+                value
+            );
+        }
+    }
+}
@@ -106,12 +106,20 @@ impl LexError {
     }
 }
 
-#[derive(Debug)]
 enum LexErrKind {
     UnclosedString,
     UnexpectedChar(char),
 }
 
+impl Debug for LexErrKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnclosedString => write!(f, "UnclosedString"),
+            Self::UnexpectedChar(c) => write!(f, "UnexpectedChar({:?})", c),
+        }
+    }
+}
+
 fn is_ident_char(c: char) -> bool {
     !['(', ')', '"', ';'].contains(&c) && !c.is_whitespace()
 }
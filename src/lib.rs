@@ -0,0 +1,126 @@
+//! # callisp - Callum's Lisp
+//!
+//! This is a Lisp interpreter. Doesn't do much yet.
+
+#![warn(missing_docs)]
+
+use std::{fs::read_to_string, path::PathBuf};
+
+use ast::Ast;
+use error::LispError;
+
+pub mod analysis;
+pub mod ast;
+pub mod bigint;
+mod builtins;
+mod compiler;
+pub mod env;
+pub mod error;
+pub mod eval;
+mod json;
+mod lexer;
+pub mod map;
+pub mod parser;
+pub mod port;
+pub mod printer;
+pub mod promise;
+pub mod random;
+mod rational;
+pub mod repl;
+mod special_forms;
+mod template;
+mod time;
+mod vm;
+pub mod xref;
+
+/// Parse and evaluate every expression in a file.
+pub fn execute_file(filename: PathBuf, env: &mut env::Environment) -> Result<Ast, LispError> {
+    let contents = read_to_string(&filename).map_err(|e| LispError::io(filename.display(), e))?;
+
+    // Parse the whole file first, so nothing runs if any of it is malformed
+    for expr in parser::parse_program(&contents, env.fold_case())? {
+        eval::eval_expr(expr, env)?;
+    }
+
+    Ok(Ast::Unspecified)
+}
+
+/// Run a file as the main program. If the file runs to the end or calls `exit`, the thunks
+/// registered with `at-exit` are called. Returns the exit code, which a thunk can replace by
+/// calling `exit` itself.
+pub fn run_script(filename: PathBuf, env: &mut env::Environment) -> Result<i32, LispError> {
+    let code = match execute_file(filename, env) {
+        Ok(_) => 0,
+        Err(LispError::Exit(code)) => code,
+        Err(e) => return Err(e),
+    };
+    Ok(env.run_at_exit().unwrap_or(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::Environment;
+
+    fn run_fixture(name: &str) -> (i32, Environment) {
+        let mut env = Environment::outer_new();
+        let path = PathBuf::from("tests/fixtures/at_exit").join(name);
+        let code = run_script(path, &mut env).unwrap();
+        (code, env)
+    }
+
+    #[test]
+    fn at_exit_on_completion() {
+        let (code, env) = run_fixture("complete.lisp");
+        assert_eq!(code, 0);
+        // Thunks run in reverse order, so log is 0 * 10 + 2, then 2 * 10 + 1
+        assert_eq!(env.get("log"), Some(Ast::from(21)));
+    }
+
+    #[test]
+    fn at_exit_on_exit() {
+        let (code, env) = run_fixture("exit.lisp");
+        assert_eq!(code, 1);
+        // The thunk ran, and nothing after the call to exit did
+        assert_eq!(env.get("log"), Some(Ast::from(1)));
+    }
+
+    #[test]
+    fn comments_in_files() {
+        let mut env = Environment::outer_new();
+        let path = PathBuf::from("tests/fixtures/comments/only.lisp");
+        assert!(matches!(execute_file(path, &mut env), Ok(Ast::Unspecified)));
+
+        let path = PathBuf::from("tests/fixtures/comments/commented.lisp");
+        execute_file(path, &mut env).unwrap();
+        assert_eq!(env.get("x"), Some(Ast::from(3)));
+    }
+
+    #[test]
+    fn unclosed_block_comment_in_file() {
+        let mut env = Environment::outer_new();
+        let path = PathBuf::from("tests/fixtures/comments/unclosed_block.lisp");
+        match execute_file(path, &mut env) {
+            Err(LispError::ParseError(message)) => {
+                assert_eq!(message, "line 6, column 1\n#| never closed\n^")
+            }
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+        // Nothing in the file ran
+        assert_eq!(env.get("x"), None);
+    }
+
+    #[test]
+    fn parse_errors_give_the_line() {
+        let mut env = Environment::outer_new();
+        let path = PathBuf::from("tests/fixtures/parse_errors/unclosed_paren.lisp");
+        match execute_file(path, &mut env) {
+            // The list that was never closed, not the end of the file
+            Err(LispError::ParseError(message)) => {
+                assert_eq!(message, "line 3, column 1\n(define z (+ x y)\n^")
+            }
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+        assert_eq!(env.get("x"), None);
+    }
+}
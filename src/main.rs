@@ -1,68 +1,60 @@
 //! # callisp - Callum's Lisp
 //!
-//! This is a Lisp interpreter. Doesn't do much yet.
+//! Command line interface for the interpreter. Runs a file or starts the REPL.
 
-#![warn(missing_docs)]
-
-use std::{fs::read_to_string, path::PathBuf};
+use std::path::PathBuf;
 
 use structopt::StructOpt;
 
-use ast::Ast;
-use env::Environment;
-use error::LispError;
-
-mod ast;
-mod builtins;
-mod compiler;
-mod env;
-mod error;
-mod eval;
-mod lexer;
-mod parser;
-mod repl;
-mod special_forms;
-mod vm;
+use callisp::env::Environment;
+use callisp::{repl, run_script, xref};
 
 #[derive(Debug, StructOpt)]
-#[structopt(name = "callisp", about = "Simple Lisp interpreter.")]
+#[structopt(
+    name = "callisp",
+    about = "Simple Lisp interpreter.",
+    setting = structopt::clap::AppSettings::TrailingVarArg
+)]
 struct Opt {
     /// Input file. If left empty, will start REPL instead.
     #[structopt(parse(from_os_str))]
     file: Option<PathBuf>,
-}
 
-fn execute_file(filename: PathBuf, env: &mut env::Environment) -> Result<Ast, LispError> {
-    let contents = read_to_string(filename).map_err(|_| LispError::IOError)?;
-    let mut to_parse = contents.as_str();
-    let mut exprs = vec![];
+    /// Arguments passed to the script as `*args*`.
+    #[structopt(requires = "file")]
+    args: Vec<String>,
 
-    // Parse whole file
-    while let Ok((rest, expr)) = parser::parse_expr(to_parse) {
-        to_parse = rest;
-        exprs.push(expr);
-    }
+    /// Print a cross-reference of the definitions in the input file and the files it uses instead
+    /// of running it.
+    #[structopt(long, requires = "file")]
+    xref: bool,
 
-    if !to_parse.is_empty() {
-        return Err(LispError::ParseError(to_parse.to_string()));
-    }
-
-    for expr in exprs {
-        eval::eval_expr(expr, env)?;
-    }
-
-    Ok(Ast::Unspecified)
+    /// Read symbols case-insensitively by converting them to lowercase.
+    #[structopt(long)]
+    fold_case: bool,
 }
 
 fn main() {
     let opt = Opt::from_args();
 
-    if let Some(file) = opt.file {
+    if let (true, Some(file)) = (opt.xref, &opt.file) {
+        match xref::xref(file, opt.fold_case) {
+            Ok(table) => print!("{}", table),
+            Err(e) => eprintln!("{}", e),
+        }
+    } else if let Some(file) = opt.file {
         let mut env = Environment::outer_new();
-        if let Err(e) = execute_file(file, &mut env) {
-            eprintln!("{}", e);
+        env.set_fold_case(opt.fold_case);
+        env.bind_args(Some(&file.to_string_lossy()), opt.args);
+        match run_script(file, &mut env) {
+            Ok(code) => {
+                // Run finalizers before exiting
+                drop(env);
+                std::process::exit(code);
+            }
+            Err(e) => eprintln!("{}", e),
         }
     } else {
-        repl::repl();
+        repl::repl(opt.fold_case);
     }
 }
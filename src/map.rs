@@ -0,0 +1,175 @@
+//! Contains [LispMap], the value created by `hash-map` and `{...}` literals.
+
+use crate::ast::{Ast, LispAtom};
+use crate::error::LispError;
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+/// A key in a [LispMap]. Keys are atoms, so they can be ordered without looking inside lists or
+/// functions. Atoms of different kinds are never the same key, so `1` and `1.0` are different
+/// keys, and floats are compared with `total_cmp` so that every key equals itself, even NaN.
+#[derive(Clone, Debug)]
+pub struct MapKey(LispAtom);
+
+impl MapKey {
+    /// The position of the kind of atom in the order of keys.
+    fn rank(&self) -> u8 {
+        match self.0 {
+            LispAtom::Symbol(_) => 0,
+            LispAtom::String(_) => 1,
+            LispAtom::Int(_) => 2,
+            LispAtom::BigInt(_) => 3,
+            LispAtom::Float(_) => 4,
+            LispAtom::Rational(..) => 5,
+            LispAtom::Bool(_) => 6,
+            LispAtom::Keyword(_) => 7,
+            LispAtom::Bytes(_) => 8,
+            LispAtom::Char(_) => 9,
+            LispAtom::Nil => 10,
+        }
+    }
+}
+
+impl Ord for MapKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (&self.0, &other.0) {
+            (LispAtom::Symbol(a), LispAtom::Symbol(b))
+            | (LispAtom::String(a), LispAtom::String(b))
+            | (LispAtom::Keyword(a), LispAtom::Keyword(b)) => a.cmp(b),
+            (LispAtom::Int(a), LispAtom::Int(b)) => a.cmp(b),
+            (LispAtom::BigInt(a), LispAtom::BigInt(b)) => a.cmp(b),
+            (LispAtom::Float(a), LispAtom::Float(b)) => a.total_cmp(b),
+            (LispAtom::Rational(a, b), LispAtom::Rational(c, d)) => (a, b).cmp(&(c, d)),
+            (LispAtom::Bool(a), LispAtom::Bool(b)) => a.cmp(b),
+            (LispAtom::Bytes(a), LispAtom::Bytes(b)) => a.cmp(b),
+            (LispAtom::Char(a), LispAtom::Char(b)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for MapKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MapKey {}
+
+impl TryFrom<&Ast> for MapKey {
+    type Error = LispError;
+
+    /// Only atoms can be keys. Anything else is a type error.
+    fn try_from(value: &Ast) -> Result<Self, Self::Error> {
+        match value {
+            Ast::Atom(atom) => Ok(Self(atom.clone())),
+            _ => Err(LispError::TypeError),
+        }
+    }
+}
+
+/// A map from atoms to values. The entries are kept in order of their keys, so two maps with the
+/// same entries always display the same way.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LispMap(BTreeMap<MapKey, Ast>);
+
+impl LispMap {
+    /// Create an empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the value of a key, replacing any value it already has. The key must be an atom.
+    pub fn insert(&mut self, key: &Ast, value: Ast) -> Result<(), LispError> {
+        self.0.insert(MapKey::try_from(key)?, value);
+        Ok(())
+    }
+
+    /// Remove a key and return its value, if it has one.
+    pub fn remove(&mut self, key: &Ast) -> Option<Ast> {
+        self.0.remove(&MapKey::try_from(key).ok()?)
+    }
+
+    /// Get the value of a key. Keys that aren't atoms are never in a map.
+    pub fn get(&self, key: &Ast) -> Option<&Ast> {
+        self.0.get(&MapKey::try_from(key).ok()?)
+    }
+
+    /// Returns true if the map has a value for key.
+    pub fn contains_key(&self, key: &Ast) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// The number of entries.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns true if the map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The keys and values, in order of the keys.
+    pub fn iter(&self) -> impl Iterator<Item = (Ast, &Ast)> {
+        self.0
+            .iter()
+            .map(|(key, value)| (Ast::Atom(key.0.clone()), value))
+    }
+}
+
+impl Display for LispMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{{")?;
+        for (i, (key, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{} {}", key.0, value)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_atoms() {
+        let mut map = LispMap::new();
+        map.insert(&Ast::from(1), Ast::from("int")).unwrap();
+        map.insert(&Ast::Atom(LispAtom::Float(1.0)), Ast::from("float"))
+            .unwrap();
+        map.insert(&Ast::from("b"), Ast::from(2)).unwrap();
+        map.insert(&Ast::from("a"), Ast::from(1)).unwrap();
+        map.insert(&Ast::Atom(LispAtom::Float(f64::NAN)), Ast::from(0))
+            .unwrap();
+        map.insert(&Ast::from("a"), Ast::from(3)).unwrap();
+        assert_eq!(map.len(), 5);
+        assert_eq!(map.get(&Ast::from("a")), Some(&Ast::from(3)));
+        assert_eq!(map.get(&Ast::from(1)), Some(&Ast::from("int")));
+        assert_eq!(
+            map.get(&Ast::Atom(LispAtom::Float(f64::NAN))),
+            Some(&Ast::from(0))
+        );
+        assert!(matches!(
+            map.insert(&Ast::List(vec![]), Ast::from(1)),
+            Err(LispError::TypeError)
+        ));
+        assert_eq!(map.get(&Ast::List(vec![])), None);
+
+        assert_eq!(map.remove(&Ast::from("b")), Some(Ast::from(2)));
+        assert!(!map.contains_key(&Ast::from("b")));
+        // Strings come before numbers, and each kind is in order
+        assert_eq!(map.to_string(), r#"{"a" 3 1 "int" 1.0 "float" nan 0}"#);
+    }
+}
@@ -1,25 +1,99 @@
 //! Contains parser created using the nom crate.
 
-use crate::ast::{Ast, LispAtom};
+use crate::ast::{Ast, LispAtom, CHAR_NAMES};
+use crate::bigint::BigInt;
+use crate::error::LispError;
+use crate::rational;
 
 use nom::branch::alt;
-use nom::bytes::complete::{tag, take_while};
-use nom::character::complete::{char, digit1, multispace0, multispace1, satisfy};
-use nom::combinator::{cut, map, opt, recognize};
-use nom::multi::separated_list0;
-use nom::sequence::{delimited, preceded, terminated, tuple};
+use nom::bytes::complete::{tag, take_while, take_while1, take_while_m_n};
+use nom::character::complete::{char, multispace1, satisfy};
+use nom::combinator::{cut, map, map_opt, not, opt, recognize, verify};
+use nom::multi::{many0_count, many1_count, separated_list0};
+use nom::sequence::{delimited, pair, preceded, terminated, tuple};
 use nom::IResult;
 
+use std::cell::{Cell, RefCell};
+
+/// Parses the text after a reader macro's prefix. Returning a nom error lets the built-in syntax
+/// try instead, while a failure is reported as a parse error where it happened, so errors (and
+/// the remaining input) must point into the text the handler was given.
+pub type ReaderMacro = fn(&str) -> IResult<&str, Ast>;
+
+thread_local! {
+    // Only set for the duration of with_fold_case
+    static FOLD_CASE: Cell<bool> = const { Cell::new(false) };
+    static READER_MACROS: RefCell<Vec<(String, ReaderMacro)>> = const { RefCell::new(vec![]) };
+    // How many expressions parse_expr is currently inside
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// How deeply lists, vectors, maps, and quotes can be nested. Parsing is recursive, so deeper
+/// input is an error rather than overflowing the stack.
+pub const MAX_DEPTH: usize = 1000;
+
+/// Run parse with case folding turned on or off. When it is on, symbols are converted to
+/// lowercase as they are read, so `(DEF X 3)` is the same as `(def x 3)`. Every symbol is folded,
+/// including type names, so folded code refers to types by aliases like `<int>`. String contents
+/// are never changed. The setting only lasts for the call, so it comes from whatever is reading,
+/// such as an environment, rather than leaking into other reads.
+pub fn with_fold_case<T>(fold_case: bool, parse: impl FnOnce() -> T) -> T {
+    let previous = FOLD_CASE.with(|cell| cell.replace(fold_case));
+    let result = parse();
+    FOLD_CASE.with(|cell| cell.set(previous));
+    result
+}
+
+/// Add literal syntax for expressions parsed on this thread afterwards. Atoms starting with prefix
+/// are parsed by handler, which is given the text after the prefix, before any of the built-in
+/// atom syntax is tried. If several prefixes match, the longest one is used. Registering a prefix
+/// again replaces its handler.
+///
+/// # Panics
+///
+/// Panics if prefix is empty.
+pub fn register_reader_macro(prefix: &str, handler: ReaderMacro) {
+    assert!(!prefix.is_empty(), "reader macro prefixes can't be empty");
+    READER_MACROS.with(|macros| {
+        let mut macros = macros.borrow_mut();
+        match macros.iter_mut().find(|(existing, _)| existing == prefix) {
+            Some((_, existing)) => *existing = handler,
+            None => macros.push((prefix.to_string(), handler)),
+        }
+    });
+}
+
+/// Parse an atom with the reader macro for the longest registered prefix of input.
+fn parse_reader_macro(input: &str) -> IResult<&str, Ast> {
+    let handler = READER_MACROS.with(|macros| {
+        macros
+            .borrow()
+            .iter()
+            .filter(|(prefix, _)| input.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(prefix, handler)| (prefix.len(), *handler))
+    });
+    match handler {
+        // The handler is called after the borrow ends, since it may parse nested expressions
+        Some((length, handler)) => handler(&input[length..]),
+        None => Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Tag,
+        ))),
+    }
+}
+
 /// Parse a lisp expression. input must only contain the expression and nothing else (except for
-/// whitespace). Used only in REPL.
+/// whitespace and comments). Used only in REPL.
 pub fn parse_complete_expr(input: &str) -> IResult<&str, Ast> {
-    if input.is_empty() {
-        return IResult::Ok((input, Ast::Unspecified));
+    if skip_ignored(input).is_empty() {
+        return IResult::Ok(("", Ast::Unspecified));
     }
     let (remaining, ast) = parse_expr(input)?;
+    let remaining = skip_ignored(remaining);
     if !remaining.is_empty() {
         IResult::Err(nom::Err::Error(nom::error::Error {
-            input,
+            input: remaining,
             code: nom::error::ErrorKind::Complete,
         }))
     } else {
@@ -27,73 +101,588 @@ pub fn parse_complete_expr(input: &str) -> IResult<&str, Ast> {
     }
 }
 
-/// Parse a lisp expression.
+/// Parse every expression in a program, like the contents of a file. Whitespace and comments
+/// between and after the expressions are skipped. If an expression can't be parsed, the error
+/// gives the line and column where it went wrong.
+///
+/// A first line starting with `#!` is skipped too, so a script can be run directly with
+/// `#!/usr/bin/env callisp` at the top. `#!` anywhere else is an error. Symbols are converted to
+/// lowercase if fold_case is true, as with [with_fold_case].
+pub fn parse_program(input: &str, fold_case: bool) -> Result<Vec<Ast>, LispError> {
+    let exprs = with_fold_case(fold_case, || parse_exprs(input))?;
+    Ok(exprs.into_iter().map(|(_, expr)| expr).collect())
+}
+
+/// Where something starts in the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    /// The line, counted from 1.
+    pub line: usize,
+
+    /// The column, counted in characters from 1.
+    pub column: usize,
+}
+
+/// Parse every expression in a program like [parse_program], along with the position each
+/// expression starts at.
+pub fn parse_program_located(
+    input: &str,
+    fold_case: bool,
+) -> Result<Vec<(Position, Ast)>, LispError> {
+    with_fold_case(fold_case, || parse_exprs(input))
+}
+
+fn parse_exprs(input: &str) -> Result<Vec<(Position, Ast)>, LispError> {
+    let mut to_parse = skip_ignored(skip_shebang(input));
+    let mut exprs = vec![];
+    while !to_parse.is_empty() {
+        match parse_expr(to_parse) {
+            Ok((rest, expr)) => {
+                exprs.push((position(input, input.len() - to_parse.len()), expr));
+                to_parse = skip_ignored(rest);
+            }
+            // Failures know where the problem is, like the start of an unclosed block comment
+            Err(nom::Err::Failure(e)) => return Err(error_for(input, e)),
+            Err(_) => return Err(error_at(input, to_parse)),
+        }
+    }
+    Ok(exprs)
+}
+
+/// The input after a `#!` line at the very start, or all of it if it doesn't start with one.
+fn skip_shebang(input: &str) -> &str {
+    match input.strip_prefix("#!") {
+        Some(rest) => rest.find('\n').map_or("", |i| &rest[i..]),
+        None => input,
+    }
+}
+
+/// The position of the byte at offset in input.
+fn position(input: &str, offset: usize) -> Position {
+    let line_start = input[..offset].rfind('\n').map_or(0, |i| i + 1);
+    Position {
+        line: input[..offset].matches('\n').count() + 1,
+        column: input[line_start..offset].chars().count() + 1,
+    }
+}
+
+/// A ParseError for input that couldn't be parsed from rest onwards, where rest is the end of
+/// input. The error gives the line and column (both counted from 1) and shows the line with a
+/// caret under the column.
+fn error_at(input: &str, rest: &str) -> LispError {
+    let offset = input.len() - skip_ignored(rest).len();
+    let line_start = input[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = input[offset..]
+        .find('\n')
+        .map_or(input.len(), |i| offset + i);
+    let Position { line, column } = position(input, offset);
+    let before = &input[line_start..offset];
+    // Keep tabs so the caret lines up however wide they are shown
+    let indent: String = before
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    LispError::ParseError(format!(
+        "line {}, column {}\n{}\n{}^",
+        line,
+        column,
+        input[line_start..line_end].trim_end_matches('\r'),
+        indent
+    ))
+}
+
+/// The ParseError for parsing input failing with error, which says if the input was nested too
+/// deeply.
+fn error_for(input: &str, error: nom::error::Error<&str>) -> LispError {
+    match (error.code, error_at(input, error.input)) {
+        (nom::error::ErrorKind::TooLarge, LispError::ParseError(message)) => LispError::ParseError(
+            format!("nested more than {} deep at {}", MAX_DEPTH, message),
+        ),
+        (_, located) => located,
+    }
+}
+
+/// The ParseError for parsing input failing with error.
+pub(crate) fn error_from(input: &str, error: nom::Err<nom::error::Error<&str>>) -> LispError {
+    match error {
+        nom::Err::Error(e) | nom::Err::Failure(e) => error_for(input, e),
+        nom::Err::Incomplete(_) => error_at(input, ""),
+    }
+}
+
+/// Returns true if input ends inside a string, a block comment, or a list, vector, or map that
+/// hasn't been closed, so reading more input could still complete it.
+pub(crate) fn is_incomplete(input: &str) -> bool {
+    let mut depth = 0;
+    let mut block_depth = 0;
+    let mut in_string = false;
+    let mut in_comment = false;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => in_comment = false,
+            _ if in_comment => {}
+            // Skip the escaped character, which might be a quote
+            '\\' if in_string => {
+                chars.next();
+            }
+            // The character in a character literal, which might be a bracket or a quote
+            '#' if !in_string && chars.peek() == Some(&'\\') => {
+                chars.next();
+                chars.next();
+            }
+            '#' if !in_string && chars.peek() == Some(&'|') => {
+                chars.next();
+                block_depth += 1;
+            }
+            '|' if block_depth > 0 && chars.peek() == Some(&'#') => {
+                chars.next();
+                block_depth -= 1;
+            }
+            _ if block_depth > 0 => {}
+            '"' => in_string = !in_string,
+            ';' if !in_string => in_comment = true,
+            '(' | '[' | '{' if !in_string => depth += 1,
+            ')' | ']' | '}' if !in_string => depth -= 1,
+            _ => {}
+        }
+    }
+    in_string || block_depth > 0 || depth > 0
+}
+
+/// Return the rest of input after any whitespace and comments at the start of it.
+pub(crate) fn skip_ignored(input: &str) -> &str {
+    match ignored(input) {
+        Ok((rest, _)) => rest,
+        Err(_) => input,
+    }
+}
+
+/// A comment, which runs from `;` to the end of the line.
+fn comment(input: &str) -> IResult<&str, &str> {
+    recognize(pair(char(';'), take_while(|c| c != '\n')))(input)
+}
+
+/// A block comment, which runs from `#|` to the matching `|#`. Block comments nest, so
+/// `#| a #| b |# c |#` is a single comment. A block comment that is never closed is a failure at
+/// its opening `#|`.
+fn block_comment(input: &str) -> IResult<&str, &str> {
+    let (mut rest, _) = tag("#|")(input)?;
+    let mut depth = 1;
+    while depth > 0 {
+        if let Some(after) = rest.strip_prefix("#|") {
+            depth += 1;
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("|#") {
+            depth -= 1;
+            rest = after;
+        } else if let Some(c) = rest.chars().next() {
+            rest = &rest[c.len_utf8()..];
+        } else {
+            return Err(nom::Err::Failure(nom::error::Error::new(
+                input,
+                nom::error::ErrorKind::TakeUntil,
+            )));
+        }
+    }
+    Ok((rest, &input[..input.len() - rest.len()]))
+}
+
+/// A datum comment, `#;` followed by an expression, which skips that expression. A `#;` with no
+/// expression after it is a failure.
+fn datum_comment(input: &str) -> IResult<&str, &str> {
+    recognize(preceded(tag("#;"), cut(parse_expr)))(input)
+}
+
+/// A single piece of whitespace or a comment.
+fn ignorable(input: &str) -> IResult<&str, &str> {
+    alt((multispace1, comment, block_comment, datum_comment))(input)
+}
+
+/// Any amount of whitespace and comments.
+fn ignored(input: &str) -> IResult<&str, &str> {
+    recognize(many0_count(ignorable))(input)
+}
+
+/// Whitespace or comments separating two expressions.
+fn separator(input: &str) -> IResult<&str, &str> {
+    recognize(many1_count(ignorable))(input)
+}
+
+/// Parse a lisp expression. Lists, vectors, maps, and quotes nested more than [MAX_DEPTH] deep
+/// are a failure.
 pub fn parse_expr(input: &str) -> IResult<&str, Ast> {
-    // skip whitespace
+    let depth = DEPTH.with(Cell::get);
+    let start = skip_ignored(input);
+    if depth >= MAX_DEPTH && start.starts_with(['(', '[', '{', '\'', '`', ',']) {
+        return Err(nom::Err::Failure(nom::error::Error::new(
+            start,
+            nom::error::ErrorKind::TooLarge,
+        )));
+    }
+
+    // skip whitespace and comments
     // if first char == '(', call parse_list
     // if first char =='[', parse vec
     // else parse atom
-    preceded(multispace0, alt((parse_list, parse_atom)))(input)
+    DEPTH.with(|cell| cell.set(depth + 1));
+    let result = preceded(
+        ignored,
+        alt((
+            parse_quoted,
+            parse_list,
+            parse_vector,
+            parse_map,
+            parse_atom,
+        )),
+    )(input);
+    DEPTH.with(|cell| cell.set(depth));
+    result
+}
+
+/// Parse the reader shorthands for quoting: `'expr` is `(quote expr)`, `` `expr`` is
+/// `(quasiquote expr)`, `,expr` is `(unquote expr)`, and `,@expr` is `(unquote-splicing expr)`.
+/// A prefix with no expression after it is a failure.
+fn parse_quoted(input: &str) -> IResult<&str, Ast> {
+    let (rest, name) = alt((
+        map(char('\''), |_| "quote"),
+        map(char('`'), |_| "quasiquote"),
+        map(tag(",@"), |_| "unquote-splicing"),
+        map(char(','), |_| "unquote"),
+    ))(input)?;
+    map(cut(parse_expr), |expr| {
+        Ast::List(vec![Ast::Atom(LispAtom::Symbol(name.to_string())), expr])
+    })(rest)
 }
 
 fn parse_list(input: &str) -> IResult<&str, Ast> {
-    // whitespace separated expressions
+    // expressions separated by whitespace or comments, optionally ending with `. tail`
+    let (rest, (items, tail)) = preceded(
+        terminated(char('('), ignored),
+        pair(separated_list0(separator, parse_expr), opt(dotted_tail)),
+    )(input)?;
+    let Some(tail) = tail else {
+        let (rest, _) = closing(input, ')')(rest)?;
+        return Ok((rest, Ast::List(items)));
+    };
+
+    // There has to be something before the dot, and nothing after the tail
+    let fail = |at| nom::Err::Failure(nom::error::Error::new(at, nom::error::ErrorKind::Char));
+    if items.is_empty() {
+        return Err(fail(input));
+    }
+    let (rest, _) = preceded(ignored, char(')'))(rest).map_err(|_| fail(rest))?;
+    let list = items
+        .into_iter()
+        .rev()
+        .fold(tail, |tail, head| Ast::cons(head, tail));
+    Ok((rest, list))
+}
+
+/// The `. tail` at the end of a dotted pair or improper list. The dot has to be on its own, so
+/// `(a .b)` is a list of two symbols.
+fn dotted_tail(input: &str) -> IResult<&str, Ast> {
+    preceded(
+        tuple((ignored, char('.'), not(satisfy(is_symbol_character)))),
+        cut(parse_expr),
+    )(input)
+}
+
+/// The bracket that closes a list, vector, or map opened at opening. Once the opening bracket has
+/// been read, anything else here is a failure where it is, except running out of input, which is a
+/// failure at the bracket that was never closed.
+fn closing<'a>(opening: &'a str, bracket: char) -> impl FnMut(&'a str) -> IResult<&'a str, char> {
+    move |input| {
+        preceded(ignored, char(bracket))(input).map_err(|_| {
+            let rest = skip_ignored(input);
+            let at = if rest.is_empty() { opening } else { rest };
+            nom::Err::Failure(nom::error::Error::new(at, nom::error::ErrorKind::Char))
+        })
+    }
+}
+
+fn parse_vector(input: &str) -> IResult<&str, Ast> {
     map(
         delimited(
-            terminated(char('('), multispace0),
-            separated_list0(multispace1, parse_expr),
-            preceded(multispace0, char(')')),
+            terminated(char('['), ignored),
+            separated_list0(separator, parse_expr),
+            closing(input, ']'),
         ),
-        Ast::List,
+        Ast::Vector,
     )(input)
 }
 
+/// Parse `{key value ...}` as `(hash-map key value ...)`, so the keys and values are evaluated. An
+/// odd number of expressions between the braces is a failure.
+fn parse_map(input: &str) -> IResult<&str, Ast> {
+    let (rest, mut items) = delimited(
+        terminated(char('{'), ignored),
+        separated_list0(separator, parse_expr),
+        closing(input, '}'),
+    )(input)?;
+    if items.len() % 2 != 0 {
+        return Err(nom::Err::Failure(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Count,
+        )));
+    }
+    items.insert(0, Ast::Atom(LispAtom::Symbol("hash-map".to_string())));
+    Ok((rest, Ast::List(items)))
+}
+
 fn parse_atom(input: &str) -> IResult<&str, Ast> {
     alt((
+        parse_reader_macro,
+        misplaced_separator,
         parse_float,
+        parse_rational,
         parse_int,
         parse_string,
+        parse_char,
+        parse_bytes,
         parse_bool,
+        parse_nil,
+        parse_keyword,
         parse_symbol,
     ))(input)
 }
 
+/// Fails on a token that would be a number if its `_` separators were in the right places, like
+/// `1__0` or `_1`, rather than letting it be read as something else. Tokens like `_1a` and `-_1`
+/// aren't numbers at all, so they're left for parse_symbol. Never succeeds.
+fn misplaced_separator(input: &str) -> IResult<&str, Ast> {
+    let error = || nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Digit));
+    let (_, token) = take_while1(is_symbol_character)(input)?;
+    let starts_with_digit = |s: &str| s.starts_with(|c: char| c.is_ascii_digit());
+    let numeric = match token.strip_prefix(['+', '-']) {
+        Some(unsigned) => starts_with_digit(unsigned),
+        None => starts_with_digit(token.strip_prefix('_').unwrap_or(token)),
+    };
+    if !numeric || !token.contains('_') {
+        return Err(error());
+    }
+    let is_number = |s: &str| {
+        matches!(
+            alt((parse_float, parse_rational, parse_int))(s),
+            Ok(("", _))
+        )
+    };
+    match !is_number(token) && is_number(&token.replace('_', "")) {
+        true => Err(nom::Err::Failure(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Digit,
+        ))),
+        false => Err(error()),
+    }
+}
+
+/// Digits with `_` separators between them, like `1_000_000`. Digits followed by a `_` that isn't
+/// followed by more digits don't match at all. is_digit decides what counts as a digit, so
+/// literals in other bases can have separators too.
+fn separated_digits<'a>(
+    is_digit: fn(char) -> bool,
+) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    move |input| {
+        let (rest, digits) = recognize(pair(
+            take_while1(is_digit),
+            many0_count(pair(char('_'), take_while1(is_digit))),
+        ))(input)?;
+        if rest.starts_with('_') {
+            return Err(nom::Err::Error(nom::error::Error::new(
+                input,
+                nom::error::ErrorKind::Digit,
+            )));
+        }
+        Ok((rest, digits))
+    }
+}
+
+/// Decimal digits, which may have `_` separators.
+fn decimal(input: &str) -> IResult<&str, &str> {
+    separated_digits(|c| c.is_ascii_digit())(input)
+}
+
+/// Decimal digits with an optional sign.
+fn signed_decimal(input: &str) -> IResult<&str, &str> {
+    recognize(pair(opt(alt((char('+'), char('-')))), decimal))(input)
+}
+
 fn recognize_float_exponent(input: &str) -> IResult<&str, &str> {
     recognize(tuple((
         alt((char('e'), char('E'))),
         opt(alt((char('+'), char('-')))),
-        cut(digit1),
+        cut(decimal),
     )))(input)
 }
 
+/// Parse `nan`, `inf`, or `-inf`, as long as they aren't the start of a longer symbol.
+fn parse_special_float(input: &str) -> IResult<&str, Ast> {
+    let (remaining, name) = terminated(
+        alt((tag("nan"), tag("inf"), tag("-inf"))),
+        not(satisfy(is_symbol_character)),
+    )(input)?;
+    let num = match name {
+        "nan" => f64::NAN,
+        "inf" => f64::INFINITY,
+        _ => f64::NEG_INFINITY,
+    };
+    Ok((remaining, Ast::Atom(LispAtom::Float(num))))
+}
+
+/// Parse a float literal like `1.5`, `2.`, or `3e-7`. Literals are rounded to the nearest float, the
+/// same as the result of arithmetic, so `1e999` is `inf` and `1e-999` is `0.0`.
 fn parse_float(input: &str) -> IResult<&str, Ast> {
-    let res: IResult<&str, &str> = recognize(tuple((
-        opt(alt((char('+'), char('-')))),
-        digit1,
+    if let Ok(result) = parse_special_float(input) {
+        return Ok(result);
+    }
+
+    let res: IResult<&str, &str> = recognize(pair(
+        signed_decimal,
         alt((
             map(
-                tuple((char('.'), opt(digit1), opt(recognize_float_exponent))),
+                tuple((char('.'), opt(decimal), opt(recognize_float_exponent))),
                 |_| (),
             ),
             map(recognize_float_exponent, |_| ()),
         )),
-    )))(input);
+    ))(input);
     let (remaining, num_str) = res?;
-    let num = num_str.parse::<f64>().unwrap();
+    let num = num_str.replace('_', "").parse::<f64>().map_err(|_| {
+        nom::Err::Failure(nom::error::Error::new(input, nom::error::ErrorKind::Float))
+    })?;
     Ok((remaining, Ast::Atom(LispAtom::Float(num))))
 }
 
+fn parse_rational(input: &str) -> IResult<&str, Ast> {
+    let (remaining, (num_str, _, den_str)) = tuple((signed_decimal, char('/'), decimal))(input)?;
+    // Zero denominators and numbers that don't fit are errors rather than falling back to
+    // parsing something else
+    let fail = || nom::Err::Failure(nom::error::Error::new(input, nom::error::ErrorKind::Verify));
+    let num = num_str
+        .replace('_', "")
+        .parse::<i64>()
+        .map_err(|_| fail())?;
+    let den = den_str
+        .replace('_', "")
+        .parse::<i64>()
+        .map_err(|_| fail())?;
+    let ratio = rational::make(num.into(), den.into()).ok_or_else(fail)?;
+    Ok((remaining, rational::to_ast(ratio)))
+}
+
+/// Parse an int literal. The sign is part of the literal, so `-9223372036854775808` is an int, and
+/// literals that don't fit in an int are read as big ints.
 fn parse_int(input: &str) -> IResult<&str, Ast> {
-    let (remaining, num_str) = recognize(tuple((opt(alt((char('+'), char('-')))), digit1)))(input)?;
-    let num = num_str.parse::<i64>().unwrap();
-    Ok((remaining, Ast::Atom(LispAtom::Int(num))))
+    let (remaining, num_str) = signed_decimal(input)?;
+    let num_str = num_str.replace('_', "");
+    let num = match num_str.parse::<i64>() {
+        Ok(num) => Ast::Atom(LispAtom::Int(num)),
+        // Can't fail, since num_str is just digits with an optional sign
+        Err(_) => Ast::from(num_str.parse::<BigInt>().unwrap()),
+    };
+    Ok((remaining, num))
 }
 
+/// Parse a string literal. The escapes `\"`, `\\`, `\n`, `\t`, `\r`, and `\u{XXXX}` are
+/// supported. An unknown escape is a failure at its backslash, and a string with no closing quote
+/// is a failure at its opening quote.
 fn parse_string(input: &str) -> IResult<&str, Ast> {
-    map(
-        delimited(char('"'), take_while(|c| c != '"'), char('"')),
-        |s: &str| Ast::Atom(LispAtom::String(s.to_string())),
-    )(input)
+    let (mut rest, _) = char('"')(input)?;
+    let fail = |at, kind| nom::Err::Failure(nom::error::Error::new(at, kind));
+    let mut string = String::new();
+    loop {
+        let mut chars = rest.chars();
+        match chars.next() {
+            None => return Err(fail(input, nom::error::ErrorKind::Char)),
+            Some('"') => break,
+            Some('\\') => {
+                let (after, c) = parse_escape(chars.as_str())
+                    .map_err(|_| fail(rest, nom::error::ErrorKind::Escaped))?;
+                string.push(c);
+                rest = after;
+                continue;
+            }
+            Some(c) => string.push(c),
+        }
+        rest = chars.as_str();
+    }
+    Ok((&rest[1..], Ast::Atom(LispAtom::String(string))))
+}
+
+/// Parse the part of an escape sequence after the backslash.
+fn parse_escape(input: &str) -> IResult<&str, char> {
+    alt((
+        map(char('"'), |_| '"'),
+        map(char('\\'), |_| '\\'),
+        map(char('n'), |_| '\n'),
+        map(char('t'), |_| '\t'),
+        map(char('r'), |_| '\r'),
+        map_opt(
+            delimited(
+                tag("u{"),
+                take_while_m_n(1, 6, |c: char| c.is_ascii_hexdigit()),
+                char('}'),
+            ),
+            |hex| char::from_u32(u32::from_str_radix(hex, 16).ok()?),
+        ),
+    ))(input)
+}
+
+/// Parse a character literal: `#\` followed by a single character like `#\a` or `#\(`, a name
+/// like `#\space`, or a code point in hex like `#\x41`. An unknown name is a failure.
+fn parse_char(input: &str) -> IResult<&str, Ast> {
+    let (rest, _) = tag("#\\")(input)?;
+    let Some(first) = rest.chars().next() else {
+        return Err(nom::Err::Failure(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Char,
+        )));
+    };
+    // A name is a run of symbol characters, anything else is just the one character
+    let (remaining, name) = match is_symbol_character(first) {
+        true => take_while(is_symbol_character)(rest)?,
+        false => (&rest[first.len_utf8()..], &rest[..first.len_utf8()]),
+    };
+
+    let mut chars = name.chars();
+    let c = match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => CHAR_NAMES
+            .iter()
+            .find(|(char_name, _)| *char_name == name)
+            .map(|(_, c)| *c)
+            .or_else(|| {
+                let hex = name.strip_prefix('x')?;
+                char::from_u32(u32::from_str_radix(hex, 16).ok()?)
+            }),
+    };
+    match c {
+        Some(c) => Ok((remaining, Ast::Atom(LispAtom::Char(c)))),
+        None => Err(nom::Err::Failure(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Char,
+        ))),
+    }
+}
+
+/// Parse bytes written in hex, like `#x"00ff"`.
+fn parse_bytes(input: &str) -> IResult<&str, Ast> {
+    let (remaining, hex) = delimited(
+        tag("#x\""),
+        take_while(|c: char| c.is_ascii_hexdigit()),
+        cut(char('"')),
+    )(input)?;
+    if hex.len() % 2 != 0 {
+        return Err(nom::Err::Failure(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Verify,
+        )));
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect();
+    Ok((remaining, Ast::Atom(LispAtom::Bytes(bytes))))
 }
 
 fn parse_bool(input: &str) -> IResult<&str, Ast> {
@@ -103,18 +692,48 @@ fn parse_bool(input: &str) -> IResult<&str, Ast> {
     )(input)
 }
 
+fn parse_nil(input: &str) -> IResult<&str, Ast> {
+    map(
+        terminated(tag("nil"), not(satisfy(is_symbol_character))),
+        |_| Ast::Atom(LispAtom::Nil),
+    )(input)
+}
+
+/// Parse a keyword like `:name`. A colon with no name after it is a failure.
+fn parse_keyword(input: &str) -> IResult<&str, Ast> {
+    map(
+        preceded(char(':'), cut(take_while1(is_symbol_character))),
+        |name: &str| Ast::Atom(LispAtom::Keyword(name.to_string())),
+    )(input)
+}
+
 fn parse_symbol(input: &str) -> IResult<&str, Ast> {
     map(
-        recognize(tuple((
-            satisfy(|c| is_symbol_character(c) && !c.is_ascii_digit()),
-            take_while(is_symbol_character),
-        ))),
-        |s: &str| Ast::Atom(LispAtom::Symbol(s.to_string())),
+        verify(
+            recognize(tuple((
+                // These start comments, shebang lines, and quoted expressions rather than symbols
+                not(alt((tag("#|"), tag("#;"), tag("#!"), tag("'")))),
+                satisfy(|c| is_symbol_character(c) && !c.is_ascii_digit()),
+                take_while(is_symbol_character),
+            ))),
+            // A dot on its own is only for dotted pairs
+            |s: &str| s != ".",
+        ),
+        |s: &str| match FOLD_CASE.with(Cell::get) {
+            true => Ast::Atom(LispAtom::Symbol(s.to_lowercase())),
+            false => Ast::Atom(LispAtom::Symbol(s.to_string())),
+        },
     )(input)
 }
 
-fn is_symbol_character(c: char) -> bool {
-    c != '(' && c != ')' && c != '"' && c != ';' && !c.is_whitespace()
+/// Returns true if name would be read back by the parser as a symbol with the same name.
+pub(crate) fn is_valid_symbol(name: &str) -> bool {
+    matches!(parse_atom(name), Ok(("", Ast::Atom(LispAtom::Symbol(_)))))
+}
+
+/// Returns true if c can appear in a symbol. A symbol can't start with a digit or `'` either.
+pub(crate) fn is_symbol_character(c: char) -> bool {
+    !matches!(c, '(' | ')' | '[' | ']' | '{' | '}' | '"' | ';' | '`' | ',') && !c.is_whitespace()
 }
 
 #[cfg(test)]
@@ -164,4 +783,729 @@ mod tests {
         ]);
         assert_eq!(ast, expected);
     }
+
+    #[test]
+    fn parse_rational_works() {
+        let (_, ast) = parse_atom("1/3").expect("parse atom failed");
+        assert_eq!(ast, Ast::Atom(LispAtom::Rational(1, 3)));
+
+        let (_, ast) = parse_atom("-2/4").expect("parse atom failed");
+        assert_eq!(ast, Ast::Atom(LispAtom::Rational(-1, 2)));
+
+        let (_, ast) = parse_atom("6/3").expect("parse atom failed");
+        assert_eq!(ast, Ast::Atom(LispAtom::Int(2)));
+
+        parse_atom("1/0").expect_err("parsed zero denominator");
+
+        let (_, ast) = parse_atom("/").expect("parse atom failed");
+        assert_eq!(ast, Ast::Atom(LispAtom::Symbol("/".to_string())));
+    }
+
+    #[test]
+    fn fold_case_symbols() {
+        let input = "(DEF Foo \"MiXed\")";
+        let (_, ast) = parse_expr(input).unwrap();
+        assert_eq!(ast.to_string(), input);
+
+        let (_, ast) = with_fold_case(true, || parse_expr(input)).unwrap();
+        assert_eq!(ast.to_string(), "(def foo \"MiXed\")");
+        // Every symbol is folded the same way, including type names
+        let (_, ast) = with_fold_case(true, || parse_expr("(TYPE-OF Int <INT>)")).unwrap();
+        assert_eq!(ast.to_string(), "(type-of int <int>)");
+
+        // Folding stops when the call returns
+        let (_, ast) = parse_expr(input).unwrap();
+        assert_eq!(ast.to_string(), input);
+    }
+
+    #[test]
+    fn parse_big_int() {
+        let (_, ast) = parse_atom("-123456789012345678901234567890").expect("parse atom failed");
+        assert_eq!(ast.to_string(), "-123456789012345678901234567890");
+        assert!(matches!(ast, Ast::Atom(LispAtom::BigInt(_))));
+
+        let (_, ast) = parse_atom("-9223372036854775808").expect("parse atom failed");
+        assert_eq!(ast, Ast::Atom(LispAtom::Int(i64::MIN)));
+    }
+
+    #[test]
+    fn numbers_out_of_range() {
+        let boundaries = [
+            ("9223372036854775807", Some(i64::MAX)),
+            ("+9223372036854775807", Some(i64::MAX)),
+            ("-9223372036854775808", Some(i64::MIN)),
+            ("9223372036854775808", None),
+            ("+9223372036854775808", None),
+            ("-9223372036854775809", None),
+        ];
+        for (input, int) in boundaries {
+            let (rest, ast) = parse_atom(input).unwrap();
+            assert_eq!(rest, "");
+            match int {
+                Some(int) => assert_eq!(ast, Ast::Atom(LispAtom::Int(int))),
+                None => {
+                    assert!(matches!(ast, Ast::Atom(LispAtom::BigInt(_))));
+                    assert_eq!(ast.to_string(), input.trim_start_matches('+'));
+                }
+            }
+        }
+
+        for digits in [20, 400, 10_000] {
+            let input = format!("-{}", "9".repeat(digits));
+            let (_, ast) = parse_atom(&input).unwrap();
+            assert_eq!(ast.to_string(), input);
+
+            // Lots of digits are fine in a float as long as it isn't too large
+            let (_, ast) = parse_atom(&format!("0.{}", "3".repeat(digits))).unwrap();
+            assert_eq!(ast, Ast::Atom(LispAtom::Float(1.0 / 3.0)));
+        }
+
+        // Floats round like arithmetic does
+        let floats = [
+            ("1e-999", 0.0),
+            ("1e999", f64::INFINITY),
+            ("-1.5E400", f64::NEG_INFINITY),
+            (&format!("{}.0", "9".repeat(400)), f64::INFINITY),
+            (&format!("1e{}", "9".repeat(400)), f64::INFINITY),
+        ];
+        for (input, float) in floats {
+            assert_eq!(
+                parse_atom(input).unwrap(),
+                ("", Ast::Atom(LispAtom::Float(float)))
+            );
+        }
+    }
+
+    #[test]
+    fn floats_round_trip() {
+        let floats = [
+            0.0,
+            -0.0,
+            1.0,
+            -2.0,
+            0.1,
+            0.1 + 0.2,
+            1.5e-7,
+            3e300,
+            1e16,
+            123456789.125,
+            f64::MAX,
+            f64::MIN_POSITIVE,
+            f64::EPSILON,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+        ];
+        for num in floats {
+            let written = Ast::Atom(LispAtom::Float(num)).to_string();
+            let Ok(("", Ast::Atom(LispAtom::Float(read)))) = parse_atom(&written) else {
+                panic!("{} did not read back as a float", written);
+            };
+            assert_eq!(read.to_bits(), num.to_bits(), "{}", written);
+        }
+
+        let Ok(("", Ast::Atom(LispAtom::Float(nan)))) = parse_atom("nan") else {
+            panic!("nan did not read back as a float");
+        };
+        assert!(nan.is_nan());
+        assert_eq!(
+            parse_atom("info"),
+            Ok(("", Ast::Atom(LispAtom::Symbol("info".to_string()))))
+        );
+    }
+
+    #[test]
+    fn parse_bytes_works() {
+        let (_, ast) = parse_atom("#x\"00fF7a\"").expect("parse bytes failed");
+        assert_eq!(ast, Ast::Atom(LispAtom::Bytes(vec![0x00, 0xff, 0x7a])));
+        assert_eq!(ast.to_string(), "#x\"00ff7a\"");
+        assert_eq!(
+            parse_atom("#x\"\"").unwrap().1,
+            Ast::Atom(LispAtom::Bytes(vec![]))
+        );
+
+        assert!(parse_atom("#x\"abc\"").is_err());
+        assert!(parse_atom("#x\"zz\"").is_err());
+    }
+
+    #[test]
+    fn comments() {
+        let symbol = |s: &str| Ast::Atom(LispAtom::Symbol(s.to_string()));
+        let (rest, ast) =
+            parse_expr("; leading comment\n(a ; after a\n b;no space\n ; whole line\n c)")
+                .expect("parse expr failed");
+        assert_eq!(ast, Ast::List(vec![symbol("a"), symbol("b"), symbol("c")]));
+        assert_eq!(rest, "");
+
+        let (rest, ast) = parse_expr("x ; comment at the end").unwrap();
+        assert_eq!(ast, symbol("x"));
+        assert_eq!(skip_ignored(rest), "");
+        // A comment can't close a list
+        parse_expr("(a ; )").expect_err("comment closed a list");
+        // Semicolons in strings aren't comments
+        let (_, ast) = parse_expr("\"a ; b\"").unwrap();
+        assert_eq!(ast, Ast::Atom(LispAtom::String("a ; b".to_string())));
+
+        assert_eq!(
+            parse_complete_expr("(a) ; done").unwrap().1,
+            Ast::List(vec![symbol("a")])
+        );
+        assert!(matches!(
+            parse_complete_expr("; only a comment").unwrap().1,
+            Ast::Unspecified
+        ));
+        assert!(is_incomplete("(a ; )\n"));
+        assert!(!is_incomplete("(a) ; (\n"));
+        assert!(!is_incomplete("\"; (\""));
+    }
+
+    #[test]
+    fn block_comments() {
+        let symbol = |s: &str| Ast::Atom(LispAtom::Symbol(s.to_string()));
+        let (rest, ast) = parse_expr("#| a #| b |# c |# (x #|in\nside|# y)").unwrap();
+        assert_eq!(ast, Ast::List(vec![symbol("x"), symbol("y")]));
+        assert_eq!(rest, "");
+        // Only a comment at the start of a token
+        let (_, ast) = parse_expr("a#|b").unwrap();
+        assert_eq!(ast, symbol("a#|b"));
+
+        // An unclosed comment fails at the opening #|, even when an inner one is closed
+        let input = "(x #| a #| b |# c)";
+        match parse_expr(input) {
+            Err(nom::Err::Failure(e)) => assert_eq!(e.input, "#| a #| b |# c)"),
+            other => panic!("expected a failure, got {other:?}"),
+        }
+        assert!(is_incomplete(input));
+        assert!(!is_incomplete("#| ( |# 1"));
+        assert!(!is_valid_symbol("#|a"));
+    }
+
+    #[test]
+    fn datum_comments() {
+        let (_, ast) = parse_expr("(1 #;(2 (3)) 4)").unwrap();
+        assert_eq!(ast, Ast::List(vec![Ast::from(1), Ast::from(4)]));
+        let (_, ast) = parse_expr("(#;1 #; #; 2 3 4 #;5)").unwrap();
+        assert_eq!(ast, Ast::List(vec![Ast::from(4)]));
+        let (_, ast) = parse_expr("#;skipped kept").unwrap();
+        assert_eq!(ast, Ast::Atom(LispAtom::Symbol("kept".to_string())));
+
+        // Nothing left to skip
+        assert!(matches!(parse_expr("(1 #;)"), Err(nom::Err::Failure(_))));
+        assert!(matches!(parse_expr("#;"), Err(nom::Err::Failure(_))));
+        parse_complete_expr("1 #;").expect_err("parsed a datum comment with no datum");
+    }
+
+    #[test]
+    fn string_escapes() {
+        let string = |s: &str| Ast::Atom(LispAtom::String(s.to_string()));
+        let cases = [
+            (r#""say \"hi\"""#, "say \"hi\""),
+            (r#""C:\\dir""#, "C:\\dir"),
+            (r#""a\tb\r\nc""#, "a\tb\r\nc"),
+            (r#""\u{e9}\u{1F600}""#, "\u{e9}\u{1F600}"),
+            ("\"line\nbreak\"", "line\nbreak"),
+        ];
+        for (input, expected) in cases {
+            let (rest, ast) = parse_expr(input).unwrap();
+            assert_eq!(ast, string(expected), "{input}");
+            assert_eq!(rest, "");
+        }
+
+        // Unknown and malformed escapes fail at the backslash
+        for input in [
+            r#""a\qb""#,
+            r#""\u{}""#,
+            r#""\u{110000}""#,
+            r#""\u{d800}""#,
+            r#""\u41""#,
+        ] {
+            match parse_expr(input) {
+                Err(nom::Err::Failure(e)) => assert!(e.input.starts_with('\\'), "{input}"),
+                other => panic!("expected a failure for {input}, got {other:?}"),
+            }
+        }
+        // Unterminated strings fail at the opening quote
+        for input in [r#"(a "bc"#, r#"(a "bc\")"#] {
+            match parse_expr(input) {
+                Err(nom::Err::Failure(e)) => assert!(e.input.starts_with("\"bc"), "{input}"),
+                other => panic!("expected a failure for {input}, got {other:?}"),
+            }
+            assert!(is_incomplete(input));
+        }
+        assert!(!is_incomplete(r#"(a "\"(")"#));
+    }
+
+    #[test]
+    fn strings_round_trip() {
+        let cases = [
+            "",
+            "plain",
+            "quote \" and \\ backslash",
+            "\\\"",
+            "tab\tnew\nline",
+            "\u{1F600}",
+        ];
+        for s in cases {
+            let ast = Ast::Atom(LispAtom::String(s.to_string()));
+            let printed = ast.to_string();
+            let (rest, read) = parse_expr(&printed).unwrap();
+            assert_eq!(read, ast);
+            assert_eq!(rest, "");
+        }
+    }
+
+    #[test]
+    fn quote_sugar() {
+        let symbol = |s: &str| Ast::Atom(LispAtom::Symbol(s.to_string()));
+        let quote = |expr| Ast::List(vec![symbol("quote"), expr]);
+
+        assert_eq!(parse_expr("'x").unwrap(), ("", quote(symbol("x"))));
+        assert_eq!(
+            parse_expr("'(1 two)").unwrap().1,
+            quote(Ast::List(vec![Ast::from(1), symbol("two")]))
+        );
+        assert_eq!(parse_expr("''x").unwrap().1, quote(quote(symbol("x"))));
+        assert_eq!(
+            parse_expr("(f 'a '() b)").unwrap().1,
+            Ast::List(vec![
+                symbol("f"),
+                quote(symbol("a")),
+                quote(Ast::List(vec![])),
+                symbol("b")
+            ])
+        );
+        // Only a quote at the start of a symbol is sugar
+        assert_eq!(parse_expr("don't").unwrap().1, symbol("don't"));
+        assert!(!is_valid_symbol("'x"));
+
+        for input in ["'", "(a ')", "(a '", "' ; nothing"] {
+            assert!(
+                matches!(parse_expr(input), Err(nom::Err::Failure(_))),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn quasiquote_sugar() {
+        let symbol = |s: &str| Ast::Atom(LispAtom::Symbol(s.to_string()));
+        let wrap = |name, expr| Ast::List(vec![symbol(name), expr]);
+
+        assert_eq!(
+            parse_expr("`(a ,b ,@c)").unwrap(),
+            (
+                "",
+                wrap(
+                    "quasiquote",
+                    Ast::List(vec![
+                        symbol("a"),
+                        wrap("unquote", symbol("b")),
+                        wrap("unquote-splicing", symbol("c")),
+                    ])
+                )
+            )
+        );
+        assert_eq!(
+            parse_expr("`(,(f x) ,'y)").unwrap().1,
+            wrap(
+                "quasiquote",
+                Ast::List(vec![
+                    wrap("unquote", Ast::List(vec![symbol("f"), symbol("x")])),
+                    wrap("unquote", wrap("quote", symbol("y"))),
+                ])
+            )
+        );
+        // Commas end symbols now
+        assert_eq!(parse_symbol("a,b").unwrap(), (",b", symbol("a")));
+        for input in ["`", "(,)", "(a ,@)"] {
+            assert!(
+                matches!(parse_expr(input), Err(nom::Err::Failure(_))),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn symbol_punctuation() {
+        // Every ASCII punctuation character except these can be part of a symbol
+        let not_allowed = "()[]{}\";`,";
+        for c in (' '..='~').filter(char::is_ascii_punctuation) {
+            let allowed = !not_allowed.contains(c);
+            assert_eq!(is_valid_symbol(&format!("a{c}b")), allowed, "{c:?}");
+            // A quote at the start is quote sugar instead, and a colon makes a keyword
+            assert_eq!(
+                is_valid_symbol(&format!("{c}a")),
+                allowed && c != '\'' && c != ':',
+                "{c:?}"
+            );
+        }
+        for name in [
+            "+", "-", "...", "->>", "set-car!", "nil?", "a.b/c", "<=>", "#", "|x|",
+        ] {
+            assert!(is_valid_symbol(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn char_literals() {
+        let ch = |c| Ast::Atom(LispAtom::Char(c));
+        let cases = [
+            ("#\\a", 'a'),
+            ("#\\A", 'A'),
+            ("#\\x", 'x'),
+            ("#\\7", '7'),
+            ("#\\(", '('),
+            ("#\\\"", '"'),
+            ("#\\space", ' '),
+            ("#\\newline", '\n'),
+            ("#\\tab", '\t'),
+            ("#\\return", '\r'),
+            ("#\\x41", 'A'),
+            ("#\\x1F600", '\u{1F600}'),
+            ("#\\λ", 'λ'),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_expr(input).unwrap(), ("", ch(expected)), "{input}");
+            // And it prints back the same way
+            let (_, read) = parse_expr(&ch(expected).to_string()).unwrap();
+            assert_eq!(read, ch(expected), "{input}");
+        }
+        assert_eq!(ch(' ').to_string(), "#\\space");
+        assert_eq!(ch('\u{7}').to_string(), "#\\x7");
+        assert_eq!(
+            parse_expr("(#\\( #\\))").unwrap().1,
+            Ast::List(vec![ch('('), ch(')')])
+        );
+        assert!(!is_incomplete("(list #\\( #\\\")"));
+
+        for input in ["#\\spaces", "#\\xyz", "#\\x110000", "#\\"] {
+            assert!(
+                matches!(parse_expr(input), Err(nom::Err::Failure(_))),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn vector_literals() {
+        let symbol = |s: &str| Ast::Atom(LispAtom::Symbol(s.to_string()));
+        assert_eq!(
+            parse_expr("[1 [a] (b [])]").unwrap(),
+            (
+                "",
+                Ast::Vector(vec![
+                    Ast::from(1),
+                    Ast::Vector(vec![symbol("a")]),
+                    Ast::List(vec![symbol("b"), Ast::Vector(vec![])]),
+                ])
+            )
+        );
+        assert_eq!(
+            parse_expr("[ ; comment\n 1 #;2 ]").unwrap().1,
+            Ast::Vector(vec![Ast::from(1)])
+        );
+        let (_, ast) = parse_expr("'[x (y)]").unwrap();
+        let (_, read) = parse_expr(&ast.to_string()).unwrap();
+        assert_eq!(read, ast);
+
+        parse_expr("[1 2)").expect_err("vector closed with a paren");
+        parse_expr("(1 2]").expect_err("list closed with a bracket");
+        assert!(is_incomplete("[1 (2"));
+        assert!(!is_incomplete("[1 (2)]"));
+        // Brackets end symbols
+        assert_eq!(parse_symbol("a]").unwrap(), ("]", symbol("a")));
+    }
+
+    #[test]
+    fn map_literals() {
+        let symbol = |s: &str| Ast::Atom(LispAtom::Symbol(s.to_string()));
+        assert_eq!(
+            parse_expr(r#"{ "a" 1 "b" {x y} }"#).unwrap(),
+            (
+                "",
+                Ast::List(vec![
+                    symbol("hash-map"),
+                    Ast::from("a"),
+                    Ast::from(1),
+                    Ast::from("b"),
+                    Ast::List(vec![symbol("hash-map"), symbol("x"), symbol("y")]),
+                ])
+            )
+        );
+        assert_eq!(
+            parse_expr("{}").unwrap().1,
+            Ast::List(vec![symbol("hash-map")])
+        );
+
+        for input in ["{1}", "{1 2 3}", "({a 1 b})"] {
+            assert!(
+                matches!(parse_expr(input), Err(nom::Err::Failure(_))),
+                "{input}"
+            );
+        }
+        assert!(is_incomplete("{:a (1"));
+        assert_eq!(parse_symbol("a}").unwrap(), ("}", symbol("a")));
+    }
+
+    #[test]
+    fn keyword_literals() {
+        let keyword = |s: &str| Ast::Atom(LispAtom::Keyword(s.to_string()));
+        assert_eq!(parse_expr(":a").unwrap(), ("", keyword("a")));
+        assert_eq!(
+            parse_expr("(:when :a-b? a:b)").unwrap().1,
+            Ast::List(vec![
+                keyword("when"),
+                keyword("a-b?"),
+                Ast::Atom(LispAtom::Symbol("a:b".to_string()))
+            ])
+        );
+        assert_eq!(keyword("x").to_string(), ":x");
+        assert!(!is_valid_symbol(":x"));
+
+        for input in [":", "(a :)", ": a"] {
+            assert!(
+                matches!(parse_expr(input), Err(nom::Err::Failure(_))),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn dotted_pairs() {
+        let pair = |head, tail| Ast::Pair(Box::new(head), Box::new(tail));
+        let symbol = |s: &str| Ast::Atom(LispAtom::Symbol(s.to_string()));
+        assert_eq!(
+            parse_expr("(1 . 2)").unwrap(),
+            ("", pair(Ast::from(1), Ast::from(2)))
+        );
+        assert_eq!(
+            parse_expr("(1 2 . 3)").unwrap().1,
+            pair(Ast::from(1), pair(Ast::from(2), Ast::from(3)))
+        );
+        // A list as the tail makes a proper list
+        assert_eq!(
+            parse_expr("(1 . (2 3))").unwrap().1,
+            Ast::List(vec![Ast::from(1), Ast::from(2), Ast::from(3)])
+        );
+        assert_eq!(
+            parse_expr("(a ; comment\n . #;skipped (b . c))").unwrap().1,
+            pair(symbol("a"), pair(symbol("b"), symbol("c")))
+        );
+        // Only a dot on its own
+        assert_eq!(
+            parse_expr("(a .b ...)").unwrap().1,
+            Ast::List(vec![symbol("a"), symbol(".b"), symbol("...")])
+        );
+        assert!(!is_valid_symbol("."));
+
+        for input in [
+            "(1 . 2)",
+            "(1 2 . 3)",
+            "((1 . 2) . (3 . 4))",
+            "(\"a\" . [b])",
+        ] {
+            let (_, ast) = parse_expr(input).unwrap();
+            assert_eq!(ast.to_string(), input.replace(". (3 . 4)", "3 . 4"));
+            assert_eq!(parse_expr(&ast.to_string()).unwrap().1, ast);
+        }
+
+        for input in ["(. 1)", "(1 .)", "(1 . 2 3)", "(1 . 2 . 3)", "."] {
+            assert!(parse_expr(input).is_err(), "{input}");
+        }
+        assert!(matches!(
+            parse_expr("(1 . 2 3)"),
+            Err(nom::Err::Failure(e)) if e.input == " 3)"
+        ));
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        // Parsing is recursive, so give it room for the deepest nesting allowed in a debug build
+        let parse = |depth: usize| {
+            std::thread::Builder::new()
+                .stack_size(64 << 20)
+                .spawn(move || {
+                    let input = format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+                    parse_program(&input, false)
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .unwrap()
+                .join()
+                .unwrap()
+        };
+        assert_eq!(parse(MAX_DEPTH), Ok(()));
+        let message = parse(MAX_DEPTH + 1).unwrap_err();
+        assert!(
+            message.contains("nested more than 1000 deep at line 1, column 1001\n"),
+            "{}",
+            message.get(..200).unwrap_or(&message)
+        );
+        // Far past the limit, where it would otherwise overflow the stack
+        assert!(parse(100_000).is_err());
+
+        let (_, ast) = parse_expr("(1 (2))").unwrap();
+        assert_eq!(ast.to_string(), "(1 (2))");
+    }
+
+    #[test]
+    fn errors_give_line_and_column() {
+        let error_message = |input| match parse_complete_expr(input) {
+            Err(error) => error_from(input, error).to_string(),
+            Ok(_) => panic!("parsed {input:?}"),
+        };
+        let message = error_message("(list 1 ] 2)");
+        assert!(message.ends_with("line 1, column 9\n(list 1 ] 2)\n        ^"));
+
+        // Tabs are kept so the caret lines up
+        let message = error_message("(list\n\t1 ] 2)");
+        assert!(message.ends_with("line 2, column 4\n\t1 ] 2)\n\t  ^"));
+
+        // Trailing text is reported where it starts
+        let message = error_message("(+ 1 2) ; sum\n  )");
+        assert!(message.ends_with("line 2, column 3\n  )\n  ^"));
+
+        let message = error_message("[1 (2 \"é\" 3]");
+        assert!(message.ends_with("line 1, column 12\n[1 (2 \"é\" 3]\n           ^"));
+    }
+
+    #[test]
+    fn parse_program_works() {
+        assert!(parse_program("", false).unwrap().is_empty());
+        assert!(parse_program(" \n; nothing here\n#| at all |#\n", false)
+            .unwrap()
+            .is_empty());
+
+        let exprs = parse_program("(def x 1)\n(+ x 1) ; two\n\n#| done |#  \n", false).unwrap();
+        assert_eq!(exprs.len(), 2);
+        assert_eq!(exprs[1].to_string(), "(+ x 1)");
+
+        // The error is at the malformed expression, not the end of the ones before it
+        match parse_program("(def x 1)\n(def y 2) (def z ]\n(+ x y)", false) {
+            Err(LispError::ParseError(message)) => {
+                assert_eq!(
+                    message,
+                    "line 2, column 18\n(def y 2) (def z ]\n                 ^"
+                )
+            }
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+        match parse_program("(def x 1) 2 ]", false) {
+            Err(LispError::ParseError(message)) => {
+                assert_eq!(message, "line 1, column 13\n(def x 1) 2 ]\n            ^")
+            }
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn digit_separators() {
+        let numbers = [
+            ("1_000", Ast::from(1000)),
+            ("-1_000_000", Ast::from(-1_000_000)),
+            ("+9_223_372_036_854_775_807", Ast::from(i64::MAX)),
+            ("1_000.000_1", Ast::Atom(LispAtom::Float(1_000.000_1))),
+            ("1_0e1_0", Ast::Atom(LispAtom::Float(1e11))),
+            ("2_0/3_0", Ast::Atom(LispAtom::Rational(2, 3))),
+        ];
+        for (input, expected) in numbers {
+            assert_eq!(parse_atom(input).unwrap(), ("", expected));
+        }
+        let (_, ast) = parse_atom("1_000_000_000_000_000_000_000").unwrap();
+        assert_eq!(ast.to_string(), "1000000000000000000000");
+
+        // Numbers are tried before symbols, and a symbol can't start with a digit anyway
+        parse_symbol("1_000").expect_err("parsed 1_000 as a symbol");
+        assert_eq!(
+            parse_expr("(f 1_000 -2_0)").unwrap().1.to_string(),
+            "(f 1000 -20)"
+        );
+
+        for input in [
+            "1__0", "_1", "-1__0", "1_", "1_.5", "1._5", "1.5_", "1e_5", "1_e5", "1e5_", "1_/2",
+            "1/_2",
+        ] {
+            assert!(
+                matches!(parse_atom(input), Err(nom::Err::Failure(_))),
+                "parsed {input}"
+            );
+        }
+
+        // Tokens that aren't numbers even without their underscores are still symbols
+        for input in [
+            "_", "_foo", "a_1", "-_x", "_1a", "-_1", "+_2", "_1x2", "__1",
+        ] {
+            let (rest, ast) = parse_atom(input).unwrap();
+            assert_eq!(rest, "");
+            assert_eq!(ast, Ast::Atom(LispAtom::Symbol(input.to_string())));
+        }
+    }
+
+    /// Reads `#date"2024-01-31"` as `(list 2024 1 31)`.
+    fn parse_date(input: &str) -> IResult<&str, Ast> {
+        let (rest, string) = parse_string(input)?;
+        let Ast::Atom(LispAtom::String(date)) = &string else {
+            unreachable!("parse_string only parses strings");
+        };
+        let parts: Option<Vec<_>> = date
+            .split('-')
+            .map(|part| part.parse::<i64>().ok())
+            .collect();
+        match parts.as_deref() {
+            Some(&[year, month, day]) => Ok((
+                rest,
+                Ast::List(vec![
+                    Ast::Atom(LispAtom::Symbol("list".to_string())),
+                    Ast::from(year),
+                    Ast::from(month),
+                    Ast::from(day),
+                ]),
+            )),
+            _ => Err(nom::Err::Failure(nom::error::Error::new(
+                input,
+                nom::error::ErrorKind::Verify,
+            ))),
+        }
+    }
+
+    /// Reads `#x:ff` as 255, and leaves anything else to the built-in syntax.
+    fn parse_hex_int(input: &str) -> IResult<&str, Ast> {
+        let (rest, hex) = preceded(char(':'), take_while1(|c: char| c.is_ascii_hexdigit()))(input)?;
+        match i64::from_str_radix(hex, 16) {
+            Ok(int) => Ok((rest, Ast::from(int))),
+            Err(_) => Err(nom::Err::Failure(nom::error::Error::new(
+                input,
+                nom::error::ErrorKind::HexDigit,
+            ))),
+        }
+    }
+
+    #[test]
+    fn reader_macros() {
+        register_reader_macro("#date", parse_date);
+        register_reader_macro("#x", parse_hex_int);
+
+        let result = crate::eval::eval_str(
+            "(def d #date\"2024-01-31\") (list (first d) (first (rest (rest d))) #x:ff)",
+        );
+        assert_eq!(result.unwrap().to_string(), "(2024 31 255)");
+
+        // Returning an error leaves the input to the built-in syntax
+        let (_, ast) = parse_expr("#x\"00ff\"").unwrap();
+        assert_eq!(ast, Ast::Atom(LispAtom::Bytes(vec![0x00, 0xff])));
+        // Longer prefixes win, and symbols that start with a shorter one still work
+        let (_, ast) = parse_expr("#date\"1-2-3\"").unwrap();
+        assert_eq!(ast.to_string(), "(list 1 2 3)");
+        let (_, ast) = parse_expr("#dat").unwrap();
+        assert_eq!(ast, Ast::Atom(LispAtom::Symbol("#dat".to_string())));
+
+        // Failures are reported where they happen
+        match parse_program("(def d\n  #date\"soon\")", false) {
+            Err(LispError::ParseError(message)) => assert!(message.starts_with("line 2, column 8")),
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+
+        // Registering a prefix again replaces its handler
+        register_reader_macro("#date", |input| Ok((input, Ast::from("today"))));
+        assert_eq!(parse_expr("#date").unwrap().1, Ast::from("today"));
+    }
 }
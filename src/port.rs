@@ -0,0 +1,129 @@
+//! Contains [Port], a handle to something that strings can be read from or written to.
+
+use crate::env::{self, Environment, Sink, Source};
+use crate::error::LispError;
+
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::rc::Rc;
+
+/// Where a port reads from or writes to.
+enum Target {
+    Reader(Source),
+    Writer(Sink),
+    /// The input of whichever environment reads from the port, which is normally stdin.
+    EnvInput,
+    /// The output of whichever environment writes to the port, which is normally stdout.
+    EnvOutput,
+    Closed,
+}
+
+struct State {
+    name: String,
+    target: Target,
+}
+
+/// A handle to an open file or stream. Copies of a port share the same stream, so closing one
+/// copy closes all of them. Ports are compared by identity.
+#[derive(Clone)]
+pub struct Port(Rc<RefCell<State>>);
+
+impl Port {
+    fn new(name: &str, target: Target) -> Self {
+        Self(Rc::new(RefCell::new(State {
+            name: name.to_string(),
+            target,
+        })))
+    }
+
+    /// Create a port that reads from source. name is used in error messages.
+    pub fn reader(name: &str, source: Source) -> Self {
+        Self::new(name, Target::Reader(source))
+    }
+
+    /// Create a port that writes to sink. name is used in error messages.
+    pub fn writer(name: &str, sink: Sink) -> Self {
+        Self::new(name, Target::Writer(sink))
+    }
+
+    /// Create a port that reads from the input of the environment using it, so it follows
+    /// [Environment::set_input].
+    pub fn stdin() -> Self {
+        Self::new("stdin", Target::EnvInput)
+    }
+
+    /// Create a port that writes to the output of the environment using it, so it follows
+    /// [Environment::set_output].
+    pub fn stdout() -> Self {
+        Self::new("stdout", Target::EnvOutput)
+    }
+
+    /// The name of the port, such as the path of the file it was opened from.
+    pub fn name(&self) -> String {
+        self.0.borrow().name.clone()
+    }
+
+    /// Returns true if the port hasn't been closed.
+    pub fn is_open(&self) -> bool {
+        !matches!(self.0.borrow().target, Target::Closed)
+    }
+
+    /// Returns true if both ports are handles to the same stream.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Read a line from the port, including the trailing newline if there is one. Returns `None`
+    /// at the end of the input.
+    pub(crate) fn read_line(&self, env: &Environment) -> Result<Option<String>, LispError> {
+        let state = self.0.borrow();
+        match &state.target {
+            Target::Reader(source) => {
+                env::read_source_line(source).map_err(|e| LispError::io(&state.name, e))
+            }
+            Target::EnvInput => env.read_input_line(),
+            Target::Writer(_) | Target::EnvOutput => Err(self.wrong_direction("reading")),
+            Target::Closed => Err(self.closed()),
+        }
+    }
+
+    /// Write text to the port.
+    pub(crate) fn write_str(&self, text: &str, env: &Environment) -> Result<(), LispError> {
+        let state = self.0.borrow();
+        match &state.target {
+            Target::Writer(sink) => {
+                env::write_sink(sink, text).map_err(|e| LispError::io(&state.name, e))
+            }
+            Target::EnvOutput => env.write_output(text),
+            Target::Reader(_) | Target::EnvInput => Err(self.wrong_direction("writing")),
+            Target::Closed => Err(self.closed()),
+        }
+    }
+
+    /// Close the port, releasing the file or stream behind it. Closing a port twice is an error.
+    pub(crate) fn close(&self) -> Result<(), LispError> {
+        if !self.is_open() {
+            return Err(self.closed());
+        }
+        self.0.borrow_mut().target = Target::Closed;
+        Ok(())
+    }
+
+    fn wrong_direction(&self, direction: &str) -> LispError {
+        LispError::ValueError(format!(
+            "port {} is not open for {}",
+            self.name(),
+            direction
+        ))
+    }
+
+    fn closed(&self) -> LispError {
+        LispError::ValueError(format!("port {} is closed", self.name()))
+    }
+}
+
+impl Debug for Port {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Port {{ name: {:?} }}", self.name())
+    }
+}
@@ -0,0 +1,128 @@
+//! Contains the settings and functions used to convert values into their display form.
+//!
+//! The display form is what the REPL and printing builtins show to the user. It is allowed to
+//! lose information (such as float precision) to stay readable. The write form, produced by the
+//! [Display](std::fmt::Display) impl of [Ast], always stays re-readable by the parser.
+
+use std::fmt::Write;
+
+use crate::ast::{format_float, write_tree, Ast, LispAtom};
+
+/// Settings that control how values are shown in display form.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrinterSettings {
+    /// Floats with an absolute value greater than this are shown in scientific notation.
+    pub sci_upper: f64,
+
+    /// Non-zero floats with an absolute value smaller than this are shown in scientific notation.
+    pub sci_lower: f64,
+
+    /// Number of significant digits shown for floats in scientific notation.
+    pub sig_digits: usize,
+}
+
+impl Default for PrinterSettings {
+    fn default() -> Self {
+        Self {
+            sci_upper: 1e16,
+            sci_lower: 1e-4,
+            sig_digits: 6,
+        }
+    }
+}
+
+/// Convert a value into its display form.
+pub fn display_string(ast: &Ast, settings: &PrinterSettings) -> String {
+    let mut output = String::new();
+    write_tree(ast, &mut output, |leaf, output| match leaf {
+        Ast::Atom(LispAtom::Float(num)) => output.write_str(&display_float(*num, settings)),
+        _ => write!(output, "{}", leaf),
+    })
+    .expect("writing to a String can't fail");
+    output
+}
+
+fn display_float(num: f64, settings: &PrinterSettings) -> String {
+    let abs = num.abs();
+    if !num.is_finite() {
+        format_float(num)
+    } else if abs > settings.sci_upper || (abs != 0.0 && abs < settings.sci_lower) {
+        format!("{:.*e}", settings.sig_digits.saturating_sub(1), num)
+    } else if num.fract() == 0.0 {
+        // Keep the decimal point so floats with integer values don't look like Ints
+        format!("{:.1}", num)
+    } else {
+        num.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn float(num: f64) -> Ast {
+        Ast::Atom(LispAtom::Float(num))
+    }
+
+    #[test]
+    fn upper_threshold_boundary() {
+        let settings = PrinterSettings::default();
+        assert_eq!(
+            display_string(&float(1e16), &settings),
+            "10000000000000000.0"
+        );
+        assert_eq!(display_string(&float(2e16), &settings), "2.00000e16");
+        assert_eq!(
+            display_string(&float(-1e16), &settings),
+            "-10000000000000000.0"
+        );
+        assert_eq!(display_string(&float(-2e16), &settings), "-2.00000e16");
+    }
+
+    #[test]
+    fn lower_threshold_boundary() {
+        let settings = PrinterSettings::default();
+        assert_eq!(display_string(&float(1e-4), &settings), "0.0001");
+        assert_eq!(display_string(&float(9e-5), &settings), "9.00000e-5");
+        assert_eq!(display_string(&float(-1e-4), &settings), "-0.0001");
+        assert_eq!(display_string(&float(-9e-5), &settings), "-9.00000e-5");
+        assert_eq!(display_string(&float(0.0), &settings), "0.0");
+    }
+
+    #[test]
+    fn configurable_settings() {
+        let settings = PrinterSettings {
+            sci_upper: 1000.0,
+            sci_lower: 0.1,
+            sig_digits: 3,
+        };
+        assert_eq!(display_string(&float(1234.5), &settings), "1.23e3");
+        assert_eq!(display_string(&float(0.05), &settings), "5.00e-2");
+        assert_eq!(display_string(&float(500.0), &settings), "500.0");
+    }
+
+    #[test]
+    fn nested_in_list() {
+        let settings = PrinterSettings::default();
+        let list = Ast::List(vec![float(1e300), Ast::Atom(LispAtom::Int(1))]);
+        assert_eq!(display_string(&list, &settings), "(1.00000e300 1)");
+    }
+
+    #[test]
+    fn write_form_unaffected() {
+        // The write form (used by quote and anything that must be re-read) keeps full precision.
+        assert_eq!(float(1e300).to_string(), "1e300");
+        assert_eq!(float(1.5e-7).to_string(), "1.5e-7");
+        assert_eq!(float(0.1 + 0.2).to_string(), "0.30000000000000004");
+    }
+
+    #[test]
+    fn floats_look_like_floats() {
+        let settings = PrinterSettings::default();
+        assert_eq!(float(1.0).to_string(), "1.0");
+        assert_eq!(float(-2.0).to_string(), "-2.0");
+        assert_eq!(display_string(&float(1.0), &settings), "1.0");
+        assert_eq!(display_string(&float(f64::NAN), &settings), "nan");
+        assert_eq!(display_string(&float(f64::NEG_INFINITY), &settings), "-inf");
+    }
+}
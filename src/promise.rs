@@ -0,0 +1,75 @@
+//! Contains [Promise], an expression whose evaluation is delayed until its value is needed.
+
+use crate::ast::Ast;
+use crate::env::Environment;
+use crate::error::LispError;
+use crate::eval;
+
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::rc::Rc;
+
+enum State {
+    /// The expression and the scopes it was delayed in, which it is evaluated in when forced.
+    Delayed(Ast, Box<Environment>),
+    /// The expression is being evaluated.
+    Forcing,
+    Forced(Ast),
+}
+
+/// A delayed expression created by `delay`. The expression is evaluated the first time the
+/// promise is forced, and the value is kept for later forces. Copies of a promise share the same
+/// state, and promises are compared by identity.
+#[derive(Clone)]
+pub struct Promise(Rc<RefCell<State>>);
+
+impl Promise {
+    /// Delay expr, to be evaluated in the scopes currently in env.
+    pub fn new(expr: Ast, env: &Environment) -> Self {
+        Self(Rc::new(RefCell::new(State::Delayed(
+            expr,
+            Box::new(env.share()),
+        ))))
+    }
+
+    /// Returns true if the promise has been forced.
+    pub fn is_forced(&self) -> bool {
+        matches!(*self.0.borrow(), State::Forced(_))
+    }
+
+    /// Returns true if both promises are handles to the same delayed expression.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Evaluate the expression if it hasn't been already, and return its value. If evaluating it
+    /// fails, the promise stays unforced so forcing it again evaluates it again. Forcing the
+    /// promise again while its expression is being evaluated is an error, since it would never
+    /// finish.
+    pub fn force(&self) -> Result<Ast, LispError> {
+        let (expr, mut env) = match &*self.0.borrow() {
+            State::Forced(value) => return Ok(value.clone()),
+            State::Forcing => {
+                return Err(LispError::ValueError(
+                    "promise forced recursively".to_string(),
+                ))
+            }
+            State::Delayed(expr, env) => (expr.clone(), env.share()),
+        };
+        let delayed = std::mem::replace(&mut *self.0.borrow_mut(), State::Forcing);
+        let result = eval::eval_expr(expr, &mut env);
+
+        // Dropping the delayed state after a success releases the scopes it was holding on to
+        *self.0.borrow_mut() = match &result {
+            Ok(value) => State::Forced(value.clone()),
+            Err(_) => delayed,
+        };
+        result
+    }
+}
+
+impl Debug for Promise {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Promise {{ forced: {} }}", self.is_forced())
+    }
+}
@@ -0,0 +1,57 @@
+//! Contains a small pseudo-random number generator used by the `random` builtins.
+//!
+//! This avoids depending on the rand crate, which needs extra setup to get entropy under WASM.
+
+/// A xorshift64* pseudo-random number generator. Not suitable for cryptography.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Create a generator that always produces the same sequence for the same seed.
+    pub fn with_seed(seed: u64) -> Self {
+        // Run the seed through splitmix64 so that similar seeds give unrelated sequences and the
+        // state is never zero (which xorshift can't escape from).
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Self {
+            state: if z == 0 { 1 } else { z },
+        }
+    }
+
+    /// Create a generator seeded from the current time. Under WASM there is no clock available
+    /// without JavaScript so a fixed seed is used instead.
+    pub fn from_time() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(0);
+        #[cfg(target_arch = "wasm32")]
+        let seed = 0;
+
+        Self::with_seed(seed)
+    }
+
+    /// Get the next random 64-bit number.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Get a random float in [0, 1).
+    pub fn next_float(&mut self) -> f64 {
+        // Use the top 53 bits so every value is exactly representable.
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Get a random integer in [0, bound). bound must be positive.
+    pub fn next_below(&mut self, bound: u64) -> u64 {
+        ((self.next_u64() as u128 * bound as u128) >> 64) as u64
+    }
+}
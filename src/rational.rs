@@ -0,0 +1,151 @@
+//! Exact arithmetic on rational numbers, used by the numeric builtins.
+//!
+//! Ints and Rationals are both exact numbers. An exact number is handled as a numerator and a
+//! denominator, with Ints having a denominator of 1. Results are normalized: they are in lowest
+//! terms with a positive denominator, and become Ints when the denominator is 1.
+
+use std::cmp::Ordering;
+
+use crate::ast::{Ast, LispAtom};
+
+/// A numerator and a positive denominator.
+pub(crate) type Exact = (i64, i64);
+
+/// Get the exact value of an Int or Rational.
+pub(crate) fn exact(ast: &Ast) -> Option<Exact> {
+    match ast {
+        Ast::Atom(LispAtom::Int(num)) => Some((*num, 1)),
+        Ast::Atom(LispAtom::Rational(num, den)) => Some((*num, *den)),
+        _ => None,
+    }
+}
+
+/// Convert a normalized exact number to an Int or Rational.
+pub(crate) fn to_ast((num, den): Exact) -> Ast {
+    if den == 1 {
+        Ast::Atom(LispAtom::Int(num))
+    } else {
+        Ast::Atom(LispAtom::Rational(num, den))
+    }
+}
+
+/// Reduce num/den to lowest terms with a positive denominator. Returns None if den is zero or the
+/// result doesn't fit.
+pub(crate) fn make(num: i128, den: i128) -> Option<Exact> {
+    if den == 0 {
+        return None;
+    }
+    let divisor = gcd(num.unsigned_abs(), den.unsigned_abs()) as i128;
+    let sign = den.signum();
+    let num = i64::try_from(sign * num / divisor).ok()?;
+    let den = i64::try_from(sign * den / divisor).ok()?;
+    Some((num, den))
+}
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a.max(1)
+}
+
+/// Add two exact numbers. Returns None on overflow.
+pub(crate) fn add((a, b): Exact, (c, d): Exact) -> Option<Exact> {
+    let (a, b, c, d) = (a as i128, b as i128, c as i128, d as i128);
+    make((a * d).checked_add(c * b)?, b * d)
+}
+
+/// Subtract two exact numbers. Returns None on overflow.
+pub(crate) fn sub((a, b): Exact, (c, d): Exact) -> Option<Exact> {
+    let (a, b, c, d) = (a as i128, b as i128, c as i128, d as i128);
+    make((a * d).checked_sub(c * b)?, b * d)
+}
+
+/// Multiply two exact numbers. Returns None on overflow.
+pub(crate) fn mul((a, b): Exact, (c, d): Exact) -> Option<Exact> {
+    make(a as i128 * c as i128, b as i128 * d as i128)
+}
+
+/// Divide two exact numbers. Returns None on overflow or if the divisor is zero.
+pub(crate) fn div((a, b): Exact, (c, d): Exact) -> Option<Exact> {
+    make(a as i128 * d as i128, b as i128 * c as i128)
+}
+
+/// Compare two exact numbers.
+pub(crate) fn cmp((a, b): Exact, (c, d): Exact) -> Ordering {
+    // Denominators are positive, so cross multiplying keeps the order
+    (a as i128 * d as i128).cmp(&(c as i128 * b as i128))
+}
+
+/// Convert an exact number to the nearest float.
+pub(crate) fn to_float((num, den): Exact) -> f64 {
+    num as f64 / den as f64
+}
+
+/// Round an exact number to an integer: toward negative infinity, toward positive infinity,
+/// toward zero, or to the nearest integer with halves rounded away from zero.
+pub(crate) fn floor((num, den): Exact) -> i64 {
+    num.div_euclid(den)
+}
+
+/// See [floor].
+pub(crate) fn ceil((num, den): Exact) -> i64 {
+    // Can't overflow, since den > 1 for anything that isn't already an integer
+    let floor = num.div_euclid(den);
+    if num.rem_euclid(den) == 0 {
+        floor
+    } else {
+        floor + 1
+    }
+}
+
+/// See [floor].
+pub(crate) fn truncate((num, den): Exact) -> i64 {
+    num / den
+}
+
+/// See [floor].
+pub(crate) fn round((num, den): Exact) -> i64 {
+    let trunc = num / den;
+    let rem = (num % den).unsigned_abs();
+    if 2 * rem as u128 >= den as u128 {
+        trunc + num.signum()
+    } else {
+        trunc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn make_normalizes() {
+        assert_eq!(make(2, 4), Some((1, 2)));
+        assert_eq!(make(2, -4), Some((-1, 2)));
+        assert_eq!(make(-3, -9), Some((1, 3)));
+        assert_eq!(make(6, 3), Some((2, 1)));
+        assert_eq!(make(0, -5), Some((0, 1)));
+        assert_eq!(make(1, 0), None);
+        assert_eq!(make(i64::MIN as i128, -1), None);
+        assert_eq!(to_ast((2, 1)), Ast::Atom(LispAtom::Int(2)));
+        assert_eq!(to_ast((1, 2)), Ast::Atom(LispAtom::Rational(1, 2)));
+    }
+
+    #[test]
+    fn rounding() {
+        let cases = [
+            ((7, 2), 3, 4, 3, 4),
+            ((-7, 2), -4, -3, -3, -4),
+            ((1, 3), 0, 1, 0, 0),
+            ((-1, 3), -1, 0, 0, 0),
+            ((5, 3), 1, 2, 1, 2),
+        ];
+        for (num, f, c, t, r) in cases {
+            assert_eq!(floor(num), f, "floor {:?}", num);
+            assert_eq!(ceil(num), c, "ceil {:?}", num);
+            assert_eq!(truncate(num), t, "truncate {:?}", num);
+            assert_eq!(round(num), r, "round {:?}", num);
+        }
+    }
+}
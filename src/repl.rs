@@ -1,23 +1,27 @@
+//! Contains the read-eval-print loop used when no file is given.
+
 use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use crate::ast::Ast;
 use crate::env::Environment;
 use crate::error::LispError;
 use crate::eval::eval_expr;
-use crate::parser::parse_complete_expr;
+use crate::parser::{self, parse_complete_expr};
+use crate::printer::display_string;
 
-fn read() -> Result<Ast, LispError> {
+fn read(env: &Environment) -> Result<Ast, LispError> {
     print!("callisp> ");
-    io::stdout().flush().map_err(|_| LispError::IOError)?;
-    let mut buf = String::new();
-    io::stdin()
-        .read_line(&mut buf)
-        .map_err(|_| LispError::IOError)?;
+    io::stdout()
+        .flush()
+        .map_err(|e| LispError::io("stdout", e))?;
+    let buf = env.read_input_line()?.unwrap_or_default();
     let buf = buf.trim_end().to_string();
 
     // TODO: Process input
-    let expr = parse_complete_expr(&buf)
-        .map_err(|_| LispError::ParseError(buf.to_string()))?
+    let expr = parser::with_fold_case(env.fold_case(), || parse_complete_expr(&buf))
+        .map_err(|error| parser::error_from(&buf, error))?
         .1;
 
     Ok(expr)
@@ -27,29 +31,71 @@ fn eval(input: Ast, env: &mut Environment) -> Result<Ast, LispError> {
     eval_expr(input, env)
 }
 
-fn print(input: Ast) {
+fn print(input: Ast, env: &Environment) {
     if !matches!(input, Ast::Unspecified) {
-        println!("{}", input);
+        println!("{}", display_string(&input, env.printer()));
+    }
+}
+
+/// Install a Ctrl-C handler that interrupts the current evaluation. Pressing Ctrl-C twice while
+/// nothing is being evaluated exits.
+#[cfg(not(target_arch = "wasm32"))]
+fn install_interrupt_handler(interrupt: Arc<AtomicBool>, evaluating: Arc<AtomicBool>) {
+    let result = ctrlc::set_handler(move || {
+        if evaluating.load(Ordering::Relaxed) {
+            interrupt.store(true, Ordering::Relaxed);
+        } else if interrupt.swap(true, Ordering::Relaxed) {
+            println!();
+            std::process::exit(0);
+        } else {
+            print!("\n(To exit, press Ctrl-C again)\ncallisp> ");
+            let _ = io::stdout().flush();
+        }
+    });
+
+    if let Err(e) = result {
+        eprintln!("Could not install Ctrl-C handler: {}", e);
     }
 }
 
-pub fn repl() {
+/// Run the REPL until the process exits. If fold_case is true, symbols are read
+/// case-insensitively by converting them to lowercase.
+pub fn repl(fold_case: bool) {
     let mut env = Environment::outer_new();
+    env.set_fold_case(fold_case);
+    env.bind_args(None, vec![]);
+    let interrupt = env.interrupt_flag();
+    let evaluating = Arc::new(AtomicBool::new(false));
+    #[cfg(not(target_arch = "wasm32"))]
+    install_interrupt_handler(interrupt.clone(), evaluating.clone());
+
     loop {
-        let input = match read() {
+        let input = match read(&env) {
             Ok(expr) => expr,
             Err(e) => {
                 eprintln!("{}", e);
                 continue;
             }
         };
-        let result = match eval(input, &mut env) {
+        // Forget about any Ctrl-C pressed while waiting for input
+        interrupt.store(false, Ordering::Relaxed);
+        evaluating.store(true, Ordering::Relaxed);
+        let result = eval(input, &mut env);
+        evaluating.store(false, Ordering::Relaxed);
+        interrupt.store(false, Ordering::Relaxed);
+
+        let result = match result {
             Ok(val) => val,
+            Err(LispError::Exit(code)) => {
+                let code = env.run_at_exit().unwrap_or(code);
+                drop(env);
+                std::process::exit(code);
+            }
             Err(e) => {
                 eprintln!("{}", e);
                 continue;
             }
         };
-        print(result);
+        print(result, &env);
     }
 }
@@ -2,10 +2,11 @@
 
 use std::collections::HashMap;
 
-use crate::ast::{Ast, LispAtom, LispLambda};
+use crate::ast::{Ast, LispAtom, LispCallable, LispLambda, Params};
 use crate::env::Environment;
 use crate::error::LispError;
 use crate::eval;
+use crate::promise::Promise;
 
 use lazy_static::lazy_static;
 
@@ -17,10 +18,82 @@ lazy_static! {
         map.insert("λ", lambda);
         map.insert("lambda", lambda);
         map.insert("def", define);
+        map.insert("set!", set);
+        map.insert("define-values", define_values);
+        map.insert("let-values", let_values);
         map.insert("if", lisp_if);
         map.insert("quote", quote);
+        map.insert("quasiquote", quasiquote);
+        map.insert("unquote", unquote);
+        map.insert("unquote-splicing", unquote);
+        map.insert("let", lisp_let);
+        map.insert("let*", let_star);
+        map.insert("letrec", letrec);
+        map.insert("letfn", letfn);
+        map.insert("cond", cond);
+        map.insert("and", and);
+        map.insert("or", or);
+        map.insert("loop", lisp_loop);
+        map.insert("recur", recur);
+        map.insert("dotimes", dotimes);
+        map.insert("for-each", for_each);
+        map.insert("for", lisp_for);
+        map.insert("match", lisp_match);
+        map.insert("delay", delay);
+        map.insert("->", thread_first);
+        map.insert("->>", thread_last);
         map
     };
+    static ref EXPANDERS: HashMap<&'static str, Expander> = {
+        let mut map: HashMap<&'static str, Expander> = HashMap::new();
+        map.insert("def", expand_def);
+        map.insert("->", expand_thread_first);
+        map.insert("->>", expand_thread_last);
+        map
+    };
+}
+
+/// Rewrites a use of a special form into other code, given the arguments of the form. Returns
+/// `None` if this use of the form isn't rewritten.
+type Expander = fn(&[Ast]) -> Result<Option<Ast>, LispError>;
+
+/// Expand expr once if it uses a special form that is defined by rewriting it into other code,
+/// such as `(def (name params ...) body)`. Returns `None` if expr isn't rewritten.
+pub(crate) fn expand_once(expr: &Ast) -> Result<Option<Ast>, LispError> {
+    let Ast::List(list) = expr else {
+        return Ok(None);
+    };
+    match list.split_first() {
+        Some((Ast::Atom(LispAtom::Symbol(head)), args)) => match EXPANDERS.get(head.as_str()) {
+            Some(expander) => expander(args),
+            None => Ok(None),
+        },
+        _ => Ok(None),
+    }
+}
+
+/// Expand expr until it is no longer rewritten. Forms inside it are left alone.
+pub(crate) fn expand(mut expr: Ast) -> Result<Ast, LispError> {
+    while let Some(expanded) = expand_once(&expr)? {
+        expr = expanded;
+    }
+    Ok(expr)
+}
+
+/// Expand expr and every form inside it, except quoted and quasiquoted ones.
+pub(crate) fn expand_all(expr: Ast) -> Result<Ast, LispError> {
+    let mut expr = expand(expr)?;
+    if let Ast::List(items) = &mut expr {
+        if let Some(Ast::Atom(LispAtom::Symbol(head))) = items.first() {
+            if head == "quote" || head == "quasiquote" {
+                return Ok(expr);
+            }
+        }
+        for item in items.iter_mut() {
+            *item = expand_all(std::mem::replace(item, Ast::Unspecified))?;
+        }
+    }
+    Ok(expr)
 }
 
 #[inline(always)]
@@ -40,7 +113,7 @@ pub fn lisp_if(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError>
     let condition = args.next().ok_or(LispError::BadArity)?;
     let condition = eval::eval_expr(condition, env)?;
 
-    if condition != Ast::Atom(LispAtom::Bool(false)) {
+    if condition.is_truthy() {
         // Evaluate true block
         eval::eval_expr(args.next().ok_or(LispError::BadArity)?, env)
     } else {
@@ -49,49 +122,1055 @@ pub fn lisp_if(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError>
     }
 }
 
+/// Evaluate the body of the first clause whose test is true.
+///
+/// Example:
+/// `(cond ((< x 0) "negative") ((= x 0) "zero") (else "positive"))`. A test of `else` always
+/// matches, and if no clause matches the result is unspecified. Tests after the matching clause
+/// are not evaluated.
+pub fn cond(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    for (index, mut clause) in args.into_iter().enumerate() {
+        let Ast::List(clause) = &mut clause else {
+            return Err(LispError::SyntaxError(format!(
+                "cond clause {} is not a list: {}",
+                index, clause
+            )));
+        };
+        let mut clause = std::mem::take(clause).into_iter();
+        let Some(test) = clause.next() else {
+            return Err(LispError::SyntaxError(format!(
+                "cond clause {} is empty",
+                index
+            )));
+        };
+
+        let value = match &test {
+            Ast::Atom(LispAtom::Symbol(symbol)) if symbol == "else" => Ast::Unspecified,
+            _ => match eval::eval_expr(test, env)? {
+                value if value.is_truthy() => value,
+                _ => continue,
+            },
+        };
+        // A clause with no body returns the value of its test
+        if clause.len() == 0 {
+            return Ok(value);
+        }
+        return eval_body(clause, env);
+    }
+    Ok(Ast::Unspecified)
+}
+
+/// Evaluate arguments from left to right until one is false, returning it, or the last value if
+/// none is. Returns true if there are no arguments.
+pub fn and(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    let mut result = Ast::Atom(LispAtom::Bool(true));
+    for arg in args {
+        result = eval::eval_expr(arg, env)?;
+        if !result.is_truthy() {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+/// Evaluate arguments from left to right until one is true, returning it, or the last value if
+/// none is. Returns false if there are no arguments.
+pub fn or(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    let mut result = Ast::Atom(LispAtom::Bool(false));
+    for arg in args {
+        result = eval::eval_expr(arg, env)?;
+        if result.is_truthy() {
+            break;
+        }
+    }
+    Ok(result)
+}
+
 /// Create a binding in the current environment.
 ///
 /// Example:
 /// `(define x 3)` binds x to 3. Now the expression `x` returns 3.
+///
+/// `(def (add1 x) (+ x 1))` is short for `(def add1 (lambda (x) (+ x 1)))`, and the body can
+/// have several expressions like a lambda's.
 pub fn define(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    if let Some(expanded) = expand_def(&args)? {
+        return eval::eval_expr(expanded, env);
+    }
+
     let mut args = args.into_iter();
 
-    let Some(Ast::Atom(LispAtom::Symbol(binding))) = args.next() else {
+    let target = args.next().ok_or(LispError::BadArity)?;
+    let binding = match &target {
+        Ast::Atom(LispAtom::Symbol(binding)) => binding,
+        Ast::Atom(LispAtom::Keyword(keyword)) => {
+            return Err(LispError::SyntaxError(format!(
+                "can't define :{}, since keywords always evaluate to themselves",
+                keyword
+            )))
+        }
+        _ => return Err(LispError::TypeError),
+    };
+
+    let value = eval::eval_expr(args.next().ok_or(LispError::BadArity)?, env)?;
+
+    env.bind(binding.clone(), value);
+
+    Ok(Ast::Unspecified)
+}
+
+/// Rewrite `(def (name params ...) body ...)` into `(def name (lambda (params ...) body ...))`.
+fn expand_def(args: &[Ast]) -> Result<Option<Ast>, LispError> {
+    let Some((Ast::List(signature), body)) = args.split_first() else {
+        return Ok(None);
+    };
+    let Some((name @ Ast::Atom(LispAtom::Symbol(_)), params)) = signature.split_first() else {
+        return Err(LispError::TypeError);
+    };
+
+    let mut lambda = vec![
+        Ast::Atom(LispAtom::Symbol("lambda".to_string())),
+        Ast::List(params.to_vec()),
+    ];
+    lambda.extend_from_slice(body);
+    Ok(Some(Ast::List(vec![
+        Ast::Atom(LispAtom::Symbol("def".to_string())),
+        name.clone(),
+        Ast::List(lambda),
+    ])))
+}
+
+/// Rewrite a threading form into nested calls, inserting each value as the first argument of the
+/// next step, or as the last argument if last is true. A step that isn't a list, such as a bare
+/// function name, is called with the value as its only argument.
+fn expand_thread(args: &[Ast], last: bool) -> Result<Ast, LispError> {
+    let (value, steps) = args.split_first().ok_or(LispError::BadArity)?;
+
+    let mut value = value.clone();
+    for step in steps {
+        value = match step {
+            Ast::List(call) if call.is_empty() => {
+                return Err(LispError::SyntaxError(
+                    "can't thread a value through ()".to_string(),
+                ))
+            }
+            Ast::List(call) => {
+                let mut call = call.clone();
+                match last {
+                    true => call.push(value),
+                    false => call.insert(1, value),
+                }
+                Ast::List(call)
+            }
+            _ => Ast::List(vec![step.clone(), value]),
+        };
+    }
+    Ok(value)
+}
+
+/// Rewrite `(-> x (f a) (g b))` into `(g (f x a) b)`.
+fn expand_thread_first(args: &[Ast]) -> Result<Option<Ast>, LispError> {
+    expand_thread(args, false).map(Some)
+}
+
+/// Rewrite `(->> x (f a) (g b))` into `(g b (f a x))`.
+fn expand_thread_last(args: &[Ast]) -> Result<Option<Ast>, LispError> {
+    expand_thread(args, true).map(Some)
+}
+
+/// Thread a value through a series of calls as their first argument.
+///
+/// Example:
+/// `(-> 5 (- 1) (list 2) count)` is `(count (list (- 5 1) 2))`, which returns 2.
+pub fn thread_first(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    eval::eval_expr(expand_thread(&args, false)?, env)
+}
+
+/// Thread a value through a series of calls as their last argument.
+///
+/// Example:
+/// `(->> 5 (- 1) (list 2))` is `(list 2 (- 1 5))`, which returns `(2 -4)`.
+pub fn thread_last(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    eval::eval_expr(expand_thread(&args, true)?, env)
+}
+
+/// Change the value of an existing binding in the innermost scope that has it, rather than
+/// creating a new binding like `def`. Returns unspecified, like `def`.
+///
+/// Example:
+/// `(def n 0) ((lambda () (set! n (+ n 1))))` leaves n as 1.
+pub fn set(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    let mut args = args.into_iter();
+
+    let Some(Ast::Atom(LispAtom::Symbol(name))) = &args.next() else {
         return Err(LispError::TypeError);
     };
 
     let value = eval::eval_expr(args.next().ok_or(LispError::BadArity)?, env)?;
 
-    env.bind(binding, value);
+    env.set_existing(name, value)?;
+
+    Ok(Ast::Unspecified)
+}
+
+/// Convert a list of names such as `(q r)` into strings, checking that they are distinct symbols.
+fn value_names(names: &Ast) -> Result<Vec<String>, LispError> {
+    let Ast::List(names) = names else {
+        return Err(LispError::SyntaxError(format!(
+            "expected a list of names but got {}",
+            names
+        )));
+    };
+
+    let mut parsed: Vec<String> = Vec::with_capacity(names.len());
+    for name in names {
+        match name {
+            Ast::Atom(LispAtom::Symbol(name)) if !parsed.contains(name) => {
+                parsed.push(name.clone())
+            }
+            Ast::Atom(LispAtom::Symbol(name)) => {
+                return Err(LispError::SyntaxError(format!("duplicate name: {}", name)))
+            }
+            _ => return Err(LispError::SyntaxError(format!("{} is not a name", name))),
+        }
+    }
+    Ok(parsed)
+}
+
+/// Evaluate expr and pair each name with an item of the list it returns.
+fn eval_values(
+    names: Vec<String>,
+    expr: Ast,
+    env: &mut Environment,
+) -> Result<Vec<(String, Ast)>, LispError> {
+    let mut values = eval::eval_expr(expr, env)?;
+    let Ast::List(values) = &mut values else {
+        return Err(LispError::TypeMismatch(format!(
+            "expected a list of {} values but got {}",
+            names.len(),
+            values
+        )));
+    };
+    if values.len() != names.len() {
+        return Err(LispError::TypeMismatch(format!(
+            "expected {} values but got {}",
+            names.len(),
+            values.len()
+        )));
+    }
+    Ok(names.into_iter().zip(std::mem::take(values)).collect())
+}
+
+/// Bind each name to an item of a list in the current environment.
+///
+/// Example:
+/// `(define-values (q r) (list 3 2))` binds q to 3 and r to 2. The list must have exactly one
+/// item for each name.
+pub fn define_values(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    let [names, expr] = <[Ast; 2]>::try_from(args).map_err(|_| LispError::BadArity)?;
+    let names = value_names(&names)?;
 
+    for (name, value) in eval_values(names, expr, env)? {
+        env.bind(name, value);
+    }
     Ok(Ast::Unspecified)
 }
 
-/// Create a lambda function.
+/// Like `let`, but each binding is a list of names bound to the items of a list.
+///
+/// Example:
+/// `(let-values (((q r) (list 3 2))) (+ q r))` returns 5.
+pub fn let_values(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    let mut args = args.into_iter();
+    let Some(Ast::List(bindings)) = &args.next() else {
+        return Err(LispError::TypeError);
+    };
+
+    let mut scope = HashMap::new();
+    for binding in bindings {
+        let Ast::List(pair) = binding else {
+            return Err(LispError::TypeError);
+        };
+        let [names, expr] = pair.as_slice() else {
+            return Err(LispError::TypeError);
+        };
+        scope.extend(eval_values(value_names(names)?, expr.clone(), env)?);
+    }
+
+    env.new_scope(scope);
+    let result = eval_body(args, env);
+    env.pop_scope();
+    result
+}
+
+/// Create a lambda function. The body can have several expressions, which are evaluated in order
+/// with the value of the last one returned. If there is more than one and the first is a string,
+/// it is the function's documentation rather than part of the body.
 ///
 /// Example:
 /// `(lambda (x) (+ x 1))` creates a function that adds 1 to x.
 pub fn lambda(args: Vec<Ast>, _env: &mut Environment) -> Result<Ast, LispError> {
     let mut args = args.into_iter();
 
-    // convert Vec<Ast> to Vec<String>
-    let bindings: Vec<_> = if let Some(Ast::List(bindings)) = args.next() {
+    let params = parse_params(args.next().ok_or(LispError::BadArity)?)?;
+
+    let mut body: Vec<Ast> = args.collect();
+    if body.is_empty() {
+        return Err(LispError::SyntaxError(format!(
+            "lambda ({}) has no body",
+            params
+        )));
+    }
+    // A lone string is the value the function returns, not its documentation
+    let has_more = body.len() > 1;
+    let docstring = match body.first_mut() {
+        Some(Ast::Atom(LispAtom::String(doc))) if has_more => Some(std::mem::take(doc)),
+        _ => None,
+    };
+
+    let lambda = match docstring {
+        Some(docstring) => LispLambda::new(params, body.split_off(1)).with_docstring(docstring),
+        None => LispLambda::new(params, body),
+    };
+
+    Ok(Ast::Function(Box::new(lambda)))
+}
+
+/// Convert a parameter list such as `(x y &optional (z 1) &key w)` into [Params].
+///
+/// The required parameters come first, followed by optional ones after `&optional`, then either a
+/// single parameter after `&rest` or keyword parameters after `&key`. Optional and keyword
+/// parameters can be written `(name default)`. Every name must be a symbol and no name may appear
+/// twice.
+fn parse_params(params: Ast) -> Result<Params, LispError> {
+    let Ast::List(params) = &params else {
+        return Err(LispError::SyntaxError(format!(
+            "expected a list of parameters but got {}, e.g. (lambda (x) ...)",
+            params
+        )));
+    };
+
+    let mut parsed = Params::default();
+    let mut names: Vec<&str> = Vec::with_capacity(params.len());
+    // The marker of the section being read, in the order they have to appear
+    let markers = ["", "&optional", "&rest", "&key"];
+    let mut section = 0;
+    for (index, param) in params.iter().enumerate() {
+        if let Ast::Atom(LispAtom::Symbol(symbol)) = param {
+            if let Some(position) = markers.iter().position(|marker| marker == symbol) {
+                if position <= section {
+                    return Err(LispError::SyntaxError(format!(
+                        "unexpected {} in parameter list",
+                        symbol
+                    )));
+                }
+                if section == 2 {
+                    return Err(LispError::SyntaxError(match parsed.rest {
+                        Some(_) => "&rest and &key can't be used together".to_string(),
+                        None => "&rest must be followed by exactly one parameter".to_string(),
+                    }));
+                }
+                section = position;
+                continue;
+            }
+        }
+
+        let (name, default) = match param {
+            Ast::Atom(LispAtom::Symbol(symbol)) => (symbol, None),
+            Ast::List(pair) if section == 1 || section == 3 => match pair.as_slice() {
+                [Ast::Atom(LispAtom::Symbol(symbol)), default] => (symbol, Some(default.clone())),
+                _ => {
+                    return Err(LispError::SyntaxError(format!(
+                        "parameter {} should be a symbol or (name default): {}",
+                        index, param
+                    )))
+                }
+            },
+            _ => {
+                return Err(LispError::SyntaxError(format!(
+                    "parameter {} is not a symbol: {}",
+                    index, param
+                )))
+            }
+        };
+
+        if names.contains(&name.as_str()) {
+            return Err(LispError::SyntaxError(format!(
+                "duplicate parameter name: {}",
+                name
+            )));
+        }
+        names.push(name);
+
+        match section {
+            0 => parsed.required.push(name.clone()),
+            1 => parsed.optional.push((name.clone(), default)),
+            2 if parsed.rest.is_none() => parsed.rest = Some(name.clone()),
+            2 => {
+                return Err(LispError::SyntaxError(
+                    "&rest must be followed by exactly one parameter".to_string(),
+                ))
+            }
+            _ => parsed.keys.push((name.clone(), default)),
+        }
+    }
+    if section == 2 && parsed.rest.is_none() {
+        return Err(LispError::SyntaxError(
+            "&rest must be followed by exactly one parameter".to_string(),
+        ));
+    }
+
+    Ok(parsed)
+}
+
+/// Split a let binding list such as `((x 1) (y 2))` into names and unevaluated values.
+fn let_bindings(bindings: Ast) -> Result<Vec<(String, Ast)>, LispError> {
+    let mut bindings = bindings;
+    let Ast::List(bindings) = &mut bindings else {
+        return Err(LispError::TypeError);
+    };
+
+    bindings
+        .iter_mut()
+        .map(|binding| match binding {
+            Ast::List(pair) => match pair.as_mut_slice() {
+                [Ast::Atom(LispAtom::Symbol(name)), value] => Ok((
+                    std::mem::take(name),
+                    std::mem::replace(value, Ast::Unspecified),
+                )),
+                _ => Err(LispError::TypeError),
+            },
+            _ => Err(LispError::TypeError),
+        })
+        .collect()
+}
+
+/// Evaluate each expression in body in order, returning the value of the last one.
+fn eval_body(body: impl Iterator<Item = Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    let mut result = Err(LispError::BadArity);
+    for expr in body {
+        result = Ok(eval::eval_expr(expr, env)?);
+    }
+    result
+}
+
+/// Evaluate each value and bind it in the current scope in order, then evaluate body.
+fn bind_sequentially(
+    bindings: Vec<(String, Ast)>,
+    body: impl Iterator<Item = Ast>,
+    env: &mut Environment,
+) -> Result<Ast, LispError> {
+    for (name, value) in bindings {
+        let value = eval::eval_expr(value, env)?;
+        env.bind(name, value);
+    }
+    eval_body(body, env)
+}
+
+/// Bind names to values in a new scope while evaluating a body.
+///
+/// Example:
+/// `(let ((x 1) (y 2)) (+ x y))` returns 3. Every value is evaluated before any name is bound, so
+/// values can't refer to each other.
+///
+/// With a name before the bindings, the body becomes a function of the bound names, which is
+/// called with the values and can call itself using the name:
+/// `(let count ((i 0)) (if (< i 3) (count (+ i 1)) i))` returns 3.
+pub fn lisp_let(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    let mut args = args.into_iter();
+    let first = args.next().ok_or(LispError::BadArity)?;
+    if let Ast::Atom(LispAtom::Symbol(name)) = &first {
+        let bindings = let_bindings(args.next().ok_or(LispError::BadArity)?)?;
+        return named_let(name, bindings, args.collect(), env);
+    }
+    let bindings = let_bindings(first)?;
+
+    let mut scope = HashMap::new();
+    for (name, value) in bindings {
+        let value = eval::eval_expr(value, env)?;
+        scope.insert(name, value);
+    }
+
+    env.new_scope(scope);
+    let result = eval_body(args, env);
+    // Pop the scope even if the body failed, so the error doesn't leave it behind
+    env.pop_scope();
+    result
+}
+
+/// Call a function made from the bindings and body of a named let with the initial values, with
+/// the function bound to name while it runs.
+///
+/// Special forms are looked up before bindings, so a name like `loop` is renamed to a fresh one
+/// in the body, where it shadows the special form.
+fn named_let(
+    name: &str,
+    bindings: Vec<(String, Ast)>,
+    mut body: Vec<Ast>,
+    env: &mut Environment,
+) -> Result<Ast, LispError> {
+    if body.is_empty() {
+        return Err(LispError::BadArity);
+    }
+    let mut name = name.to_string();
+    if SPECIAL_FORMS.contains_key(name.as_str()) {
+        let fresh = format!("{}__{}", name, env.next_gensym());
+        for expr in &mut body {
+            rename_symbol(expr, &name, &fresh, false);
+        }
+        name = fresh;
+    }
+    let mut names = Vec::with_capacity(bindings.len());
+    let mut values = Vec::with_capacity(bindings.len());
+    for (name, value) in bindings {
+        names.push(name);
+        values.push(eval::eval_expr(value, env)?);
+    }
+
+    let function = LispLambda::new(Params::positional(names), body);
+    env.new_scope(HashMap::from([(
+        name,
+        Ast::Function(Box::new(function.clone())),
+    )]));
+    let result = function.call(values, env);
+    env.pop_scope();
+    result
+}
+
+/// Replace the symbol from with to everywhere in expr that it would be evaluated, leaving quoted
+/// data alone. quoted is true inside a quasiquote template, outside any `unquote`.
+fn rename_symbol(expr: &mut Ast, from: &str, to: &str, quoted: bool) {
+    match expr {
+        Ast::Atom(LispAtom::Symbol(symbol)) if !quoted && symbol == from => {
+            *symbol = to.to_string()
+        }
+        Ast::List(items) => {
+            let quoted = match items.first() {
+                Some(Ast::Atom(LispAtom::Symbol(head))) => match head.as_str() {
+                    "quote" if !quoted => return,
+                    "quasiquote" if !quoted => true,
+                    "unquote" | "unquote-splicing" if quoted => false,
+                    _ => quoted,
+                },
+                _ => quoted,
+            };
+            // An unquoted dotted tail is evaluated too
+            let unquoted_tail = quoted && split_unquoted_tail(items).1.is_some();
+            let len = items.len();
+            for (i, item) in items.iter_mut().enumerate() {
+                rename_symbol(item, from, to, quoted && !(unquoted_tail && i == len - 1));
+            }
+        }
+        Ast::Vector(items) => {
+            for item in items {
+                rename_symbol(item, from, to, quoted);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Like `let`, but each value is evaluated in a scope that already has the bindings before it.
+///
+/// Example:
+/// `(let* ((x 1) (y (+ x 1))) y)` returns 2.
+pub fn let_star(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    let mut args = args.into_iter();
+    let bindings = let_bindings(args.next().ok_or(LispError::BadArity)?)?;
+
+    env.new_scope(HashMap::new());
+    let result = bind_sequentially(bindings, args, env);
+    env.pop_scope();
+    result
+}
+
+/// Like `let`, but every name is bound (to unspecified) before any value is evaluated, so local
+/// functions can call themselves and each other.
+///
+/// Lambdas look names up in the environment they are called from rather than capturing it, so
+/// this relies on the functions only being called while the letrec scope is still active.
+///
+/// Example:
+/// `(letrec ((f (lambda (n) (if (= n 0) 1 (* n (f (- n 1))))))) (f 5))` returns 120.
+pub fn letrec(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    let mut args = args.into_iter();
+    let bindings = let_bindings(args.next().ok_or(LispError::BadArity)?)?;
+    bind_recursively(bindings, args, env)
+}
+
+/// Bind every name before evaluating any value, so the values can refer to each other, then
+/// evaluate body.
+fn bind_recursively(
+    bindings: Vec<(String, Ast)>,
+    body: impl Iterator<Item = Ast>,
+    env: &mut Environment,
+) -> Result<Ast, LispError> {
+    env.new_scope(
         bindings
             .iter()
-            .map(|ast| match ast {
-                Ast::Atom(LispAtom::Symbol(symbol)) => Ok(symbol.clone()),
-                _ => Err(LispError::TypeError),
-            })
-            .collect::<Result<_, LispError>>()?
-    } else {
+            .map(|(name, _)| (name.clone(), Ast::Unspecified))
+            .collect(),
+    );
+    let result = bind_sequentially(bindings, body, env);
+    env.pop_scope();
+    result
+}
+
+/// Define local functions that can call themselves and each other, like `letrec` with a lambda
+/// for each binding. The parameter lists are the same as `lambda`'s.
+///
+/// Example:
+/// `(letfn ((square (x) (* x x)) (quad (x) (square (square x)))) (quad 3))` returns 81.
+pub fn letfn(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    let mut args = args.into_iter();
+    let Some(Ast::List(definitions)) = &args.next() else {
+        return Err(LispError::SyntaxError(
+            "letfn needs a list of functions, e.g. (letfn ((f (x) x)) (f 1))".to_string(),
+        ));
+    };
+
+    let mut bindings = Vec::with_capacity(definitions.len());
+    for (index, definition) in definitions.iter().enumerate() {
+        let Some((Ast::Atom(LispAtom::Symbol(name)), lambda)) = (match definition {
+            Ast::List(definition) => definition.split_first(),
+            _ => None,
+        }) else {
+            return Err(LispError::SyntaxError(format!(
+                "letfn function {} should be (name (params ...) body ...): {}",
+                index, definition
+            )));
+        };
+        let mut lambda = lambda.to_vec();
+        lambda.insert(0, Ast::Atom(LispAtom::Symbol("lambda".to_string())));
+        bindings.push((name.clone(), Ast::List(lambda)));
+    }
+    bind_recursively(bindings, args, env)
+}
+
+/// Bind names like `let`, then evaluate the body, starting again with new values whenever `recur`
+/// is called. Each iteration unwinds back here before the next starts, so loops run in constant
+/// stack space.
+///
+/// Example:
+/// `(loop ((i 0) (acc 0)) (if (> i 10) acc (recur (+ i 1) (+ acc i))))` returns 55.
+pub fn lisp_loop(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    let mut args = args.into_iter();
+    let bindings = let_bindings(args.next().ok_or(LispError::BadArity)?)?;
+    let body: Vec<Ast> = args.collect();
+    check_recur_body(&body, RecurPosition::Tail)?;
+
+    let mut names = Vec::with_capacity(bindings.len());
+    let mut values = Vec::with_capacity(bindings.len());
+    for (name, value) in bindings {
+        names.push(name);
+        values.push(eval::eval_expr(value, env)?);
+    }
+
+    env.adjust_loop_depth(1);
+    let result = loop {
+        env.new_scope(names.iter().cloned().zip(values).collect());
+        let result = eval_body(body.iter().cloned(), env);
+        env.pop_scope();
+        match result {
+            Err(LispError::Recur(new_values)) if new_values.len() == names.len() => {
+                values = new_values;
+            }
+            Err(LispError::Recur(_)) => break Err(LispError::BadArity),
+            result => break result,
+        }
+    };
+    env.adjust_loop_depth(-1);
+    result
+}
+
+/// Where an expression is, relative to the body of the `loop` being checked.
+#[derive(Clone, Copy, PartialEq)]
+enum RecurPosition {
+    /// The value of the expression is the value of the loop body.
+    Tail,
+    /// Something still has to be done with the value of the expression.
+    NotTail,
+    /// Inside a function, which could be called after the loop has finished.
+    Function,
+}
+
+impl RecurPosition {
+    /// The position of an expression whose value is used by the expression in this position.
+    fn inner(self) -> Self {
+        match self {
+            Self::Function => Self::Function,
+            _ => Self::NotTail,
+        }
+    }
+}
+
+/// Check that every `recur` in a loop body is in tail position, so that it only ever throws away
+/// work that the loop would have thrown away anyway. The bodies of nested loops are left for
+/// those loops to check.
+///
+/// Example:
+/// `(loop ((i 0)) (if (< i 3) (recur (+ i 1)) i))` is fine, but
+/// `(loop ((i 0)) (if (< i 3) (+ 1 (recur (+ i 1))) i))` is a syntax error.
+fn check_recur(expr: &Ast, position: RecurPosition) -> Result<(), LispError> {
+    let Ast::List(list) = expr else {
+        return Ok(());
+    };
+    let Some((Ast::Atom(LispAtom::Symbol(head)), args)) = list.split_first() else {
+        return check_recur_each(list, position.inner());
+    };
+    let inner = position.inner();
+    match head.as_str() {
+        "quote" => Ok(()),
+        // Only the unquoted parts of a template are evaluated
+        "quasiquote" => args
+            .iter()
+            .try_for_each(|template| check_recur_template(template, inner)),
+        "recur" => match position {
+            RecurPosition::Tail => check_recur_each(args, inner),
+            RecurPosition::NotTail => Err(LispError::SyntaxError(format!(
+                "recur must be in tail position of loop: {}",
+                expr
+            ))),
+            RecurPosition::Function => Err(LispError::SyntaxError(
+                "recur used outside of loop".to_string(),
+            )),
+        },
+        "if" => match args.split_first() {
+            Some((condition, branches)) => {
+                check_recur(condition, inner)?;
+                check_recur_each(branches, position)
+            }
+            None => Ok(()),
+        },
+        "do" | "and" | "or" => check_recur_body(args, position),
+        "cond" => args.iter().try_for_each(|clause| match clause {
+            Ast::List(clause) => check_recur_body(clause, position),
+            clause => check_recur(clause, inner),
+        }),
+        "match" => match args.split_first() {
+            Some((value, clauses)) => {
+                check_recur(value, inner)?;
+                clauses.iter().try_for_each(|clause| match clause {
+                    // Patterns aren't evaluated
+                    Ast::List(clause) => check_recur_body(clause.get(1..).unwrap_or(&[]), position),
+                    clause => check_recur(clause, inner),
+                })
+            }
+            None => Ok(()),
+        },
+        "let" | "let*" | "letrec" | "let-values" => match args {
+            // A named let's body is a function
+            [Ast::Atom(LispAtom::Symbol(_)), bindings, body @ ..] => {
+                check_recur(bindings, inner)?;
+                check_recur_each(body, RecurPosition::Function)
+            }
+            [bindings, body @ ..] => {
+                check_recur(bindings, inner)?;
+                check_recur_body(body, position)
+            }
+            [] => Ok(()),
+        },
+        "letfn" => match args.split_first() {
+            Some((functions, body)) => {
+                check_recur(functions, RecurPosition::Function)?;
+                check_recur_body(body, position)
+            }
+            None => Ok(()),
+        },
+        "loop" => match args.first() {
+            Some(bindings) => check_recur(bindings, inner),
+            None => Ok(()),
+        },
+        "lambda" | "λ" | "delay" => check_recur_each(args, RecurPosition::Function),
+        "def" => match args.first() {
+            Some(Ast::List(_)) => check_recur_each(args, RecurPosition::Function),
+            _ => check_recur_each(args, inner),
+        },
+        _ => check_recur_each(args, inner),
+    }
+}
+
+/// Check a body where the last expression is in position and the rest are not in tail position.
+fn check_recur_body(body: &[Ast], position: RecurPosition) -> Result<(), LispError> {
+    match body.split_last() {
+        Some((last, rest)) => {
+            check_recur_each(rest, position.inner())?;
+            check_recur(last, position)
+        }
+        None => Ok(()),
+    }
+}
+
+/// Check the expressions unquoted in a quasiquoted template, which are never in tail position.
+fn check_recur_template(template: &Ast, position: RecurPosition) -> Result<(), LispError> {
+    match unwrap_form(template, "unquote").or_else(|| unwrap_form(template, "unquote-splicing")) {
+        Some(expr) => check_recur(expr, position),
+        None => match template {
+            Ast::List(items) => {
+                let (items, tail) = split_unquoted_tail(items);
+                items
+                    .iter()
+                    .try_for_each(|item| check_recur_template(item, position))?;
+                tail.map_or(Ok(()), |tail| check_recur(tail, position))
+            }
+            _ => Ok(()),
+        },
+    }
+}
+
+fn check_recur_each(exprs: &[Ast], position: RecurPosition) -> Result<(), LispError> {
+    exprs
+        .iter()
+        .try_for_each(|expr| check_recur(expr, position))
+}
+
+/// Start the innermost `loop` again with new values for its bindings. The loop checks that every
+/// `recur` in its body is in tail position before running it, and functions hide the loops of
+/// their callers, so `recur` can only ever restart the loop it is written in.
+pub fn recur(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    let values = args
+        .into_iter()
+        .map(|arg| eval::eval_expr(arg, env))
+        .collect::<Result<_, _>>()?;
+
+    if env.adjust_loop_depth(0) == 0 {
+        return Err(LispError::SyntaxError(
+            "recur used outside of loop".to_string(),
+        ));
+    }
+    Err(LispError::Recur(values))
+}
+
+/// Split a loop spec such as `(i 10)` into the name and the evaluated value.
+fn loop_spec(spec: Ast, env: &mut Environment) -> Result<(String, Ast), LispError> {
+    let mut bindings = let_bindings(Ast::List(vec![spec]))?;
+    let (name, value) = bindings.pop().expect("one spec gives one binding");
+    Ok((name, eval::eval_expr(value, env)?))
+}
+
+/// Evaluate body once for each value, with name bound to the value in a fresh scope each time.
+fn run_each(
+    name: &str,
+    values: impl Iterator<Item = Ast>,
+    body: &[Ast],
+    env: &mut Environment,
+) -> Result<Ast, LispError> {
+    for value in values {
+        env.new_scope(HashMap::from([(name.to_string(), value)]));
+        let result = body
+            .iter()
+            .try_for_each(|expr| eval::eval_expr(expr.clone(), env).map(|_| ()));
+        // Pop the scope even if the body failed, so the variable doesn't leak
+        env.pop_scope();
+        result?;
+    }
+    Ok(Ast::Unspecified)
+}
+
+/// Evaluate a body a number of times for its side effects.
+///
+/// Example:
+/// `(dotimes (i 3) (println i))` prints 0, 1, and 2.
+pub fn dotimes(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    let mut args = args.into_iter();
+    let (name, count) = loop_spec(args.next().ok_or(LispError::BadArity)?, env)?;
+    let Ast::Atom(LispAtom::Int(count)) = count else {
         return Err(LispError::TypeError);
     };
+    let body: Vec<Ast> = args.collect();
 
-    let body = args.next().ok_or(LispError::BadArity)?;
+    run_each(&name, (0..count).map(Ast::from), &body, env)
+}
+
+/// Evaluate a body once for each item of a list, for its side effects.
+///
+/// Example:
+/// `(for-each (x (list 1 2)) (println x))` prints 1 and 2.
+pub fn for_each(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    let mut args = args.into_iter();
+    let (name, mut list) = loop_spec(args.next().ok_or(LispError::BadArity)?, env)?;
+    let Ast::List(items) = &mut list else {
+        return Err(LispError::TypeError);
+    };
+    let body: Vec<Ast> = args.collect();
 
-    let lambda = LispLambda::new(bindings.len(), bindings, body);
+    run_each(&name, std::mem::take(items).into_iter(), &body, env)
+}
 
-    Ok(Ast::Function(Box::new(lambda)))
+/// Returns true if expr is the keyword `:name`.
+fn is_keyword(expr: &Ast, name: &str) -> bool {
+    matches!(expr, Ast::Atom(LispAtom::Keyword(keyword)) if keyword == name)
+}
+
+/// Evaluate body for every combination of items from the lists in bindings that passes the
+/// filters, adding the value of the last body expression to results.
+fn comprehend(
+    bindings: &[(String, Ast)],
+    filters: &[Ast],
+    body: &[Ast],
+    env: &mut Environment,
+    results: &mut Vec<Ast>,
+) -> Result<(), LispError> {
+    let Some(((name, list), bindings)) = bindings.split_first() else {
+        for filter in filters {
+            if !eval::eval_expr(filter.clone(), env)?.is_truthy() {
+                return Ok(());
+            }
+        }
+        results.push(eval_body(body.iter().cloned(), env)?);
+        return Ok(());
+    };
+
+    let mut list = eval::eval_expr(list.clone(), env)?;
+    let Ast::List(items) = &mut list else {
+        return Err(LispError::TypeError);
+    };
+    for item in std::mem::take(items) {
+        env.new_scope(HashMap::from([(name.clone(), item)]));
+        let result = comprehend(bindings, filters, body, env, results);
+        env.pop_scope();
+        result?;
+    }
+    Ok(())
+}
+
+fn bad_for_binding(index: usize, spec: &Ast) -> LispError {
+    LispError::SyntaxError(format!(
+        "for binding {} should be (name list): {}",
+        index, spec
+    ))
+}
+
+/// Build a list from the values of a body for every combination of items from some lists.
+///
+/// Example:
+/// `(for ((x (list 1 2 3)) (y (list 10 20))) :when (odd? x) (+ x y))` returns `(11 21 13 23)`.
+/// Each list can refer to the names bound before it, and every `:when` test has to be true for a
+/// combination to be included.
+pub fn lisp_for(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    let mut args = args.into_iter().peekable();
+    let Some(Ast::List(specs)) = &args.next() else {
+        return Err(LispError::SyntaxError(
+            "for needs a list of bindings, e.g. (for ((x list)) x)".to_string(),
+        ));
+    };
+
+    let mut bindings = Vec::with_capacity(specs.len());
+    for (index, spec) in specs.iter().enumerate() {
+        let Ast::List(pair) = spec else {
+            return Err(bad_for_binding(index, spec));
+        };
+        let [Ast::Atom(LispAtom::Symbol(name)), list] = pair.as_slice() else {
+            return Err(bad_for_binding(index, spec));
+        };
+        bindings.push((name.clone(), list.clone()));
+    }
+
+    let mut filters = vec![];
+    while args.next_if(|arg| is_keyword(arg, "when")).is_some() {
+        filters
+            .push(args.next().ok_or_else(|| {
+                LispError::SyntaxError(":when needs a test after it".to_string())
+            })?);
+    }
+    let body: Vec<Ast> = args.collect();
+    if body.is_empty() {
+        return Err(LispError::SyntaxError("for has no body".to_string()));
+    }
+
+    let mut results = vec![];
+    comprehend(&bindings, &filters, &body, env, &mut results)?;
+    Ok(Ast::List(results))
+}
+
+/// Check whether value matches pattern, adding the names the pattern binds to bindings.
+///
+/// `_` matches anything, any other symbol matches anything and binds the value to it,
+/// `(list pattern ...)` matches a list of the same length whose items match the patterns,
+/// `(quote x)` matches x itself, and any other atom matches values `equal?` to it.
+fn match_pattern(
+    pattern: &Ast,
+    value: &Ast,
+    bindings: &mut HashMap<String, Ast>,
+) -> Result<bool, LispError> {
+    let items = match pattern {
+        Ast::Atom(LispAtom::Symbol(name)) if name == "_" => return Ok(true),
+        Ast::Atom(LispAtom::Symbol(name)) => {
+            if bindings.insert(name.clone(), value.clone()).is_some() {
+                return Err(LispError::SyntaxError(format!(
+                    "duplicate pattern variable: {}",
+                    name
+                )));
+            }
+            return Ok(true);
+        }
+        Ast::Atom(_) => return Ok(pattern == value),
+        Ast::List(items) => items,
+        _ => &[][..],
+    };
+
+    match items.split_first() {
+        Some((Ast::Atom(LispAtom::Symbol(head)), [literal])) if head == "quote" => {
+            Ok(literal == value)
+        }
+        Some((Ast::Atom(LispAtom::Symbol(head)), patterns)) if head == "list" => {
+            let Ast::List(values) = value else {
+                return Ok(false);
+            };
+            if values.len() != patterns.len() {
+                return Ok(false);
+            }
+            for (pattern, value) in patterns.iter().zip(values) {
+                if !match_pattern(pattern, value, bindings)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        _ => Err(LispError::SyntaxError(format!(
+            "invalid pattern: {}",
+            pattern
+        ))),
+    }
+}
+
+/// Evaluate the body of the first clause whose pattern matches a value, with the names bound by
+/// the pattern in a new scope.
+///
+/// Example:
+/// `(match x (0 "zero") ((list a b) (+ a b)) (_ "other"))`. See [match_pattern] for the patterns.
+/// If no clause matches, it is an error.
+pub fn lisp_match(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    let mut args = args.into_iter();
+    let value = eval::eval_expr(args.next().ok_or(LispError::BadArity)?, env)?;
+
+    for (index, mut clause) in args.enumerate() {
+        let Ast::List(clause) = &mut clause else {
+            return Err(LispError::SyntaxError(format!(
+                "match clause {} is not a list: {}",
+                index, clause
+            )));
+        };
+        let mut clause = std::mem::take(clause).into_iter();
+        let (Some(pattern), true) = (clause.next(), clause.len() > 0) else {
+            return Err(LispError::SyntaxError(format!(
+                "match clause {} needs a pattern and a body",
+                index
+            )));
+        };
+
+        let mut bindings = HashMap::new();
+        if match_pattern(&pattern, &value, &mut bindings)? {
+            env.new_scope(bindings);
+            let result = eval_body(clause, env);
+            env.pop_scope();
+            return result;
+        }
+    }
+    Err(LispError::ValueError(format!(
+        "no match clause matches {}",
+        value
+    )))
 }
 
 /// Quote a lisp value.
@@ -108,3 +1187,976 @@ pub fn quote(args: Vec<Ast>, _env: &mut Environment) -> Result<Ast, LispError> {
 
     Ok(arg)
 }
+
+/// Create a promise that evaluates an expression the first time it is forced with `force`.
+///
+/// Example:
+/// `(def p (delay (+ 1 2)))` doesn't evaluate `(+ 1 2)` until `(force p)`, which returns 3. The
+/// expression can use the local bindings in scope where it was delayed.
+pub fn delay(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    if args.len() != 1 {
+        return Err(LispError::BadArity);
+    }
+    let expr = args.into_iter().next().ok_or(LispError::BadArity)?;
+    Ok(Ast::Promise(Promise::new(expr, env)))
+}
+
+/// If expr is a list starting with the symbol name and one other item, return that item.
+fn unwrap_form<'a>(expr: &'a Ast, name: &str) -> Option<&'a Ast> {
+    match expr {
+        Ast::List(list) => match list.as_slice() {
+            [Ast::Atom(LispAtom::Symbol(head)), arg] if head == name => Some(arg),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Split the items of a template list from an unquoted dotted tail. The reader reads
+/// `(a . (unquote b))` as `(a unquote b)`, so a trailing unquote after other items is the tail.
+fn split_unquoted_tail(items: &[Ast]) -> (&[Ast], Option<&Ast>) {
+    match items {
+        [init @ .., Ast::Atom(LispAtom::Symbol(unquote)), tail]
+            if !init.is_empty() && unquote == "unquote" =>
+        {
+            (init, Some(tail))
+        }
+        _ => (items, None),
+    }
+}
+
+/// Copy a quasiquoted template, replacing unquoted expressions with their values.
+fn fill_template(template: &Ast, env: &mut Environment) -> Result<Ast, LispError> {
+    if let Some(expr) = unwrap_form(template, "unquote") {
+        return eval::eval_expr(expr.clone(), env);
+    }
+    if unwrap_form(template, "quasiquote").is_some() {
+        return Err(LispError::SyntaxError(
+            "nested quasiquote is not supported".to_string(),
+        ));
+    }
+    if unwrap_form(template, "unquote-splicing").is_some() {
+        return Err(LispError::SyntaxError(
+            "unquote-splicing used outside of a list".to_string(),
+        ));
+    }
+    let Ast::List(items) = template else {
+        return Ok(template.clone());
+    };
+
+    let (items, tail) = split_unquoted_tail(items);
+
+    let mut filled = Vec::with_capacity(items.len());
+    for item in items {
+        match unwrap_form(item, "unquote-splicing") {
+            Some(expr) => match &mut eval::eval_expr(expr.clone(), env)? {
+                Ast::List(spliced) => filled.append(spliced),
+                _ => return Err(LispError::TypeError),
+            },
+            None => filled.push(fill_template(item, env)?),
+        }
+    }
+    match tail {
+        Some(tail) => {
+            let tail = eval::eval_expr(tail.clone(), env)?;
+            Ok(filled
+                .into_iter()
+                .rev()
+                .fold(tail, |tail, head| Ast::cons(head, tail)))
+        }
+        None => Ok(Ast::List(filled)),
+    }
+}
+
+/// Quote a value, except for the parts wrapped in `unquote` or `unquote-splicing`.
+///
+/// Example:
+/// `(quasiquote (a (unquote (+ 1 2)) (unquote-splicing (list 4 5)) b))` returns
+/// `(a 3 4 5 b)`. Quasiquotes inside the template are an error.
+pub fn quasiquote(args: Vec<Ast>, env: &mut Environment) -> Result<Ast, LispError> {
+    if args.len() != 1 {
+        return Err(LispError::BadArity);
+    }
+    fill_template(&args[0], env)
+}
+
+/// `unquote` and `unquote-splicing` only mean something inside a quasiquote.
+pub fn unquote(_args: Vec<Ast>, _env: &mut Environment) -> Result<Ast, LispError> {
+    Err(LispError::SyntaxError(
+        "unquote used outside of quasiquote".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::eval_str;
+
+    fn syntax_error(input: &str) -> String {
+        match eval_str(input) {
+            Err(LispError::SyntaxError(msg)) => msg,
+            other => panic!("expected syntax error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn lambda_duplicate_parameter() {
+        let msg = syntax_error("(lambda (x y x) x)");
+        assert!(msg.contains("duplicate parameter name: x"), "{}", msg);
+    }
+
+    #[test]
+    fn lambda_non_symbol_parameter() {
+        let msg = syntax_error("(lambda (x 1) x)");
+        assert!(msg.contains("parameter 1 is not a symbol: 1"), "{}", msg);
+    }
+
+    #[test]
+    fn lambda_parameters_not_a_list() {
+        let msg = syntax_error("(lambda x x)");
+        assert!(msg.contains("(lambda (x) ...)"), "{}", msg);
+    }
+
+    #[test]
+    fn lambda_valid() {
+        let result = eval_str("((lambda (x y) (+ x y)) 1 2)").unwrap();
+        assert_eq!(result, Ast::Atom(LispAtom::Int(3)));
+
+        let result = eval_str("((lambda () 5))").unwrap();
+        assert_eq!(result, Ast::Atom(LispAtom::Int(5)));
+    }
+
+    #[test]
+    fn let_binds_in_a_new_scope() {
+        let result = eval_str("(def x 10) (list (let ((x 1) (y (+ x 1))) (def z 3) (+ x y z)) x)");
+        assert_eq!(
+            result.unwrap(),
+            Ast::List(vec![
+                Ast::Atom(LispAtom::Int(15)),
+                Ast::Atom(LispAtom::Int(10))
+            ])
+        );
+        assert!(matches!(
+            eval_str("(let ((y 1)) y) y"),
+            Err(LispError::Undefined(name)) if name == "y"
+        ));
+        assert_eq!(eval_str("(let () 5)").unwrap(), Ast::Atom(LispAtom::Int(5)));
+    }
+
+    #[test]
+    fn let_pops_its_scope_on_error() {
+        let mut env = Environment::outer_new();
+        let (_, expr) = crate::parser::parse_expr("(let ((y 1)) (undefined-function y))").unwrap();
+        assert!(eval::eval_expr(expr, &mut env).is_err());
+        assert!(env.get("y").is_none());
+    }
+
+    #[test]
+    fn let_malformed_bindings() {
+        for input in [
+            "(let x x)",
+            "(let (x) x)",
+            "(let ((x)) x)",
+            "(let ((x 1 2)) x)",
+            "(let ((1 2)) 1)",
+        ] {
+            assert!(
+                matches!(eval_str(input), Err(LispError::TypeError)),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn let_star_binds_sequentially() {
+        let program = "((x 1) (y (+ x 1))) (def z y) (* y 10))";
+        assert!(matches!(
+            eval_str(&format!("(let {}", program)),
+            Err(LispError::Undefined(name)) if name == "x"
+        ));
+        assert_eq!(
+            eval_str(&format!("(let* {}", program)).unwrap(),
+            Ast::Atom(LispAtom::Int(20))
+        );
+        assert_eq!(
+            eval_str("(let* () 1 2)").unwrap(),
+            Ast::Atom(LispAtom::Int(2))
+        );
+
+        let mut env = Environment::outer_new();
+        let (_, expr) = crate::parser::parse_expr("(let* ((x 1) (y (missing x))) y)").unwrap();
+        assert!(eval::eval_expr(expr, &mut env).is_err());
+        assert!(env.get("x").is_none());
+    }
+
+    #[test]
+    fn letrec_mutual_recursion() {
+        let program = "(letrec ((my-even? (lambda (n) (if (= n 0) true (my-odd? (- n 1))))) \
+                                (my-odd? (lambda (n) (if (= n 0) false (my-even? (- n 1)))))) \
+                         (list (my-even? 10) (my-odd? 7) (my-even? 3)))";
+        assert_eq!(
+            eval_str(program).unwrap(),
+            Ast::List(vec![
+                Ast::Atom(LispAtom::Bool(true)),
+                Ast::Atom(LispAtom::Bool(true)),
+                Ast::Atom(LispAtom::Bool(false))
+            ])
+        );
+        assert!(matches!(
+            eval_str("(letrec ((f (lambda () 1))) (f)) f"),
+            Err(LispError::Undefined(_))
+        ));
+    }
+
+    #[test]
+    fn cond_picks_first_true_clause() {
+        let program = |x: i64| {
+            format!(
+                "(def x {}) (cond ((< x 0) \"negative\") ((= x 0) (def y 1) \"zero\") \
+                 ((> x 100) (undefined-function)) (else \"positive\"))",
+                x
+            )
+        };
+        let string = |s: &str| Ast::Atom(LispAtom::String(s.to_string()));
+        assert_eq!(eval_str(&program(-1)).unwrap(), string("negative"));
+        assert_eq!(eval_str(&program(0)).unwrap(), string("zero"));
+        assert_eq!(eval_str(&program(5)).unwrap(), string("positive"));
+        // Tests after a match aren't evaluated
+        assert_eq!(
+            eval_str("(cond (true 1) ((undefined-function) 2))").unwrap(),
+            Ast::Atom(LispAtom::Int(1))
+        );
+        assert!(matches!(eval_str("(cond (false 1))"), Ok(Ast::Unspecified)));
+        assert_eq!(eval_str("(cond (7))").unwrap(), Ast::Atom(LispAtom::Int(7)));
+    }
+
+    #[test]
+    fn cond_malformed_clauses() {
+        let msg = syntax_error("(cond (false 1) x)");
+        assert!(msg.contains("cond clause 1 is not a list"), "{}", msg);
+        let msg = syntax_error("(cond ())");
+        assert!(msg.contains("cond clause 0 is empty"), "{}", msg);
+    }
+
+    #[test]
+    fn and_or_short_circuit() {
+        let int = |n| Ast::Atom(LispAtom::Int(n));
+        let bool = |b| Ast::Atom(LispAtom::Bool(b));
+        assert_eq!(eval_str("(and)").unwrap(), bool(true));
+        assert_eq!(eval_str("(or)").unwrap(), bool(false));
+        assert_eq!(eval_str("(and 1 2 3)").unwrap(), int(3));
+        assert_eq!(eval_str("(or false 2 3)").unwrap(), int(2));
+        assert_eq!(eval_str("(or false false)").unwrap(), bool(false));
+        // The undefined name after the deciding operand is never looked up
+        assert_eq!(
+            eval_str("(and 1 false undefined-name)").unwrap(),
+            bool(false)
+        );
+        assert_eq!(eval_str("(or false 4 undefined-name)").unwrap(), int(4));
+        assert!(matches!(
+            eval_str("(and 1 undefined-name)"),
+            Err(LispError::Undefined(_))
+        ));
+    }
+
+    #[test]
+    fn set_updates_outer_binding() {
+        let result = eval_str(
+            "(def counter 0) \
+             (def increment (lambda (by) (set! counter (+ counter by)))) \
+             (increment 1) (increment 2) \
+             (let ((counter 10)) (set! counter 20)) \
+             counter",
+        );
+        assert_eq!(result.unwrap(), Ast::Atom(LispAtom::Int(3)));
+        assert!(matches!(
+            eval_str("(set! unbound-name 1)"),
+            Err(LispError::Undefined(name)) if name == "unbound-name"
+        ));
+    }
+
+    #[test]
+    fn define_function_sugar() {
+        let result = eval_str(
+            "(def (add x y) (+ x y)) (def (five) 5) (def six (+ (five) 1)) (add (five) six)",
+        );
+        assert_eq!(result.unwrap(), Ast::Atom(LispAtom::Int(11)));
+        assert_eq!(
+            eval_str("(def (f x) (* x 2)) (def g (lambda (x) (* x 2))) (equal? f g)").unwrap(),
+            Ast::Atom(LispAtom::Bool(true))
+        );
+
+        assert!(matches!(
+            eval_str("(def (1 x) x)"),
+            Err(LispError::TypeError)
+        ));
+        assert!(matches!(eval_str("(def () 1)"), Err(LispError::TypeError)));
+        let msg = syntax_error("(def (f x 2) x)");
+        assert!(msg.contains("parameter 1 is not a symbol: 2"), "{}", msg);
+    }
+
+    #[test]
+    fn loop_recur_runs_in_constant_stack() {
+        // Far more iterations than the stack could hold as recursion
+        let result =
+            eval_str("(loop ((i 0) (acc 0)) (if (> i 100000) acc (recur (+ i 1) (+ acc i))))");
+        assert_eq!(result.unwrap(), Ast::Atom(LispAtom::Int(5_000_050_000)));
+
+        // Factorial by repeated addition, with a nested loop doing the multiplication
+        let result = eval_str(
+            "(loop ((n 1) (fact 1)) \
+               (if (> n 10) fact \
+                 (recur (+ n 1) \
+                   (loop ((k 0) (sum 0)) (if (= k n) sum (recur (+ k 1) (+ sum fact)))))))",
+        );
+        assert_eq!(result.unwrap(), Ast::Atom(LispAtom::Int(3_628_800)));
+    }
+
+    #[test]
+    fn recur_errors() {
+        assert!(matches!(
+            eval_str("(loop ((i 0)) (recur 1 2))"),
+            Err(LispError::BadArity)
+        ));
+        let msg = syntax_error("(recur 1)");
+        assert!(msg.contains("recur used outside of loop"), "{}", msg);
+        let msg = syntax_error("(def f (loop () (lambda () (recur)))) (f)");
+        assert!(msg.contains("recur used outside of loop"), "{}", msg);
+    }
+
+    #[test]
+    fn recur_only_in_tail_position() {
+        for input in [
+            "(loop ((i 0)) (if (< i 3) (+ 100 (recur (+ i 1))) i))",
+            "(loop ((i 0)) (recur (recur i)))",
+            "(loop ((i 0)) (if (recur 1) 1 2))",
+            "(loop ((i 0)) (let ((x (recur 1))) x))",
+            "(loop ((i 0)) (recur 1) i)",
+            "(loop ((i 0)) (dotimes (j 2) (recur 1)))",
+            "(loop ((i 0)) (if (< i 3) (list (quasiquote (unquote (recur (+ i 1))))) i))",
+            "(loop ((i 0)) (if (< i 3) (quasiquote (a (unquote-splicing (recur 1)))) i))",
+            "(loop ((i 0)) (if (< i 3) (quasiquote (a . (unquote (recur 1)))) i))",
+        ] {
+            let msg = syntax_error(input);
+            assert!(msg.contains("recur must be in tail position"), "{}", msg);
+        }
+
+        // Tail position carries through if, cond, do, let, and match
+        let result = eval_str(
+            "(loop ((i 0)) \
+               (cond ((= i 0) (do 1 (recur 1))) \
+                     ((= i 1) (let ((j 2)) (recur j))) \
+                     ((= i 2) (match i (2 (recur 3)))) \
+                     (else i)))",
+        );
+        assert_eq!(result.unwrap(), Ast::Atom(LispAtom::Int(3)));
+    }
+
+    #[test]
+    fn recur_does_not_reach_the_callers_loop() {
+        let msg = syntax_error("(def (g) (recur 5)) (loop ((i 0)) (if (= i 5) i (g)))");
+        assert!(msg.contains("recur used outside of loop"), "{}", msg);
+        let msg = syntax_error("(loop ((i 0)) (if (= i 5) i (eval (quote (recur 5)) :local)))");
+        assert!(msg.contains("recur used outside of loop"), "{}", msg);
+        let msg = syntax_error("(loop ((i 0)) (if (= i 5) i ((lambda () (recur 5)))))");
+        assert!(msg.contains("recur used outside of loop"), "{}", msg);
+
+        // A loop inside a function called from a loop still works
+        let result = eval_str(
+            "(def (count-to n) (loop ((i 0)) (if (= i n) i (recur (+ i 1))))) \
+             (loop ((n 0) (acc 0)) (if (= n 3) acc (recur (+ n 1) (+ acc (count-to n)))))",
+        );
+        assert_eq!(result.unwrap(), Ast::Atom(LispAtom::Int(3)));
+    }
+
+    #[test]
+    fn dotimes_and_for_each() {
+        let result = eval_str(
+            "(def acc (list)) \
+             (def (push x) (set! acc (list x acc))) \
+             (dotimes (i 3) (push i)) \
+             (for-each (x (list \"a\" \"b\")) (push x) (push x)) \
+             (dotimes (i 0) (push 100)) \
+             acc",
+        )
+        .unwrap();
+        let expected = crate::parser::parse_expr("(\"b\" (\"b\" (\"a\" (\"a\" (2 (1 (0 ())))))))")
+            .unwrap()
+            .1;
+        assert_eq!(result, expected);
+
+        for input in ["(dotimes (i 2) 1) i", "(for-each (x (list 1)) 1) x"] {
+            assert!(
+                matches!(eval_str(input), Err(LispError::Undefined(_))),
+                "{}",
+                input
+            );
+        }
+
+        let mut env = Environment::outer_new();
+        let (_, expr) = crate::parser::parse_expr("(dotimes (i 5) (missing i))").unwrap();
+        assert!(eval::eval_expr(expr, &mut env).is_err());
+        assert!(env.get("i").is_none());
+
+        assert!(matches!(
+            eval_str("(dotimes (i 1.5) 1)"),
+            Err(LispError::TypeError)
+        ));
+        assert!(matches!(
+            eval_str("(for-each (x 1) 1)"),
+            Err(LispError::TypeError)
+        ));
+    }
+
+    #[test]
+    fn match_patterns() {
+        let describe = "(def (describe x) \
+             (match x \
+               (1 \"one\") \
+               (\"hi\" \"greeting\") \
+               (true \"yes\") \
+               ((quote sym) \"the symbol\") \
+               ((list) \"empty\") \
+               ((list a) (list \"one item\" a)) \
+               ((list 0 b) (list \"starts with zero\" b)) \
+               ((list (list a _) (list c d)) (list a c d)) \
+               ((list a b) (list \"pair\" a b)) \
+               (_ \"other\"))) ";
+        let cases = [
+            ("1", "\"one\""),
+            ("1.0", "\"other\""),
+            ("\"hi\"", "\"greeting\""),
+            ("true", "\"yes\""),
+            ("(quote sym)", "\"the symbol\""),
+            ("(quote other)", "\"other\""),
+            ("(list)", "\"empty\""),
+            ("(list 5)", "(\"one item\" 5)"),
+            ("(list 0 7)", "(\"starts with zero\" 7)"),
+            ("(list 2 3)", "(\"pair\" 2 3)"),
+            ("(list (list 1 2) (list 3 4))", "(1 3 4)"),
+            ("(list (list 1 2 3) (list 3 4))", "(\"pair\" (1 2 3) (3 4))"),
+            ("(list 1 2 3)", "\"other\""),
+            ("\"bye\"", "\"other\""),
+        ];
+        for (input, expected) in cases {
+            let program = format!("{}(describe {})", describe, input);
+            let expected = crate::parser::parse_expr(expected).unwrap().1;
+            assert_eq!(eval_str(&program).unwrap(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn match_scoping_and_errors() {
+        // Pattern variables shadow outer names only inside the clause body
+        assert_eq!(
+            eval_str("(def a 10) (list (match (list 1 2) ((list a b) (+ a b))) a)").unwrap(),
+            crate::parser::parse_expr("(3 10)").unwrap().1
+        );
+        assert!(matches!(
+            eval_str("(match 1 (x (missing))) x"),
+            Err(LispError::Undefined(name)) if name == "missing"
+        ));
+        assert!(matches!(
+            eval_str("(match 1 (x 1)) x"),
+            Err(LispError::Undefined(name)) if name == "x"
+        ));
+        // Only the matching clause's body is evaluated
+        assert_eq!(
+            eval_str("(match 2 (1 (missing)) (2 \"two\") (_ (missing)))").unwrap(),
+            Ast::Atom(LispAtom::String("two".to_string()))
+        );
+
+        match eval_str("(match (list 1 2) (1 \"one\"))") {
+            Err(LispError::ValueError(msg)) => {
+                assert_eq!(msg, "no match clause matches (1 2)")
+            }
+            other => panic!("expected value error, got {:?}", other),
+        }
+        let msg = syntax_error("(match (list 1 1) ((list a a) a))");
+        assert!(msg.contains("duplicate pattern variable: a"), "{}", msg);
+        let msg = syntax_error("(match 1 ((vector a) a))");
+        assert!(msg.contains("invalid pattern: (vector a)"), "{}", msg);
+        let msg = syntax_error("(match 1 (_))");
+        assert!(
+            msg.contains("match clause 0 needs a pattern and a body"),
+            "{}",
+            msg
+        );
+        let msg = syntax_error("(match 1 2)");
+        assert!(msg.contains("match clause 0 is not a list: 2"), "{}", msg);
+    }
+
+    #[test]
+    fn quasiquote_templates() {
+        let cases = [
+            ("(quasiquote (a b))", "(a b)"),
+            ("(quasiquote x)", "x"),
+            ("(quasiquote (unquote (+ 1 2)))", "3"),
+            (
+                "(def x 5) (quasiquote (a (unquote x) (b (unquote (+ x 1)))))",
+                "(a 5 (b 6))",
+            ),
+            (
+                "(def xs (list 2 3)) (quasiquote (1 (unquote-splicing xs) 4))",
+                "(1 2 3 4)",
+            ),
+            (
+                "(quasiquote ((unquote-splicing (list)) a (unquote-splicing (list 1)) b))",
+                "(a 1 b)",
+            ),
+            (
+                "(quasiquote (a (nested (unquote-splicing (list 1 2)) end)))",
+                "(a (nested 1 2 end))",
+            ),
+            // Quoted parts are left alone, even ones that look like calls
+            ("(quasiquote (+ 1 (missing)))", "(+ 1 (missing))"),
+            // An unquoted dotted tail is the rest of the list
+            ("(quasiquote (1 . (unquote (+ 1 1))))", "(1 . 2)"),
+            (
+                "(quasiquote (1 (unquote (+ 1 1)) . (unquote (list 3 4))))",
+                "(1 2 3 4)",
+            ),
+        ];
+        for (input, expected) in cases {
+            let expected = crate::parser::parse_expr(expected).unwrap().1;
+            assert_eq!(eval_str(input).unwrap(), expected, "{}", input);
+        }
+
+        assert!(matches!(
+            eval_str("(quasiquote (a (unquote-splicing 1)))"),
+            Err(LispError::TypeError)
+        ));
+        let msg = syntax_error("(quasiquote (a (quasiquote (b (unquote c)))))");
+        assert!(msg.contains("nested quasiquote"), "{}", msg);
+        let msg = syntax_error("(quasiquote (unquote-splicing (list 1)))");
+        assert!(
+            msg.contains("unquote-splicing used outside of a list"),
+            "{}",
+            msg
+        );
+        let msg = syntax_error("(unquote x)");
+        assert!(
+            msg.contains("unquote used outside of quasiquote"),
+            "{}",
+            msg
+        );
+        let msg = syntax_error("(unquote-splicing x)");
+        assert!(
+            msg.contains("unquote used outside of quasiquote"),
+            "{}",
+            msg
+        );
+    }
+
+    #[test]
+    fn expansion() {
+        let parse = |input: &str| crate::parser::parse_expr(input).unwrap().1;
+
+        let def = parse("(def (add x y) (+ x y))");
+        let expanded = parse("(def add (lambda (x y) (+ x y)))");
+        assert_eq!(expand_once(&def).unwrap(), Some(expanded.clone()));
+        assert_eq!(expand(def).unwrap(), expanded);
+
+        // Forms that aren't rewritten are returned unchanged
+        for input in ["(def x 1)", "(+ 1 2)", "x", "()", "((def (f) 1))"] {
+            assert_eq!(expand_once(&parse(input)).unwrap(), None, "{}", input);
+            assert_eq!(expand(parse(input)).unwrap(), parse(input), "{}", input);
+        }
+
+        // expand only rewrites the outer form, and expand_all skips quoted code
+        let nested = parse("(list (def (f) 1) (quote (def (g) 2)))");
+        assert_eq!(expand(nested.clone()).unwrap(), nested);
+        assert_eq!(
+            expand_all(nested).unwrap(),
+            parse("(list (def f (lambda () 1)) (quote (def (g) 2)))")
+        );
+        assert_eq!(
+            expand_all(parse("(def (f) (def (g) 2))")).unwrap(),
+            parse("(def f (lambda () (def g (lambda () 2))))")
+        );
+
+        assert!(matches!(
+            expand_once(&parse("(def (1 x) x)")),
+            Err(LispError::TypeError)
+        ));
+    }
+
+    #[test]
+    fn optional_rest_and_keyword_params() {
+        let program = "(def (show v) (if (equal? (type v) Unspecified) (quote none) v)) \
+                       (def (window title &optional (width 80) height &key (x 0) y) \
+                         (list title width (show height) x (show y))) ";
+        let cases = [
+            ("(window 1)", "(1 80 none 0 none)"),
+            ("(window 1 2 3)", "(1 2 3 0 none)"),
+            ("(window 1 2 :y 5)", "(1 2 none 0 5)"),
+            ("(window 1 :y 5 :x 6)", "(1 80 none 6 5)"),
+            ("(window 1 2 3 :x 4)", "(1 2 3 4 none)"),
+        ];
+        for (call, expected) in cases {
+            let result = eval_str(&format!("{}{}", program, call)).unwrap();
+            let expected = crate::parser::parse_expr(expected).unwrap().1;
+            assert_eq!(result, expected, "{}", call);
+        }
+
+        assert_eq!(
+            eval_str("(def (f a &rest more) (list a more)) (list (f 1) (f 1 2 3))").unwrap(),
+            crate::parser::parse_expr("((1 ()) (1 (2 3)))").unwrap().1
+        );
+        // Defaults are evaluated at each call and can refer to earlier parameters
+        assert_eq!(
+            eval_str("(def (f a &key (b (+ a 1))) (list a b)) (list (f 1) (f 5 :b 0))").unwrap(),
+            crate::parser::parse_expr("((1 2) (5 0))").unwrap().1
+        );
+
+        let value_error = |call: &str| match eval_str(&format!("{}{}", program, call)) {
+            Err(LispError::ValueError(msg)) => msg,
+            other => panic!("expected value error for {}, got {:?}", call, other),
+        };
+        assert_eq!(
+            value_error("(window 1 :z 1)"),
+            "unknown keyword argument :z"
+        );
+        assert_eq!(
+            value_error("(window 1 :x)"),
+            "keyword argument :x has no value"
+        );
+        assert_eq!(
+            value_error("(window 1 :x 1 :x 2)"),
+            "keyword argument :x given more than once"
+        );
+        assert_eq!(
+            value_error("(window 1 2 3 4)"),
+            "expected a keyword argument but got 4"
+        );
+        for call in ["(window)", "((lambda (a &optional b) a) 1 2 3)"] {
+            assert!(
+                matches!(
+                    eval_str(&format!("{}{}", program, call)),
+                    Err(LispError::BadArity)
+                ),
+                "{}",
+                call
+            );
+        }
+    }
+
+    #[test]
+    fn bad_parameter_lists() {
+        let cases = [
+            (
+                "(lambda (a &optional b &optional c) a)",
+                "unexpected &optional",
+            ),
+            ("(lambda (a &key b &optional c) a)", "unexpected &optional"),
+            (
+                "(lambda (&rest) 1)",
+                "&rest must be followed by exactly one parameter",
+            ),
+            (
+                "(lambda (&rest a b) 1)",
+                "&rest must be followed by exactly one parameter",
+            ),
+            (
+                "(lambda (&rest &key a) 1)",
+                "&rest must be followed by exactly one parameter",
+            ),
+            (
+                "(lambda (&rest a &key b) 1)",
+                "&rest and &key can't be used together",
+            ),
+            ("(lambda ((a 1)) a)", "parameter 0 is not a symbol: (a 1)"),
+            (
+                "(lambda (&optional (a)) a)",
+                "parameter 1 should be a symbol or (name default)",
+            ),
+            ("(lambda (a &key (a 1)) a)", "duplicate parameter name: a"),
+        ];
+        for (input, expected) in cases {
+            let msg = syntax_error(input);
+            assert!(msg.contains(expected), "{}: {}", input, msg);
+        }
+    }
+
+    #[test]
+    fn lambda_with_several_body_expressions() {
+        let (result, stdout, _) = crate::eval::eval_captured(
+            "(def (greet name) (putstr \"hello\") (putstr name) (count name)) (greet \"bob\")",
+        );
+        assert_eq!(result.unwrap(), Ast::Atom(LispAtom::Int(3)));
+        assert_eq!(stdout, "hello\nbob\n");
+
+        // Definitions earlier in the body are visible later, but not outside the call
+        assert_eq!(
+            eval_str("((lambda (x) (def y (* x 2)) (def z (+ y 1)) (list y z)) 5)").unwrap(),
+            crate::parser::parse_expr("(10 11)").unwrap().1
+        );
+        assert!(matches!(
+            eval_str("((lambda () (def y 1) y)) y"),
+            Err(LispError::Undefined(name)) if name == "y"
+        ));
+
+        let msg = syntax_error("(lambda (x y))");
+        assert!(msg.contains("lambda (x y) has no body"), "{}", msg);
+        let msg = syntax_error("(def (f x))");
+        assert!(msg.contains("lambda (x) has no body"), "{}", msg);
+    }
+
+    #[test]
+    fn named_let() {
+        assert_eq!(
+            eval_str("(let iter ((i 0) (acc 1)) (if (= i 5) acc (iter (+ i 1) (* acc 2))))")
+                .unwrap(),
+            Ast::Atom(LispAtom::Int(32))
+        );
+        // The initial values are evaluated outside, so they can't see the bindings or the name
+        assert_eq!(
+            eval_str(
+                "(def i 10) (let next ((i (+ i 1)) (n 0)) (if (= n 2) i (next (* i 2) (+ n 1))))"
+            )
+            .unwrap(),
+            Ast::Atom(LispAtom::Int(44))
+        );
+
+        for input in [
+            "(let iter ((i 0)) i) iter",
+            "(let iter ((i 0)) i) i",
+            "(let iter ((i 0)) (missing)) iter",
+        ] {
+            assert!(
+                matches!(eval_str(input), Err(LispError::Undefined(_))),
+                "{}",
+                input
+            );
+        }
+        let mut env = Environment::outer_new();
+        let (_, expr) = crate::parser::parse_expr("(let iter ((i 0)) (missing))").unwrap();
+        assert!(eval::eval_expr(expr, &mut env).is_err());
+        assert!(env.get("iter").is_none());
+
+        // The name shadows a special form in the body, but nowhere else
+        assert_eq!(
+            eval_str("(let loop ((i 0) (acc 1)) (if (= i 5) acc (loop (+ i 1) (* acc 2))))")
+                .unwrap(),
+            Ast::Atom(LispAtom::Int(32))
+        );
+        assert_eq!(
+            eval_str(
+                "(def (count-to n) (loop ((i 0)) (if (= i n) i (recur (+ i 1))))) \
+                 (let loop ((i 0) (acc (quote (loop)))) \
+                   (if (= i 2) (list acc (quasiquote (loop (unquote (count-to 3))))) \
+                       (loop (+ i 1) acc)))"
+            )
+            .unwrap(),
+            crate::parser::parse_expr("((loop) (loop 3))").unwrap().1
+        );
+        assert_eq!(
+            eval_str("(let loop ((i 0)) (if (= i 1) i (quasiquote (a . (unquote (loop 1))))))")
+                .unwrap(),
+            crate::parser::parse_expr("(a . 1)").unwrap().1
+        );
+        assert!(matches!(
+            eval_str("(let iter ((i 0)))"),
+            Err(LispError::BadArity)
+        ));
+        assert!(matches!(
+            eval_str("(let iter ((i 0)) (iter))"),
+            Err(LispError::BadArity)
+        ));
+    }
+
+    #[test]
+    fn threading() {
+        let pairs = [
+            ("(-> 5 (- 1) (list 2) count)", "(count (list (- 5 1) 2))"),
+            ("(->> 5 (- 1) (list 2))", "(list 2 (- 1 5))"),
+            ("(-> 3)", "3"),
+            (
+                "(->> (list 1 2 3) count (* 2))",
+                "(* 2 (count (list 1 2 3)))",
+            ),
+            (
+                "(-> (list (list 1 2) 3) (nth 0) (nth 1) (- 10))",
+                "(- (nth (nth (list (list 1 2) 3) 0) 1) 10)",
+            ),
+        ];
+        for (threaded, nested) in pairs {
+            assert_eq!(
+                eval_str(threaded).unwrap(),
+                eval_str(nested).unwrap(),
+                "{}",
+                threaded
+            );
+            let expanded = expand(crate::parser::parse_expr(threaded).unwrap().1).unwrap();
+            assert_eq!(
+                expanded,
+                crate::parser::parse_expr(nested).unwrap().1,
+                "{}",
+                threaded
+            );
+        }
+
+        assert!(matches!(eval_str("(->)"), Err(LispError::BadArity)));
+        let msg = syntax_error("(-> 1 ())");
+        assert!(msg.contains("can't thread a value through ()"), "{}", msg);
+    }
+
+    #[test]
+    fn for_comprehensions() {
+        let parse = |input: &str| crate::parser::parse_expr(input).unwrap().1;
+        let cases = [
+            (
+                "(for ((x (list 1 2)) (y (list 10 20 30))) (list x y))",
+                "((1 10) (1 20) (1 30) (2 10) (2 20) (2 30))",
+            ),
+            (
+                "(for ((x (list 1 2 3 4)) (y (list 10 20))) :when (even? x) (+ x y))",
+                "(12 22 14 24)",
+            ),
+            (
+                "(for ((x (list 1 2 3 4)) (y (list 1 2 3))) :when (< y x) :when (odd? x) (list x y))",
+                "((3 1) (3 2))",
+            ),
+            // Later lists can use earlier names
+            (
+                "(for ((xs (list (list 1 2) (list 3))) (x xs)) (* x 10))",
+                "(10 20 30)",
+            ),
+            ("(for ((x (list)) (y (list 1 2))) (list x y))", "()"),
+            ("(for ((x (list 1 2)) (y (list))) (list x y))", "()"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(eval_str(input).unwrap(), parse(expected), "{}", input);
+        }
+
+        // An error partway through is returned, and the names don't leak
+        let mut env = Environment::outer_new();
+        let expr = parse("(for ((x (list 1 2 0 4))) (/ 12 x))");
+        assert!(matches!(
+            eval::eval_expr(expr, &mut env),
+            Err(LispError::DivideByZero)
+        ));
+        assert!(env.get("x").is_none());
+        assert!(matches!(
+            eval_str("(for ((x 5)) x)"),
+            Err(LispError::TypeError)
+        ));
+
+        let cases = [
+            ("(for x x)", "for needs a list of bindings"),
+            ("(for ((x)) x)", "for binding 0 should be (name list): (x)"),
+            (
+                "(for ((x (list 1)) y) x)",
+                "for binding 1 should be (name list): y",
+            ),
+            ("(for ((x (list 1))) :when)", ":when needs a test after it"),
+            ("(for ((x (list 1))) :when true)", "for has no body"),
+        ];
+        for (input, expected) in cases {
+            let msg = syntax_error(input);
+            assert!(msg.contains(expected), "{}: {}", input, msg);
+        }
+    }
+
+    #[test]
+    fn define_values_and_let_values() {
+        let parse = |input: &str| crate::parser::parse_expr(input).unwrap().1;
+        assert_eq!(
+            eval_str("(define-values (q r) (list (quot 17 5) (rem 17 5))) (list q r)").unwrap(),
+            parse("(3 2)")
+        );
+        assert_eq!(
+            eval_str("(define-values () (list)) 1").unwrap(),
+            Ast::Atom(LispAtom::Int(1))
+        );
+        // Every expression is evaluated before any names are bound, as in let
+        assert_eq!(
+            eval_str(
+                "(def a 10) \
+                 (list (let-values (((a b) (list 1 2)) ((c) (list a))) (list a b c)) a)"
+            )
+            .unwrap(),
+            parse("((1 2 10) 10)")
+        );
+        assert!(matches!(
+            eval_str("(let-values (((x) (list 1))) x) x"),
+            Err(LispError::Undefined(name)) if name == "x"
+        ));
+
+        let type_error = |input: &str| match eval_str(input) {
+            Err(LispError::TypeMismatch(msg)) => msg,
+            other => panic!("expected type error for {}, got {:?}", input, other),
+        };
+        assert_eq!(
+            type_error("(define-values (q r) (list 1 2 3))"),
+            "expected 2 values but got 3"
+        );
+        assert_eq!(
+            type_error("(let-values (((a b c) (list 1))) a)"),
+            "expected 3 values but got 1"
+        );
+        assert_eq!(
+            type_error("(define-values (q r) 5)"),
+            "expected a list of 2 values but got 5"
+        );
+        assert!(matches!(
+            eval_str("(let-values ((a (list 1))) a)"),
+            Err(LispError::SyntaxError(_))
+        ));
+        let msg = syntax_error("(define-values (q q) (list 1 2))");
+        assert!(msg.contains("duplicate name: q"), "{}", msg);
+        let msg = syntax_error("(define-values (q 1) (list 1 2))");
+        assert!(msg.contains("1 is not a name"), "{}", msg);
+    }
+
+    #[test]
+    fn letfn_local_functions() {
+        let int = |n| Ast::Atom(LispAtom::Int(n));
+        assert_eq!(
+            eval_str("(letfn ((square (x) (* x x)) (quad (x) (square (square x)))) (quad 3))")
+                .unwrap(),
+            int(81)
+        );
+        assert_eq!(
+            eval_str(
+                "(letfn ((ev? (n) (if (= n 0) true (od? (- n 1)))) \
+                         (od? (n) (if (= n 0) false (ev? (- n 1))))) \
+                   (list (ev? 10) (od? 7) (ev? 3)))"
+            )
+            .unwrap(),
+            crate::parser::parse_expr("(true true false)").unwrap().1
+        );
+        assert_eq!(
+            eval_str("(letfn ((down (n &optional (acc 0)) (if (= n 0) acc (down (- n 1) (+ acc n))))) (down 4))")
+                .unwrap(),
+            int(10)
+        );
+
+        for input in [
+            "(letfn ((f (x) x)) (f 1)) f",
+            "(letfn ((f (x) (missing))) (f 1)) f",
+        ] {
+            assert!(
+                matches!(eval_str(input), Err(LispError::Undefined(_))),
+                "{}",
+                input
+            );
+        }
+        let mut env = Environment::outer_new();
+        let (_, expr) = crate::parser::parse_expr("(letfn ((f () (missing))) (f))").unwrap();
+        assert!(eval::eval_expr(expr, &mut env).is_err());
+        assert!(env.get("f").is_none());
+
+        let msg = syntax_error("(letfn (f (x) x) 1)");
+        assert!(
+            msg.contains("letfn function 0 should be (name (params ...) body ...): f"),
+            "{}",
+            msg
+        );
+        let msg = syntax_error("(letfn ((f (x x) x)) 1)");
+        assert!(msg.contains("duplicate parameter name: x"), "{}", msg);
+        let msg = syntax_error("(letfn ((f (x))) 1)");
+        assert!(msg.contains("lambda (x) has no body"), "{}", msg);
+    }
+}
@@ -0,0 +1,177 @@
+//! Renders text templates with embedded callisp expressions.
+//!
+//! `<%= expr %>` is replaced by the value of expr, and `<% expr... %>` evaluates its expressions
+//! for their side effects (such as `def`) and is replaced by nothing. Strings are substituted
+//! without quotes and characters without `#\`, and every other value is substituted in its
+//! display form.
+
+use crate::ast::{Ast, LispAtom};
+use crate::env::Environment;
+use crate::error::LispError;
+use crate::eval::eval_expr;
+use crate::parser;
+use crate::printer::display_string;
+
+/// Render template, evaluating its tags in env. Errors report the byte offset of the tag they
+/// happened in, and on error nothing is returned. Like a function body, a tag can't `recur` to a
+/// loop that the template is rendered from.
+pub(crate) fn render(template: &str, env: &mut Environment) -> Result<String, LispError> {
+    let mut output = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("<%") {
+        output.push_str(&rest[..start]);
+        let offset = template.len() - rest.len() + start;
+
+        let after = &rest[start + 2..];
+        let (substitute, body) = match after.strip_prefix('=') {
+            Some(body) => (true, body),
+            None => (false, after),
+        };
+        let end = find_close(body).ok_or_else(|| {
+            LispError::ParseError(format!("unterminated tag at template offset {}", offset))
+        })?;
+        let exprs = parser::parse_program(&body[..end], env.fold_case()).map_err(|_| {
+            LispError::ParseError(format!(
+                "could not parse tag at template offset {}: {}",
+                offset,
+                &body[..end]
+            ))
+        })?;
+        rest = &body[end + 2..];
+
+        if substitute {
+            let [expr] = <[Ast; 1]>::try_from(exprs).map_err(|_| {
+                LispError::SyntaxError(format!(
+                    "<%= tag at template offset {} must contain exactly one expression",
+                    offset
+                ))
+            })?;
+            match env
+                .without_loops(|env| eval_expr(expr, env))
+                .map_err(|e| in_template(offset, e))?
+            {
+                Ast::Atom(LispAtom::String(ref string)) => output.push_str(string),
+                Ast::Atom(LispAtom::Char(c)) => output.push(c),
+                value => output.push_str(&display_string(&value, env.printer())),
+            }
+        } else {
+            env.without_loops(|env| {
+                exprs
+                    .into_iter()
+                    .try_for_each(|expr| eval_expr(expr, env).map(drop))
+            })
+            .map_err(|e| in_template(offset, e))?;
+        }
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Find the `%>` that closes a tag, skipping over any inside string literals. Escaped characters
+/// in strings and character literals like `#\"` are skipped too, so they can't end a string.
+fn find_close(body: &str) -> Option<usize> {
+    let mut in_string = false;
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => in_string = !in_string,
+            '\\' if in_string => {
+                chars.next();
+            }
+            '#' if !in_string && body[i..].starts_with("#\\") => {
+                chars.next();
+                chars.next();
+            }
+            '%' if !in_string && body[i..].starts_with("%>") => return Some(i),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Attach the template offset to an error from evaluating a tag. Interrupts and exits aren't
+/// really errors in the template, so they are passed through unchanged.
+fn in_template(offset: usize, error: LispError) -> LispError {
+    match error {
+        LispError::Interrupted | LispError::Exit(_) => error,
+        error => LispError::InTemplate(offset, Box::new(error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_str(template: &str) -> Result<String, LispError> {
+        render(template, &mut Environment::outer_new())
+    }
+
+    #[test]
+    fn substitutes_values() {
+        let mut env = Environment::outer_new();
+        env.bind_value("name", "World");
+        env.bind_value("qty", 3);
+        env.bind_value("price", 5);
+        let output = render(
+            "Hello <%= name %>, total: <%= (* qty price) %>, <%= (list 1 2.5) %>",
+            &mut env,
+        );
+        assert_eq!(output.unwrap(), "Hello World, total: 15, (1 2.5)");
+    }
+
+    #[test]
+    fn statements_are_discarded() {
+        let output = render_str("<% (def x 2) (def y 3) %>x*y=<%=(* x y)%>");
+        assert_eq!(output.unwrap(), "x*y=6");
+    }
+
+    #[test]
+    fn nested_quotes() {
+        let output = render_str(r#"[<%= (name "%> and <%=") %>]"#);
+        assert_eq!(output.unwrap(), "[%> and <%=]");
+
+        // Escaped quotes and character literals don't start or end strings
+        let output = render_str(r#"<%= "a\"%>b" %>|<%= #\" %>|<%= (list #\" "%>") %>"#);
+        assert_eq!(output.unwrap(), r#"a"%>b|"|(#\" "%>")"#);
+    }
+
+    #[test]
+    fn adjacent_tags() {
+        assert_eq!(render_str("<%= 1 %><%= 2 %><%%><%= 3 %>").unwrap(), "123");
+        assert_eq!(render_str("no tags").unwrap(), "no tags");
+    }
+
+    #[test]
+    fn unterminated_tag() {
+        let Err(LispError::ParseError(msg)) = render_str("abc <%= (+ 1 2)") else {
+            panic!("expected parse error");
+        };
+        assert!(msg.contains("offset 4"), "{}", msg);
+    }
+
+    #[test]
+    fn error_mid_template() {
+        let result = render_str(r#"a <%= 1 %> b <%= (+ 1 "x") %> c"#);
+        assert!(matches!(
+            result,
+            Err(LispError::InTemplate(13, error)) if matches!(*error, LispError::TypeError)
+        ));
+    }
+
+    #[test]
+    fn recur_does_not_leave_the_template() {
+        let result = crate::eval::eval_str(
+            r#"(loop ((i 0)) (if (< i 3) (+ 1 (render-template "<%= (recur 3) %>")) i))"#,
+        );
+        let Err(LispError::InTemplate(0, error)) = result else {
+            panic!("expected an error in the template, got {:?}", result);
+        };
+        assert!(
+            matches!(&*error, LispError::SyntaxError(msg) if msg.contains("recur used outside of loop")),
+            "{:?}",
+            error
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Contains the clocks and the date formatting used by the time builtins.
+//!
+//! Dates are always in UTC. Formatting is hand-rolled to avoid depending on a date crate.
+
+use crate::error::LispError;
+
+const MS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+/// Milliseconds since the Unix epoch. Times before the epoch are negative.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn now_ms() -> i64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_millis() as i64,
+        Err(e) => -(e.duration().as_millis() as i64),
+    }
+}
+
+/// Milliseconds since the Unix epoch, from the browser's clock.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn now_ms() -> i64 {
+    js_sys::Date::now() as i64
+}
+
+/// Milliseconds since the first time this was called, from a clock that never goes backwards.
+/// Only differences between two readings are meaningful.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn monotonic_ms() -> f64 {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_secs_f64() * 1000.0
+}
+
+/// Convert a number of days since 1970-01-01 into a (year, month, day) date. This is Howard
+/// Hinnant's `civil_from_days`, which works for any date in the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Format a time given in milliseconds since the Unix epoch. `%Y`, `%m`, `%d`, `%H`, `%M`, and
+/// `%S` are replaced with the (zero-padded) year, month, day, hour, minute, and second in UTC, and
+/// `%%` with a percent sign. Any other directive is an error.
+pub(crate) fn format_time(ms: i64, format: &str) -> Result<String, LispError> {
+    let (year, month, day) = civil_from_days(ms.div_euclid(MS_PER_DAY));
+    let seconds = ms.rem_euclid(MS_PER_DAY) / 1000;
+    let (hour, minute, second) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+
+    let mut output = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => output += &format!("{:04}", year),
+            Some('m') => output += &format!("{:02}", month),
+            Some('d') => output += &format!("{:02}", day),
+            Some('H') => output += &format!("{:02}", hour),
+            Some('M') => output += &format!("{:02}", minute),
+            Some('S') => output += &format!("{:02}", second),
+            Some('%') => output.push('%'),
+            Some(other) => {
+                return Err(LispError::ValueError(format!(
+                    "unknown time format directive %{}",
+                    other
+                )))
+            }
+            None => {
+                return Err(LispError::ValueError(
+                    "time format ends with a lone %".to_string(),
+                ))
+            }
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_known_times() {
+        let format = "%Y-%m-%d %H:%M:%S";
+        assert_eq!(format_time(0, format).unwrap(), "1970-01-01 00:00:00");
+        assert_eq!(
+            format_time(1_700_000_000_123, format).unwrap(),
+            "2023-11-14 22:13:20"
+        );
+        // Leap day, and a time before the epoch
+        assert_eq!(
+            format_time(951_782_400_000, "%d/%m/%Y").unwrap(),
+            "29/02/2000"
+        );
+        assert_eq!(format_time(-1, format).unwrap(), "1969-12-31 23:59:59");
+        assert_eq!(format_time(0, "100%%").unwrap(), "100%");
+
+        assert!(format_time(0, "%q").is_err());
+        assert!(format_time(0, "%").is_err());
+    }
+}
@@ -0,0 +1,221 @@
+//! Builds a cross-reference of the top-level definitions in a script and every script it uses,
+//! without evaluating anything.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Display;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use crate::analysis::free_vars;
+use crate::ast::{Ast, LispAtom};
+use crate::builtins;
+use crate::error::LispError;
+use crate::parser::{self, Position};
+
+/// A place in a script: the start of a top-level form. Written as `file:line:column`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Site {
+    /// The file, as it was named on the command line or in a `use` form.
+    pub file: PathBuf,
+
+    /// The line the form starts on, counted from 1.
+    pub line: usize,
+
+    /// The column the form starts at, counted in characters from 1.
+    pub column: usize,
+}
+
+impl Display for Site {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.file.display(), self.line, self.column)
+    }
+}
+
+/// Where a single name is defined and referenced.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Entry {
+    /// Top-level `def` forms that define the name.
+    pub definitions: Vec<Site>,
+
+    /// Top-level forms that reference the name.
+    pub references: Vec<Site>,
+}
+
+/// A cross-reference table for a whole program. Builtins are left out, so every name in it is
+/// either defined or referenced by the program itself.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Xref {
+    /// Every name that is defined or referenced, in sorted order.
+    pub names: BTreeMap<String, Entry>,
+}
+
+impl Xref {
+    /// Names that are referenced but never defined.
+    pub fn undefined(&self) -> impl Iterator<Item = &str> {
+        self.names
+            .iter()
+            .filter(|(_, entry)| entry.definitions.is_empty())
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Names that are defined but never referenced.
+    pub fn unused(&self) -> impl Iterator<Item = &str> {
+        self.names
+            .iter()
+            .filter(|(_, entry)| entry.references.is_empty())
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+impl Display for Xref {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, entry) in &self.names {
+            write!(f, "{}", name)?;
+            if entry.definitions.is_empty() {
+                write!(f, " (never defined)")?;
+            } else if entry.references.is_empty() {
+                write!(f, " (never referenced)")?;
+            }
+            writeln!(f)?;
+
+            for site in &entry.definitions {
+                writeln!(f, "  defined at {}", site)?;
+            }
+            for site in &entry.references {
+                writeln!(f, "  referenced at {}", site)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Build the cross-reference table for the script at path and every script it uses. Only `use`
+/// forms at the top level with a string literal argument are followed, and their paths are
+/// resolved the same way `use` resolves them when the script runs. Symbols are converted to
+/// lowercase if fold_case is true, like the `--fold-case` flag does when running the script.
+pub fn xref(path: &Path, fold_case: bool) -> Result<Xref, LispError> {
+    let builtins = builtins::builtins_hashmap();
+    let mut table = Xref::default();
+    let mut seen = HashSet::new();
+    let mut queue = vec![path.to_path_buf()];
+
+    while let Some(file) = queue.pop() {
+        let key = file
+            .canonicalize()
+            .map_err(|e| LispError::io(file.display(), e))?;
+        if !seen.insert(key) {
+            continue;
+        }
+
+        let forms = parse_file(&file, fold_case)?;
+        let mut used = vec![];
+        for (position, form) in &forms {
+            let site = Site {
+                file: file.clone(),
+                line: position.line,
+                column: position.column,
+            };
+
+            if let Some(used_file) = used_file(form) {
+                used.push(PathBuf::from(used_file));
+            }
+            if let Some(name) = defined_name(form) {
+                table
+                    .names
+                    .entry(name)
+                    .or_default()
+                    .definitions
+                    .push(site.clone());
+            }
+            for name in free_vars(form) {
+                if !builtins.contains_key(name.as_str()) {
+                    table
+                        .names
+                        .entry(name)
+                        .or_default()
+                        .references
+                        .push(site.clone());
+                }
+            }
+        }
+
+        // Visit used files in the order they appear
+        queue.extend(used.into_iter().rev());
+    }
+
+    Ok(table)
+}
+
+fn parse_file(path: &Path, fold_case: bool) -> Result<Vec<(Position, Ast)>, LispError> {
+    let contents = read_to_string(path).map_err(|e| LispError::io(path.display(), e))?;
+    parser::parse_program_located(&contents, fold_case)
+}
+
+/// The name defined by a top-level `(def name value)` or `(def (name params...) body...)`.
+fn defined_name(form: &Ast) -> Option<String> {
+    let Ast::List(list) = form else {
+        return None;
+    };
+    match list.as_slice() {
+        [Ast::Atom(LispAtom::Symbol(head)), target, ..] if head == "def" => match target {
+            Ast::Atom(LispAtom::Symbol(name)) => Some(name.clone()),
+            Ast::List(signature) => match signature.first() {
+                Some(Ast::Atom(LispAtom::Symbol(name))) => Some(name.clone()),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The file named by a top-level `(use "file")`.
+fn used_file(form: &Ast) -> Option<&str> {
+    let Ast::List(list) = form else {
+        return None;
+    };
+    match list.as_slice() {
+        [Ast::Atom(LispAtom::Symbol(head)), Ast::Atom(LispAtom::String(file))] if head == "use" => {
+            Some(file)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURES: &str = "tests/fixtures/xref";
+
+    #[test]
+    fn fixture_table() {
+        let table = xref(&Path::new(FIXTURES).join("main.lisp"), false).unwrap();
+        let expected = format!(
+            "area
+  defined at {dir}/shapes.lisp:2:1
+  referenced at {dir}/main.lisp:4:22
+radius (never defined)
+  referenced at {dir}/main.lisp:4:22
+square
+  defined at {dir}/shapes.lisp:1:1
+  referenced at {dir}/main.lisp:4:1
+  referenced at {dir}/shapes.lisp:2:1
+unused (never referenced)
+  defined at {dir}/shapes.lisp:3:1
+",
+            dir = FIXTURES
+        );
+        assert_eq!(table.to_string(), expected);
+        assert_eq!(table.undefined().collect::<Vec<_>>(), ["radius"]);
+        assert_eq!(table.unused().collect::<Vec<_>>(), ["unused"]);
+    }
+
+    #[test]
+    fn missing_file() {
+        assert!(matches!(
+            xref(&Path::new(FIXTURES).join("missing.lisp"), false),
+            Err(LispError::IOError(_))
+        ));
+    }
+}
@@ -0,0 +1,29 @@
+//! Runs scripts with command-line arguments through the callisp binary.
+
+use std::process::Command;
+
+#[test]
+fn script_sees_its_arguments() {
+    let output = Command::new(env!("CARGO_BIN_EXE_callisp"))
+        .args(["tests/fixtures/args/echo.lisp", "foo", "--bar", "two words"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "tests/fixtures/args/echo.lisp 3 (\"foo\" \"--bar\" \"two words\")\n"
+    );
+}
+
+#[test]
+fn script_without_arguments() {
+    let output = Command::new(env!("CARGO_BIN_EXE_callisp"))
+        .arg("tests/fixtures/args/echo.lisp")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "tests/fixtures/args/echo.lisp 0 ()\n"
+    );
+}
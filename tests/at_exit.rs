@@ -0,0 +1,15 @@
+//! Runs scripts that register `at-exit` handlers through the callisp binary.
+
+use std::process::Command;
+
+#[test]
+fn handler_exit_code_replaces_the_scripts() {
+    let output = Command::new(env!("CARGO_BIN_EXE_callisp"))
+        .arg("tests/fixtures/at_exit/exit_in_handler.lisp")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(4));
+    // The handler registered first still runs, and the exit isn't reported as an error
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "still runs\n");
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+}
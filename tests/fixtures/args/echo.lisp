@@ -0,0 +1 @@
+(println *script-name* (count *args*) *args*)
\ No newline at end of file
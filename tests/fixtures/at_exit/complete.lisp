@@ -0,0 +1,3 @@
+(def log 0)
+(at-exit (lambda () (eval (quote (def log (+ (* log 10) 1))))))
+(at-exit (lambda () (eval (quote (def log (+ (* log 10) 2))))))
\ No newline at end of file
@@ -0,0 +1,4 @@
+(def log 0)
+(at-exit (lambda () (eval (quote (def log (+ log 1))))))
+(exit 1)
+(def log 100)
\ No newline at end of file
@@ -0,0 +1,3 @@
+(at-exit (lambda () (putstr "still runs")))
+(at-exit (lambda () (exit 4)))
+(exit 3)
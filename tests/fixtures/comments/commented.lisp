@@ -0,0 +1,7 @@
+; Sum a few numbers
+(def total ; the running total
+  (+ 1 ; one
+     2 ; two
+  ))
+
+(def x total) ; no newline at the end
\ No newline at end of file
@@ -0,0 +1,4 @@
+; Nothing but comments
+;; in this file
+
+   ; indented, with no newline at the end
\ No newline at end of file
@@ -0,0 +1,7 @@
+(def x 1)
+#| the end of the file
+   #| never closed |#
+|#
+
+#| never closed
+(def y 2)
\ No newline at end of file
@@ -0,0 +1,4 @@
+(define x 1)
+(define y 2)
+(define z (+ x y)
+(display z)
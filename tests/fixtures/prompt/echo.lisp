@@ -0,0 +1,4 @@
+(print "name? ")
+(def name (readline))
+(print "hello" name)
+(newline)
\ No newline at end of file
@@ -0,0 +1,3 @@
+#!/usr/bin/env callisp
+; Can be run directly once it is executable
+(println "hello from" *script-name*)
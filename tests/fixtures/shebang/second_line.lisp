@@ -0,0 +1,2 @@
+(println "never printed")
+#!/usr/bin/env callisp
@@ -0,0 +1,4 @@
+(use "tests/fixtures/xref/shapes.lisp")
+
+; Two forms on one line start at different columns
+(println (square 3)) (println (area radius))
@@ -0,0 +1,3 @@
+(def (square x) (* x x))
+(def (area r) (* pi (square r)))
+(def unused 42)
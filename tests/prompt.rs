@@ -0,0 +1,22 @@
+//! Runs scripts that interact through stdin and stdout with the callisp binary.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn prompt_and_echo() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_callisp"))
+        .arg("tests/fixtures/prompt/echo.lisp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"world\n").unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "name? hello world\n"
+    );
+}
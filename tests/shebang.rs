@@ -0,0 +1,28 @@
+//! Runs scripts that start with a `#!` line through the callisp binary.
+
+use std::process::Command;
+
+#[test]
+fn shebang_line_is_skipped() {
+    let output = Command::new(env!("CARGO_BIN_EXE_callisp"))
+        .arg("tests/fixtures/shebang/hello.lisp")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "hello from tests/fixtures/shebang/hello.lisp\n"
+    );
+}
+
+#[test]
+fn shebang_only_on_the_first_line() {
+    let output = Command::new(env!("CARGO_BIN_EXE_callisp"))
+        .arg("tests/fixtures/shebang/second_line.lisp")
+        .output()
+        .unwrap();
+    // Nothing runs, since the file doesn't parse
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("line 2, column 1\n#!/usr/bin/env callisp\n^"));
+}